@@ -0,0 +1,756 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Offline decoding of MGS/SP packets for `faux-mgs decode`.
+
+use crate::component_details_to_json;
+use crate::Output;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use base64::Engine;
+use gateway_sp_comms::SpComponentDetails;
+use gateway_sp_comms::TlvEntries;
+use serde_json::json;
+use slog::Logger;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::net::Ipv6Addr;
+use std::net::SocketAddrV6;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum InputFormat {
+    /// Hex-encoded packets (whitespace and `:` separators are ignored).
+    Hex,
+    Base64,
+    /// Packets formatted as Rust byte slices (e.g., `[1, 0, 0, 0, ...]`), as
+    /// logged by MGS when it fails to parse a packet.
+    Bytes,
+    /// A pcap or pcapng capture; only UDP packets to or from the SP port are
+    /// decoded.
+    Pcap,
+}
+
+pub(crate) fn input_format_from_str(s: &str) -> Result<InputFormat> {
+    match s {
+        "hex" => Ok(InputFormat::Hex),
+        "base64" => Ok(InputFormat::Base64),
+        "bytes" => Ok(InputFormat::Bytes),
+        "pcap" | "pcapng" => Ok(InputFormat::Pcap),
+        _ => Err(anyhow!("Invalid input format: {s}")),
+    }
+}
+
+/// A packet to decode and a description of where it came from.
+struct RawPacket {
+    source: String,
+    data: Vec<u8>,
+}
+
+/// Decode the packet given by `input` (or a capture file at `input`, for
+/// [`InputFormat::Pcap`]); if `input` is `None`, read packets (one per line)
+/// or a capture from stdin.
+pub(crate) fn run(
+    format: InputFormat,
+    input: Option<&str>,
+    json: bool,
+    log: &Logger,
+) -> Result<Output> {
+    let packets = match (format, input) {
+        (InputFormat::Pcap, Some(path)) => {
+            let data = fs::read(path)
+                .with_context(|| format!("failed to read {path}"))?;
+            read_capture(&data)?
+        }
+        (InputFormat::Pcap, None) => {
+            let mut data = Vec::new();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut data)
+                .context("failed to read stdin")?;
+            read_capture(&data)?
+        }
+        (format, Some(packet)) => vec![RawPacket {
+            source: "argument".to_string(),
+            data: parse_packet_text(format, packet)?,
+        }],
+        (format, None) => {
+            let mut packets = Vec::new();
+            for (i, line) in io::stdin().lock().lines().enumerate() {
+                let line = line.context("failed to read stdin")?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                packets.push(RawPacket {
+                    source: format!("line {}", i + 1),
+                    data: parse_packet_text(format, &line)
+                        .with_context(|| format!("line {}", i + 1))?,
+                });
+            }
+            packets
+        }
+    };
+
+    if json {
+        let decoded = packets
+            .iter()
+            .map(|packet| decode_to_json(packet, log))
+            .collect::<Vec<_>>();
+        Ok(Output::Json(json!({ "packets": decoded })))
+    } else {
+        let mut lines = Vec::new();
+        for packet in &packets {
+            decode_to_lines(packet, log, &mut lines);
+        }
+        Ok(Output::Lines(lines))
+    }
+}
+
+fn parse_packet_text(format: InputFormat, s: &str) -> Result<Vec<u8>> {
+    let s = s.trim();
+    match format {
+        InputFormat::Hex => {
+            let s = s.strip_prefix("0x").unwrap_or(s);
+            let digits = s
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != ':')
+                .collect::<String>();
+            hex::decode(digits).context("invalid hex")
+        }
+        InputFormat::Base64 => base64::engine::general_purpose::STANDARD
+            .decode(s)
+            .context("invalid base64"),
+        InputFormat::Bytes => {
+            let s = s
+                .strip_prefix('[')
+                .and_then(|s| s.strip_suffix(']'))
+                .ok_or_else(|| anyhow!("expected `[..]`"))?;
+            s.split(',')
+                .map(str::trim)
+                .filter(|b| !b.is_empty())
+                .map(|b| {
+                    b.parse::<u8>().with_context(|| format!("bad byte {b:?}"))
+                })
+                .collect()
+        }
+        InputFormat::Pcap => unreachable!("captures are not parsed as text"),
+    }
+}
+
+fn decode_to_json(packet: &RawPacket, log: &Logger) -> serde_json::Value {
+    let (header, kind, data) =
+        match gateway_sp_comms::parse_packet(&packet.data) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                return json!({
+                    "source": packet.source,
+                    "error": err.to_string(),
+                    "data": hex::encode(&packet.data),
+                })
+            }
+        };
+
+    let mut decoded = json!({
+        "source": packet.source,
+        "header": header,
+        "kind": kind,
+        "trailing_data": hex::encode(data),
+    });
+    if let gateway_messages::MessageKind::SpResponse(response) = &kind {
+        match gateway_sp_comms::decode_tlv_page(response, data, log) {
+            Ok(Some(entries)) => decoded["tlv"] = tlv_entries_to_json(entries),
+            Ok(None) => (),
+            Err(err) => decoded["tlv_error"] = json!(err.to_string()),
+        }
+    }
+    decoded
+}
+
+fn decode_to_lines(packet: &RawPacket, log: &Logger, lines: &mut Vec<String>) {
+    lines.push(format!("{}:", packet.source));
+    let (header, kind, data) =
+        match gateway_sp_comms::parse_packet(&packet.data) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                lines.push(format!("    error: {err}"));
+                lines.push(format!("    data: {}", hex::encode(&packet.data)));
+                return;
+            }
+        };
+
+    lines.push(format!("    header: {header:?}"));
+    lines.push(format!("    kind: {kind:?}"));
+    if !data.is_empty() {
+        lines.push(format!(
+            "    trailing data ({} bytes): {}",
+            data.len(),
+            hex::encode(data)
+        ));
+    }
+    if let gateway_messages::MessageKind::SpResponse(response) = &kind {
+        match gateway_sp_comms::decode_tlv_page(response, data, log) {
+            Ok(Some(entries)) => {
+                let entries: Vec<String> = match entries {
+                    TlvEntries::Inventory(v) => debug_all(v),
+                    TlvEntries::ComponentDetails(v) => debug_all(v),
+                    TlvEntries::BulkMeasurements(v) => debug_all(v),
+                    TlvEntries::BulkIgnitionState(v) => debug_all(v),
+                    TlvEntries::BulkIgnitionLinkEvents(v) => debug_all(v),
+                };
+                for entry in entries {
+                    lines.push(format!("    tlv: {entry}"));
+                }
+            }
+            Ok(None) => (),
+            Err(err) => lines.push(format!("    tlv error: {err}")),
+        }
+    }
+}
+
+fn debug_all<T: std::fmt::Debug>(entries: Vec<T>) -> Vec<String> {
+    entries.iter().map(|e| format!("{e:?}")).collect()
+}
+
+fn tlv_entries_to_json(entries: TlvEntries) -> serde_json::Value {
+    match entries {
+        TlvEntries::Inventory(devices) => {
+            json!({ "inventory": devices })
+        }
+        TlvEntries::ComponentDetails(entries) => {
+            json!({
+                "component_details":
+                    component_details_to_json(SpComponentDetails { entries }),
+            })
+        }
+        TlvEntries::BulkMeasurements(measurements) => {
+            let measurements = measurements
+                .into_iter()
+                .map(|m| {
+                    json!({
+                        "component": m.component,
+                        "name": m.measurement.name(),
+                        "kind": m.measurement.kind(),
+                        "value": m.measurement.value(),
+                        "metadata": m.measurement.metadata(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "measurements": measurements })
+        }
+        TlvEntries::BulkIgnitionState(states) => {
+            json!({ "ignition_state": states })
+        }
+        TlvEntries::BulkIgnitionLinkEvents(events) => {
+            json!({ "ignition_link_events": events })
+        }
+    }
+}
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV6: u32 = 229;
+
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IPPROTO_UDP: u8 = 17;
+
+// Extract management network packets from a pcap or pcapng capture.
+fn read_capture(data: &[u8]) -> Result<Vec<RawPacket>> {
+    let magic = data.get(..4).ok_or_else(|| anyhow!("capture too short"))?;
+    let frames = match magic {
+        [0x0a, 0x0d, 0x0d, 0x0a] => read_pcapng_frames(data)?,
+        _ => read_pcap_frames(data)?,
+    };
+
+    let mut packets = Vec::new();
+    for (i, (linktype, frame)) in frames.into_iter().enumerate() {
+        let (src, dst, payload) =
+            match ipv6_from_frame(linktype, frame).and_then(udp_from_ipv6) {
+                Some(udp) => udp,
+                None => continue,
+            };
+        if src.port() != gateway_sp_comms::SP_PORT
+            && dst.port() != gateway_sp_comms::SP_PORT
+        {
+            continue;
+        }
+        packets.push(RawPacket {
+            source: format!("frame {} ({src} -> {dst})", i + 1),
+            data: payload.to_vec(),
+        });
+    }
+    Ok(packets)
+}
+
+// Reads little- or big-endian integers from a capture file.
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, data: &[u8], offset: usize) -> Result<u16> {
+        let bytes = data
+            .get(offset..offset + 2)
+            .ok_or_else(|| anyhow!("truncated capture"))?;
+        let bytes = bytes.try_into().unwrap();
+        Ok(if self.big {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(self, data: &[u8], offset: usize) -> Result<u32> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated capture"))?;
+        let bytes = bytes.try_into().unwrap();
+        Ok(if self.big {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+// Returns the link type and contents of each frame in a classic pcap file.
+fn read_pcap_frames(data: &[u8]) -> Result<Vec<(u32, &[u8])>> {
+    let big = match data[..4] {
+        // Microsecond and nanosecond timestamp variants.
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => false,
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => true,
+        _ => bail!("not a pcap or pcapng capture"),
+    };
+    let endian = Endian { big };
+    let linktype = endian.u32(data, 20)?;
+
+    let mut frames = Vec::new();
+    let mut offset = 24;
+    while offset < data.len() {
+        let caplen = endian.u32(data, offset + 8)? as usize;
+        let start = offset + 16;
+        let frame = data
+            .get(start..start + caplen)
+            .ok_or_else(|| anyhow!("truncated capture"))?;
+        frames.push((linktype, frame));
+        offset = start + caplen;
+    }
+    Ok(frames)
+}
+
+// Returns the link type and contents of each frame in a pcapng file.
+fn read_pcapng_frames(data: &[u8]) -> Result<Vec<(u32, &[u8])>> {
+    const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+    const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+    const BLOCK_SIMPLE_PACKET: u32 = 3;
+    const BLOCK_ENHANCED_PACKET: u32 = 6;
+
+    let mut endian = Endian { big: false };
+    let mut linktypes = Vec::new();
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        // The section header's byte-order magic tells us how to read it (and
+        // every block in its section).
+        let block_type = endian.u32(data, offset)?;
+        if block_type == BLOCK_SECTION_HEADER {
+            endian = match data.get(offset + 8..offset + 12) {
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => Endian { big: false },
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => Endian { big: true },
+                _ => bail!("bad pcapng byte-order magic"),
+            };
+            linktypes.clear();
+        }
+
+        let block_len = endian.u32(data, offset + 4)? as usize;
+        if block_len < 12 {
+            bail!("bad pcapng block length {block_len}");
+        }
+        let body = data
+            .get(offset + 8..offset + block_len - 4)
+            .ok_or_else(|| anyhow!("truncated capture"))?;
+
+        match block_type {
+            BLOCK_INTERFACE_DESCRIPTION => {
+                linktypes.push(u32::from(endian.u16(body, 0)?));
+            }
+            BLOCK_ENHANCED_PACKET => {
+                let interface = endian.u32(body, 0)? as usize;
+                let caplen = endian.u32(body, 12)? as usize;
+                let frame = body
+                    .get(20..20 + caplen)
+                    .ok_or_else(|| anyhow!("truncated capture"))?;
+                let linktype = *linktypes.get(interface).ok_or_else(|| {
+                    anyhow!("packet on unknown interface {interface}")
+                })?;
+                frames.push((linktype, frame));
+            }
+            BLOCK_SIMPLE_PACKET => {
+                let len = endian.u32(body, 0)? as usize;
+                let frame = &body[4..];
+                let frame = &frame[..len.min(frame.len())];
+                let linktype = *linktypes
+                    .first()
+                    .ok_or_else(|| anyhow!("packet before any interface"))?;
+                frames.push((linktype, frame));
+            }
+            _ => (),
+        }
+
+        offset += block_len;
+    }
+    Ok(frames)
+}
+
+// Strip the link layer from `frame`, returning its IPv6 packet (if any).
+fn ipv6_from_frame(linktype: u32, frame: &[u8]) -> Option<&[u8]> {
+    let (ethertype, rest) = match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV6 => {
+            return (frame.first()? >> 4 == 6).then_some(frame);
+        }
+        LINKTYPE_ETHERNET => {
+            let mut ethertype =
+                u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+            let mut rest = frame.get(14..)?;
+            // The management network runs over VLANs.
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype =
+                    u16::from_be_bytes(rest.get(2..4)?.try_into().ok()?);
+                rest = rest.get(4..)?;
+            }
+            (ethertype, rest)
+        }
+        LINKTYPE_LINUX_SLL => {
+            let ethertype =
+                u16::from_be_bytes(frame.get(14..16)?.try_into().ok()?);
+            (ethertype, frame.get(16..)?)
+        }
+        _ => return None,
+    };
+    (ethertype == ETHERTYPE_IPV6).then_some(rest)
+}
+
+// Returns the source, destination, and payload of a UDP packet carried
+// directly (i.e., without extension headers) by `ip`.
+fn udp_from_ipv6(ip: &[u8]) -> Option<(SocketAddrV6, SocketAddrV6, &[u8])> {
+    if *ip.get(6)? != IPPROTO_UDP {
+        return None;
+    }
+    let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+    let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+    let udp = ip.get(40..)?;
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let udp_len =
+        usize::from(u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?));
+    let payload = udp.get(8..udp_len)?;
+    Some((
+        SocketAddrV6::new(Ipv6Addr::from(src), src_port, 0, 0),
+        SocketAddrV6::new(Ipv6Addr::from(dst), dst_port, 0, 0),
+        payload,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gateway_messages::serialize_with_trailing_data;
+    use gateway_messages::tlv;
+    use gateway_messages::version;
+    use gateway_messages::DeviceCapabilities;
+    use gateway_messages::DeviceDescriptionHeader;
+    use gateway_messages::DevicePresence;
+    use gateway_messages::Header;
+    use gateway_messages::HubpackError;
+    use gateway_messages::Message;
+    use gateway_messages::MessageKind;
+    use gateway_messages::SpComponent;
+    use gateway_messages::SpResponse;
+    use gateway_messages::TlvPage;
+    use gateway_messages::MAX_SERIALIZED_SIZE;
+
+    #[test]
+    fn parse_packet_text_formats() {
+        let expected = vec![0x01, 0xab, 0x00, 0xff];
+        for (format, s) in [
+            (InputFormat::Hex, "01ab00ff"),
+            (InputFormat::Hex, "0x01:ab:00:ff"),
+            (InputFormat::Hex, "01 ab 00 ff"),
+            (InputFormat::Base64, "AasA/w=="),
+            (InputFormat::Bytes, "[1, 171, 0, 255]"),
+        ] {
+            assert_eq!(parse_packet_text(format, s).unwrap(), expected, "{s}");
+        }
+    }
+
+    // An IPv6 packet carrying a UDP datagram from port 22222 to the SP port.
+    fn ipv6_udp_to_sp(payload: &[u8]) -> Vec<u8> {
+        let udp_len = (payload.len() + 8) as u16;
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend_from_slice(&udp_len.to_be_bytes());
+        ip.extend_from_slice(&[IPPROTO_UDP, 64]);
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&22222u16.to_be_bytes());
+        ip.extend_from_slice(&gateway_sp_comms::SP_PORT.to_be_bytes());
+        ip.extend_from_slice(&udp_len.to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        ip
+    }
+
+    #[test]
+    fn read_classic_pcap_of_raw_ipv6() {
+        let payload = [1, 2, 3];
+        let ip = ipv6_udp_to_sp(&payload);
+
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 12]);
+        pcap.extend_from_slice(&LINKTYPE_IPV6.to_le_bytes());
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        pcap.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        pcap.extend_from_slice(&ip);
+
+        let packets = read_capture(&pcap).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, payload);
+        assert_eq!(packets[0].source, "frame 1 ([::1]:22222 -> [::1]:11111)");
+    }
+
+    // Append a pcapng block with the given type and body (padded to 32 bits)
+    // to `out`, in the given byte order.
+    fn push_pcapng_block(
+        out: &mut Vec<u8>,
+        big: bool,
+        block_type: u32,
+        body: &[u8],
+    ) {
+        let u32_bytes = |n: u32| {
+            if big {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+        let padded = (body.len() + 3) / 4 * 4;
+        let len = (padded + 12) as u32;
+        out.extend_from_slice(&u32_bytes(block_type));
+        out.extend_from_slice(&u32_bytes(len));
+        out.extend_from_slice(body);
+        out.resize(out.len() + padded - body.len(), 0);
+        out.extend_from_slice(&u32_bytes(len));
+    }
+
+    // Append a pcapng section with an interface of each of `linktypes` to
+    // `out`, followed by an enhanced packet block for each of `packets` (on
+    // the interface with the given index).
+    fn push_pcapng_section(
+        out: &mut Vec<u8>,
+        big: bool,
+        linktypes: &[u32],
+        packets: &[(u32, &[u8])],
+    ) {
+        let u16_bytes = |n: u16| {
+            if big {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+        let u32_bytes = |n: u32| {
+            if big {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+
+        let mut shb = u32_bytes(0x1a2b_3c4d).to_vec();
+        shb.extend_from_slice(&u16_bytes(1));
+        shb.extend_from_slice(&u16_bytes(0));
+        shb.extend_from_slice(&[0xff; 8]);
+        push_pcapng_block(out, big, 0x0a0d_0d0a, &shb);
+
+        for &linktype in linktypes {
+            let mut idb = u16_bytes(linktype as u16).to_vec();
+            idb.extend_from_slice(&[0; 6]);
+            push_pcapng_block(out, big, 1, &idb);
+        }
+
+        for &(interface, frame) in packets {
+            let mut epb = u32_bytes(interface).to_vec();
+            epb.extend_from_slice(&[0; 8]);
+            epb.extend_from_slice(&u32_bytes(frame.len() as u32));
+            epb.extend_from_slice(&u32_bytes(frame.len() as u32));
+            epb.extend_from_slice(frame);
+            push_pcapng_block(out, big, 6, &epb);
+        }
+    }
+
+    #[test]
+    fn read_pcapng_frames_by_interface_and_section() {
+        let mut ethernet = vec![0; 12];
+        ethernet.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        ethernet.extend_from_slice(&ipv6_udp_to_sp(&[4, 5]));
+        let raw = ipv6_udp_to_sp(&[1, 2, 3]);
+
+        // A little-endian section with packets on both of its interfaces, an
+        // unknown block type (which should be skipped), then a big-endian
+        // section whose only interface is numbered from zero again.
+        let mut pcapng = Vec::new();
+        push_pcapng_section(
+            &mut pcapng,
+            false,
+            &[LINKTYPE_ETHERNET, LINKTYPE_IPV6],
+            &[(1, &raw), (0, &ethernet)],
+        );
+        push_pcapng_block(&mut pcapng, false, 0x0bad, &[1, 2, 3, 4, 5]);
+        push_pcapng_section(&mut pcapng, true, &[LINKTYPE_RAW], &[(0, &raw)]);
+
+        let frames = read_pcapng_frames(&pcapng).unwrap();
+        assert_eq!(
+            frames,
+            [
+                (LINKTYPE_IPV6, &raw[..]),
+                (LINKTYPE_ETHERNET, &ethernet[..]),
+                (LINKTYPE_RAW, &raw[..]),
+            ]
+        );
+
+        let packets = read_capture(&pcapng).unwrap();
+        let payloads =
+            packets.iter().map(|p| p.data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(payloads, [&[1, 2, 3][..], &[4, 5], &[1, 2, 3]]);
+        assert_eq!(packets[1].source, "frame 2 ([::1]:22222 -> [::1]:11111)");
+    }
+
+    #[test]
+    fn read_pcapng_frames_rejects_bad_captures() {
+        let raw = ipv6_udp_to_sp(&[1, 2, 3]);
+
+        // A packet on an interface that was never described.
+        let mut pcapng = Vec::new();
+        push_pcapng_section(&mut pcapng, false, &[LINKTYPE_IPV6], &[(1, &raw)]);
+        let err = read_pcapng_frames(&pcapng).unwrap_err();
+        assert_eq!(err.to_string(), "packet on unknown interface 1");
+
+        // A truncated packet block.
+        let mut pcapng = Vec::new();
+        push_pcapng_section(&mut pcapng, false, &[LINKTYPE_IPV6], &[(0, &raw)]);
+        pcapng.truncate(pcapng.len() - 8);
+        let err = read_pcapng_frames(&pcapng).unwrap_err();
+        assert_eq!(err.to_string(), "truncated capture");
+
+        // A section header with a bad byte-order magic.
+        let mut pcapng = Vec::new();
+        push_pcapng_section(&mut pcapng, false, &[], &[]);
+        pcapng[8] = 0;
+        let err = read_pcapng_frames(&pcapng).unwrap_err();
+        assert_eq!(err.to_string(), "bad pcapng byte-order magic");
+    }
+
+    // An SP's inventory response carrying `devices` as its TLV-encoded
+    // trailing data.
+    fn inventory_page(devices: &[(&str, &str)]) -> Vec<u8> {
+        let mut tlv = vec![0; 1024];
+        let mut n = 0;
+        for (device, description) in devices {
+            n += tlv::encode(
+                &mut tlv[n..],
+                DeviceDescriptionHeader::TAG,
+                |buf| {
+                    let header = DeviceDescriptionHeader {
+                        component: SpComponent::try_from(*device).unwrap(),
+                        device_len: device.len() as u32,
+                        description_len: description.len() as u32,
+                        capabilities: DeviceCapabilities::empty(),
+                        presence: DevicePresence::Present,
+                    };
+                    let mut n = gateway_messages::serialize(buf, &header)?;
+                    for s in [device, description] {
+                        buf[n..][..s.len()].copy_from_slice(s.as_bytes());
+                        n += s.len();
+                    }
+                    Ok::<_, HubpackError>(n)
+                },
+            )
+            .unwrap();
+        }
+
+        let message = Message {
+            header: Header { version: version::CURRENT, message_id: 1 },
+            kind: MessageKind::SpResponse(SpResponse::Inventory(TlvPage {
+                offset: 0,
+                total: devices.len() as u32,
+            })),
+        };
+        let mut out = [0; MAX_SERIALIZED_SIZE];
+        let (len, _) =
+            serialize_with_trailing_data(&mut out, &message, &[&tlv[..n]]);
+        out[..len].to_vec()
+    }
+
+    fn raw_packet(data: Vec<u8>) -> RawPacket {
+        RawPacket { source: "test".to_string(), data }
+    }
+
+    #[test]
+    fn decode_tlv_inventory_page() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let packet = raw_packet(inventory_page(&[
+            ("dev-0", "first device"),
+            ("dev-1", "second device"),
+        ]));
+
+        let decoded = decode_to_json(&packet, &log);
+        let devices = decoded["tlv"]["inventory"].as_array().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0]["device"], "dev-0");
+        assert_eq!(devices[0]["description"], "first device");
+        assert_eq!(devices[1]["device"], "dev-1");
+        assert_eq!(devices[1]["description"], "second device");
+        assert!(decoded.get("tlv_error").is_none());
+
+        let mut lines = Vec::new();
+        decode_to_lines(&packet, &log, &mut lines);
+        let tlv_lines = lines
+            .iter()
+            .filter(|line| line.starts_with("    tlv: "))
+            .collect::<Vec<_>>();
+        assert_eq!(tlv_lines.len(), 2);
+        assert!(tlv_lines[0].contains("\"first device\""), "{lines:?}");
+        assert!(tlv_lines[1].contains("\"second device\""), "{lines:?}");
+    }
+
+    #[test]
+    fn decode_tlv_page_errors_are_reported() {
+        let log = Logger::root(slog::Discard, slog::o!());
+
+        // Cut the last device's TLV triple short.
+        let mut data = inventory_page(&[("dev-0", "first device")]);
+        data.truncate(data.len() - 4);
+        let packet = raw_packet(data);
+
+        let decoded = decode_to_json(&packet, &log);
+        assert!(decoded.get("tlv").is_none());
+        assert!(decoded["tlv_error"].is_string(), "{decoded}");
+
+        let mut lines = Vec::new();
+        decode_to_lines(&packet, &log, &mut lines);
+        assert!(
+            lines.iter().any(|line| line.starts_with("    tlv error: ")),
+            "{lines:?}"
+        );
+    }
+}
@@ -14,14 +14,19 @@ use futures::stream::FuturesOrdered;
 use futures::FutureExt;
 use futures::StreamExt;
 use gateway_messages::ignition::TransceiverSelect;
+use gateway_messages::measurement::VersionedMeasurement;
+use gateway_messages::monorail_port_status::PortMode;
+use gateway_messages::monorail_port_status::Speed;
 use gateway_messages::ComponentAction;
 use gateway_messages::IgnitionCommand;
 use gateway_messages::LedComponentAction;
+use gateway_messages::MonorailComponentAction;
 use gateway_messages::PowerState;
 use gateway_messages::SpComponent;
 use gateway_messages::StartupOptions;
 use gateway_messages::UpdateId;
 use gateway_messages::UpdateStatus;
+use gateway_sp_comms::DiscoveryConfig;
 use gateway_sp_comms::InMemoryHostPhase2Provider;
 use gateway_sp_comms::SharedSocket;
 use gateway_sp_comms::SingleSp;
@@ -40,6 +45,8 @@ use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io;
+use std::io::BufWriter;
+use std::net::SocketAddr;
 use std::net::SocketAddrV6;
 use std::path::Path;
 use std::path::PathBuf;
@@ -47,6 +54,8 @@ use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
 
+mod decode;
+mod metrics_server;
 mod picocom_map;
 mod usart;
 
@@ -66,6 +75,10 @@ struct Args {
     #[clap(long)]
     logfile: Option<PathBuf>,
 
+    /// Capture all packets exchanged with SPs to a pcapng file.
+    #[clap(long)]
+    pcap: Option<PathBuf>,
+
     /// Emit parseable JSON on stdout instead of "human-readable" (often
     /// `Debug`-formatted) data.
     #[clap(long, value_names = ["pretty"], value_parser = json_pretty_from_str)]
@@ -81,11 +94,12 @@ struct Args {
     #[clap(long, default_value_t = gateway_sp_comms::default_discovery_addr())]
     discovery_addr: SocketAddrV6,
 
-    /// Interface(s) to use to communicate with target SP(s).
+    /// Interface(s) to use to communicate with target SP(s). Required for all
+    /// commands except `decode`.
     ///
     /// Supports shell-like glob patterns (e.g., "gimlet*"). May be specified
     /// multiple times.
-    #[clap(long, required = true)]
+    #[clap(long)]
     interface: Vec<String>,
 
     /// Maximum number of attempts to make when sending requests to the SP.
@@ -96,6 +110,31 @@ struct Args {
     #[clap(long, default_value = "2000")]
     per_attempt_timeout_millis: u64,
 
+    /// Delay (in milliseconds) before retrying failed initial discovery; this
+    /// doubles after each failure, up to `--discovery-max-retry-millis`.
+    #[clap(long)]
+    discovery_retry_millis: Option<u64>,
+
+    /// Maximum delay (in milliseconds) between initial discovery attempts.
+    #[clap(long)]
+    discovery_max_retry_millis: Option<u64>,
+
+    /// Interval (in milliseconds) between discovery packets once the SP has
+    /// been discovered and we are otherwise idle.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    discovery_idle_millis: Option<u64>,
+
+    /// After this many consecutive requests to the SP fail, send discovery
+    /// packets every `--discovery-aggressive-millis` until it responds. 0
+    /// disables aggressive rediscovery.
+    #[clap(long)]
+    discovery_aggressive_after_failures: Option<usize>,
+
+    /// Interval (in milliseconds) between discovery packets while
+    /// rediscovering aggressively.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    discovery_aggressive_millis: Option<u64>,
+
     #[clap(subcommand)]
     command: Command,
 }
@@ -124,6 +163,19 @@ enum Command {
     /// Discover a connected SP.
     Discover,
 
+    /// Decode packets offline (e.g., from MGS logs or a packet capture)
+    /// without contacting any SPs.
+    Decode {
+        /// Format of the input: "hex", "base64", "bytes" (e.g.,
+        /// `[1, 0, 0, 0]`, as logged by MGS), or "pcap" (pcap or pcapng).
+        #[clap(long, default_value = "hex", value_parser = decode::input_format_from_str)]
+        format: decode::InputFormat,
+
+        /// Packet to decode, or the path of a capture to decode. If omitted,
+        /// packets (one per line) or a capture are read from stdin.
+        input: Option<String>,
+    },
+
     /// Ask SP for its current state.
     State,
 
@@ -200,6 +252,9 @@ enum Command {
         component: SpComponent,
     },
 
+    /// Ask SP for all measurements across all of its components.
+    Measurements,
+
     /// Ask SP to clear the state (e.g., reset counters) on a component.
     ComponentClearStatus {
         #[clap(value_parser = parse_sp_component)]
@@ -242,6 +297,18 @@ enum Command {
     /// Serve host phase 2 images.
     ServeHostPhase2 { directory: PathBuf },
 
+    /// Periodically collect telemetry from all SPs and serve it over HTTP in
+    /// the Prometheus text format.
+    MetricsServer {
+        /// Address on which to serve `/metrics`.
+        #[clap(long, default_value = "[::1]:9101")]
+        http_addr: SocketAddr,
+
+        /// Interval (in milliseconds) between telemetry collections.
+        #[clap(long, default_value = "10000")]
+        interval_millis: u64,
+    },
+
     /// Upload a new image to the SP or one of its components.
     ///
     /// To update the SP itself:
@@ -336,6 +403,13 @@ enum Command {
         #[clap(subcommand)]
         cmd: LedCommand,
     },
+
+    /// Controls the management network switch (only valid if the SP is a
+    /// sidecar).
+    Monorail {
+        #[clap(subcommand)]
+        cmd: MonorailCommand,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -348,6 +422,27 @@ enum LedCommand {
     Blink,
 }
 
+#[derive(Subcommand, Debug, Clone)]
+enum MonorailCommand {
+    /// Enables a port
+    EnablePort { port: u32 },
+    /// Disables a port
+    DisablePort { port: u32 },
+    /// Changes the mode (and speed) of a port
+    SetPortMode {
+        port: u32,
+        #[clap(
+            help = "'sfi', 'base-kr', 'sgmii-{100m,1g,10g}', or 'qsgmii-{100m,1g,10g}'",
+            value_parser = port_mode_from_str,
+        )]
+        mode: PortMode,
+    },
+    /// Resets the counters of a single port
+    ResetCounters { port: u32 },
+    /// Restarts the PHY attached to a port
+    RestartPhy { port: u32 },
+}
+
 impl Command {
     // If the user didn't specify a listening port, what should we use? We
     // allow this to vary by command so that client commands (most of them) can
@@ -422,6 +517,28 @@ fn power_state_from_str(s: &str) -> Result<PowerState> {
     }
 }
 
+fn port_mode_from_str(s: &str) -> Result<PortMode> {
+    let speed_from_str = |speed: &str| match speed {
+        "100m" | "100M" => Ok(Speed::Speed100M),
+        "1g" | "1G" => Ok(Speed::Speed1G),
+        "10g" | "10G" => Ok(Speed::Speed10G),
+        _ => Err(anyhow!("Invalid port speed: {speed}")),
+    };
+    match s {
+        "sfi" => Ok(PortMode::Sfi),
+        "base-kr" => Ok(PortMode::BaseKr),
+        _ => {
+            if let Some(speed) = s.strip_prefix("sgmii-") {
+                Ok(PortMode::Sgmii(speed_from_str(speed)?))
+            } else if let Some(speed) = s.strip_prefix("qsgmii-") {
+                Ok(PortMode::Qsgmii(speed_from_str(speed)?))
+            } else {
+                Err(anyhow!("Invalid port mode: {s}"))
+            }
+        }
+    }
+}
+
 fn ignition_command_from_str(s: &str) -> Result<IgnitionCommand> {
     match s {
         "power-on" => Ok(IgnitionCommand::PowerOn),
@@ -503,9 +620,61 @@ async fn main() -> Result<()> {
 
     let log = build_logger(args.log_level, args.logfile.as_deref())?;
 
+    // Decoding doesn't talk to any SPs; handle it before we set any up.
+    if let Command::Decode { format, input } = &args.command {
+        let output =
+            decode::run(*format, input.as_deref(), args.json.is_some(), &log)?;
+        match (output, args.json) {
+            (Output::Json(value), Some(Some(JsonPretty))) => {
+                serde_json::to_writer_pretty(io::stdout().lock(), &value)
+                    .context("failed to write to stdout")?;
+            }
+            (Output::Json(value), _) => {
+                serde_json::to_writer(io::stdout().lock(), &value)
+                    .context("failed to write to stdout")?;
+            }
+            (Output::Lines(lines), _) => {
+                for line in lines {
+                    println!("{line}");
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if args.interface.is_empty() {
+        bail!("at least one `--interface` is required");
+    }
+
     let per_attempt_timeout =
         Duration::from_millis(args.per_attempt_timeout_millis);
 
+    let defaults = DiscoveryConfig::default();
+    let millis_or = |millis: Option<u64>, default| {
+        millis.map(Duration::from_millis).unwrap_or(default)
+    };
+    let discovery = DiscoveryConfig::new(
+        millis_or(
+            args.discovery_retry_millis,
+            defaults.initial_retry_interval(),
+        ),
+        millis_or(
+            args.discovery_max_retry_millis,
+            defaults.max_retry_interval(),
+        ),
+        millis_or(args.discovery_idle_millis, defaults.idle_interval()),
+        match args.discovery_aggressive_after_failures {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => defaults.aggressive_after_failures(),
+        },
+        millis_or(
+            args.discovery_aggressive_millis,
+            defaults.aggressive_interval(),
+        ),
+    )
+    .context("invalid discovery configuration")?;
+
     let listen_port =
         args.listen_port.unwrap_or_else(|| args.command.default_listen_port());
 
@@ -522,6 +691,15 @@ async fn main() -> Result<()> {
     .await
     .context("SharedSocket:bind() failed")?;
 
+    if let Some(path) = args.pcap.as_deref() {
+        let file = File::create(path).with_context(|| {
+            format!("failed to create pcap file {}", path.display())
+        })?;
+        shared_socket
+            .start_capture(BufWriter::new(file))
+            .context("failed to start packet capture")?;
+    }
+
     let interfaces = build_requested_interfaces(args.interface)?;
     let mut sps = Vec::with_capacity(interfaces.len());
     for interface in interfaces {
@@ -532,6 +710,7 @@ async fn main() -> Result<()> {
                 SwitchPortConfig {
                     discovery_addr: args.discovery_addr,
                     interface,
+                    discovery: discovery.clone(),
                 },
                 args.max_attempts,
                 per_attempt_timeout,
@@ -548,7 +727,8 @@ async fn main() -> Result<()> {
     //    SPs.
     // 2. serve-host-phase2 runs forever; we _should_ accept multiple SPs (all
     //    the SPs to serve) but only need to run the command once.
-    // 3. update: ensure the user passed `--allow-multiple-update` if they gave
+    // 3. metrics-server runs forever and collects from all SPs itself.
+    // 4. update: ensure the user passed `--allow-multiple-update` if they gave
     //    us multiple SPs to avoid accidentally trying to update many SPs
     //    simultaneously. (Actually peforming the update is still handled
     //    below.)
@@ -598,6 +778,22 @@ async fn main() -> Result<()> {
                 tokio::time::sleep(Duration::from_secs(1024)).await;
             }
         }
+        Command::MetricsServer { http_addr, interval_millis } => {
+            assert!(
+                args.json.is_none(),
+                "--json not supported for metrics-server"
+            );
+            metrics_server::run(
+                sps,
+                http_addr,
+                Duration::from_millis(interval_millis),
+                log,
+            )
+            .await?;
+
+            // metrics_server::run() only returns on error.
+            return Ok(());
+        }
         Command::Update { allow_multiple_update, .. } => {
             if num_sps > 1 && !allow_multiple_update {
                 bail!("Did you mean to attempt to update multiple SPs? If so, add `--allow-multiple-updates`.");
@@ -674,9 +870,10 @@ async fn run_command(
 ) -> Result<Output> {
     match command {
         // Skip special commands handled by `main()` above.
-        Command::UsartAttach { .. } | Command::ServeHostPhase2 { .. } => {
-            unreachable!()
-        }
+        Command::Decode { .. }
+        | Command::UsartAttach { .. }
+        | Command::ServeHostPhase2 { .. }
+        | Command::MetricsServer { .. } => unreachable!(),
 
         // Remainder of commands.
         Command::Discover => {
@@ -938,7 +1135,36 @@ async fn run_command(
             }
             let mut lines = Vec::new();
             for entry in details.entries {
-                lines.push(format!("{entry:?}"));
+                lines.push(component_details_entry_to_line(entry));
+            }
+            Ok(Output::Lines(lines))
+        }
+        Command::Measurements => {
+            let measurements = sp.all_measurements().await?;
+            if json {
+                let measurements = measurements
+                    .into_iter()
+                    .map(|m| {
+                        json!({
+                            "component": m.component,
+                            "name": m.measurement.name(),
+                            "kind": m.measurement.kind(),
+                            "value": m.measurement.value(),
+                            "metadata": m.measurement.metadata(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                return Ok(Output::Json(json!({
+                    "measurements": measurements
+                })));
+            }
+            let mut lines = Vec::new();
+            for m in measurements {
+                lines.push(format!(
+                    "{:<16} {}",
+                    m.component.as_str().unwrap_or("???"),
+                    measurement_to_line(&m.measurement),
+                ));
             }
             Ok(Output::Lines(lines))
         }
@@ -1148,6 +1374,36 @@ async fn run_command(
                 Ok(Output::Lines(vec!["done".to_string()]))
             }
         }
+        Command::Monorail { cmd } => {
+            let action = match cmd {
+                MonorailCommand::EnablePort { port } => {
+                    MonorailComponentAction::EnablePort { port }
+                }
+                MonorailCommand::DisablePort { port } => {
+                    MonorailComponentAction::DisablePort { port }
+                }
+                MonorailCommand::SetPortMode { port, mode } => {
+                    MonorailComponentAction::SetPortMode { port, mode }
+                }
+                MonorailCommand::ResetCounters { port } => {
+                    MonorailComponentAction::ResetPortCounters { port }
+                }
+                MonorailCommand::RestartPhy { port } => {
+                    MonorailComponentAction::RestartPhy { port }
+                }
+            };
+            sp.component_action(
+                SpComponent::MONORAIL,
+                ComponentAction::Monorail(action),
+            )
+            .await?;
+            info!(log, "monorail action {action:?} complete");
+            if json {
+                Ok(Output::Json(json!({ "ack": "monorail" })))
+            } else {
+                Ok(Output::Lines(vec!["done".to_string()]))
+            }
+        }
         Command::ReadComponentCaboose { component, slot, key } => {
             let slot = match (component, slot.as_deref()) {
                 (SpComponent::SP_ITSELF, Some("active" | "0") | None) => 0,
@@ -1340,9 +1596,71 @@ enum Output {
     Lines(Vec<String>),
 }
 
+fn component_details_entry_to_line(
+    entry: gateway_messages::ComponentDetails,
+) -> String {
+    use gateway_messages::ComponentDetails;
+
+    match entry {
+        ComponentDetails::MacTableEntry(e) => {
+            let mac = e
+                .mac
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(":");
+            format!(
+                "mac {mac} vlan {} port {} age {}s",
+                e.vlan, e.port, e.age_secs
+            )
+        }
+        ComponentDetails::LinkFlapEvent(e) => {
+            let timestamp = Duration::from_millis(e.timestamp_ms);
+            format!(
+                "port {} link {:?} at {:?} (SP uptime)",
+                e.port, e.status, timestamp
+            )
+        }
+        ComponentDetails::Measurement(m) => measurement_to_line(&m.into()),
+        ComponentDetails::MeasurementV2(m) => measurement_to_line(&m.into()),
+        ComponentDetails::PortStatus(_) => format!("{entry:?}"),
+    }
+}
+
+fn measurement_to_line(m: &VersionedMeasurement) -> String {
+    let metadata = m.metadata();
+    let suffix = metadata.map(|md| md.unit.suffix()).unwrap_or("");
+    let value = match m.value() {
+        Ok(value) => format!("{value}{suffix}"),
+        Err(err) => format!("{err:?}"),
+    };
+    let mut line = format!("{} ({:?}): {value}", m.name(), m.kind());
+    if let Some(md) = metadata {
+        let fmt_opt = |v: Option<f32>| match v {
+            Some(v) => format!("{v}{suffix}"),
+            None => "-".to_string(),
+        };
+        line.push_str(&format!(
+            " [sensor {}, warn {}, crit {}, min {}, max {}, \
+             sampled at {:?} (SP uptime)]",
+            md.sensor_id,
+            fmt_opt(md.warning_threshold),
+            fmt_opt(md.critical_threshold),
+            fmt_opt(md.min_since_boot),
+            fmt_opt(md.max_since_boot),
+            Duration::from_millis(md.timestamp_ms),
+        ));
+    }
+    line
+}
+
 fn component_details_to_json(details: SpComponentDetails) -> serde_json::Value {
-    use gateway_messages::measurement::{MeasurementError, MeasurementKind};
-    use gateway_messages::monorail_port_status::{PortStatus, PortStatusError};
+    use gateway_messages::measurement::{
+        MeasurementError, MeasurementKindV2, MeasurementMetadata,
+    };
+    use gateway_messages::monorail_port_status::{
+        LinkFlapEvent, MacTableEntry, PortStatus, PortStatusError,
+    };
 
     // SpComponentDetails and Measurement from gateway_messages intentionally do
     // not derive `Serialize` to avoid accidental misuse in MGS / the SP, so we
@@ -1352,13 +1670,17 @@ fn component_details_to_json(details: SpComponentDetails) -> serde_json::Value {
     enum ComponentDetails {
         PortStatus(Result<PortStatus, PortStatusError>),
         Measurement(Measurement),
+        MacTableEntry(MacTableEntry),
+        LinkFlapEvent(LinkFlapEvent),
     }
 
     #[derive(serde::Serialize)]
     struct Measurement {
         pub name: String,
-        pub kind: MeasurementKind,
+        pub kind: MeasurementKindV2,
         pub value: Result<f32, MeasurementError>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<MeasurementMetadata>,
     }
 
     let entries = details
@@ -1369,12 +1691,27 @@ fn component_details_to_json(details: SpComponentDetails) -> serde_json::Value {
                 ComponentDetails::PortStatus(r)
             }
             gateway_messages::ComponentDetails::Measurement(m) => {
+                ComponentDetails::Measurement(Measurement {
+                    name: m.name,
+                    kind: m.kind.into(),
+                    value: m.value,
+                    metadata: None,
+                })
+            }
+            gateway_messages::ComponentDetails::MeasurementV2(m) => {
                 ComponentDetails::Measurement(Measurement {
                     name: m.name,
                     kind: m.kind,
                     value: m.value,
+                    metadata: Some(m.metadata),
                 })
             }
+            gateway_messages::ComponentDetails::MacTableEntry(e) => {
+                ComponentDetails::MacTableEntry(e)
+            }
+            gateway_messages::ComponentDetails::LinkFlapEvent(e) => {
+                ComponentDetails::LinkFlapEvent(e)
+            }
         })
         .collect::<Vec<_>>();
 
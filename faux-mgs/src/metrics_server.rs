@@ -0,0 +1,428 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Minimal Prometheus exporter for SP telemetry.
+//!
+//! This is intended for watching a bench rack from lab dashboards, not as a
+//! production-quality HTTP server: we answer `GET /metrics` with the
+//! Prometheus text exposition format and reject everything else.
+
+use anyhow::Context;
+use anyhow::Result;
+use futures::future;
+use gateway_sp_comms::MetricKey;
+use gateway_sp_comms::MetricValue;
+use gateway_sp_comms::SingleSp;
+use gateway_sp_comms::TelemetrySampler;
+use gateway_sp_comms::TelemetrySchedule;
+use gateway_sp_comms::TelemetrySnapshot;
+use gateway_sp_comms::VersionedSpState;
+use slog::info;
+use slog::o;
+use slog::warn;
+use slog::Logger;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+/// Upper bound on the size of an HTTP request we're willing to read.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// How long we'll wait for a client to send its full request before giving up
+/// on the connection.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Labels attached to every metric exported for a single SP.
+struct SpLabels {
+    interface: String,
+    serial_number: String,
+    model: String,
+}
+
+struct ScrapeTarget {
+    labels: SpLabels,
+    sampler: TelemetrySampler,
+}
+
+pub(crate) async fn run(
+    sps: Vec<SingleSp>,
+    listen_addr: SocketAddr,
+    interval: Duration,
+    log: Logger,
+) -> Result<()> {
+    // Look up the identity of each SP once up front; we use it to label all
+    // the metrics we export for that SP.
+    let identities =
+        future::join_all(sps.iter().map(|sp| sp_identity(sp, &log))).await;
+
+    let targets = sps
+        .into_iter()
+        .zip(identities)
+        .map(|(sp, (serial_number, model))| {
+            let interface = sp.interface().to_string();
+            let schedule = TelemetrySchedule {
+                measurements: Some(interval),
+                power_state: Some(interval),
+                ignition: Some(interval),
+                monorail: None,
+                ..Default::default()
+            };
+            let sampler = TelemetrySampler::new(
+                sp,
+                schedule,
+                log.new(o!("interface" => interface.clone())),
+            );
+            ScrapeTarget {
+                labels: SpLabels { interface, serial_number, model },
+                sampler,
+            }
+        })
+        .collect::<Vec<_>>();
+    let targets = Arc::new(targets);
+
+    let listener = TcpListener::bind(listen_addr)
+        .await
+        .with_context(|| format!("failed to bind to {listen_addr}"))?;
+    info!(log, "serving metrics (ctrl-c to stop)"; "addr" => %listen_addr);
+
+    loop {
+        let (stream, peer) =
+            listener.accept().await.context("failed to accept connection")?;
+        let targets = Arc::clone(&targets);
+        let log = log.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &targets).await {
+                warn!(
+                    log, "failed to handle metrics request";
+                    "peer" => %peer,
+                    "err" => %err,
+                );
+            }
+        });
+    }
+}
+
+async fn sp_identity(sp: &SingleSp, log: &Logger) -> (String, String) {
+    let zero_padded_to_str = |bytes: [u8; 32]| {
+        let stop = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..stop]).to_string()
+    };
+
+    match sp.state().await {
+        Ok(VersionedSpState::V1(state)) => (
+            zero_padded_to_str(state.serial_number),
+            zero_padded_to_str(state.model),
+        ),
+        Ok(VersionedSpState::V2(state)) => (
+            zero_padded_to_str(state.serial_number),
+            zero_padded_to_str(state.model),
+        ),
+        Err(err) => {
+            warn!(
+                log, "failed to get SP state; metrics will not be labeled with \
+                      serial number or model";
+                "interface" => sp.interface(),
+                "err" => %err,
+            );
+            ("unknown".to_string(), "unknown".to_string())
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    targets: &[ScrapeTarget],
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    let read_request = async {
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 || buf.len() + n > MAX_REQUEST_LEN {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(REQUEST_READ_TIMEOUT, read_request)
+        .await
+        .context("timed out reading request")??;
+
+    let request = String::from_utf8_lossy(&buf);
+    let mut request_line = request.lines().next().unwrap_or("").split(' ');
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = render_metrics(
+                targets.iter().map(|t| (&t.labels, t.sampler.latest())),
+            );
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\n\
+              Content-Length: 0\r\n\
+              Connection: close\r\n\r\n"
+            .to_string(),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn render_metrics<'a>(
+    targets: impl IntoIterator<Item = (&'a SpLabels, TelemetrySnapshot)>,
+) -> String {
+    let mut measurements = String::new();
+    let mut measurement_errors = String::new();
+    let mut power_states = String::new();
+    let mut ignition_present = String::new();
+    let mut ignition_power = String::new();
+
+    // Writing to a `String` is infallible, so we ignore the results of
+    // `writeln!` below.
+    for (labels, snapshot) in targets {
+        let sp_labels = format!(
+            "interface=\"{}\",serial_number=\"{}\",model=\"{}\"",
+            escape_label(&labels.interface),
+            escape_label(&labels.serial_number),
+            escape_label(&labels.model),
+        );
+
+        for (key, sample) in snapshot {
+            match (key, sample.value) {
+                (
+                    MetricKey::Measurement { component, name },
+                    MetricValue::Measurement { kind, value, .. },
+                ) => {
+                    let labels = format!(
+                        "{sp_labels},component=\"{}\",name=\"{}\",\
+                         kind=\"{kind:?}\"",
+                        escape_label(component.as_str().unwrap_or("???")),
+                        escape_label(&name),
+                    );
+                    match value {
+                        Ok(value) => {
+                            let _ = writeln!(
+                                measurements,
+                                "sp_measurement{{{labels}}} {value}"
+                            );
+                        }
+                        Err(err) => {
+                            let _ = writeln!(
+                                measurement_errors,
+                                "sp_measurement_error{{{labels},\
+                                 error=\"{err:?}\"}} 1"
+                            );
+                        }
+                    }
+                }
+                (MetricKey::PowerState, MetricValue::PowerState(state)) => {
+                    let _ = writeln!(
+                        power_states,
+                        "sp_power_state{{{sp_labels},state=\"{state:?}\"}} 1"
+                    );
+                }
+                (
+                    MetricKey::Ignition { target },
+                    MetricValue::Ignition(state),
+                ) => {
+                    let labels = format!("{sp_labels},target=\"{target}\"");
+                    let _ = writeln!(
+                        ignition_present,
+                        "sp_ignition_target_present{{{labels}}} {}",
+                        u8::from(state.target.is_some())
+                    );
+                    if let Some(target_state) = state.target {
+                        let _ = writeln!(
+                            ignition_power,
+                            "sp_ignition_target_power_state{{{labels},\
+                             system_type=\"{:?}\",state=\"{:?}\"}} 1",
+                            target_state.system_type, target_state.power_state,
+                        );
+                    }
+                }
+                // We don't collect monorail counters, and keys and values
+                // always come in matching pairs.
+                _ => (),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (name, help, body) in [
+        (
+            "sp_measurement",
+            "Most recent value of an SP measurement.",
+            measurements,
+        ),
+        (
+            "sp_measurement_error",
+            "Set if the most recent reading of an SP measurement failed.",
+            measurement_errors,
+        ),
+        (
+            "sp_power_state",
+            "Current power state of the SP's system.",
+            power_states,
+        ),
+        (
+            "sp_ignition_target_present",
+            "Whether an ignition target is present.",
+            ignition_present,
+        ),
+        (
+            "sp_ignition_target_power_state",
+            "Power state of a present ignition target.",
+            ignition_power,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        out.push_str(&body);
+    }
+    out
+}
+
+/// Escape a label value per the Prometheus text exposition format.
+fn escape_label(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gateway_messages::ignition::IgnitionState;
+    use gateway_messages::ignition::ReceiverStatus;
+    use gateway_messages::measurement::MeasurementError;
+    use gateway_messages::measurement::MeasurementKindV2;
+    use gateway_messages::PowerState;
+    use gateway_messages::SpComponent;
+    use gateway_sp_comms::TelemetrySample;
+    use tokio::time::Instant;
+
+    fn insert(
+        snapshot: &mut TelemetrySnapshot,
+        key: MetricKey,
+        value: MetricValue,
+    ) {
+        let sample =
+            TelemetrySample { key: key.clone(), time: Instant::now(), value };
+        snapshot.insert(key, sample);
+    }
+
+    #[test]
+    fn escape_label_values() {
+        assert_eq!(escape_label("sp"), "sp");
+        assert_eq!(escape_label(r#"a"b"#), r#"a\"b"#);
+        assert_eq!(escape_label(r"a\b"), r"a\\b");
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+        assert_eq!(escape_label("\"\\\n"), r#"\"\\\n"#);
+    }
+
+    #[test]
+    fn render_all_metric_types() {
+        let labels = SpLabels {
+            interface: "sidecar0".to_string(),
+            serial_number: "BRM\"1".to_string(),
+            model: "913-0000019".to_string(),
+        };
+
+        let mut snapshot = TelemetrySnapshot::new();
+        insert(
+            &mut snapshot,
+            MetricKey::Measurement {
+                component: SpComponent::SP_ITSELF,
+                name: "temp".to_string(),
+            },
+            MetricValue::Measurement {
+                kind: MeasurementKindV2::Temperature,
+                value: Ok(45.5),
+                metadata: None,
+            },
+        );
+        insert(
+            &mut snapshot,
+            MetricKey::Measurement {
+                component: SpComponent::ROT,
+                name: "fan".to_string(),
+            },
+            MetricValue::Measurement {
+                kind: MeasurementKindV2::Speed,
+                value: Err(MeasurementError::DeviceOff),
+                metadata: None,
+            },
+        );
+        insert(
+            &mut snapshot,
+            MetricKey::PowerState,
+            MetricValue::PowerState(PowerState::A2),
+        );
+        insert(
+            &mut snapshot,
+            MetricKey::Ignition { target: 3 },
+            MetricValue::Ignition(IgnitionState {
+                receiver: ReceiverStatus {
+                    aligned: true,
+                    locked: true,
+                    polarity_inverted: false,
+                },
+                target: None,
+            }),
+        );
+
+        let sp = r#"interface="sidecar0",serial_number="BRM\"1",model="913-0000019""#;
+        let expected = format!(
+            "# HELP sp_measurement Most recent value of an SP measurement.\n\
+             # TYPE sp_measurement gauge\n\
+             sp_measurement{{{sp},component=\"sp\",name=\"temp\",\
+             kind=\"Temperature\"}} 45.5\n\
+             # HELP sp_measurement_error Set if the most recent reading of an \
+             SP measurement failed.\n\
+             # TYPE sp_measurement_error gauge\n\
+             sp_measurement_error{{{sp},component=\"rot\",name=\"fan\",\
+             kind=\"Speed\",error=\"DeviceOff\"}} 1\n\
+             # HELP sp_power_state Current power state of the SP's system.\n\
+             # TYPE sp_power_state gauge\n\
+             sp_power_state{{{sp},state=\"A2\"}} 1\n\
+             # HELP sp_ignition_target_present Whether an ignition target is \
+             present.\n\
+             # TYPE sp_ignition_target_present gauge\n\
+             sp_ignition_target_present{{{sp},target=\"3\"}} 0\n\
+             # HELP sp_ignition_target_power_state Power state of a present \
+             ignition target.\n\
+             # TYPE sp_ignition_target_power_state gauge\n"
+        );
+        assert_eq!(render_metrics([(&labels, snapshot)]), expected);
+    }
+
+    #[test]
+    fn render_without_samples() {
+        let out = render_metrics(std::iter::empty());
+        // We always describe every metric, even if we have no samples.
+        assert_eq!(out.lines().filter(|l| l.starts_with("# HELP")).count(), 5);
+        assert!(out.lines().all(|l| l.starts_with('#')));
+    }
+}
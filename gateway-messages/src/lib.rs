@@ -5,6 +5,7 @@
 #![cfg_attr(all(not(test), not(feature = "std")), no_std)]
 
 mod mgs_to_sp;
+pub mod request;
 pub mod sp_impl;
 mod sp_to_mgs;
 pub mod tlv;
@@ -63,7 +64,7 @@ pub const MAX_SERIALIZED_SIZE: usize = 1024;
 /// for more detail and discussion.
 pub mod version {
     pub const MIN: u32 = 2;
-    pub const CURRENT: u32 = 7;
+    pub const CURRENT: u32 = 10;
 }
 
 #[derive(
@@ -160,7 +161,7 @@ impl From<UpdateId> for uuid::Uuid {
 }
 
 /// Identifier for a single component managed by an SP.
-#[derive(Clone, Copy, PartialEq, Eq, Hash, SerializedSize)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, SerializedSize)]
 pub struct SpComponent {
     /// The ID of the component.
     ///
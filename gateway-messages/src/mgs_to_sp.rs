@@ -5,6 +5,7 @@
 //! Types for messages sent from MGS to SPs.
 
 use crate::ignition::TransceiverSelect;
+use crate::monorail_port_status::PortMode;
 use crate::BadRequestReason;
 use crate::PowerState;
 use crate::RotSlotId;
@@ -158,6 +159,18 @@ pub enum MgsRequest {
         slot: u16,
         key: [u8; 4],
     },
+
+    /// Get every measurement known to the SP (across all components), starting
+    /// with `offset`.
+    BulkMeasurements {
+        offset: u32,
+    },
+
+    /// Get the set of protocol features supported by the SP.
+    ///
+    /// SPs that predate this request respond with an error, which MGS treats
+    /// as an empty set of capabilities.
+    ProtocolCapabilities,
 }
 
 #[derive(
@@ -229,6 +242,7 @@ pub struct ComponentUpdatePrepare {
 )]
 pub enum ComponentAction {
     Led(LedComponentAction),
+    Monorail(MonorailComponentAction),
 }
 
 /// Actions for LED components, i.e. components with `IS_LED` set
@@ -241,6 +255,28 @@ pub enum LedComponentAction {
     Blink,
 }
 
+/// Actions for the monorail (management network switch) component.
+///
+/// Every action targets a single port; use
+/// [`MgsRequest::ComponentClearStatus`] to clear the counters on all ports at
+/// once.
+#[derive(
+    Copy, Clone, Serialize, SerializedSize, Deserialize, PartialEq, Eq, Debug,
+)]
+pub enum MonorailComponentAction {
+    /// Enable a port that was previously disabled.
+    EnablePort { port: u32 },
+    /// Disable a port; it will not pass traffic until reenabled.
+    DisablePort { port: u32 },
+    /// Reconfigure a port's mode (and therefore its speed, for SGMII and QSGMII
+    /// ports).
+    SetPortMode { port: u32, mode: PortMode },
+    /// Reset the packet counters and sticky link-down bits of a single port.
+    ResetPortCounters { port: u32 },
+    /// Restart the PHY attached to a port.
+    RestartPhy { port: u32 },
+}
+
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, SerializedSize, Serialize, Deserialize,
 )]
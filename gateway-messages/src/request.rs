@@ -0,0 +1,831 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Typed requests from MGS to SPs.
+//!
+//! Each type in this module corresponds to the [`MgsRequest`] variant of the
+//! same name (and has the same fields), and implements [`Request`] to pair it
+//! with the [`SpResponse`] an SP sends back. `ResetTrigger` has no type here,
+//! since the SP resets instead of responding to it.
+//!
+//! Some requests are followed by trailing data (e.g., [`UpdateChunk`]); the
+//! types here describe only the request itself, so sending one without its
+//! trailing data is up to the caller.
+
+use crate::ignition::LinkEvents;
+use crate::ignition::TransceiverSelect;
+use crate::sp_to_mgs::response_kind_names;
+use crate::DiscoverResponse;
+use crate::MgsRequest;
+use crate::PowerState;
+use crate::RotSlotId;
+use crate::SpComponent;
+use crate::SpResponse;
+use crate::StartupOptions;
+use crate::SwitchDuration;
+use crate::UpdateId;
+use crate::VersionedSpState;
+
+#[cfg(feature = "std")]
+use crate::TlvPage;
+
+/// A request MGS can send to an SP, paired with the response it expects.
+pub trait Request {
+    /// The contents of a successful response.
+    type Response;
+
+    /// The [`SpResponse::name()`] of the kind of response we expect.
+    const RESPONSE_KIND: &'static str;
+
+    /// The message to send to the SP.
+    fn to_mgs_request(&self) -> MgsRequest;
+
+    /// Extract our response from `response` and the `trailing_data` that
+    /// followed it.
+    ///
+    /// If `response` is not the kind we expect (including if it is an
+    /// [`SpResponse::Error`]), it is returned unchanged.
+    fn parse_response(
+        response: SpResponse,
+        trailing_data: &[u8],
+    ) -> Result<Self::Response, SpResponse>;
+}
+
+/// A page of TLV-encoded entries: the [`TlvPage`] describing it and the data
+/// that followed it.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlvResponse {
+    pub page: TlvPage,
+    pub data: Vec<u8>,
+}
+
+// Implement `Request` for `$request`, converting it into an `MgsRequest` with
+// `$to_mgs_request` (with `$request` bound to `$this`) and extracting a
+// `$response` from the first matching pattern (with the trailing data bound to
+// `$data`).
+macro_rules! impl_request {
+    (
+        $(#[$attr:meta])*
+        $request:ty, |$this:pat_param| $to_mgs_request:expr,
+        $response:ty = $kind:ident,
+        |$data:pat_param| { $($pattern:pat => $value:expr),+ $(,)? }
+    ) => {
+        $(#[$attr])*
+        impl Request for $request {
+            type Response = $response;
+
+            const RESPONSE_KIND: &'static str = response_kind_names::$kind;
+
+            fn to_mgs_request(&self) -> MgsRequest {
+                let $this = self;
+                $to_mgs_request
+            }
+
+            fn parse_response(
+                response: SpResponse,
+                trailing_data: &[u8],
+            ) -> Result<$response, SpResponse> {
+                let $data = trailing_data;
+                match response {
+                    $($pattern => Ok($value),)+
+                    other => Err(other),
+                }
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discover;
+
+impl_request!(
+    Discover, |_| MgsRequest::Discover,
+    DiscoverResponse = DISCOVER,
+    |_| { SpResponse::Discover(discover) => discover }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IgnitionState {
+    pub target: u8,
+}
+
+impl_request!(
+    IgnitionState, |r| MgsRequest::IgnitionState { target: r.target },
+    crate::IgnitionState = IGNITION_STATE,
+    |_| { SpResponse::IgnitionState(state) => state }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkIgnitionState {
+    pub offset: u32,
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    BulkIgnitionState,
+    |r| MgsRequest::BulkIgnitionState { offset: r.offset },
+    TlvResponse = BULK_IGNITION_STATE,
+    |data| {
+        SpResponse::BulkIgnitionState(page) => {
+            TlvResponse { page, data: data.to_vec() }
+        }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IgnitionCommand {
+    pub target: u8,
+    pub command: crate::IgnitionCommand,
+}
+
+impl_request!(
+    IgnitionCommand,
+    |r| MgsRequest::IgnitionCommand { target: r.target, command: r.command },
+    () = IGNITION_COMMAND_ACK,
+    |_| { SpResponse::IgnitionCommandAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpState;
+
+impl_request!(
+    SpState, |_| MgsRequest::SpState,
+    VersionedSpState = VERSIONED_SP_STATE,
+    |_| {
+        SpResponse::SpState(state) => VersionedSpState::V1(state),
+        SpResponse::SpStateV2(state) => VersionedSpState::V2(state),
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsoleAttach(pub SpComponent);
+
+impl_request!(
+    SerialConsoleAttach, |r| MgsRequest::SerialConsoleAttach(r.0),
+    () = SERIAL_CONSOLE_ATTACH_ACK,
+    |_| { SpResponse::SerialConsoleAttachAck => () }
+);
+
+/// Always followed by trailing data; the response is the furthest offset the
+/// SP has ingested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsoleWrite {
+    pub offset: u64,
+}
+
+impl_request!(
+    SerialConsoleWrite,
+    |r| MgsRequest::SerialConsoleWrite { offset: r.offset },
+    u64 = SERIAL_CONSOLE_WRITE_ACK,
+    |_| {
+        SpResponse::SerialConsoleWriteAck { furthest_ingested_offset } => {
+            furthest_ingested_offset
+        }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsoleDetach;
+
+impl_request!(
+    SerialConsoleDetach, |_| MgsRequest::SerialConsoleDetach,
+    () = SERIAL_CONSOLE_DETACH_ACK,
+    |_| { SpResponse::SerialConsoleDetachAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpUpdatePrepare(pub crate::SpUpdatePrepare);
+
+impl_request!(
+    SpUpdatePrepare, |r| MgsRequest::SpUpdatePrepare(r.0),
+    () = SP_UPDATE_PREPARE_ACK,
+    |_| { SpResponse::SpUpdatePrepareAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentUpdatePrepare(pub crate::ComponentUpdatePrepare);
+
+impl_request!(
+    ComponentUpdatePrepare, |r| MgsRequest::ComponentUpdatePrepare(r.0),
+    () = COMPONENT_UPDATE_PREPARE_ACK,
+    |_| { SpResponse::ComponentUpdatePrepareAck => () }
+);
+
+/// Always followed by trailing data (the chunk itself).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateChunk(pub crate::UpdateChunk);
+
+impl_request!(
+    UpdateChunk, |r| MgsRequest::UpdateChunk(r.0),
+    () = UPDATE_CHUNK_ACK,
+    |_| { SpResponse::UpdateChunkAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateStatus(pub SpComponent);
+
+impl_request!(
+    UpdateStatus, |r| MgsRequest::UpdateStatus(r.0),
+    crate::UpdateStatus = UPDATE_STATUS,
+    |_| { SpResponse::UpdateStatus(status) => status }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateAbort {
+    pub component: SpComponent,
+    pub id: UpdateId,
+}
+
+impl_request!(
+    UpdateAbort,
+    |r| MgsRequest::UpdateAbort { component: r.component, id: r.id },
+    () = UPDATE_ABORT_ACK,
+    |_| { SpResponse::UpdateAbortAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetPowerState;
+
+impl_request!(
+    GetPowerState, |_| MgsRequest::GetPowerState,
+    PowerState = POWER_STATE,
+    |_| { SpResponse::PowerState(power_state) => power_state }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetPowerState(pub PowerState);
+
+impl_request!(
+    SetPowerState, |r| MgsRequest::SetPowerState(r.0),
+    () = SET_POWER_STATE_ACK,
+    |_| { SpResponse::SetPowerStateAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetPrepare;
+
+impl_request!(
+    ResetPrepare, |_| MgsRequest::ResetPrepare,
+    () = RESET_PREPARE_ACK,
+    |_| { SpResponse::ResetPrepareAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inventory {
+    pub device_index: u32,
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    Inventory,
+    |r| MgsRequest::Inventory { device_index: r.device_index },
+    TlvResponse = INVENTORY,
+    |data| {
+        SpResponse::Inventory(page) => TlvResponse { page, data: data.to_vec() }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetStartupOptions;
+
+impl_request!(
+    GetStartupOptions, |_| MgsRequest::GetStartupOptions,
+    StartupOptions = STARTUP_OPTIONS,
+    |_| { SpResponse::StartupOptions(options) => options }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetStartupOptions(pub StartupOptions);
+
+impl_request!(
+    SetStartupOptions, |r| MgsRequest::SetStartupOptions(r.0),
+    () = SET_STARTUP_OPTIONS_ACK,
+    |_| { SpResponse::SetStartupOptionsAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentDetails {
+    pub component: SpComponent,
+    pub offset: u32,
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    ComponentDetails,
+    |r| MgsRequest::ComponentDetails {
+        component: r.component,
+        offset: r.offset,
+    },
+    TlvResponse = COMPONENT_DETAILS,
+    |data| {
+        SpResponse::ComponentDetails(page) => {
+            TlvResponse { page, data: data.to_vec() }
+        }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IgnitionLinkEvents {
+    pub target: u8,
+}
+
+impl_request!(
+    IgnitionLinkEvents,
+    |r| MgsRequest::IgnitionLinkEvents { target: r.target },
+    LinkEvents = IGNITION_LINK_EVENTS,
+    |_| { SpResponse::IgnitionLinkEvents(events) => events }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkIgnitionLinkEvents {
+    pub offset: u32,
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    BulkIgnitionLinkEvents,
+    |r| MgsRequest::BulkIgnitionLinkEvents { offset: r.offset },
+    TlvResponse = BULK_IGNITION_LINK_EVENTS,
+    |data| {
+        SpResponse::BulkIgnitionLinkEvents(page) => {
+            TlvResponse { page, data: data.to_vec() }
+        }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClearIgnitionLinkEvents {
+    pub target: Option<u8>,
+    pub transceiver_select: Option<TransceiverSelect>,
+}
+
+impl_request!(
+    ClearIgnitionLinkEvents,
+    |r| MgsRequest::ClearIgnitionLinkEvents {
+        target: r.target,
+        transceiver_select: r.transceiver_select,
+    },
+    () = CLEAR_IGNITION_LINK_EVENTS_ACK,
+    |_| { SpResponse::ClearIgnitionLinkEventsAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentClearStatus(pub SpComponent);
+
+impl_request!(
+    ComponentClearStatus, |r| MgsRequest::ComponentClearStatus(r.0),
+    () = COMPONENT_CLEAR_STATUS_ACK,
+    |_| { SpResponse::ComponentClearStatusAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentGetActiveSlot(pub SpComponent);
+
+impl_request!(
+    ComponentGetActiveSlot, |r| MgsRequest::ComponentGetActiveSlot(r.0),
+    u16 = COMPONENT_ACTIVE_SLOT,
+    |_| { SpResponse::ComponentActiveSlot(slot) => slot }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentSetActiveSlot {
+    pub component: SpComponent,
+    pub slot: u16,
+}
+
+impl_request!(
+    ComponentSetActiveSlot,
+    |r| MgsRequest::ComponentSetActiveSlot {
+        component: r.component,
+        slot: r.slot,
+    },
+    () = COMPONENT_SET_ACTIVE_SLOT_ACK,
+    |_| { SpResponse::ComponentSetActiveSlotAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsoleBreak;
+
+impl_request!(
+    SerialConsoleBreak, |_| MgsRequest::SerialConsoleBreak,
+    () = SERIAL_CONSOLE_BREAK_ACK,
+    |_| { SpResponse::SerialConsoleBreakAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendHostNmi;
+
+impl_request!(
+    SendHostNmi, |_| MgsRequest::SendHostNmi,
+    () = SEND_HOST_NMI_ACK,
+    |_| { SpResponse::SendHostNmiAck => () }
+);
+
+/// Always followed by trailing data (the value).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetIpccKeyLookupValue {
+    pub key: u8,
+}
+
+impl_request!(
+    SetIpccKeyLookupValue,
+    |r| MgsRequest::SetIpccKeyLookupValue { key: r.key },
+    () = SET_IPCC_KEY_LOOKUP_VALUE_ACK,
+    |_| { SpResponse::SetIpccKeyLookupValueAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentSetAndPersistActiveSlot {
+    pub component: SpComponent,
+    pub slot: u16,
+}
+
+impl_request!(
+    ComponentSetAndPersistActiveSlot,
+    |r| MgsRequest::ComponentSetAndPersistActiveSlot {
+        component: r.component,
+        slot: r.slot,
+    },
+    () = COMPONENT_SET_AND_PERSIST_ACTIVE_SLOT_ACK,
+    |_| { SpResponse::ComponentSetAndPersistActiveSlotAck => () }
+);
+
+/// The response is the value, which the SP sends as trailing data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCaboose {
+    pub key: [u8; 4],
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    ReadCaboose, |r| MgsRequest::ReadCaboose { key: r.key },
+    Vec<u8> = CABOOSE_VALUE,
+    |data| { SpResponse::CabooseValue => data.to_vec() }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsoleKeepAlive;
+
+impl_request!(
+    SerialConsoleKeepAlive, |_| MgsRequest::SerialConsoleKeepAlive,
+    () = SERIAL_CONSOLE_KEEPALIVE_ACK,
+    |_| { SpResponse::SerialConsoleKeepAliveAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetComponentPrepare {
+    pub component: SpComponent,
+}
+
+impl_request!(
+    ResetComponentPrepare,
+    |r| MgsRequest::ResetComponentPrepare { component: r.component },
+    () = RESET_COMPONENT_PREPARE_ACK,
+    |_| { SpResponse::ResetComponentPrepareAck => () }
+);
+
+/// Only components other than the SP itself acknowledge this request; the SP
+/// resets instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetComponentTrigger {
+    pub component: SpComponent,
+}
+
+impl_request!(
+    ResetComponentTrigger,
+    |r| MgsRequest::ResetComponentTrigger { component: r.component },
+    () = RESET_COMPONENT_TRIGGER_ACK,
+    |_| { SpResponse::ResetComponentTriggerAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchDefaultImage {
+    pub component: SpComponent,
+    pub slot: RotSlotId,
+    pub duration: SwitchDuration,
+}
+
+impl_request!(
+    SwitchDefaultImage,
+    |r| MgsRequest::SwitchDefaultImage {
+        component: r.component,
+        slot: r.slot,
+        duration: r.duration,
+    },
+    () = SWITCH_DEFAULT_IMAGE_ACK,
+    |_| { SpResponse::SwitchDefaultImageAck => () }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentAction {
+    pub component: SpComponent,
+    pub action: crate::ComponentAction,
+}
+
+impl_request!(
+    ComponentAction,
+    |r| MgsRequest::ComponentAction {
+        component: r.component,
+        action: r.action,
+    },
+    () = COMPONENT_ACTION_ACK,
+    |_| { SpResponse::ComponentActionAck => () }
+);
+
+/// The response is the value, which the SP sends as trailing data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadComponentCaboose {
+    pub component: SpComponent,
+    pub slot: u16,
+    pub key: [u8; 4],
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    ReadComponentCaboose,
+    |r| MgsRequest::ReadComponentCaboose {
+        component: r.component,
+        slot: r.slot,
+        key: r.key,
+    },
+    Vec<u8> = CABOOSE_VALUE,
+    |data| { SpResponse::CabooseValue => data.to_vec() }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkMeasurements {
+    pub offset: u32,
+}
+
+impl_request!(
+    #[cfg(feature = "std")]
+    BulkMeasurements,
+    |r| MgsRequest::BulkMeasurements { offset: r.offset },
+    TlvResponse = BULK_MEASUREMENTS,
+    |data| {
+        SpResponse::BulkMeasurements(page) => {
+            TlvResponse { page, data: data.to_vec() }
+        }
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolCapabilities;
+
+impl_request!(
+    ProtocolCapabilities, |_| MgsRequest::ProtocolCapabilities,
+    crate::ProtocolCapabilities = PROTOCOL_CAPABILITIES,
+    |_| { SpResponse::ProtocolCapabilities(capabilities) => capabilities }
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignition::ReceiverStatus;
+    use crate::ignition::TransceiverEvents;
+    use crate::ImageVersion;
+    use crate::RotError;
+    use crate::SpError;
+    use crate::SpPort;
+    use crate::SpStateV1;
+    use crate::SpStateV2;
+
+    fn sp_state_v1() -> SpStateV1 {
+        SpStateV1 {
+            hubris_archive_id: [1; 8],
+            serial_number: [2; 32],
+            model: [3; 32],
+            revision: 4,
+            base_mac_address: [5; 6],
+            version: ImageVersion { epoch: 6, version: 7 },
+            power_state: PowerState::A0,
+            rot: Err(RotError::MessageError { code: 8 }),
+        }
+    }
+
+    fn sp_state_v2() -> SpStateV2 {
+        SpStateV2 {
+            hubris_archive_id: [1; 8],
+            serial_number: [2; 32],
+            model: [3; 32],
+            revision: 4,
+            base_mac_address: [5; 6],
+            power_state: PowerState::A2,
+            rot: Err(RotError::MessageError { code: 8 }),
+        }
+    }
+
+    // `R` should accept `response`, whose name should be `R::RESPONSE_KIND`.
+    fn assert_response_kind<R: Request>(response: SpResponse) {
+        let name = response.name();
+        assert_eq!(name, R::RESPONSE_KIND);
+        assert!(R::parse_response(response, &[]).is_ok(), "{name}");
+    }
+
+    #[test]
+    fn response_kinds_match_response_names() {
+        let transceiver = TransceiverEvents {
+            encoding_error: false,
+            decoding_error: false,
+            ordered_set_invalid: false,
+            message_version_invalid: false,
+            message_type_invalid: false,
+            message_checksum_invalid: false,
+        };
+
+        assert_response_kind::<Discover>(SpResponse::Discover(
+            DiscoverResponse { sp_port: SpPort::One },
+        ));
+        assert_response_kind::<IgnitionState>(SpResponse::IgnitionState(
+            crate::IgnitionState {
+                receiver: ReceiverStatus {
+                    aligned: true,
+                    locked: true,
+                    polarity_inverted: false,
+                },
+                target: None,
+            },
+        ));
+        assert_response_kind::<IgnitionCommand>(SpResponse::IgnitionCommandAck);
+        assert_response_kind::<SpState>(SpResponse::SpState(sp_state_v1()));
+        assert_response_kind::<SpState>(SpResponse::SpStateV2(sp_state_v2()));
+        assert_response_kind::<SerialConsoleAttach>(
+            SpResponse::SerialConsoleAttachAck,
+        );
+        assert_response_kind::<SerialConsoleWrite>(
+            SpResponse::SerialConsoleWriteAck { furthest_ingested_offset: 1 },
+        );
+        assert_response_kind::<SerialConsoleDetach>(
+            SpResponse::SerialConsoleDetachAck,
+        );
+        assert_response_kind::<SpUpdatePrepare>(SpResponse::SpUpdatePrepareAck);
+        assert_response_kind::<ComponentUpdatePrepare>(
+            SpResponse::ComponentUpdatePrepareAck,
+        );
+        assert_response_kind::<UpdateChunk>(SpResponse::UpdateChunkAck);
+        assert_response_kind::<UpdateStatus>(SpResponse::UpdateStatus(
+            crate::UpdateStatus::None,
+        ));
+        assert_response_kind::<UpdateAbort>(SpResponse::UpdateAbortAck);
+        assert_response_kind::<GetPowerState>(SpResponse::PowerState(
+            PowerState::A1,
+        ));
+        assert_response_kind::<SetPowerState>(SpResponse::SetPowerStateAck);
+        assert_response_kind::<ResetPrepare>(SpResponse::ResetPrepareAck);
+        assert_response_kind::<GetStartupOptions>(SpResponse::StartupOptions(
+            StartupOptions::empty(),
+        ));
+        assert_response_kind::<SetStartupOptions>(
+            SpResponse::SetStartupOptionsAck,
+        );
+        assert_response_kind::<IgnitionLinkEvents>(
+            SpResponse::IgnitionLinkEvents(LinkEvents {
+                controller: transceiver,
+                target_link0: transceiver,
+                target_link1: transceiver,
+            }),
+        );
+        assert_response_kind::<ClearIgnitionLinkEvents>(
+            SpResponse::ClearIgnitionLinkEventsAck,
+        );
+        assert_response_kind::<ComponentClearStatus>(
+            SpResponse::ComponentClearStatusAck,
+        );
+        assert_response_kind::<ComponentGetActiveSlot>(
+            SpResponse::ComponentActiveSlot(1),
+        );
+        assert_response_kind::<ComponentSetActiveSlot>(
+            SpResponse::ComponentSetActiveSlotAck,
+        );
+        assert_response_kind::<SerialConsoleBreak>(
+            SpResponse::SerialConsoleBreakAck,
+        );
+        assert_response_kind::<SendHostNmi>(SpResponse::SendHostNmiAck);
+        assert_response_kind::<SetIpccKeyLookupValue>(
+            SpResponse::SetIpccKeyLookupValueAck,
+        );
+        assert_response_kind::<ComponentSetAndPersistActiveSlot>(
+            SpResponse::ComponentSetAndPersistActiveSlotAck,
+        );
+        assert_response_kind::<SerialConsoleKeepAlive>(
+            SpResponse::SerialConsoleKeepAliveAck,
+        );
+        assert_response_kind::<ResetComponentPrepare>(
+            SpResponse::ResetComponentPrepareAck,
+        );
+        assert_response_kind::<ResetComponentTrigger>(
+            SpResponse::ResetComponentTriggerAck,
+        );
+        assert_response_kind::<SwitchDefaultImage>(
+            SpResponse::SwitchDefaultImageAck,
+        );
+        assert_response_kind::<ComponentAction>(SpResponse::ComponentActionAck);
+        assert_response_kind::<ProtocolCapabilities>(
+            SpResponse::ProtocolCapabilities(
+                crate::ProtocolCapabilities::CONCURRENT_RPCS,
+            ),
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let page = TlvPage { offset: 0, total: 0 };
+            assert_response_kind::<BulkIgnitionState>(
+                SpResponse::BulkIgnitionState(page),
+            );
+            assert_response_kind::<Inventory>(SpResponse::Inventory(page));
+            assert_response_kind::<ComponentDetails>(
+                SpResponse::ComponentDetails(page),
+            );
+            assert_response_kind::<BulkIgnitionLinkEvents>(
+                SpResponse::BulkIgnitionLinkEvents(page),
+            );
+            assert_response_kind::<ReadCaboose>(SpResponse::CabooseValue);
+            assert_response_kind::<ReadComponentCaboose>(
+                SpResponse::CabooseValue,
+            );
+            assert_response_kind::<BulkMeasurements>(
+                SpResponse::BulkMeasurements(page),
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_responses_are_returned_unchanged() {
+        assert_eq!(
+            GetPowerState::parse_response(SpResponse::SetPowerStateAck, &[]),
+            Err(SpResponse::SetPowerStateAck)
+        );
+        assert_eq!(
+            SetPowerState::parse_response(
+                SpResponse::PowerState(PowerState::A0),
+                &[]
+            ),
+            Err(SpResponse::PowerState(PowerState::A0))
+        );
+        assert_eq!(
+            SpState::parse_response(SpResponse::ComponentActiveSlot(0), &[]),
+            Err(SpResponse::ComponentActiveSlot(0))
+        );
+    }
+
+    #[test]
+    fn error_responses_are_returned_unchanged() {
+        for err in [SpError::Busy, SpError::RequestUnsupportedForSp] {
+            assert_eq!(
+                GetPowerState::parse_response(SpResponse::Error(err), &[]),
+                Err(SpResponse::Error(err))
+            );
+            assert_eq!(
+                SpState::parse_response(SpResponse::Error(err), &[]),
+                Err(SpResponse::Error(err))
+            );
+        }
+    }
+
+    #[test]
+    fn sp_state_responses_map_to_versioned_sp_state() {
+        let v1 = sp_state_v1();
+        assert_eq!(
+            SpState::parse_response(SpResponse::SpState(v1), &[]),
+            Ok(VersionedSpState::V1(v1))
+        );
+
+        let v2 = sp_state_v2();
+        assert_eq!(
+            SpState::parse_response(SpResponse::SpStateV2(v2), &[]),
+            Ok(VersionedSpState::V2(v2))
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn caboose_value_is_the_trailing_data() {
+        assert_eq!(
+            ReadCaboose::parse_response(SpResponse::CabooseValue, b"gimlet"),
+            Ok(b"gimlet".to_vec())
+        );
+        assert_eq!(
+            ReadComponentCaboose::parse_response(
+                SpResponse::CabooseValue,
+                b"oxide-rot-1"
+            ),
+            Ok(b"oxide-rot-1".to_vec())
+        );
+        assert_eq!(
+            ReadCaboose::parse_response(SpResponse::CabooseValue, &[]),
+            Ok(Vec::new())
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn tlv_responses_keep_their_trailing_data() {
+        let page = TlvPage { offset: 2, total: 5 };
+        assert_eq!(
+            Inventory::parse_response(SpResponse::Inventory(page), &[1, 2, 3]),
+            Ok(TlvResponse { page, data: vec![1, 2, 3] })
+        );
+    }
+}
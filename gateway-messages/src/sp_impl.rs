@@ -6,6 +6,8 @@
 
 use crate::ignition;
 use crate::ignition::LinkEvents;
+use crate::measurement::ComponentMeasurement;
+use crate::measurement::MeasurementHeaderV2;
 use crate::tlv;
 use crate::version;
 use crate::BadRequestReason;
@@ -25,6 +27,7 @@ use crate::MgsError;
 use crate::MgsRequest;
 use crate::MgsResponse;
 use crate::PowerState;
+use crate::ProtocolCapabilities;
 use crate::RotSlotId;
 use crate::SerializedSize;
 use crate::SpComponent;
@@ -264,6 +267,10 @@ pub trait SpHandler {
     /// checked and is guaranteed to be in the range
     /// `0..num_component_details(_, _, component)`.
     ///
+    /// `handle_message` downgrades [`ComponentDetails::MeasurementV2`]s to
+    /// [`ComponentDetails::Measurement`]s for MGS instances that don't
+    /// understand them; see [`crate::MeasurementV2`].
+    ///
     /// # Panics
     ///
     /// Implementors are allowed to panic if `index` is not in range (i.e., is
@@ -275,6 +282,30 @@ pub trait SpHandler {
         index: BoundsChecked,
     ) -> ComponentDetails;
 
+    /// Number of measurements (across all components) returned in response to
+    /// [`MgsRequest::BulkMeasurements`].
+    fn num_bulk_measurements(
+        &mut self,
+        sender: SocketAddrV6,
+        port: SpPort,
+    ) -> Result<u32, SpError>;
+
+    /// Get a single measurement (and the component that produced it).
+    ///
+    /// When this method is called by `handle_message`, `index` has been bounds
+    /// checked and is guaranteed to be in the range
+    /// `0..num_bulk_measurements(_, _)`.
+    ///
+    /// # Panics
+    ///
+    /// Implementors are allowed to panic if `index` is not in range (i.e., is
+    /// greater than or equal to the value returned by
+    /// `num_bulk_measurements()`).
+    fn bulk_measurement(
+        &mut self,
+        index: BoundsChecked,
+    ) -> ComponentMeasurement;
+
     fn component_clear_status(
         &mut self,
         sender: SocketAddrV6,
@@ -298,6 +329,12 @@ pub trait SpHandler {
         persist: bool,
     ) -> Result<(), SpError>;
 
+    /// Perform `action` on `component`.
+    ///
+    /// Implementors should return
+    /// [`SpError::RequestUnsupportedForComponent`] if `action` does not apply
+    /// to `component` (e.g., a [`ComponentAction::Monorail`] action sent to an
+    /// LED).
     fn component_action(
         &mut self,
         sender: SocketAddrV6,
@@ -373,6 +410,18 @@ pub trait SpHandler {
         port: SpPort,
         component: SpComponent,
     ) -> Result<(), SpError>;
+
+    /// Protocol features supported by this SP.
+    ///
+    /// Only advertise [`ProtocolCapabilities::CONCURRENT_RPCS`] if the SP
+    /// responds to each request independently of any other requests it has
+    /// received, and can keep responding to other requests while one is
+    /// outstanding.
+    fn protocol_capabilities(
+        &mut self,
+        sender: SocketAddrV6,
+        port: SpPort,
+    ) -> ProtocolCapabilities;
 }
 
 /// Handle a single incoming message.
@@ -395,7 +444,7 @@ pub fn handle_message<H: SpHandler>(
 ) -> Option<usize> {
     // If we were able to peel off the header, chain the rest of the data
     // then chain the rest of the data through to the handler.
-    let (message_id, response, outgoing_trailing_data) =
+    let (message_id, request_version, response, outgoing_trailing_data) =
         match read_request_header(data) {
             ReadHeaderResult::Ok { header, remaining_data } => {
                 let (response, outgoing_trailing_data) = handle_message_impl(
@@ -406,14 +455,22 @@ pub fn handle_message<H: SpHandler>(
                     handler,
                     &mut out[Message::MAX_SIZE..],
                 )?;
-                (header.message_id, response, outgoing_trailing_data)
-            }
-            ReadHeaderResult::HeaderValidationFailed { header, error } => {
-                (header.message_id, SpResponse::Error(error), None)
+                (
+                    header.message_id,
+                    header.version,
+                    response,
+                    outgoing_trailing_data,
+                )
             }
+            ReadHeaderResult::HeaderValidationFailed { header, error } => (
+                header.message_id,
+                header.version,
+                SpResponse::Error(error),
+                None,
+            ),
             ReadHeaderResult::HeaderParsingFailed { error } => {
                 // We didn't even get a message_id; make one up.
-                (u32::MAX, SpResponse::Error(error), None)
+                (u32::MAX, version::CURRENT, SpResponse::Error(error), None)
             }
         };
 
@@ -473,9 +530,34 @@ pub fn handle_message<H: SpHandler>(
             (offset..total).map(|i| {
                 let details =
                     handler.component_details(component, BoundsChecked(i));
+                // MGS instances older than `MeasurementHeaderV2::MIN_VERSION`
+                // can't parse `MeasurementV2`s; send them what they can.
+                let details = match details {
+                    ComponentDetails::MeasurementV2(m)
+                        if request_version
+                            < MeasurementHeaderV2::MIN_VERSION =>
+                    {
+                        match m.downgrade() {
+                            Some(m) => ComponentDetails::Measurement(m),
+                            None => ComponentDetails::MeasurementV2(m),
+                        }
+                    }
+                    details => details,
+                };
                 (details.tag(), move |buf: &mut [u8]| details.serialize(buf))
             }),
         ),
+        Some(OutgoingTrailingData::BulkMeasurements { offset, total }) => {
+            encode_tlv_structs(
+                &mut out[n..],
+                (offset..total).map(|i| {
+                    let m = handler.bulk_measurement(BoundsChecked(i));
+                    (ComponentMeasurement::TAG, move |buf: &mut [u8]| {
+                        m.serialize(buf)
+                    })
+                }),
+            )
+        }
         Some(OutgoingTrailingData::BulkIgnitionState(iter)) => {
             encode_tlv_structs(
                 &mut out[n..],
@@ -916,6 +998,25 @@ fn handle_mgs_request<H: SpHandler>(
             }
             r.map(|_| SpResponse::CabooseValue)
         }
+        MgsRequest::BulkMeasurements { offset } => {
+            handler.num_bulk_measurements(sender, port).map(|total| {
+                // If a caller asks for an index past our end, clamp it.
+                let offset = u32::min(offset, total);
+                // We need to pack TLV-encoded measurements as our outgoing
+                // trailing data.
+                outgoing_trailing_data =
+                    Some(OutgoingTrailingData::BulkMeasurements {
+                        offset,
+                        total,
+                    });
+                SpResponse::BulkMeasurements(TlvPage { offset, total })
+            })
+        }
+        MgsRequest::ProtocolCapabilities => {
+            Ok(SpResponse::ProtocolCapabilities(
+                handler.protocol_capabilities(sender, port),
+            ))
+        }
     };
 
     let response = match result {
@@ -938,6 +1039,10 @@ enum OutgoingTrailingData<H: SpHandler> {
     },
     BulkIgnitionState(H::BulkIgnitionStateIter),
     BulkIgnitionLinkEvents(H::BulkIgnitionLinkEventsIter),
+    BulkMeasurements {
+        offset: u32,
+        total: u32,
+    },
 
     /// Shift some number of bytes from `tx_buf[Message::MAX_SIZE..]`
     ///
@@ -1172,6 +1277,21 @@ mod tests {
             unimplemented!()
         }
 
+        fn num_bulk_measurements(
+            &mut self,
+            _sender: SocketAddrV6,
+            _port: SpPort,
+        ) -> Result<u32, SpError> {
+            unimplemented!()
+        }
+
+        fn bulk_measurement(
+            &mut self,
+            _index: BoundsChecked,
+        ) -> ComponentMeasurement {
+            unimplemented!()
+        }
+
         fn component_clear_status(
             &mut self,
             _sender: SocketAddrV6,
@@ -1294,6 +1414,14 @@ mod tests {
         ) -> Result<usize, SpError> {
             unimplemented!()
         }
+
+        fn protocol_capabilities(
+            &mut self,
+            _sender: SocketAddrV6,
+            _port: SpPort,
+        ) -> ProtocolCapabilities {
+            unimplemented!()
+        }
     }
 
     #[cfg(feature = "std")]
@@ -25,10 +25,12 @@ pub mod monorail_port_status;
 
 pub use ignition::IgnitionState;
 pub use measurement::Measurement;
+pub use measurement::MeasurementV2;
 
 use ignition::IgnitionError;
-use measurement::MeasurementHeader;
-use monorail_port_status::{PortStatus, PortStatusError};
+use monorail_port_status::{
+    LinkFlapEvent, MacTableEntry, PortStatus, PortStatusError,
+};
 
 use ignition::LinkEvents;
 
@@ -113,6 +115,107 @@ pub enum SpResponse {
     ComponentActionAck,
 
     SpStateV2(SpStateV2),
+
+    /// A `BulkMeasurements` response is followed by a TLV-encoded set of
+    /// [`measurement::ComponentMeasurement`]s.
+    BulkMeasurements(TlvPage),
+
+    ProtocolCapabilities(ProtocolCapabilities),
+}
+
+impl SpResponse {
+    /// A short, stable name for the kind of this response (e.g., `power_state`),
+    /// for logs and error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Discover(_) => response_kind_names::DISCOVER,
+            Self::IgnitionState(_) => response_kind_names::IGNITION_STATE,
+            Self::IgnitionLinkEvents(_) => {
+                response_kind_names::IGNITION_LINK_EVENTS
+            }
+            Self::BulkIgnitionState(_) => {
+                response_kind_names::BULK_IGNITION_STATE
+            }
+            Self::BulkIgnitionLinkEvents(_) => {
+                response_kind_names::BULK_IGNITION_LINK_EVENTS
+            }
+            Self::IgnitionCommandAck => {
+                response_kind_names::IGNITION_COMMAND_ACK
+            }
+            Self::ClearIgnitionLinkEventsAck => {
+                response_kind_names::CLEAR_IGNITION_LINK_EVENTS_ACK
+            }
+            Self::SpState(_) => response_kind_names::VERSIONED_SP_STATE,
+            Self::SerialConsoleAttachAck => {
+                response_kind_names::SERIAL_CONSOLE_ATTACH_ACK
+            }
+            Self::SerialConsoleWriteAck { .. } => {
+                response_kind_names::SERIAL_CONSOLE_WRITE_ACK
+            }
+            Self::SerialConsoleDetachAck => {
+                response_kind_names::SERIAL_CONSOLE_DETACH_ACK
+            }
+            Self::SerialConsoleKeepAliveAck => {
+                response_kind_names::SERIAL_CONSOLE_KEEPALIVE_ACK
+            }
+            Self::SerialConsoleBreakAck => {
+                response_kind_names::SERIAL_CONSOLE_BREAK_ACK
+            }
+            Self::SpUpdatePrepareAck => {
+                response_kind_names::SP_UPDATE_PREPARE_ACK
+            }
+            Self::ComponentUpdatePrepareAck => {
+                response_kind_names::COMPONENT_UPDATE_PREPARE_ACK
+            }
+            Self::UpdateStatus(_) => response_kind_names::UPDATE_STATUS,
+            Self::UpdateAbortAck => response_kind_names::UPDATE_ABORT_ACK,
+            Self::UpdateChunkAck => response_kind_names::UPDATE_CHUNK_ACK,
+            Self::PowerState(_) => response_kind_names::POWER_STATE,
+            Self::SetPowerStateAck => response_kind_names::SET_POWER_STATE_ACK,
+            Self::ResetPrepareAck => response_kind_names::RESET_PREPARE_ACK,
+            Self::Inventory(_) => response_kind_names::INVENTORY,
+            Self::Error(_) => response_kind_names::ERROR,
+            Self::StartupOptions(_) => response_kind_names::STARTUP_OPTIONS,
+            Self::SetStartupOptionsAck => {
+                response_kind_names::SET_STARTUP_OPTIONS_ACK
+            }
+            Self::ComponentDetails(_) => response_kind_names::COMPONENT_DETAILS,
+            Self::ComponentClearStatusAck => {
+                response_kind_names::COMPONENT_CLEAR_STATUS_ACK
+            }
+            Self::ComponentActiveSlot(_) => {
+                response_kind_names::COMPONENT_ACTIVE_SLOT
+            }
+            Self::ComponentSetActiveSlotAck => {
+                response_kind_names::COMPONENT_SET_ACTIVE_SLOT_ACK
+            }
+            Self::ComponentSetAndPersistActiveSlotAck => {
+                response_kind_names::COMPONENT_SET_AND_PERSIST_ACTIVE_SLOT_ACK
+            }
+            Self::SendHostNmiAck => response_kind_names::SEND_HOST_NMI_ACK,
+            Self::SetIpccKeyLookupValueAck => {
+                response_kind_names::SET_IPCC_KEY_LOOKUP_VALUE_ACK
+            }
+            Self::CabooseValue => response_kind_names::CABOOSE_VALUE,
+            Self::ResetComponentPrepareAck => {
+                response_kind_names::RESET_COMPONENT_PREPARE_ACK
+            }
+            Self::ResetComponentTriggerAck => {
+                response_kind_names::RESET_COMPONENT_TRIGGER_ACK
+            }
+            Self::SwitchDefaultImageAck => {
+                response_kind_names::SWITCH_DEFAULT_IMAGE_ACK
+            }
+            Self::ComponentActionAck => {
+                response_kind_names::COMPONENT_ACTION_ACK
+            }
+            Self::SpStateV2(_) => response_kind_names::VERSIONED_SP_STATE,
+            Self::BulkMeasurements(_) => response_kind_names::BULK_MEASUREMENTS,
+            Self::ProtocolCapabilities(_) => {
+                response_kind_names::PROTOCOL_CAPABILITIES
+            }
+        }
+    }
 }
 
 /// Identifier for one of of an SP's KSZ8463 management-network-facing ports.
@@ -184,6 +287,14 @@ pub struct SpStateV2 {
     pub rot: Result<RotStateV2, RotError>,
 }
 
+/// The state of an SP, in whichever form it reports it: SPs that predate
+/// `SpStateV2` respond to `MgsRequest::SpState` with an `SpStateV1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum VersionedSpState {
+    V1(SpStateV1),
+    V2(SpStateV2),
+}
+
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
 )]
@@ -266,34 +377,29 @@ pub struct TlvPage {
 pub enum ComponentDetails {
     PortStatus(Result<PortStatus, PortStatusError>),
     Measurement(Measurement),
+    MacTableEntry(MacTableEntry),
+    LinkFlapEvent(LinkFlapEvent),
+    MeasurementV2(MeasurementV2),
 }
 
 impl ComponentDetails {
     pub fn tag(&self) -> tlv::Tag {
         match self {
             ComponentDetails::PortStatus(_) => PortStatus::TAG,
-            ComponentDetails::Measurement(_) => MeasurementHeader::TAG,
+            ComponentDetails::Measurement(m) => m.tag(),
+            ComponentDetails::MacTableEntry(_) => MacTableEntry::TAG,
+            ComponentDetails::LinkFlapEvent(_) => LinkFlapEvent::TAG,
+            ComponentDetails::MeasurementV2(m) => m.tag(),
         }
     }
 
     pub fn serialize(&self, buf: &mut [u8]) -> hubpack::error::Result<usize> {
         match self {
             ComponentDetails::PortStatus(p) => hubpack::serialize(buf, p),
-            ComponentDetails::Measurement(m) => {
-                let header = MeasurementHeader::from(m);
-
-                // Serialize the header...
-                let n = hubpack::serialize(buf, &header)?;
-                let buf = &mut buf[n..];
-
-                // ... then append the name if we have room.
-                if buf.len() < m.name.len() {
-                    Err(hubpack::error::Error::Overrun)
-                } else {
-                    buf[..m.name.len()].copy_from_slice(m.name.as_bytes());
-                    Ok(n + m.name.len())
-                }
-            }
+            ComponentDetails::Measurement(m) => m.serialize(buf),
+            ComponentDetails::MacTableEntry(e) => hubpack::serialize(buf, e),
+            ComponentDetails::LinkFlapEvent(e) => hubpack::serialize(buf, e),
+            ComponentDetails::MeasurementV2(m) => m.serialize(buf),
         }
     }
 }
@@ -329,6 +435,19 @@ impl DeviceDescriptionHeader {
     pub const TAG: tlv::Tag = tlv::Tag(*b"DSC0");
 }
 
+bitflags! {
+    /// Protocol features supported by an SP, reported in response to
+    /// [`MgsRequest::ProtocolCapabilities`](crate::MgsRequest).
+    #[derive(Default, SerializedSize, Serialize, Deserialize)]
+    pub struct ProtocolCapabilities: u32 {
+        /// The SP handles requests with distinct `message_id`s independently:
+        /// MGS may send a new request before the SP has responded to earlier
+        /// ones, and the SP may respond to them in any order. (MGS never has
+        /// more than one outstanding request with the same `message_id`.)
+        const CONCURRENT_RPCS = 1 << 0;
+    }
+}
+
 bitflags! {
     #[derive(Default, SerializedSize, Serialize, Deserialize)]
     pub struct DeviceCapabilities: u32 {
@@ -924,3 +1043,61 @@ impl fmt::Display for RotError {
 
 #[cfg(feature = "std")]
 impl std::error::Error for RotError {}
+
+// Names of each kind of `SpResponse`; see `SpResponse::name()`.
+pub(crate) mod response_kind_names {
+    pub(crate) const DISCOVER: &str = "discover";
+    pub(crate) const IGNITION_STATE: &str = "ignition_state";
+    pub(crate) const BULK_IGNITION_STATE: &str = "bulk_ignition_state";
+    pub(crate) const IGNITION_LINK_EVENTS: &str = "ignition_link_events";
+    pub(crate) const BULK_IGNITION_LINK_EVENTS: &str =
+        "bulk_ignition_link_events";
+    pub(crate) const IGNITION_COMMAND_ACK: &str = "ignition_command_ack";
+    pub(crate) const CLEAR_IGNITION_LINK_EVENTS_ACK: &str =
+        "clear_ignition_link_events_ack";
+    pub(crate) const VERSIONED_SP_STATE: &str = "versioned_sp_state";
+    pub(crate) const SERIAL_CONSOLE_ATTACH_ACK: &str =
+        "serial_console_attach_ack";
+    pub(crate) const SERIAL_CONSOLE_WRITE_ACK: &str =
+        "serial_console_write_ack";
+    pub(crate) const SERIAL_CONSOLE_DETACH_ACK: &str =
+        "serial_console_detach_ack";
+    pub(crate) const SERIAL_CONSOLE_KEEPALIVE_ACK: &str =
+        "serial_console_keepalive_ack";
+    pub(crate) const SERIAL_CONSOLE_BREAK_ACK: &str =
+        "serial_console_break_ack";
+    pub(crate) const SP_UPDATE_PREPARE_ACK: &str = "sp_update_prepare_ack";
+    pub(crate) const COMPONENT_UPDATE_PREPARE_ACK: &str =
+        "component_update_prepare_ack";
+    pub(crate) const UPDATE_STATUS: &str = "update_status";
+    pub(crate) const UPDATE_ABORT_ACK: &str = "update_abort_ack";
+    pub(crate) const UPDATE_CHUNK_ACK: &str = "update_chunk_ack";
+    pub(crate) const POWER_STATE: &str = "power_state";
+    pub(crate) const SET_POWER_STATE_ACK: &str = "set_power_state_ack";
+    pub(crate) const RESET_PREPARE_ACK: &str = "reset_prepare_ack";
+    pub(crate) const INVENTORY: &str = "inventory";
+    pub(crate) const ERROR: &str = "error";
+    pub(crate) const STARTUP_OPTIONS: &str = "startup_options";
+    pub(crate) const SET_STARTUP_OPTIONS_ACK: &str = "set_startup_options_ack";
+    pub(crate) const COMPONENT_DETAILS: &str = "component_details";
+    pub(crate) const COMPONENT_CLEAR_STATUS_ACK: &str =
+        "component_clear_status_ack";
+    pub(crate) const COMPONENT_ACTIVE_SLOT: &str = "component_active_slot";
+    pub(crate) const COMPONENT_SET_ACTIVE_SLOT_ACK: &str =
+        "component_set_active_slot_ack";
+    pub(crate) const COMPONENT_SET_AND_PERSIST_ACTIVE_SLOT_ACK: &str =
+        "component_set_and_persist_active_slot_ack";
+    pub(crate) const SEND_HOST_NMI_ACK: &str = "send_host_nmi_ack";
+    pub(crate) const SET_IPCC_KEY_LOOKUP_VALUE_ACK: &str =
+        "set_ipcc_key_lookup_value_ack";
+    pub(crate) const CABOOSE_VALUE: &str = "caboose_value";
+    pub(crate) const RESET_COMPONENT_PREPARE_ACK: &str =
+        "reset_component_prepare_ack";
+    pub(crate) const RESET_COMPONENT_TRIGGER_ACK: &str =
+        "reset_component_trigger_ack";
+    pub(crate) const SWITCH_DEFAULT_IMAGE_ACK: &str =
+        "switch_default_image_ack";
+    pub(crate) const COMPONENT_ACTION_ACK: &str = "component_action";
+    pub(crate) const BULK_MEASUREMENTS: &str = "bulk_measurements";
+    pub(crate) const PROTOCOL_CAPABILITIES: &str = "protocol_capabilities";
+}
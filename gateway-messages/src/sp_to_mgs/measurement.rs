@@ -7,6 +7,7 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use crate::tlv;
+use crate::SpComponent;
 
 /// `Measurement` includes a `name` field; on the SP, this is a `&'static str`
 /// (embedded at build time), and in MGS it's a `String` (deserialized from the
@@ -15,7 +16,8 @@ use crate::tlv;
 /// This struct does not implement `Serialize`/`Deserialize` directly; when it
 /// needs to be serialized (or deserialized), it is converted to a
 /// [`MeasurementHeader`] followed by the `name` packed into a TLV triple with
-/// the tag [`MeasurementHeader::TAG`].
+/// the tag [`MeasurementHeader::TAG`]. See [`MeasurementV2`] for measurements
+/// that carry [`MeasurementMetadata`].
 #[derive(Debug, Clone)]
 pub struct Measurement {
     #[cfg(feature = "std")]
@@ -26,6 +28,173 @@ pub struct Measurement {
     pub value: Result<f32, MeasurementError>,
 }
 
+impl Measurement {
+    pub fn tag(&self) -> tlv::Tag {
+        MeasurementHeader::TAG
+    }
+
+    /// Serialize the value of the TLV triple for this measurement (i.e., its
+    /// [`MeasurementHeader`] followed by the name).
+    pub fn serialize(&self, buf: &mut [u8]) -> hubpack::error::Result<usize> {
+        serialize_with_name(buf, &MeasurementHeader::from(self), &self.name)
+    }
+}
+
+/// A [`Measurement`] with [`MeasurementMetadata`] and the extended set of
+/// [`MeasurementKindV2`]s.
+///
+/// Like `Measurement`, this is converted to a [`MeasurementHeaderV2`] followed
+/// by the `name` when serialized, and packed into a TLV triple with the tag
+/// [`MeasurementHeaderV2::TAG`].
+///
+/// MGS instances that predate this type (i.e., that send requests with a
+/// version older than [`MeasurementHeaderV2::MIN_VERSION`]) do not understand
+/// these triples. They page through component details by requesting the next
+/// offset after the entries they could parse, so unknown triples cause them to
+/// re-request overlapping pages, and a page containing only unknown triples
+/// fails outright. `sp_impl` therefore [downgrades](Self::downgrade) these
+/// when answering such requests; an SP must not report measurements whose
+/// kind has no [`MeasurementKind`] equivalent to them.
+#[derive(Debug, Clone)]
+pub struct MeasurementV2 {
+    #[cfg(feature = "std")]
+    pub name: String,
+    #[cfg(not(feature = "std"))]
+    pub name: &'static str,
+    pub kind: MeasurementKindV2,
+    pub value: Result<f32, MeasurementError>,
+    pub metadata: MeasurementMetadata,
+}
+
+impl MeasurementV2 {
+    pub fn tag(&self) -> tlv::Tag {
+        MeasurementHeaderV2::TAG
+    }
+
+    /// Serialize the value of the TLV triple for this measurement (i.e., its
+    /// [`MeasurementHeaderV2`] followed by the name).
+    pub fn serialize(&self, buf: &mut [u8]) -> hubpack::error::Result<usize> {
+        serialize_with_name(buf, &MeasurementHeaderV2::from(self), &self.name)
+    }
+
+    /// Convert this to a [`Measurement`] (dropping its metadata) for MGS
+    /// instances that don't understand [`MeasurementHeaderV2`], if its kind
+    /// has a [`MeasurementKind`] equivalent.
+    pub fn downgrade(&self) -> Option<Measurement> {
+        let kind = MeasurementKind::try_from(self.kind).ok()?;
+        let MeasurementV2 { name, value, .. } = self.clone();
+        Some(Measurement { name, kind, value })
+    }
+}
+
+/// Either form of measurement an SP may report.
+#[derive(Debug, Clone)]
+pub enum VersionedMeasurement {
+    V1(Measurement),
+    V2(MeasurementV2),
+}
+
+impl VersionedMeasurement {
+    pub fn tag(&self) -> tlv::Tag {
+        match self {
+            Self::V1(m) => m.tag(),
+            Self::V2(m) => m.tag(),
+        }
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) -> hubpack::error::Result<usize> {
+        match self {
+            Self::V1(m) => m.serialize(buf),
+            Self::V2(m) => m.serialize(buf),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::V1(m) => &m.name,
+            Self::V2(m) => &m.name,
+        }
+    }
+
+    pub fn kind(&self) -> MeasurementKindV2 {
+        match self {
+            Self::V1(m) => m.kind.into(),
+            Self::V2(m) => m.kind,
+        }
+    }
+
+    pub fn value(&self) -> Result<f32, MeasurementError> {
+        match self {
+            Self::V1(m) => m.value,
+            Self::V2(m) => m.value,
+        }
+    }
+
+    /// Always `None` for [`Measurement`]s.
+    pub fn metadata(&self) -> Option<MeasurementMetadata> {
+        match self {
+            Self::V1(_) => None,
+            Self::V2(m) => Some(m.metadata),
+        }
+    }
+}
+
+impl From<Measurement> for VersionedMeasurement {
+    fn from(m: Measurement) -> Self {
+        Self::V1(m)
+    }
+}
+
+impl From<MeasurementV2> for VersionedMeasurement {
+    fn from(m: MeasurementV2) -> Self {
+        Self::V2(m)
+    }
+}
+
+// Serialize `header` followed by `name`, if we have room for both.
+fn serialize_with_name<T: Serialize>(
+    buf: &mut [u8],
+    header: &T,
+    name: &str,
+) -> hubpack::error::Result<usize> {
+    let n = hubpack::serialize(buf, header)?;
+    let buf = &mut buf[n..];
+    if buf.len() < name.len() {
+        Err(hubpack::error::Error::Overrun)
+    } else {
+        buf[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(n + name.len())
+    }
+}
+
+/// A measurement along with the component that produced it, as reported in
+/// response to [`crate::MgsRequest::BulkMeasurements`].
+///
+/// Packed into a TLV triple with the tag [`ComponentMeasurement::TAG`]; its
+/// value is the hubpack-serialized `component` followed by `measurement`
+/// packed into its own (nested) TLV triple.
+#[derive(Debug, Clone)]
+pub struct ComponentMeasurement {
+    pub component: SpComponent,
+    pub measurement: VersionedMeasurement,
+}
+
+impl ComponentMeasurement {
+    pub const TAG: tlv::Tag = tlv::Tag(*b"CMS0");
+
+    pub fn serialize(&self, buf: &mut [u8]) -> hubpack::error::Result<usize> {
+        let n = hubpack::serialize(buf, &self.component)?;
+        let m = tlv::encode(&mut buf[n..], self.measurement.tag(), |buf| {
+            self.measurement.serialize(buf)
+        })
+        .map_err(|err| match err {
+            tlv::EncodeError::BufferTooSmall => hubpack::error::Error::Overrun,
+            tlv::EncodeError::Custom(err) => err,
+        })?;
+        Ok(n + m)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
 pub struct MeasurementHeader {
     pub name_length: u32,
@@ -43,6 +212,81 @@ impl MeasurementHeader {
     pub const TAG: tlv::Tag = tlv::Tag(*b"MEA0");
 }
 
+/// Header of a [`MeasurementV2`].
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct MeasurementHeaderV2 {
+    pub name_length: u32,
+    pub kind: MeasurementKindV2,
+    pub value: Result<f32, MeasurementError>,
+    pub metadata: MeasurementMetadata,
+}
+
+impl From<&'_ MeasurementV2> for MeasurementHeaderV2 {
+    fn from(m: &MeasurementV2) -> Self {
+        Self {
+            name_length: m.name.len() as u32,
+            kind: m.kind,
+            value: m.value,
+            metadata: m.metadata,
+        }
+    }
+}
+
+impl MeasurementHeaderV2 {
+    pub const TAG: tlv::Tag = tlv::Tag(*b"MEA1");
+
+    /// Oldest protocol version in which MGS understands this header.
+    pub const MIN_VERSION: u32 = 9;
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct MeasurementMetadata {
+    /// Identifier of the sensor that produced this measurement; unique within
+    /// a single SP.
+    pub sensor_id: u32,
+    pub unit: MeasurementUnit,
+    pub warning_threshold: Option<f32>,
+    pub critical_threshold: Option<f32>,
+    pub min_since_boot: Option<f32>,
+    pub max_since_boot: Option<f32>,
+    /// SP uptime (in milliseconds) at which the value was sampled.
+    pub timestamp_ms: u64,
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum MeasurementUnit {
+    Celsius,
+    Watts,
+    Amps,
+    Volts,
+    Rpm,
+    /// Relative humidity, in percent.
+    RelativeHumidity,
+    Joules,
+    /// Duty cycle, in percent.
+    Percent,
+}
+
+impl MeasurementUnit {
+    /// Short suffix suitable for appending to a displayed value.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            MeasurementUnit::Celsius => "°C",
+            MeasurementUnit::Watts => "W",
+            MeasurementUnit::Amps => "A",
+            MeasurementUnit::Volts => "V",
+            MeasurementUnit::Rpm => "RPM",
+            MeasurementUnit::RelativeHumidity => "%RH",
+            MeasurementUnit::Joules => "J",
+            MeasurementUnit::Percent => "%",
+        }
+    }
+}
+
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
 )]
@@ -56,6 +300,11 @@ pub enum MeasurementError {
     DeviceOff,
 }
 
+/// Kinds of [`Measurement`]s.
+///
+/// This set is frozen: MGS instances that only understand
+/// [`MeasurementHeader`] can't deserialize new variants, so new kinds are only
+/// added to [`MeasurementKindV2`].
 #[derive(
     Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, SerializedSize,
 )]
@@ -68,3 +317,56 @@ pub enum MeasurementKind {
     InputVoltage,
     Speed,
 }
+
+/// Kinds of [`MeasurementV2`]s: every [`MeasurementKind`] (in the same order),
+/// followed by kinds only reported via [`MeasurementHeaderV2`].
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum MeasurementKindV2 {
+    Temperature,
+    Power,
+    Current,
+    Voltage,
+    InputCurrent,
+    InputVoltage,
+    Speed,
+    Humidity,
+    Energy,
+    OutputPower,
+    FanPwmDuty,
+}
+
+impl From<MeasurementKind> for MeasurementKindV2 {
+    fn from(kind: MeasurementKind) -> Self {
+        match kind {
+            MeasurementKind::Temperature => Self::Temperature,
+            MeasurementKind::Power => Self::Power,
+            MeasurementKind::Current => Self::Current,
+            MeasurementKind::Voltage => Self::Voltage,
+            MeasurementKind::InputCurrent => Self::InputCurrent,
+            MeasurementKind::InputVoltage => Self::InputVoltage,
+            MeasurementKind::Speed => Self::Speed,
+        }
+    }
+}
+
+impl TryFrom<MeasurementKindV2> for MeasurementKind {
+    type Error = MeasurementKindV2;
+
+    fn try_from(kind: MeasurementKindV2) -> Result<Self, Self::Error> {
+        match kind {
+            MeasurementKindV2::Temperature => Ok(Self::Temperature),
+            MeasurementKindV2::Power => Ok(Self::Power),
+            MeasurementKindV2::Current => Ok(Self::Current),
+            MeasurementKindV2::Voltage => Ok(Self::Voltage),
+            MeasurementKindV2::InputCurrent => Ok(Self::InputCurrent),
+            MeasurementKindV2::InputVoltage => Ok(Self::InputVoltage),
+            MeasurementKindV2::Speed => Ok(Self::Speed),
+            MeasurementKindV2::Humidity
+            | MeasurementKindV2::Energy
+            | MeasurementKindV2::OutputPower
+            | MeasurementKindV2::FanPwmDuty => Err(kind),
+        }
+    }
+}
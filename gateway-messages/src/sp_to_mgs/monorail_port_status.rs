@@ -118,3 +118,38 @@ pub enum PhyType {
     Vsc8552,
     Vsc8562,
 }
+
+/// A single entry in the monorail switch's MAC address table.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct MacTableEntry {
+    pub mac: [u8; 6],
+    pub vlan: u16,
+    pub port: u32,
+    /// Seconds since this entry was last learned or refreshed.
+    pub age_secs: u32,
+}
+
+impl MacTableEntry {
+    pub const TAG: tlv::Tag = tlv::Tag(*b"MAC0");
+}
+
+/// A single link state transition on a monorail port.
+///
+/// The SP keeps a bounded history of these per port; the oldest events are
+/// discarded first.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct LinkFlapEvent {
+    pub port: u32,
+    /// SP uptime (in milliseconds) at which the transition was observed.
+    pub timestamp_ms: u64,
+    /// Link status after the transition.
+    pub status: LinkStatus,
+}
+
+impl LinkFlapEvent {
+    pub const TAG: tlv::Tag = tlv::Tag(*b"LFE0");
+}
@@ -0,0 +1,188 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! The tests in this module check that the serialized form of the measurement
+//! TLV entries (`MEA0` and `MEA1`) reported in component details and bulk
+//! measurements have not changed.
+//!
+//! `MEA0` is understood by every MGS we support, so if a test in this module
+//! fails, _do not change the test_! New measurement kinds go in
+//! `MeasurementKindV2`, and new fields in a new header with its own tag.
+
+use gateway_messages::measurement::MeasurementError;
+use gateway_messages::measurement::MeasurementHeader;
+use gateway_messages::measurement::MeasurementHeaderV2;
+use gateway_messages::measurement::MeasurementKind;
+use gateway_messages::measurement::MeasurementKindV2;
+use gateway_messages::measurement::MeasurementMetadata;
+use gateway_messages::measurement::MeasurementUnit;
+use gateway_messages::Measurement;
+use gateway_messages::MeasurementV2;
+use gateway_messages::SerializedSize;
+
+use super::assert_serialized;
+
+const ALL_KINDS: [MeasurementKind; 7] = [
+    MeasurementKind::Temperature,
+    MeasurementKind::Power,
+    MeasurementKind::Current,
+    MeasurementKind::Voltage,
+    MeasurementKind::InputCurrent,
+    MeasurementKind::InputVoltage,
+    MeasurementKind::Speed,
+];
+
+#[test]
+fn measurement_kind() {
+    let mut out = [0; MeasurementKind::MAX_SIZE];
+    for (i, kind) in ALL_KINDS.into_iter().enumerate() {
+        assert_serialized(&mut out, &[i as u8], &kind);
+    }
+}
+
+#[test]
+fn measurement_kind_v2_extends_measurement_kind() {
+    let mut out = [0; MeasurementKindV2::MAX_SIZE];
+    for (i, kind) in ALL_KINDS.into_iter().enumerate() {
+        assert_serialized(&mut out, &[i as u8], &MeasurementKindV2::from(kind));
+    }
+
+    for (kind, expected) in [
+        (MeasurementKindV2::Humidity, 7),
+        (MeasurementKindV2::Energy, 8),
+        (MeasurementKindV2::OutputPower, 9),
+        (MeasurementKindV2::FanPwmDuty, 10),
+    ] {
+        assert_serialized(&mut out, &[expected], &kind);
+    }
+}
+
+#[test]
+fn measurement_header() {
+    assert_eq!(MeasurementHeader::TAG.0, *b"MEA0");
+
+    let mut out = [0; MeasurementHeader::MAX_SIZE];
+    let header = MeasurementHeader {
+        name_length: 0x01020304,
+        kind: MeasurementKind::Speed,
+        value: Ok(1.0),
+    };
+    #[rustfmt::skip]
+    let expected = &[
+        4, 3, 2, 1, // name_length
+        6, // kind
+        0, 0, 0, 0x80, 0x3f, // value
+    ];
+    assert_serialized(&mut out, expected, &header);
+
+    let header = MeasurementHeader {
+        name_length: 0,
+        kind: MeasurementKind::Temperature,
+        value: Err(MeasurementError::DeviceOff),
+    };
+    let expected = &[0, 0, 0, 0, 0, 1, 6];
+    assert_serialized(&mut out, expected, &header);
+}
+
+#[test]
+fn measurement() {
+    let mut out = [0; MeasurementHeader::MAX_SIZE + 8];
+    let measurement = Measurement {
+        name: "fan0".into(),
+        kind: MeasurementKind::Speed,
+        value: Ok(1.0),
+    };
+    assert_eq!(measurement.tag(), MeasurementHeader::TAG);
+    let n = measurement.serialize(&mut out).unwrap();
+    #[rustfmt::skip]
+    let expected = &[
+        4, 0, 0, 0, // name_length
+        6, // kind
+        0, 0, 0, 0x80, 0x3f, // value
+        b'f', b'a', b'n', b'0', // name
+    ];
+    assert_eq!(expected, &out[..n]);
+}
+
+#[test]
+fn measurement_v2() {
+    assert_eq!(MeasurementHeaderV2::TAG.0, *b"MEA1");
+
+    let mut out = [0; MeasurementHeaderV2::MAX_SIZE + 8];
+    let measurement = MeasurementV2 {
+        name: "rh".into(),
+        kind: MeasurementKindV2::Humidity,
+        value: Ok(1.0),
+        metadata: MeasurementMetadata {
+            sensor_id: 0x01020304,
+            unit: MeasurementUnit::RelativeHumidity,
+            warning_threshold: Some(2.0),
+            critical_threshold: None,
+            min_since_boot: None,
+            max_since_boot: Some(2.0),
+            timestamp_ms: 0x05060708,
+        },
+    };
+    assert_eq!(measurement.tag(), MeasurementHeaderV2::TAG);
+    let n = measurement.serialize(&mut out).unwrap();
+    #[rustfmt::skip]
+    let expected = &[
+        2, 0, 0, 0, // name_length
+        7, // kind
+        0, 0, 0, 0x80, 0x3f, // value
+        4, 3, 2, 1, // sensor_id
+        5, // unit
+        1, 0, 0, 0, 0x40, // warning_threshold
+        0, // critical_threshold
+        0, // min_since_boot
+        1, 0, 0, 0, 0x40, // max_since_boot
+        8, 7, 6, 5, 0, 0, 0, 0, // timestamp_ms
+        b'r', b'h', // name
+    ];
+    assert_eq!(expected, &out[..n]);
+}
+
+#[test]
+fn measurement_v2_downgrade() {
+    let metadata = MeasurementMetadata {
+        sensor_id: 1,
+        unit: MeasurementUnit::Rpm,
+        warning_threshold: None,
+        critical_threshold: None,
+        min_since_boot: None,
+        max_since_boot: None,
+        timestamp_ms: 0,
+    };
+    let measurement = MeasurementV2 {
+        name: "fan0".into(),
+        kind: MeasurementKindV2::Speed,
+        value: Ok(1.0),
+        metadata,
+    };
+
+    // Downgrading must produce exactly the `MEA0` triple an old MGS expects.
+    let downgraded = measurement.downgrade().unwrap();
+    assert_eq!(downgraded.tag(), MeasurementHeader::TAG);
+    let mut out = [0; MeasurementHeader::MAX_SIZE + 4];
+    let n = downgraded.serialize(&mut out).unwrap();
+    #[rustfmt::skip]
+    let expected = &[
+        4, 0, 0, 0, // name_length
+        6, // kind
+        0, 0, 0, 0x80, 0x3f, // value
+        b'f', b'a', b'n', b'0', // name
+    ];
+    assert_eq!(expected, &out[..n]);
+
+    // Kinds that only exist in `MeasurementKindV2` can't be downgraded.
+    let measurement = MeasurementV2 {
+        name: "rh".into(),
+        kind: MeasurementKindV2::Humidity,
+        value: Ok(1.0),
+        metadata,
+    };
+    assert!(measurement.downgrade().is_none());
+}
@@ -6,12 +6,16 @@
 
 use serde::Serialize;
 
+mod measurement;
 mod v2;
 mod v3;
 mod v4;
 mod v5;
 mod v6;
 mod v7;
+mod v8;
+mod v9;
+mod v10;
 
 pub fn assert_serialized(
     out: &mut [u8],
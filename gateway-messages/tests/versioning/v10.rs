@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! The tests in this module check that the serialized form of messages from MGS
+//! protocol version 10 have not changed.
+//!
+//! If a test in this module fails, _do not change the test_! This means you
+//! have changed, deleted, or reordered an existing message type or enum
+//! variant, and you should revert that change. This will remain true until we
+//! bump the `version::MIN` to a value higher than 10, at which point these
+//! tests can be removed as we will stop supporting v10.
+
+use gateway_messages::MgsRequest;
+use gateway_messages::ProtocolCapabilities;
+use gateway_messages::SerializedSize;
+use gateway_messages::SpResponse;
+
+use super::assert_serialized;
+
+// This test covers the ProtocolCapabilities message added in v10.
+#[test]
+fn mgs_request() {
+    let mut out = [0; MgsRequest::MAX_SIZE];
+    let request = MgsRequest::ProtocolCapabilities;
+    let expected = &[39];
+    assert_serialized(&mut out, expected, &request);
+}
+
+#[test]
+fn sp_response() {
+    let mut out = [0; SpResponse::MAX_SIZE];
+    let response =
+        SpResponse::ProtocolCapabilities(ProtocolCapabilities::CONCURRENT_RPCS);
+    let expected = &[39, 1, 0, 0, 0];
+    assert_serialized(&mut out, expected, &response);
+}
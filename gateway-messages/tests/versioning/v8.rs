@@ -0,0 +1,72 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! The tests in this module check that the serialized form of messages from MGS
+//! protocol version 8 have not changed.
+//!
+//! If a test in this module fails, _do not change the test_! This means you
+//! have changed, deleted, or reordered an existing message type or enum
+//! variant, and you should revert that change. This will remain true until we
+//! bump the `version::MIN` to a value higher than 8, at which point these tests
+//! can be removed as we will stop supporting v8.
+
+use gateway_messages::monorail_port_status::PortMode;
+use gateway_messages::monorail_port_status::Speed;
+use gateway_messages::ComponentAction;
+use gateway_messages::MgsRequest;
+use gateway_messages::MonorailComponentAction;
+use gateway_messages::SerializedSize;
+use gateway_messages::SpComponent;
+
+use super::assert_serialized;
+
+// This test covers the monorail component actions added in v8.
+#[test]
+fn mgs_request() {
+    let mut out = [0; MgsRequest::MAX_SIZE];
+
+    for (action, serialized) in [
+        (
+            MonorailComponentAction::EnablePort { port: 0x01020304 },
+            &[0_u8, 4, 3, 2, 1] as &[_],
+        ),
+        (MonorailComponentAction::DisablePort { port: 5 }, &[1, 5, 0, 0, 0]),
+        (
+            MonorailComponentAction::SetPortMode {
+                port: 6,
+                mode: PortMode::Sfi,
+            },
+            &[2, 6, 0, 0, 0, 0],
+        ),
+        (
+            MonorailComponentAction::SetPortMode {
+                port: 7,
+                mode: PortMode::Sgmii(Speed::Speed1G),
+            },
+            &[2, 7, 0, 0, 0, 2, 1],
+        ),
+        (
+            MonorailComponentAction::SetPortMode {
+                port: 8,
+                mode: PortMode::Qsgmii(Speed::Speed100M),
+            },
+            &[2, 8, 0, 0, 0, 3, 0],
+        ),
+        (
+            MonorailComponentAction::ResetPortCounters { port: 9 },
+            &[3, 9, 0, 0, 0],
+        ),
+        (MonorailComponentAction::RestartPhy { port: 10 }, &[4, 10, 0, 0, 0]),
+    ] {
+        let request = MgsRequest::ComponentAction {
+            component: SpComponent::MONORAIL,
+            action: ComponentAction::Monorail(action),
+        };
+        let mut expected = b"\x24monorail\0\0\0\0\0\0\0\0\x01".to_vec();
+        expected.extend_from_slice(serialized);
+        assert_serialized(&mut out, &expected, &request);
+    }
+}
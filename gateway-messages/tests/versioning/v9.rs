@@ -0,0 +1,41 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! The tests in this module check that the serialized form of messages from MGS
+//! protocol version 9 have not changed.
+//!
+//! If a test in this module fails, _do not change the test_! This means you
+//! have changed, deleted, or reordered an existing message type or enum
+//! variant, and you should revert that change. This will remain true until we
+//! bump the `version::MIN` to a value higher than 9, at which point these tests
+//! can be removed as we will stop supporting v9.
+
+use gateway_messages::MgsRequest;
+use gateway_messages::SerializedSize;
+use gateway_messages::SpResponse;
+use gateway_messages::TlvPage;
+
+use super::assert_serialized;
+
+// This test covers the BulkMeasurements message added in v9.
+#[test]
+fn mgs_request() {
+    let mut out = [0; MgsRequest::MAX_SIZE];
+    let request = MgsRequest::BulkMeasurements { offset: 0x01020304 };
+    let expected = &[38, 4, 3, 2, 1];
+    assert_serialized(&mut out, expected, &request);
+}
+
+#[test]
+fn sp_response() {
+    let mut out = [0; SpResponse::MAX_SIZE];
+    let response = SpResponse::BulkMeasurements(TlvPage {
+        offset: 0x01020304,
+        total: 0x05060708,
+    });
+    let expected = &[38, 4, 3, 2, 1, 8, 7, 6, 5];
+    assert_serialized(&mut out, expected, &response);
+}
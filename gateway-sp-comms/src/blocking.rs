@@ -0,0 +1,442 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! A blocking facade over [`SingleSp`](crate::SingleSp), for synchronous
+//! programs.
+//!
+//! [`SingleSp`] owns a Tokio runtime on which the underlying
+//! [`SingleSp`](crate::SingleSp) (and the [`SharedSocket`] it uses) runs; each
+//! method blocks the calling thread until the corresponding async method
+//! completes. These methods must not be called from within an async context.
+
+use crate::error::CommunicationError;
+use crate::error::UpdateError;
+use crate::BindError;
+use crate::HostPhase2Provider;
+use crate::RpcPolicies;
+use crate::SharedSocket;
+use crate::SingleSpStats;
+use crate::SpComponentDetails;
+use crate::SpIdentity;
+use crate::SpInventory;
+use crate::SwitchPortConfig;
+use crate::VersionedSpState;
+use gateway_messages::ignition::LinkEvents;
+use gateway_messages::ignition::TransceiverSelect;
+use gateway_messages::measurement::ComponentMeasurement;
+use gateway_messages::request::Request;
+use gateway_messages::ComponentAction;
+use gateway_messages::IgnitionCommand;
+use gateway_messages::IgnitionState;
+use gateway_messages::PowerState;
+use gateway_messages::SpComponent;
+use gateway_messages::StartupOptions;
+use gateway_messages::UpdateStatus;
+use slog::Logger;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+type Result<T, E = CommunicationError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum StartError {
+    #[error("failed to start tokio runtime: {0}")]
+    Runtime(io::Error),
+    #[error(transparent)]
+    Bind(#[from] BindError),
+}
+
+/// A blocking [`SingleSp`](crate::SingleSp).
+///
+/// Methods behave as the async methods of the same name; see those for
+/// details.
+#[derive(Debug)]
+pub struct SingleSp {
+    // Fields are dropped in order: our `SingleSp` and `SharedSocket` stop
+    // their background tasks before the runtime they're on shuts down.
+    inner: crate::SingleSp,
+    _shared_socket: Option<SharedSocket>,
+    runtime: Runtime,
+}
+
+impl SingleSp {
+    /// Bind a new [`SharedSocket`] to `listen_port` and use it to create a
+    /// [`SingleSp`](crate::SingleSp) for the port described by `config`.
+    ///
+    /// As with [`SingleSp::new()`](crate::SingleSp::new), this returns
+    /// without waiting for the SP to be discovered.
+    pub fn new<T: HostPhase2Provider>(
+        listen_port: u16,
+        host_phase2_provider: Arc<T>,
+        config: SwitchPortConfig,
+        rpc_policies: RpcPolicies,
+        log: Logger,
+    ) -> Result<Self, StartError> {
+        let runtime = build_runtime().map_err(StartError::Runtime)?;
+        let (shared_socket, inner) = runtime.block_on(async {
+            let shared_socket =
+                SharedSocket::bind(listen_port, host_phase2_provider, log)
+                    .await?;
+            let inner = crate::SingleSp::new_with_rpc_policies(
+                &shared_socket,
+                config,
+                rpc_policies,
+            )
+            .await;
+            Ok::<_, BindError>((shared_socket, inner))
+        })?;
+        Ok(Self { inner, _shared_socket: Some(shared_socket), runtime })
+    }
+
+    /// Wrap an existing [`SingleSp`](crate::SingleSp).
+    ///
+    /// `inner` must have been created on `runtime` (e.g., via
+    /// `runtime.block_on()`), and any [`SharedSocket`] it uses must outlive
+    /// the returned `SingleSp`.
+    pub fn from_parts(runtime: Runtime, inner: crate::SingleSp) -> Self {
+        Self { inner, _shared_socket: None, runtime }
+    }
+
+    /// The async [`SingleSp`](crate::SingleSp) we wrap.
+    pub fn as_async(&self) -> &crate::SingleSp {
+        &self.inner
+    }
+
+    pub fn interface(&self) -> &str {
+        self.inner.interface()
+    }
+
+    pub fn identity(&self) -> Option<SpIdentity> {
+        self.inner.identity()
+    }
+
+    pub fn stats(&self) -> SingleSpStats {
+        self.inner.stats()
+    }
+
+    /// Shut down the [`SingleSp`](crate::SingleSp) we wrap, followed by the
+    /// [`SharedSocket`] we created for it (if any); see
+    /// [`SingleSp::shutdown()`](crate::SingleSp::shutdown).
+    pub fn shutdown(self) {
+        let Self { inner, _shared_socket: shared_socket, runtime } = self;
+        runtime.block_on(async move {
+            inner.shutdown().await;
+            if let Some(shared_socket) = shared_socket {
+                shared_socket.shutdown().await;
+            }
+        });
+    }
+
+    pub fn call<R: Request>(&self, request: R) -> Result<R::Response> {
+        self.runtime.block_on(self.inner.call(request))
+    }
+
+    pub fn state(&self) -> Result<VersionedSpState> {
+        self.runtime.block_on(self.inner.state())
+    }
+
+    pub fn inventory(&self) -> Result<SpInventory> {
+        self.runtime.block_on(self.inner.inventory())
+    }
+
+    pub fn component_details(
+        &self,
+        component: SpComponent,
+    ) -> Result<SpComponentDetails> {
+        self.runtime.block_on(self.inner.component_details(component))
+    }
+
+    pub fn all_measurements(&self) -> Result<Vec<ComponentMeasurement>> {
+        self.runtime.block_on(self.inner.all_measurements())
+    }
+
+    pub fn component_active_slot(&self, component: SpComponent) -> Result<u16> {
+        self.runtime.block_on(self.inner.component_active_slot(component))
+    }
+
+    pub fn set_component_active_slot(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        persist: bool,
+    ) -> Result<()> {
+        self.runtime.block_on(
+            self.inner.set_component_active_slot(component, slot, persist),
+        )
+    }
+
+    pub fn component_clear_status(&self, component: SpComponent) -> Result<()> {
+        self.runtime.block_on(self.inner.component_clear_status(component))
+    }
+
+    pub fn component_action(
+        &self,
+        component: SpComponent,
+        action: ComponentAction,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.component_action(component, action))
+    }
+
+    pub fn get_startup_options(&self) -> Result<StartupOptions> {
+        self.runtime.block_on(self.inner.get_startup_options())
+    }
+
+    pub fn set_startup_options(
+        &self,
+        startup_options: StartupOptions,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.set_startup_options(startup_options))
+    }
+
+    pub fn power_state(&self) -> Result<PowerState> {
+        self.runtime.block_on(self.inner.power_state())
+    }
+
+    pub fn set_power_state(&self, power_state: PowerState) -> Result<()> {
+        self.runtime.block_on(self.inner.set_power_state(power_state))
+    }
+
+    pub fn ignition_state(&self, target: u8) -> Result<IgnitionState> {
+        self.runtime.block_on(self.inner.ignition_state(target))
+    }
+
+    pub fn bulk_ignition_state(&self) -> Result<Vec<IgnitionState>> {
+        self.runtime.block_on(self.inner.bulk_ignition_state())
+    }
+
+    pub fn ignition_link_events(&self, target: u8) -> Result<LinkEvents> {
+        self.runtime.block_on(self.inner.ignition_link_events(target))
+    }
+
+    pub fn bulk_ignition_link_events(&self) -> Result<Vec<LinkEvents>> {
+        self.runtime.block_on(self.inner.bulk_ignition_link_events())
+    }
+
+    pub fn clear_ignition_link_events(
+        &self,
+        target: Option<u8>,
+        transceiver_select: Option<TransceiverSelect>,
+    ) -> Result<()> {
+        self.runtime.block_on(
+            self.inner.clear_ignition_link_events(target, transceiver_select),
+        )
+    }
+
+    pub fn ignition_command(
+        &self,
+        target: u8,
+        command: IgnitionCommand,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.ignition_command(target, command))
+    }
+
+    /// Start an update; see
+    /// [`SingleSp::start_update()`](crate::SingleSp::start_update).
+    ///
+    /// The update continues in the background (on our runtime) after this
+    /// returns; poll its progress via [`SingleSp::update_status()`].
+    pub fn start_update(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+        slot: u16,
+        image: Vec<u8>,
+    ) -> Result<(), UpdateError> {
+        self.runtime.block_on(
+            self.inner.start_update(component, update_id, slot, image),
+        )
+    }
+
+    pub fn update_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<UpdateStatus> {
+        self.runtime.block_on(self.inner.update_status(component))
+    }
+
+    pub fn update_abort(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.update_abort(component, update_id))
+    }
+
+    pub fn serial_console_attach(
+        &self,
+        component: SpComponent,
+    ) -> Result<AttachedSerialConsole<'_>> {
+        let inner = self
+            .runtime
+            .block_on(self.inner.serial_console_attach(component))?;
+        Ok(AttachedSerialConsole { runtime: &self.runtime, inner })
+    }
+
+    pub fn serial_console_detach(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.serial_console_detach())
+    }
+
+    pub fn get_caboose_value(&self, key: [u8; 4]) -> Result<Vec<u8>> {
+        self.runtime.block_on(self.inner.get_caboose_value(key))
+    }
+
+    pub fn read_component_caboose(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        key: [u8; 4],
+    ) -> Result<Vec<u8>> {
+        self.runtime
+            .block_on(self.inner.read_component_caboose(component, slot, key))
+    }
+
+    pub fn reset_component_prepare(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.reset_component_prepare(component))
+    }
+
+    pub fn reset_component_trigger(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.reset_component_trigger(component))
+    }
+
+    pub fn send_host_nmi(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.send_host_nmi())
+    }
+
+    pub fn set_ipcc_key_lookup_value(
+        &self,
+        key: u8,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.runtime.block_on(self.inner.set_ipcc_key_lookup_value(key, value))
+    }
+}
+
+// Our runtime needs at least one worker thread of its own: the tasks backing
+// `SingleSp` and `SharedSocket` (e.g., serving host phase 2 data, or
+// buffering serial console output) must keep running between our callers'
+// blocking calls.
+fn build_runtime() -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("gateway-sp-comms")
+        .enable_all()
+        .build()
+}
+
+/// A blocking [`AttachedSerialConsole`](crate::AttachedSerialConsole).
+#[derive(Debug)]
+pub struct AttachedSerialConsole<'a> {
+    runtime: &'a Runtime,
+    inner: crate::AttachedSerialConsole,
+}
+
+impl<'a> AttachedSerialConsole<'a> {
+    pub fn split(
+        self,
+    ) -> (AttachedSerialConsoleSend<'a>, AttachedSerialConsoleRecv<'a>) {
+        let (send, recv) = self.inner.split();
+        (
+            AttachedSerialConsoleSend { runtime: self.runtime, inner: send },
+            AttachedSerialConsoleRecv { runtime: self.runtime, inner: recv },
+        )
+    }
+}
+
+/// The sending half of a blocking serial console connection.
+#[derive(Debug)]
+pub struct AttachedSerialConsoleSend<'a> {
+    runtime: &'a Runtime,
+    inner: crate::AttachedSerialConsoleSend,
+}
+
+impl AttachedSerialConsoleSend<'_> {
+    pub fn write(&mut self, data: Vec<u8>) -> Result<()> {
+        self.runtime.block_on(self.inner.write(data))
+    }
+
+    pub fn keepalive(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.keepalive())
+    }
+
+    pub fn detach(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.detach())
+    }
+
+    pub fn send_break(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.send_break())
+    }
+}
+
+/// The receiving half of a blocking serial console connection.
+///
+/// As an [`Iterator`], yields each chunk of data from the SP until the
+/// connection is closed (e.g., because the serial console was detached).
+/// Since the sending half is separate, one thread can iterate over the output
+/// while another writes input.
+#[derive(Debug)]
+pub struct AttachedSerialConsoleRecv<'a> {
+    runtime: &'a Runtime,
+    inner: crate::AttachedSerialConsoleRecv,
+}
+
+impl AttachedSerialConsoleRecv<'_> {
+    /// Block until we receive data from the SP; see
+    /// [`AttachedSerialConsoleRecv::recv()`](crate::AttachedSerialConsoleRecv::recv).
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        self.runtime.block_on(self.inner.recv())
+    }
+}
+
+impl Iterator for AttachedSerialConsoleRecv<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockSerialConsole;
+    use crate::testing::SerialConsoleEvent;
+
+    #[test]
+    fn serial_console_reader_iterates_until_closed() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let runtime = build_runtime().unwrap();
+        let (mut mock, inner) = {
+            let _guard = runtime.enter();
+            MockSerialConsole::new(&log)
+        };
+        let console = AttachedSerialConsole { runtime: &runtime, inner };
+        let (mut tx, rx) = console.split();
+
+        runtime.block_on(async {
+            assert!(mock.send(b"hello ").await);
+            assert!(mock.send(b"world").await);
+        });
+        tx.write(b"input".to_vec()).unwrap();
+        assert_eq!(
+            runtime.block_on(mock.next_event()),
+            Some(SerialConsoleEvent::Write(b"input".to_vec()))
+        );
+
+        // Closing our end of the mock ends the iterator.
+        drop(mock);
+        let output = rx.flatten().collect::<Vec<u8>>();
+        assert_eq!(output, b"hello world");
+    }
+}
@@ -0,0 +1,337 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! An abstraction over the operations [`SingleSp`] provides, so code built on
+//! it can be tested without an SP (see `testing::MockSpClient`, available with
+//! the `testing` feature).
+
+use crate::error::CommunicationError;
+use crate::error::UpdateError;
+use crate::AttachedSerialConsole;
+use crate::SingleSp;
+use crate::SpComponentDetails;
+use crate::SpInventory;
+use crate::VersionedSpState;
+use async_trait::async_trait;
+use gateway_messages::ignition::LinkEvents;
+use gateway_messages::ignition::TransceiverSelect;
+use gateway_messages::measurement::ComponentMeasurement;
+use gateway_messages::ComponentAction;
+use gateway_messages::IgnitionCommand;
+use gateway_messages::IgnitionState;
+use gateway_messages::PowerState;
+use gateway_messages::SpComponent;
+use gateway_messages::StartupOptions;
+use gateway_messages::UpdateStatus;
+use uuid::Uuid;
+
+type Result<T, E = CommunicationError> = std::result::Result<T, E>;
+
+/// The operations we can perform on a single SP.
+///
+/// Each method behaves as the [`SingleSp`] method of the same name; see those
+/// for details.
+#[async_trait]
+pub trait SpClient: Send + Sync {
+    async fn state(&self) -> Result<VersionedSpState>;
+
+    async fn inventory(&self) -> Result<SpInventory>;
+
+    async fn component_details(
+        &self,
+        component: SpComponent,
+    ) -> Result<SpComponentDetails>;
+
+    async fn all_measurements(&self) -> Result<Vec<ComponentMeasurement>>;
+
+    async fn component_active_slot(
+        &self,
+        component: SpComponent,
+    ) -> Result<u16>;
+
+    async fn set_component_active_slot(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        persist: bool,
+    ) -> Result<()>;
+
+    async fn component_clear_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<()>;
+
+    async fn component_action(
+        &self,
+        component: SpComponent,
+        action: ComponentAction,
+    ) -> Result<()>;
+
+    async fn get_startup_options(&self) -> Result<StartupOptions>;
+
+    async fn set_startup_options(
+        &self,
+        startup_options: StartupOptions,
+    ) -> Result<()>;
+
+    async fn power_state(&self) -> Result<PowerState>;
+
+    async fn set_power_state(&self, power_state: PowerState) -> Result<()>;
+
+    async fn ignition_state(&self, target: u8) -> Result<IgnitionState>;
+
+    async fn bulk_ignition_state(&self) -> Result<Vec<IgnitionState>>;
+
+    async fn ignition_link_events(&self, target: u8) -> Result<LinkEvents>;
+
+    async fn bulk_ignition_link_events(&self) -> Result<Vec<LinkEvents>>;
+
+    async fn clear_ignition_link_events(
+        &self,
+        target: Option<u8>,
+        transceiver_select: Option<TransceiverSelect>,
+    ) -> Result<()>;
+
+    async fn ignition_command(
+        &self,
+        target: u8,
+        command: IgnitionCommand,
+    ) -> Result<()>;
+
+    async fn start_update(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+        slot: u16,
+        image: Vec<u8>,
+    ) -> Result<(), UpdateError>;
+
+    async fn update_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<UpdateStatus>;
+
+    async fn update_abort(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+    ) -> Result<()>;
+
+    async fn serial_console_attach(
+        &self,
+        component: SpComponent,
+    ) -> Result<AttachedSerialConsole>;
+
+    async fn serial_console_detach(&self) -> Result<()>;
+
+    async fn get_caboose_value(&self, key: [u8; 4]) -> Result<Vec<u8>>;
+
+    async fn read_component_caboose(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        key: [u8; 4],
+    ) -> Result<Vec<u8>>;
+
+    async fn reset_component_prepare(
+        &self,
+        component: SpComponent,
+    ) -> Result<()>;
+
+    async fn reset_component_trigger(
+        &self,
+        component: SpComponent,
+    ) -> Result<()>;
+
+    async fn send_host_nmi(&self) -> Result<()>;
+
+    async fn set_ipcc_key_lookup_value(
+        &self,
+        key: u8,
+        value: Vec<u8>,
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl SpClient for SingleSp {
+    async fn state(&self) -> Result<VersionedSpState> {
+        SingleSp::state(self).await
+    }
+
+    async fn inventory(&self) -> Result<SpInventory> {
+        SingleSp::inventory(self).await
+    }
+
+    async fn component_details(
+        &self,
+        component: SpComponent,
+    ) -> Result<SpComponentDetails> {
+        SingleSp::component_details(self, component).await
+    }
+
+    async fn all_measurements(&self) -> Result<Vec<ComponentMeasurement>> {
+        SingleSp::all_measurements(self).await
+    }
+
+    async fn component_active_slot(
+        &self,
+        component: SpComponent,
+    ) -> Result<u16> {
+        SingleSp::component_active_slot(self, component).await
+    }
+
+    async fn set_component_active_slot(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        persist: bool,
+    ) -> Result<()> {
+        SingleSp::set_component_active_slot(self, component, slot, persist)
+            .await
+    }
+
+    async fn component_clear_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        SingleSp::component_clear_status(self, component).await
+    }
+
+    async fn component_action(
+        &self,
+        component: SpComponent,
+        action: ComponentAction,
+    ) -> Result<()> {
+        SingleSp::component_action(self, component, action).await
+    }
+
+    async fn get_startup_options(&self) -> Result<StartupOptions> {
+        SingleSp::get_startup_options(self).await
+    }
+
+    async fn set_startup_options(
+        &self,
+        startup_options: StartupOptions,
+    ) -> Result<()> {
+        SingleSp::set_startup_options(self, startup_options).await
+    }
+
+    async fn power_state(&self) -> Result<PowerState> {
+        SingleSp::power_state(self).await
+    }
+
+    async fn set_power_state(&self, power_state: PowerState) -> Result<()> {
+        SingleSp::set_power_state(self, power_state).await
+    }
+
+    async fn ignition_state(&self, target: u8) -> Result<IgnitionState> {
+        SingleSp::ignition_state(self, target).await
+    }
+
+    async fn bulk_ignition_state(&self) -> Result<Vec<IgnitionState>> {
+        SingleSp::bulk_ignition_state(self).await
+    }
+
+    async fn ignition_link_events(&self, target: u8) -> Result<LinkEvents> {
+        SingleSp::ignition_link_events(self, target).await
+    }
+
+    async fn bulk_ignition_link_events(&self) -> Result<Vec<LinkEvents>> {
+        SingleSp::bulk_ignition_link_events(self).await
+    }
+
+    async fn clear_ignition_link_events(
+        &self,
+        target: Option<u8>,
+        transceiver_select: Option<TransceiverSelect>,
+    ) -> Result<()> {
+        SingleSp::clear_ignition_link_events(self, target, transceiver_select)
+            .await
+    }
+
+    async fn ignition_command(
+        &self,
+        target: u8,
+        command: IgnitionCommand,
+    ) -> Result<()> {
+        SingleSp::ignition_command(self, target, command).await
+    }
+
+    async fn start_update(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+        slot: u16,
+        image: Vec<u8>,
+    ) -> Result<(), UpdateError> {
+        SingleSp::start_update(self, component, update_id, slot, image).await
+    }
+
+    async fn update_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<UpdateStatus> {
+        SingleSp::update_status(self, component).await
+    }
+
+    async fn update_abort(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+    ) -> Result<()> {
+        SingleSp::update_abort(self, component, update_id).await
+    }
+
+    async fn serial_console_attach(
+        &self,
+        component: SpComponent,
+    ) -> Result<AttachedSerialConsole> {
+        SingleSp::serial_console_attach(self, component).await
+    }
+
+    async fn serial_console_detach(&self) -> Result<()> {
+        SingleSp::serial_console_detach(self).await
+    }
+
+    async fn get_caboose_value(&self, key: [u8; 4]) -> Result<Vec<u8>> {
+        SingleSp::get_caboose_value(self, key).await
+    }
+
+    async fn read_component_caboose(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        key: [u8; 4],
+    ) -> Result<Vec<u8>> {
+        SingleSp::read_component_caboose(self, component, slot, key).await
+    }
+
+    async fn reset_component_prepare(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        SingleSp::reset_component_prepare(self, component).await
+    }
+
+    async fn reset_component_trigger(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        SingleSp::reset_component_trigger(self, component).await
+    }
+
+    async fn send_host_nmi(&self) -> Result<()> {
+        SingleSp::send_host_nmi(self).await
+    }
+
+    async fn set_ipcc_key_lookup_value(
+        &self,
+        key: u8,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        SingleSp::set_ipcc_key_lookup_value(self, key, value).await
+    }
+}
@@ -13,6 +13,7 @@ use thiserror::Error;
 
 pub use crate::scope_id_cache::InterfaceError;
 use crate::shared_socket::SingleSpHandleError;
+use crate::SpIdentity;
 
 #[derive(Debug, Clone, Error)]
 pub enum HostPhase2Error {
@@ -38,12 +39,30 @@ pub enum CommunicationError {
     UdpSendTo { addr: SocketAddrV6, interface: String, err: io::Error },
     #[error("failed to recv UDP packet: {0}")]
     UdpRecv(io::Error),
+    /// An I/O error from an [`SpTransport`](crate::SpTransport) that isn't
+    /// based on UDP.
+    #[error("transport error: {0}")]
+    Transport(io::Error),
     #[error("no SP discovered")]
     NoSpDiscovered,
+    #[error("refusing response from {addr}: SP identity does not match")]
+    ImpostorSp { addr: SocketAddrV6 },
+    /// The two paths of a [`RedundantSp`](crate::RedundantSp) lead to SPs
+    /// with different identities.
+    #[error("SPs reached via port one and port two have different identities")]
+    PathIdentityMismatch { port_one: SpIdentity, port_two: SpIdentity },
     #[error("failed to deserialize SP message from {peer}: {err}")]
     Deserialize { peer: SocketAddrV6, err: gateway_messages::HubpackError },
     #[error("RPC call failed (gave up after {0} attempts)")]
     ExhaustedNumAttempts(usize),
+    #[error("RPC call failed (deadline of {0:?} exceeded)")]
+    DeadlineExceeded(std::time::Duration),
+    #[error("RPC call cancelled")]
+    Cancelled,
+    /// The [`SingleSp`](crate::SingleSp) this request was made through has
+    /// been shut down.
+    #[error("SP handle has been shut down")]
+    ShutDown,
     #[error("bogus SP response type: expected {expected:?} but got {got:?}")]
     BadResponseType { expected: &'static str, got: &'static str },
     #[error("Error response from SP: {0}")]
@@ -79,6 +98,12 @@ impl From<SingleSpHandleError> for CommunicationError {
     }
 }
 
+#[derive(Debug, Clone, Error)]
+pub enum DiscoveryConfigError {
+    #[error("{0} must be nonzero")]
+    ZeroInterval(&'static str),
+}
+
 #[derive(Debug, Error)]
 pub enum UpdateError {
     #[error("update image cannot be empty")]
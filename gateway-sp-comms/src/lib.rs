@@ -11,34 +11,185 @@
 //! This crate provides UDP-based communication to the `control-plane-agent`
 //! task of an SP.
 
+mod client;
 mod host_phase2;
+mod pcap;
+mod redundant_sp;
+mod rpc_policy;
 mod scope_id_cache;
 mod shared_socket;
 mod single_sp;
 mod sp_response_ext;
+mod stats;
+mod telemetry;
+mod transport;
 
 use std::net::Ipv6Addr;
 use std::net::SocketAddrV6;
+use std::time::Duration;
 
 pub use usdt::register_probes;
 
+// DTrace probes. These form a contract with scripts outside this repo: the
+// name, order, and type of existing arguments must not change. New arguments
+// may be appended, and new probes added. See the README for details and
+// examples.
+//
+// Conventions shared by all probes:
+//
+// * `interface` is the name of the interface our `SingleSp` talks over.
+// * Addresses (`peer`, `discovery_addr`) are formatted as `[addr%scope]:port`.
+// * `message_id` is the id from the header of the request we sent.
+// * `request` and `response` are names of `MgsRequest` variants (e.g.,
+//   `SpState`) and of `SpResponse` kinds (e.g., `power_state`).
+// * Durations are in microseconds.
+#[usdt::provider(provider = "gateway_sp_comms")]
+mod probes {
+    // A packet was received on a shared socket, before any parsing.
+    // `data` is a pointer to the `len` bytes of the packet (usdt doesn't allow
+    // `*const u8`).
+    fn recv_packet(_peer: &str, _data: u64, _len: u64) {}
+
+    // A request was sent (or resent) to the SP. `attempt` starts at 1 and
+    // is not incremented when resending after a busy response.
+    fn rpc_send(
+        _interface: &str,
+        _message_id: u32,
+        _request: &str,
+        _attempt: u64,
+    ) {
+    }
+
+    // A response matching an outstanding request was received. `rtt_us` is
+    // measured from the most recent send of the request.
+    fn rpc_response(
+        _interface: &str,
+        _message_id: u32,
+        _response: &str,
+        _rtt_us: u64,
+    ) {
+    }
+
+    // Attempt `attempt` of a request got no response in time; the request
+    // will be resent as attempt `attempt + 1`.
+    fn rpc_retry(_interface: &str, _message_id: u32, _attempt: u64) {}
+
+    // A request failed for lack of a response. `reason` is
+    // `exhausted_attempts` or `deadline_exceeded`.
+    fn rpc_timeout(
+        _interface: &str,
+        _message_id: u32,
+        _attempts: u64,
+        _reason: &str,
+    ) {
+    }
+
+    // The SP reported it was busy; we'll resend after `backoff_us`.
+    fn rpc_sp_busy(_interface: &str, _message_id: u32, _backoff_us: u64) {}
+
+    // We're sending a discovery request (fired before the corresponding
+    // `rpc_send`).
+    fn discovery_attempt(_interface: &str, _discovery_addr: &str) {}
+
+    // Serial console data from the SP was forwarded to our attached client.
+    fn serial_console_forwarded(
+        _interface: &str,
+        _component: &str,
+        _offset: u64,
+        _len: u64,
+    ) {
+    }
+
+    // Serial console data from the SP was discarded. `reason` is
+    // `buffer_full` or `no_receiver`.
+    fn serial_console_dropped(
+        _interface: &str,
+        _component: &str,
+        _offset: u64,
+        _len: u64,
+        _reason: &str,
+    ) {
+    }
+
+    // We sent `len` bytes of host phase 2 data to an SP. `hash` is the
+    // hex-encoded hash of the requested image.
+    fn host_phase2_served(_peer: &str, _hash: &str, _offset: u64, _len: u64) {}
+
+    // The SP acked a chunk of an update. `update_id` is the update's UUID.
+    fn update_chunk_sent(
+        _update_id: &str,
+        _component: &str,
+        _offset: u64,
+        _len: u64,
+    ) {
+    }
+}
+
+// Name of the `MgsRequest` variant of `request`, for probe arguments and
+// per-request stats. Requests are small `Copy` types, so going through `Debug`
+// is cheap enough.
+fn request_name(request: &gateway_messages::MgsRequest) -> String {
+    let mut name = format!("{request:?}");
+    let end = name
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(name.len());
+    name.truncate(end);
+    name
+}
+
+pub mod blocking;
 pub mod error;
+pub mod session;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+pub use client::SpClient;
 pub use gateway_messages;
 pub use gateway_messages::SpStateV1;
 pub use gateway_messages::SpStateV2;
+pub use gateway_messages::VersionedSpState;
 pub use host_phase2::HostPhase2ImageError;
 pub use host_phase2::HostPhase2Provider;
 pub use host_phase2::InMemoryHostPhase2Provider;
+pub use redundant_sp::PathHealth;
+pub use redundant_sp::PathStatus;
+pub use redundant_sp::RedundantSp;
+pub use rpc_policy::BusyBackoff;
+pub use rpc_policy::CallOptions;
+pub use rpc_policy::RequestKind;
+pub use rpc_policy::RpcPolicies;
+pub use rpc_policy::RpcPolicy;
+pub use shared_socket::parse_packet;
 pub use shared_socket::BindError;
+pub use shared_socket::DiscoveredSp;
+pub use shared_socket::ParsePacketError;
 pub use shared_socket::SharedSocket;
+pub use shared_socket::SingleSpMessage;
+pub use single_sp::decode_tlv_page;
 pub use single_sp::AttachedSerialConsole;
 pub use single_sp::AttachedSerialConsoleRecv;
 pub use single_sp::AttachedSerialConsoleSend;
+pub use single_sp::HostPhase2Request;
 pub use single_sp::SingleSp;
 pub use single_sp::SpComponentDetails;
 pub use single_sp::SpDevice;
+pub use single_sp::SpHealth;
+pub use single_sp::SpIdentity;
+pub use single_sp::SpIdentityEvent;
 pub use single_sp::SpInventory;
+pub use single_sp::TlvEntries;
+pub use stats::LatencyHistogram;
+pub use stats::RpcStats;
+pub use stats::SharedSocketStats;
+pub use stats::SingleSpStats;
+pub use telemetry::MetricKey;
+pub use telemetry::MetricValue;
+pub use telemetry::TelemetrySample;
+pub use telemetry::TelemetrySampler;
+pub use telemetry::TelemetrySchedule;
+pub use telemetry::TelemetrySnapshot;
+pub use transport::SpTransport;
+pub use transport::UdpTransport;
 
 const SP_TO_MGS_MULTICAST_ADDR: Ipv6Addr =
     Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x1de, 1);
@@ -57,16 +208,182 @@ pub fn default_discovery_addr() -> SocketAddrV6 {
 #[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct SwitchPortConfig {
     /// Discovery address used to find the SP connected to this port.
+    ///
+    /// This is typically a multicast address, in which case we talk to
+    /// whichever SP responds on `interface`. If it is instead the (unicast)
+    /// address of a specific SP, we only talk to that SP; this allows multiple
+    /// SPs reachable via the same interface to be addressed individually (see
+    /// [`SharedSocket::discover_all()`]).
     #[serde(default = "default_discovery_addr")]
     pub discovery_addr: SocketAddrV6,
 
     /// Name of the interface for this switch port. The interface should be
     /// bound to the correct VLAN tag for this port per RFD 250.
     pub interface: String,
+
+    /// How often we send discovery packets to the SP on this port.
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+}
+
+/// Configuration of how a [`SingleSp`] discovers (and rediscovers) its SP.
+///
+/// Built via [`DiscoveryConfig::new()`] (or deserialized), either of which
+/// rejects zero intervals.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    initial_retry_interval: Duration,
+    max_retry_interval: Duration,
+    #[serde(deserialize_with = "deserialize_nonzero_interval")]
+    idle_interval: Duration,
+    aggressive_after_failures: Option<usize>,
+    #[serde(deserialize_with = "deserialize_nonzero_interval")]
+    aggressive_interval: Duration,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
-pub enum VersionedSpState {
-    V1(SpStateV1),
-    V2(SpStateV2),
+impl DiscoveryConfig {
+    /// Build a new `DiscoveryConfig`; see the accessors of the same names for
+    /// the meaning of each argument.
+    ///
+    /// Fails if `idle_interval` or `aggressive_interval` is zero.
+    pub fn new(
+        initial_retry_interval: Duration,
+        max_retry_interval: Duration,
+        idle_interval: Duration,
+        aggressive_after_failures: Option<usize>,
+        aggressive_interval: Duration,
+    ) -> Result<Self, error::DiscoveryConfigError> {
+        if idle_interval.is_zero() {
+            return Err(error::DiscoveryConfigError::ZeroInterval(
+                "idle_interval",
+            ));
+        }
+        if aggressive_interval.is_zero() {
+            return Err(error::DiscoveryConfigError::ZeroInterval(
+                "aggressive_interval",
+            ));
+        }
+        Ok(Self {
+            initial_retry_interval,
+            max_retry_interval,
+            idle_interval,
+            aggressive_after_failures,
+            aggressive_interval,
+        })
+    }
+
+    /// How long to wait before retrying if initial discovery fails. This
+    /// doubles after each consecutive failure, up to `max_retry_interval`.
+    pub fn initial_retry_interval(&self) -> Duration {
+        self.initial_retry_interval
+    }
+
+    /// Upper bound on the interval between initial discovery attempts.
+    pub fn max_retry_interval(&self) -> Duration {
+        self.max_retry_interval
+    }
+
+    /// Once we've discovered our SP, how long we wait without any traffic to
+    /// or from it before sending another discovery packet to detect changes.
+    pub fn idle_interval(&self) -> Duration {
+        self.idle_interval
+    }
+
+    /// If this many consecutive RPCs to our SP get no response (e.g., because
+    /// it's resetting), send a discovery packet every `aggressive_interval`
+    /// until it responds again. `None` disables aggressive rediscovery.
+    pub fn aggressive_after_failures(&self) -> Option<usize> {
+        self.aggressive_after_failures
+    }
+
+    /// Interval between discovery packets while rediscovering aggressively.
+    pub fn aggressive_interval(&self) -> Duration {
+        self.aggressive_interval
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            initial_retry_interval: Duration::from_secs(1),
+            max_retry_interval: Duration::from_secs(10),
+            idle_interval: Duration::from_secs(60),
+            aggressive_after_failures: Some(2),
+            aggressive_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+// Discovery intervals drive `tokio::time::interval`s, which panic on a zero
+// period.
+fn deserialize_nonzero_interval<'de, D>(
+    deserializer: D,
+) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let interval = <Duration as serde::Deserialize>::deserialize(deserializer)?;
+    if interval.is_zero() {
+        return Err(serde::de::Error::custom("interval must be nonzero"));
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_config_rejects_zero_intervals() {
+        let config: DiscoveryConfig = serde_json::from_str(
+            r#"{"idle_interval": {"secs": 30, "nanos": 0}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.idle_interval, Duration::from_secs(30));
+        assert_eq!(
+            config.aggressive_interval,
+            DiscoveryConfig::default().aggressive_interval
+        );
+
+        for field in ["idle_interval", "aggressive_interval"] {
+            let json = format!(r#"{{"{field}": {{"secs": 0, "nanos": 0}}}}"#);
+            let err =
+                serde_json::from_str::<DiscoveryConfig>(&json).unwrap_err();
+            assert!(
+                err.to_string().contains("interval must be nonzero"),
+                "unexpected error for {field}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn discovery_config_new_rejects_zero_intervals() {
+        let secs = Duration::from_secs;
+        let config =
+            DiscoveryConfig::new(secs(1), secs(10), secs(60), None, secs(1))
+                .unwrap();
+        assert_eq!(config.idle_interval(), secs(60));
+        assert_eq!(config.aggressive_after_failures(), None);
+
+        let err = DiscoveryConfig::new(
+            secs(1),
+            secs(10),
+            Duration::ZERO,
+            None,
+            secs(1),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "idle_interval must be nonzero");
+
+        let err = DiscoveryConfig::new(
+            secs(1),
+            secs(10),
+            secs(60),
+            Some(2),
+            Duration::ZERO,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "aggressive_interval must be nonzero");
+    }
 }
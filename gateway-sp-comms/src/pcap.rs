@@ -0,0 +1,507 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Packet capture of the traffic on a [`SharedSocket`](crate::SharedSocket)
+//! in pcapng format.
+//!
+//! We only see UDP payloads, so each captured datagram is wrapped in a
+//! synthesized IPv6 + UDP header (link type `LINKTYPE_IPV6`) that records the
+//! peer and our local port; this lets standard tools (e.g., Wireshark) decode
+//! the capture as if it had been taken on the link. Each interface gets its
+//! own pcapng interface, named after the management network interface the
+//! packet was sent or received on, and each packet's direction is recorded in
+//! its `epb_flags` option.
+
+use crate::scope_id_cache::ScopeIdCache;
+use crate::session::Direction;
+use slog::warn;
+use slog::Logger;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddrV6;
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const EPB_FLAGS_INBOUND: u32 = 1;
+const EPB_FLAGS_OUTBOUND: u32 = 2;
+
+const LINKTYPE_IPV6: u16 = 229;
+const IPPROTO_UDP: u8 = 17;
+
+/// Writes packets to a pcapng stream.
+struct PcapngWriter {
+    out: Box<dyn Write + Send>,
+    // Names of the interfaces we've written interface description blocks for;
+    // a name's index is its pcapng interface ID.
+    interfaces: Vec<String>,
+}
+
+// `out` isn't `Debug`.
+impl std::fmt::Debug for PcapngWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PcapngWriter")
+            .field("interfaces", &self.interfaces)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PcapngWriter {
+    fn new(mut out: Box<dyn Write + Send>) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // unknown length
+        write_block(&mut out, BLOCK_SECTION_HEADER, &body)?;
+        out.flush()?;
+        Ok(Self { out, interfaces: Vec::new() })
+    }
+
+    fn interface_id(&mut self, interface: &str) -> io::Result<u32> {
+        if let Some(i) = self.interfaces.iter().position(|i| i == interface) {
+            return Ok(i as u32);
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_IPV6.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        body.extend_from_slice(&0u32.to_le_bytes()); // no snap length
+        push_option(&mut body, OPT_IF_NAME, interface.as_bytes());
+        push_option(&mut body, OPT_END_OF_OPT, &[]);
+        write_block(&mut self.out, BLOCK_INTERFACE_DESCRIPTION, &body)?;
+
+        self.interfaces.push(interface.to_string());
+        Ok(self.interfaces.len() as u32 - 1)
+    }
+
+    fn write_packet(
+        &mut self,
+        interface: &str,
+        direction: Direction,
+        timestamp: SystemTime,
+        packet: &[u8],
+    ) -> io::Result<()> {
+        let interface_id = self.interface_id(interface)?;
+
+        // Timestamps default to microsecond resolution.
+        let micros = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        let mut body = Vec::with_capacity(packet.len() + 40);
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        pad_to_u32(&mut body);
+        let flags = match direction {
+            Direction::ToSp => EPB_FLAGS_OUTBOUND,
+            Direction::FromSp => EPB_FLAGS_INBOUND,
+        };
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_END_OF_OPT, &[]);
+        write_block(&mut self.out, BLOCK_ENHANCED_PACKET, &body)?;
+
+        self.out.flush()
+    }
+}
+
+fn write_block<W: Write + ?Sized>(
+    out: &mut W,
+    block_type: u32,
+    body: &[u8],
+) -> io::Result<()> {
+    // Block type, block length, body, and block length again.
+    let len = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&len.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad_to_u32(body);
+}
+
+fn pad_to_u32(body: &mut Vec<u8>) {
+    while body.len() % 4 != 0 {
+        body.push(0);
+    }
+}
+
+/// Build an IPv6 packet containing a UDP datagram with `payload`.
+fn ipv6_udp_packet(
+    src: SocketAddrV6,
+    dst: SocketAddrV6,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = (payload.len() + 8) as u16;
+
+    let mut packet = Vec::with_capacity(usize::from(udp_len) + 40);
+    packet.extend_from_slice(&[0x60, 0, 0, 0]); // version 6, no class/label
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.push(IPPROTO_UDP);
+    packet.push(64); // hop limit
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dst.ip().octets());
+
+    let checksum = udp_checksum(src, dst, payload);
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dst.port().to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// UDP checksums are mandatory over IPv6 (RFC 8200 § 8.1).
+fn udp_checksum(src: SocketAddrV6, dst: SocketAddrV6, payload: &[u8]) -> u16 {
+    let udp_len = (payload.len() + 8) as u32;
+
+    let mut sum = 0u32;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let hi = u32::from(chunk[0]) << 8;
+            let lo = chunk.get(1).copied().map_or(0, u32::from);
+            sum += hi | lo;
+        }
+    };
+
+    // Pseudo-header.
+    add(&src.ip().octets());
+    add(&dst.ip().octets());
+    add(&udp_len.to_be_bytes());
+    add(&[0, 0, 0, IPPROTO_UDP]);
+
+    // UDP header (with a zero checksum) and payload.
+    add(&src.port().to_be_bytes());
+    add(&dst.port().to_be_bytes());
+    add(&(udp_len as u16).to_be_bytes());
+    add(payload);
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    match !(sum as u16) {
+        // A computed checksum of 0 is transmitted as all ones.
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
+
+/// Tap shared by a `SharedSocket`, its receive task, and all its
+/// `SingleSpHandle`s, through which every datagram passes; it only writes
+/// anything while a capture is running.
+///
+/// Packets are written by a dedicated thread, so capturing a packet never
+/// blocks the task that sent or received it on file I/O.
+#[derive(Debug)]
+pub(crate) struct PacketTap {
+    local_addr: SocketAddrV6,
+    scope_id_cache: Arc<ScopeIdCache>,
+    capture: Mutex<Option<Capture>>,
+    log: Logger,
+}
+
+/// A running capture: the channel to its writer thread and the thread itself.
+#[derive(Debug)]
+struct Capture {
+    packets_tx: std_mpsc::Sender<CapturedPacket>,
+    writer: thread::JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct CapturedPacket {
+    interface: String,
+    direction: Direction,
+    timestamp: SystemTime,
+    packet: Vec<u8>,
+}
+
+impl PacketTap {
+    pub(crate) fn new(
+        local_addr: SocketAddrV6,
+        scope_id_cache: Arc<ScopeIdCache>,
+        log: Logger,
+    ) -> Self {
+        Self { local_addr, scope_id_cache, capture: Mutex::default(), log }
+    }
+
+    /// Start writing packets to `out`, replacing any previous capture.
+    ///
+    /// The pcapng section header is written before this returns, so a
+    /// capture that can't be written at all fails here.
+    pub(crate) fn start(&self, out: Box<dyn Write + Send>) -> io::Result<()> {
+        let writer = PcapngWriter::new(out)?;
+        let (packets_tx, packets_rx) = std_mpsc::channel();
+        let log = self.log.clone();
+        let writer =
+            thread::spawn(move || write_packets(writer, packets_rx, &log));
+        *self.capture.lock().unwrap() = Some(Capture { packets_tx, writer });
+        Ok(())
+    }
+
+    /// Stop the current capture, if any. Packets already captured are still
+    /// written; this doesn't wait for that to finish.
+    pub(crate) fn stop(&self) {
+        self.take_writer();
+    }
+
+    // Stop the current capture and return its writer thread, which exits
+    // once it has written every packet already captured.
+    fn take_writer(&self) -> Option<thread::JoinHandle<()>> {
+        self.capture.lock().unwrap().take().map(|capture| capture.writer)
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.capture.lock().unwrap().is_some()
+    }
+
+    /// Capture one datagram sent to or received from `peer`.
+    pub(crate) async fn record(
+        &self,
+        direction: Direction,
+        peer: SocketAddrV6,
+        payload: &[u8],
+    ) {
+        if !self.is_capturing() {
+            return;
+        }
+
+        let timestamp = SystemTime::now();
+        let interface =
+            match self.scope_id_cache.index_to_name(peer.scope_id()).await {
+                Ok(name) => name.to_string(),
+                Err(_) => format!("scope-id-{}", peer.scope_id()),
+            };
+        let (src, dst) = match direction {
+            Direction::ToSp => (self.local_addr, peer),
+            Direction::FromSp => (peer, self.local_addr),
+        };
+        let packet = CapturedPacket {
+            interface,
+            direction,
+            timestamp,
+            packet: ipv6_udp_packet(src, dst, payload),
+        };
+
+        // Sending only fails if our writer has stopped after failing to
+        // write (which it has already logged), in which case the capture is
+        // over.
+        let mut capture = self.capture.lock().unwrap();
+        if let Some(c) = capture.as_ref() {
+            if c.packets_tx.send(packet).is_err() {
+                *capture = None;
+            }
+        }
+    }
+}
+
+fn write_packets(
+    mut writer: PcapngWriter,
+    packets_rx: std_mpsc::Receiver<CapturedPacket>,
+    log: &Logger,
+) {
+    for p in packets_rx {
+        if let Err(err) = writer.write_packet(
+            &p.interface,
+            p.direction,
+            p.timestamp,
+            &p.packet,
+        ) {
+            warn!(
+                log, "packet capture failed; stopping capture";
+                "err" => %err,
+            );
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scope_id_cache::InterfaceError;
+
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Split a pcapng stream into its blocks' types and bodies, checking that
+    // each block's leading and trailing lengths agree.
+    fn read_blocks(mut buf: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        while !buf.is_empty() {
+            let block_type = u32_at(buf, 0);
+            let len = u32_at(buf, 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(buf, len - 4) as usize, len);
+            blocks.push((block_type, &buf[8..len - 4]));
+            buf = &buf[len..];
+        }
+        blocks
+    }
+
+    // Find the value of option `code` in a block's options.
+    fn option(mut options: &[u8], code: u16) -> Option<&[u8]> {
+        loop {
+            let (c, len) =
+                (u16_at(options, 0), usize::from(u16_at(options, 2)));
+            if c == OPT_END_OF_OPT {
+                return None;
+            }
+            if c == code {
+                return Some(&options[4..4 + len]);
+            }
+            options = &options[4 + (len + 3) / 4 * 4..];
+        }
+    }
+
+    #[tokio::test]
+    async fn captured_packets_round_trip() {
+        let local_addr = "[fe80::1]:11111".parse().unwrap();
+        let sp_addr =
+            SocketAddrV6::new("fe80::2".parse().unwrap(), crate::SP_PORT, 0, 1);
+        let scope_id_cache = ScopeIdCache::with_lookups(
+            |_| Err(InterfaceError::NoNameFound(0)),
+            |index| match index {
+                1 => Ok("sled0".to_string()),
+                _ => Err(InterfaceError::NoNameFound(index)),
+            },
+        );
+        let tap = PacketTap::new(
+            local_addr,
+            Arc::new(scope_id_cache),
+            Logger::root(slog::Discard, slog::o!()),
+        );
+
+        let out = SharedBuf::default();
+        tap.start(Box::new(out.clone())).unwrap();
+        tap.record(Direction::ToSp, sp_addr, b"request").await;
+        tap.record(Direction::FromSp, sp_addr, b"response!").await;
+        tap.take_writer().unwrap().join().unwrap();
+
+        // Nothing is captured once the capture has stopped.
+        tap.record(Direction::ToSp, sp_addr, b"ignored").await;
+
+        let out = out.0.lock().unwrap();
+        let blocks = read_blocks(&out);
+        assert_eq!(blocks.len(), 4);
+
+        let (block_type, shb) = blocks[0];
+        assert_eq!(block_type, BLOCK_SECTION_HEADER);
+        assert_eq!(u32_at(shb, 0), BYTE_ORDER_MAGIC);
+
+        // Both packets share the interface described by the one IDB.
+        let (block_type, idb) = blocks[1];
+        assert_eq!(block_type, BLOCK_INTERFACE_DESCRIPTION);
+        assert_eq!(u16_at(idb, 0), LINKTYPE_IPV6);
+        assert_eq!(option(&idb[8..], OPT_IF_NAME), Some(&b"sled0"[..]));
+
+        let expected = [
+            (
+                ipv6_udp_packet(local_addr, sp_addr, b"request"),
+                EPB_FLAGS_OUTBOUND,
+            ),
+            (
+                ipv6_udp_packet(sp_addr, local_addr, b"response!"),
+                EPB_FLAGS_INBOUND,
+            ),
+        ];
+        for (&(block_type, epb), (packet, flags)) in
+            blocks[2..].iter().zip(&expected)
+        {
+            assert_eq!(block_type, BLOCK_ENHANCED_PACKET);
+            assert_eq!(u32_at(epb, 0), 0); // interface ID
+            let caplen = u32_at(epb, 12) as usize;
+            assert_eq!(caplen, packet.len());
+            assert_eq!(u32_at(epb, 16) as usize, packet.len());
+            assert_eq!(&epb[20..20 + caplen], &packet[..]);
+            let options = &epb[20 + (caplen + 3) / 4 * 4..];
+            assert_eq!(
+                option(options, OPT_EPB_FLAGS),
+                Some(&flags.to_le_bytes()[..])
+            );
+        }
+    }
+
+    #[test]
+    fn udp_checksum_verifies() {
+        let src = "[fe80::1]:11111".parse().unwrap();
+        let dst = "[fe80::2]:22222".parse().unwrap();
+        let packet = ipv6_udp_packet(src, dst, b"hello");
+        assert_eq!(packet.len(), 40 + 8 + 5);
+
+        // Summing the pseudo-header and UDP datagram including the checksum
+        // should yield all ones.
+        let mut sum = 0u32;
+        let mut add = |bytes: &[u8]| {
+            for chunk in bytes.chunks(2) {
+                let hi = u32::from(chunk[0]) << 8;
+                let lo = chunk.get(1).copied().map_or(0, u32::from);
+                sum += hi | lo;
+            }
+        };
+        add(&packet[8..40]);
+        add(&13u32.to_be_bytes());
+        add(&[0, 0, 0, IPPROTO_UDP]);
+        add(&packet[40..]);
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        assert_eq!(sum, 0xffff);
+    }
+
+    #[test]
+    fn pcapng_blocks_are_well_formed() {
+        let mut out = Vec::new();
+        write_block(&mut out, BLOCK_ENHANCED_PACKET, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(out, [6, 0, 0, 0, 16, 0, 0, 0, 1, 2, 3, 4, 16, 0, 0, 0]);
+
+        let mut body = Vec::new();
+        push_option(&mut body, OPT_IF_NAME, b"sidecar0");
+        push_option(&mut body, OPT_END_OF_OPT, &[]);
+        assert_eq!(body.len() % 4, 0);
+        assert_eq!(&body[..4], &[2, 0, 8, 0]);
+        assert_eq!(&body[12..], &[0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,416 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Communication with a single SP over both of its management network ports.
+
+use crate::error::CommunicationError;
+use crate::SingleSp;
+use crate::SpIdentity;
+use futures::future::BoxFuture;
+use gateway_messages::SpPort;
+use slog::debug;
+use slog::warn;
+use slog::Logger;
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+type Result<T, E = CommunicationError> = std::result::Result<T, E>;
+
+/// Health of one path to an SP, as observed by the RPCs we've sent over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathHealth {
+    /// We haven't sent any RPCs over this path yet.
+    Unknown,
+    /// The most recent RPC over this path got a response from the SP.
+    Healthy,
+    /// The most recent RPC over this path got no response from the SP.
+    Unhealthy,
+}
+
+/// Status of one path to an SP; see [`RedundantSp::path_status()`].
+#[derive(Debug, Clone)]
+pub struct PathStatus {
+    /// The SP port this path is expected to reach.
+    pub sp_port: SpPort,
+    /// The interface of the [`SingleSp`] for this path.
+    pub interface: String,
+    /// Whether this is the path we're currently sending RPCs over.
+    pub active: bool,
+    /// The identity of the SP this path has discovered, if any.
+    pub identity: Option<SpIdentity>,
+    pub health: PathHealth,
+    pub consecutive_failures: usize,
+    pub last_success: Option<Instant>,
+    pub last_failure: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PathState {
+    consecutive_failures: usize,
+    last_success: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl PathState {
+    fn health(&self) -> PathHealth {
+        match (self.last_success, self.last_failure) {
+            (None, None) => PathHealth::Unknown,
+            (Some(_), None) => PathHealth::Healthy,
+            (None, Some(_)) => PathHealth::Unhealthy,
+            (Some(success), Some(failure)) => {
+                if success > failure {
+                    PathHealth::Healthy
+                } else {
+                    PathHealth::Unhealthy
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    active: SpPort,
+    port_one: PathState,
+    port_two: PathState,
+}
+
+impl State {
+    fn path_mut(&mut self, port: SpPort) -> &mut PathState {
+        match port {
+            SpPort::One => &mut self.port_one,
+            SpPort::Two => &mut self.port_two,
+        }
+    }
+}
+
+/// `RedundantSp` communicates with a single SP over both of its management
+/// network ports (i.e., via both switches), so that losing one switch doesn't
+/// leave us unable to reach the SP.
+///
+/// RPCs are sent over one path at a time (initially the path to
+/// [`SpPort::One`]). If an RPC fails because the SP could not be reached on
+/// that path, it is retried over the other path, which becomes the active path
+/// if it succeeds. We do not switch back to the original path until the new
+/// active path fails in turn.
+///
+/// Each path pins the identity of the SP it discovers. If the two paths have
+/// pinned different identities (e.g., due to miscabling), every RPC fails with
+/// [`CommunicationError::PathIdentityMismatch`] rather than risk sending it to
+/// the wrong SP.
+///
+/// Note that an RPC that fails over may have been executed twice by the SP
+/// (e.g., if the SP received our request via the first path but all of its
+/// responses were lost); this is the same risk `SingleSp` already takes when
+/// it retries a request, and SP requests are designed to tolerate it.
+#[derive(Debug)]
+pub struct RedundantSp {
+    port_one: SingleSp,
+    port_two: SingleSp,
+    state: Mutex<State>,
+    log: Logger,
+}
+
+impl RedundantSp {
+    /// Construct a `RedundantSp` from two `SingleSp`s that reach the same SP:
+    /// `port_one` should be attached to the switch connected to the SP's
+    /// [`SpPort::One`], and `port_two` to the switch connected to its
+    /// [`SpPort::Two`].
+    pub fn new(port_one: SingleSp, port_two: SingleSp, log: Logger) -> Self {
+        let path = PathState {
+            consecutive_failures: 0,
+            last_success: None,
+            last_failure: None,
+        };
+        let state =
+            State { active: SpPort::One, port_one: path, port_two: path };
+        Self { port_one, port_two, state: Mutex::new(state), log }
+    }
+
+    /// Get the `SingleSp` for the path to `port`.
+    ///
+    /// This is useful for operations that aren't simple RPCs (e.g., attaching
+    /// to the serial console), but RPCs sent directly through the returned
+    /// `SingleSp` do not fail over or contribute to path health.
+    pub fn path(&self, port: SpPort) -> &SingleSp {
+        match port {
+            SpPort::One => &self.port_one,
+            SpPort::Two => &self.port_two,
+        }
+    }
+
+    /// The SP port of the path we're currently sending RPCs over.
+    pub fn active_port(&self) -> SpPort {
+        self.state.lock().unwrap().active
+    }
+
+    /// Report the status of both paths to our SP.
+    pub fn path_status(&self) -> [PathStatus; 2] {
+        let state = self.state.lock().unwrap();
+        let status = |port, path: &PathState| PathStatus {
+            sp_port: port,
+            interface: self.path(port).interface().to_string(),
+            active: state.active == port,
+            identity: self.path(port).identity(),
+            health: path.health(),
+            consecutive_failures: path.consecutive_failures,
+            last_success: path.last_success,
+            last_failure: path.last_failure,
+        };
+        [
+            status(SpPort::One, &state.port_one),
+            status(SpPort::Two, &state.port_two),
+        ]
+    }
+
+    /// Perform an operation against our SP, failing over to the other path if
+    /// the SP can't be reached on the active path.
+    ///
+    /// `f` may be called twice (once per path), e.g.:
+    ///
+    /// ```ignore
+    /// let state = redundant_sp.call(|sp| sp.state().boxed()).await?;
+    /// ```
+    pub async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        F: for<'a> Fn(&'a SingleSp) -> BoxFuture<'a, Result<T>>,
+    {
+        self.check_identities()?;
+
+        let primary = self.active_port();
+        let result = f(self.path(primary)).await;
+        if !is_path_failure(&result) {
+            self.record_success(primary);
+            return result;
+        }
+        self.record_failure(primary);
+
+        let secondary = match primary {
+            SpPort::One => SpPort::Two,
+            SpPort::Two => SpPort::One,
+        };
+        debug!(
+            self.log, "SP unreachable on active path; trying other path";
+            "active_port" => ?primary,
+            "err" => %result.as_ref().err().unwrap(),
+        );
+
+        let result = f(self.path(secondary)).await;
+        if is_path_failure(&result) {
+            self.record_failure(secondary);
+        } else {
+            self.record_success(secondary);
+            self.state.lock().unwrap().active = secondary;
+            warn!(
+                self.log, "failed over to other path to SP";
+                "old_port" => ?primary,
+                "new_port" => ?secondary,
+            );
+        }
+        result
+    }
+
+    // Ensure both paths lead to the same SP. A path that hasn't discovered its
+    // SP yet can't disagree with the other.
+    fn check_identities(&self) -> Result<()> {
+        match (self.port_one.identity(), self.port_two.identity()) {
+            (Some(port_one), Some(port_two)) if port_one != port_two => {
+                warn!(
+                    self.log, "paths to SP reach different SPs";
+                    "port_one" => ?port_one,
+                    "port_two" => ?port_two,
+                );
+                Err(CommunicationError::PathIdentityMismatch {
+                    port_one,
+                    port_two,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_success(&self, port: SpPort) {
+        let mut state = self.state.lock().unwrap();
+        let path = state.path_mut(port);
+        path.consecutive_failures = 0;
+        path.last_success = Some(Instant::now());
+    }
+
+    fn record_failure(&self, port: SpPort) {
+        let mut state = self.state.lock().unwrap();
+        let path = state.path_mut(port);
+        path.consecutive_failures += 1;
+        path.last_failure = Some(Instant::now());
+    }
+}
+
+// Did `result` fail because we couldn't reach the SP? Any other result
+// (including error responses from the SP) means the path is working.
+fn is_path_failure<T>(result: &Result<T>) -> bool {
+    matches!(
+        result,
+        Err(CommunicationError::ExhaustedNumAttempts(_)
+            | CommunicationError::NoSpDiscovered)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SimulatedSp;
+    use crate::DiscoveryConfig;
+    use crate::RpcPolicies;
+    use futures::FutureExt;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Build a `SingleSp` talking to a `SimulatedSp` reporting
+    // `serial_number`, returning a flag controlling whether it's reachable.
+    fn simulated_path(
+        sp_port: SpPort,
+        serial_number: u8,
+        reachable: bool,
+    ) -> (SingleSp, Arc<AtomicBool>) {
+        let interface = match sp_port {
+            SpPort::One => "sidecar0",
+            SpPort::Two => "sidecar1",
+        };
+        let transport = SimulatedSp::new(interface, sp_port, serial_number);
+        let reachable_flag = transport.reachable();
+        reachable_flag.store(reachable, Ordering::SeqCst);
+        let defaults = DiscoveryConfig::default();
+        let discovery = DiscoveryConfig::new(
+            Duration::from_millis(10),
+            Duration::from_millis(10),
+            defaults.idle_interval(),
+            defaults.aggressive_after_failures(),
+            defaults.aggressive_interval(),
+        )
+        .unwrap();
+        let sp = SingleSp::with_transport(
+            transport,
+            discovery,
+            RpcPolicies::new(2, Duration::from_millis(50)),
+        );
+        (sp, reachable_flag)
+    }
+
+    fn redundant_sp(port_one: SingleSp, port_two: SingleSp) -> RedundantSp {
+        RedundantSp::new(
+            port_one,
+            port_two,
+            Logger::root(slog::Discard, slog::o!()),
+        )
+    }
+
+    async fn sp_serial_number(redundant_sp: &RedundantSp) -> Result<u8> {
+        let state = redundant_sp.call(|sp| sp.state().boxed()).await?;
+        Ok(SpIdentity::from(&state).serial_number[0])
+    }
+
+    async fn wait_for_identity(sp: &SingleSp) {
+        while sp.identity().is_none() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_active_path_while_it_is_healthy() {
+        let (port_one, _) = simulated_path(SpPort::One, 1, true);
+        let (port_two, _) = simulated_path(SpPort::Two, 1, true);
+        let redundant_sp = redundant_sp(port_one, port_two);
+
+        assert_eq!(sp_serial_number(&redundant_sp).await.unwrap(), 1);
+        assert_eq!(redundant_sp.active_port(), SpPort::One);
+
+        let [one, two] = redundant_sp.path_status();
+        assert!(one.active);
+        assert_eq!(one.health, PathHealth::Healthy);
+        assert_eq!(one.interface, "sidecar0");
+        assert!(!two.active);
+        assert_eq!(two.health, PathHealth::Unknown);
+    }
+
+    #[tokio::test]
+    async fn fails_over_when_active_path_is_unreachable() {
+        let (port_one, one_reachable) = simulated_path(SpPort::One, 1, true);
+        let (port_two, two_reachable) = simulated_path(SpPort::Two, 1, true);
+        let redundant_sp = redundant_sp(port_one, port_two);
+        wait_for_identity(redundant_sp.path(SpPort::One)).await;
+        wait_for_identity(redundant_sp.path(SpPort::Two)).await;
+
+        // Losing port one moves us to port two...
+        one_reachable.store(false, Ordering::SeqCst);
+        assert_eq!(sp_serial_number(&redundant_sp).await.unwrap(), 1);
+        assert_eq!(redundant_sp.active_port(), SpPort::Two);
+        let [one, two] = redundant_sp.path_status();
+        assert_eq!(one.health, PathHealth::Unhealthy);
+        assert_eq!(one.consecutive_failures, 1);
+        assert_eq!(two.health, PathHealth::Healthy);
+        assert!(two.active);
+
+        // ...and we stay there even once port one recovers.
+        one_reachable.store(true, Ordering::SeqCst);
+        assert_eq!(sp_serial_number(&redundant_sp).await.unwrap(), 1);
+        assert_eq!(redundant_sp.active_port(), SpPort::Two);
+
+        // Losing port two moves us back.
+        two_reachable.store(false, Ordering::SeqCst);
+        assert_eq!(sp_serial_number(&redundant_sp).await.unwrap(), 1);
+        assert_eq!(redundant_sp.active_port(), SpPort::One);
+        let [one, two] = redundant_sp.path_status();
+        assert_eq!(one.health, PathHealth::Healthy);
+        assert_eq!(one.consecutive_failures, 0);
+        assert_eq!(two.health, PathHealth::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn fails_when_both_paths_are_unreachable() {
+        let (port_one, _) = simulated_path(SpPort::One, 1, false);
+        let (port_two, _) = simulated_path(SpPort::Two, 1, false);
+        let redundant_sp = redundant_sp(port_one, port_two);
+
+        let err = sp_serial_number(&redundant_sp).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                CommunicationError::ExhaustedNumAttempts(_)
+                    | CommunicationError::NoSpDiscovered
+            ),
+            "unexpected error {err:?}"
+        );
+        assert_eq!(redundant_sp.active_port(), SpPort::One);
+        for path in redundant_sp.path_status() {
+            assert_eq!(path.health, PathHealth::Unhealthy);
+            assert_eq!(path.consecutive_failures, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_paths_to_different_sps() {
+        let (port_one, _) = simulated_path(SpPort::One, 1, true);
+        let (port_two, _) = simulated_path(SpPort::Two, 2, true);
+        let redundant_sp = redundant_sp(port_one, port_two);
+        wait_for_identity(redundant_sp.path(SpPort::One)).await;
+        wait_for_identity(redundant_sp.path(SpPort::Two)).await;
+
+        let err = sp_serial_number(&redundant_sp).await.unwrap_err();
+        match err {
+            CommunicationError::PathIdentityMismatch { port_one, port_two } => {
+                assert_eq!(port_one.serial_number, [1; 32]);
+                assert_eq!(port_two.serial_number, [2; 32]);
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+
+        // We didn't send the RPC over either path.
+        for path in redundant_sp.path_status() {
+            assert_eq!(path.health, PathHealth::Unknown);
+        }
+    }
+}
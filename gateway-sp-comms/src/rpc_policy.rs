@@ -0,0 +1,308 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Retry, timeout, and cancellation policies for RPCs sent by a
+//! [`SingleSp`](crate::SingleSp).
+
+use gateway_messages::MgsRequest;
+use gateway_messages::SpComponent;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    static CALL_OPTIONS: CallOptions;
+}
+
+/// Categories of requests whose [`RpcPolicy`] can be configured independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RequestKind {
+    Discovery,
+    UpdateChunk,
+    /// Resetting the SP itself (but not other components).
+    Reset,
+    CabooseRead,
+    SerialConsoleWrite,
+    /// Any request not covered by another kind.
+    Other,
+}
+
+impl RequestKind {
+    pub fn of(request: &MgsRequest) -> Self {
+        match request {
+            MgsRequest::Discover => Self::Discovery,
+            MgsRequest::UpdateChunk(_) => Self::UpdateChunk,
+            MgsRequest::ResetTrigger => Self::Reset,
+            MgsRequest::ResetComponentTrigger { component }
+                if *component == SpComponent::SP_ITSELF =>
+            {
+                Self::Reset
+            }
+            MgsRequest::ReadCaboose { .. }
+            | MgsRequest::ReadComponentCaboose { .. } => Self::CabooseRead,
+            MgsRequest::SerialConsoleWrite { .. } => Self::SerialConsoleWrite,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// How we back off when an SP tells us it's busy.
+///
+/// Busy responses do not count as failed attempts: an SP that is busy is
+/// still responding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusyBackoff {
+    pub initial_interval: Duration,
+    pub max_interval: Duration,
+    pub multiplier: f64,
+}
+
+impl Default for BusyBackoff {
+    fn default() -> Self {
+        Self {
+            initial_interval: Duration::from_millis(20),
+            max_interval: Duration::from_millis(1_000),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl BusyBackoff {
+    pub(crate) fn policy(&self) -> backoff::ExponentialBackoff {
+        backoff::ExponentialBackoff {
+            current_interval: self.initial_interval,
+            initial_interval: self.initial_interval,
+            multiplier: self.multiplier,
+            max_interval: self.max_interval,
+            max_elapsed_time: None,
+            ..Default::default()
+        }
+    }
+}
+
+/// Retry and timeout policy for a single RPC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpcPolicy {
+    /// Maximum number of times we'll send the request before giving up.
+    pub max_attempts: usize,
+    /// How long we wait for a response to each attempt.
+    pub per_attempt_timeout: Duration,
+    pub busy_backoff: BusyBackoff,
+    /// Upper bound on the total time spent on the RPC (across all attempts,
+    /// including busy backoff), if any.
+    pub deadline: Option<Duration>,
+}
+
+impl RpcPolicy {
+    pub fn new(max_attempts: usize, per_attempt_timeout: Duration) -> Self {
+        Self {
+            max_attempts,
+            per_attempt_timeout,
+            busy_backoff: BusyBackoff::default(),
+            deadline: None,
+        }
+    }
+
+    /// Run `fut`, applying this policy (instead of the configured policy for
+    /// their [`RequestKind`]) to any RPCs it makes via a
+    /// [`SingleSp`](crate::SingleSp).
+    ///
+    /// ```ignore
+    /// let state = RpcPolicy::new(1, Duration::from_millis(100))
+    ///     .scope(sp.state())
+    ///     .await?;
+    /// ```
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        CallOptions::new().with_policy(self).scope(fut).await
+    }
+}
+
+/// Options applied to every RPC made via a [`SingleSp`](crate::SingleSp)
+/// within [`CallOptions::scope()`].
+///
+/// Regardless of these options, if the future returned by a `SingleSp` method
+/// is dropped, any RPC it is waiting on stops being retried.
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    policy: Option<RpcPolicy>,
+    deadline: Option<Instant>,
+    // Upper bound on each RPC individually, measured from when it starts; see
+    // `detach()`.
+    rpc_timeout: Option<Duration>,
+    cancel: Vec<CancellationToken>,
+}
+
+impl CallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `policy` instead of the configured [`RpcPolicy`] for each RPC's
+    /// [`RequestKind`].
+    pub fn with_policy(mut self, policy: RpcPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Fail RPCs with
+    /// [`DeadlineExceeded`](crate::error::CommunicationError::DeadlineExceeded)
+    /// if they have not completed by `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Equivalent to `with_deadline(Instant::now() + timeout)`.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Fail RPCs with
+    /// [`Cancelled`](crate::error::CommunicationError::Cancelled) once
+    /// `cancel` is cancelled.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel.push(cancel);
+        self
+    }
+
+    /// Run `fut`, applying these options to any RPCs it makes via a
+    /// [`SingleSp`](crate::SingleSp).
+    ///
+    /// Options are combined with those of an enclosing `scope()`, if any: our
+    /// policy takes precedence, the earlier of the two deadlines applies, and
+    /// cancelling either scope's token cancels RPCs.
+    ///
+    /// Like any task-local value, these options do not follow `fut` into tasks
+    /// it spawns, with one exception: the task that delivers an update started
+    /// by [`SingleSp::start_update()`](crate::SingleSp::start_update) carries
+    /// the policy and cancellation tokens of the scope that started it. That
+    /// task outlives the scope, so rather than our deadline, each of its RPCs
+    /// is limited to however long remained until our deadline when the update
+    /// started.
+    ///
+    /// ```ignore
+    /// let cancel = CancellationToken::new();
+    /// let state = CallOptions::new()
+    ///     .with_timeout(Duration::from_secs(5))
+    ///     .with_cancel(cancel.clone())
+    ///     .scope(sp.state())
+    ///     .await?;
+    /// ```
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        let options = self.inherit(Self::current());
+        CALL_OPTIONS.scope(options, fut).await
+    }
+
+    // Get the options set by an enclosing call to `scope()`, if any.
+    pub(crate) fn current() -> Self {
+        CALL_OPTIONS.try_with(Self::clone).unwrap_or_default()
+    }
+
+    pub(crate) fn policy(&self) -> Option<RpcPolicy> {
+        self.policy
+    }
+
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub(crate) fn rpc_timeout(&self) -> Option<Duration> {
+        self.rpc_timeout
+    }
+
+    // Convert these options for use by a task that outlives the scope that
+    // spawned it (e.g., an update driver). Our deadline would expire partway
+    // through the task's work, so we replace it with a timeout of however long
+    // remains until it, applied to each RPC individually.
+    pub(crate) fn detach(mut self) -> Self {
+        if let Some(deadline) = self.deadline.take() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.rpc_timeout =
+                self.rpc_timeout.into_iter().chain([remaining]).min();
+        }
+        self
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.iter().any(CancellationToken::is_cancelled)
+    }
+
+    // Resolves once any of our cancellation tokens is cancelled.
+    pub(crate) async fn cancelled(&self) {
+        if self.cancel.is_empty() {
+            futures::future::pending().await
+        } else {
+            futures::future::select_all(
+                self.cancel.iter().map(|cancel| Box::pin(cancel.cancelled())),
+            )
+            .await;
+        }
+    }
+
+    fn inherit(mut self, outer: Self) -> Self {
+        self.policy = self.policy.or(outer.policy);
+        self.deadline = self.deadline.into_iter().chain(outer.deadline).min();
+        self.rpc_timeout =
+            self.rpc_timeout.into_iter().chain(outer.rpc_timeout).min();
+        self.cancel.extend(outer.cancel);
+        self
+    }
+}
+
+/// The [`RpcPolicy`] for each [`RequestKind`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcPolicies {
+    default: RpcPolicy,
+    by_kind: BTreeMap<RequestKind, RpcPolicy>,
+}
+
+impl RpcPolicies {
+    /// Construct a set of policies that use `max_attempts` and
+    /// `per_attempt_timeout` for every kind of request except
+    /// [`RequestKind::Reset`].
+    ///
+    /// It's very easy to set a timeout that is too low for resetting an SP,
+    /// especially a sidecar (which won't be able to respond until it brings
+    /// the management network back online), so resets use as many attempts
+    /// as are needed to wait at least 30 seconds.
+    pub fn new(max_attempts: usize, per_attempt_timeout: Duration) -> Self {
+        const SP_RESET_TIME_ALLOWED: Duration = Duration::from_secs(30);
+
+        let time_desired = SP_RESET_TIME_ALLOWED.as_millis();
+        let per_attempt = per_attempt_timeout.as_millis().max(1);
+        let reset_attempts =
+            ((time_desired + per_attempt - 1) / per_attempt) as usize;
+
+        Self::uniform(RpcPolicy::new(max_attempts, per_attempt_timeout)).with(
+            RequestKind::Reset,
+            RpcPolicy::new(reset_attempts, per_attempt_timeout),
+        )
+    }
+
+    /// Construct a set of policies that use `policy` for every kind of
+    /// request.
+    pub fn uniform(policy: RpcPolicy) -> Self {
+        Self { default: policy, by_kind: BTreeMap::new() }
+    }
+
+    /// Use `policy` for requests of the given `kind`.
+    pub fn with(mut self, kind: RequestKind, policy: RpcPolicy) -> Self {
+        self.set(kind, policy);
+        self
+    }
+
+    /// Use `policy` for requests of the given `kind`.
+    pub fn set(&mut self, kind: RequestKind, policy: RpcPolicy) {
+        self.by_kind.insert(kind, policy);
+    }
+
+    /// Get the policy for requests of the given `kind`.
+    pub fn get(&self, kind: RequestKind) -> RpcPolicy {
+        self.by_kind.get(&kind).copied().unwrap_or(self.default)
+    }
+}
@@ -80,6 +80,22 @@ impl ScopeIdCache {
     pub(crate) async fn refresh_by_name(&self, name: &str) -> Result<u32> {
         self.inner.refresh_by_name(name).await
     }
+
+    /// Create a cache that looks up interfaces with the given functions
+    /// instead of asking the system, for testing code that uses a cache.
+    #[cfg(test)]
+    pub(crate) fn with_lookups(
+        sys_name_to_index: StaticNameToIndex,
+        sys_index_to_name: StaticIndexToName,
+    ) -> Self {
+        Self {
+            inner: Inner {
+                sys_name_to_index,
+                sys_index_to_name,
+                map: Mutex::default(),
+            },
+        }
+    }
 }
 
 struct Inner<F, G> {
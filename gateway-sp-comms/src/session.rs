@@ -0,0 +1,723 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Recording sessions between MGS and an SP, and replaying them without the
+//! SP.
+//!
+//! A [`SessionRecorder`] writes every packet exchanged with an SP (each
+//! containing a serialized [`Message`] plus any trailing data) to a file as
+//! one JSON [`SessionRecord`] per line. A [`ReplayTransport`] built from such
+//! a file stands in for the SP: when it's sent a request, it answers with
+//! whatever the SP sent in response to the same request (same kind and
+//! arguments) in the recording. This lets a recording from the field be
+//! attached to a bug report and rerun in CI.
+
+use crate::error::CommunicationError;
+use crate::shared_socket::SingleSpMessage;
+use crate::transport::SpTransport;
+use async_trait::async_trait;
+use gateway_messages::Message;
+use gateway_messages::MessageKind;
+use gateway_messages::MgsRequest;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::debug;
+use slog::warn;
+use slog::Logger;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufWriter;
+use std::io::Write;
+use std::net::SocketAddrV6;
+use std::path::Path;
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+/// Which way a packet traveled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    ToSp,
+    FromSp,
+}
+
+/// One packet exchanged between MGS and an SP.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Time since the recording started.
+    pub elapsed: Duration,
+    pub direction: Direction,
+    /// Where we sent the packet (for [`Direction::ToSp`]) or where it came
+    /// from (for [`Direction::FromSp`]; serial console data, whose source a
+    /// transport doesn't report, is recorded as coming from the discovery
+    /// address).
+    pub addr: SocketAddrV6,
+    /// The serialized [`Message`] and any trailing data.
+    ///
+    /// Packets to the SP are recorded exactly as sent. Packets from the SP
+    /// are re-serialized from the [`SingleSpMessage`] our transport decoded:
+    /// responses keep their original header, but serial console data is
+    /// recorded with message ID 0 and our [`version::CURRENT`].
+    ///
+    /// [`version::CURRENT`]: gateway_messages::version::CURRENT
+    #[serde(with = "hex_bytes")]
+    pub packet: Vec<u8>,
+}
+
+impl SessionRecord {
+    /// Deserialize our packet into its message and trailing data.
+    pub fn message(
+        &self,
+    ) -> Result<(Message, &[u8]), gateway_messages::HubpackError> {
+        gateway_messages::deserialize::<Message>(&self.packet)
+    }
+
+    /// Read all records from a session file written by a
+    /// [`SessionRecorder`].
+    pub fn read_all<R: BufRead>(reader: R) -> io::Result<Vec<Self>> {
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            })?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+// Packets are recorded as hex strings to keep session files readable (and
+// diffable) as text.
+mod hex_bytes {
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serializer;
+
+    pub(super) fn serialize<S: Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Writes [`SessionRecord`]s to a session file, one JSON object per line.
+///
+/// Records are written by a dedicated thread, so recording a packet never
+/// blocks on file I/O. Each record is flushed as it's written, so a session
+/// file is complete up to the last packet even if MGS crashes.
+pub struct SessionRecorder {
+    // `None` once our writer has stopped.
+    records_tx: Option<std_mpsc::Sender<SessionRecord>>,
+    writer: Option<thread::JoinHandle<io::Result<()>>>,
+    start: Instant,
+}
+
+impl SessionRecorder {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        let (records_tx, records_rx) = std_mpsc::channel();
+        let writer = thread::spawn(move || write_records(out, records_rx));
+        Self {
+            records_tx: Some(records_tx),
+            writer: Some(writer),
+            start: Instant::now(),
+        }
+    }
+
+    /// Create (or truncate) the session file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Record one packet.
+    ///
+    /// The packet is handed to our writer thread; this only fails if that
+    /// thread has stopped because it failed to write an earlier record (in
+    /// which case the first failure returns that error).
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        addr: SocketAddrV6,
+        packet: &[u8],
+    ) -> io::Result<()> {
+        let record = SessionRecord {
+            elapsed: self.start.elapsed(),
+            direction,
+            addr,
+            packet: packet.to_vec(),
+        };
+        if let Some(records_tx) = &self.records_tx {
+            if records_tx.send(record).is_ok() {
+                return Ok(());
+            }
+        }
+        self.records_tx = None;
+        Err(self.stop_writer().err().unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "session recorder stopped",
+            )
+        }))
+    }
+
+    /// Stop recording, waiting until every packet recorded so far has been
+    /// written.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.records_tx = None;
+        self.stop_writer()
+    }
+
+    // Wait for our writer thread to exit, which it does once `records_tx` is
+    // dropped or it fails to write.
+    fn stop_writer(&mut self) -> io::Result<()> {
+        match self.writer.take().map(thread::JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_panic)) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "session recorder thread panicked",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_records<W: Write>(
+    mut out: W,
+    records_rx: std_mpsc::Receiver<SessionRecord>,
+) -> io::Result<()> {
+    for record in records_rx {
+        serde_json::to_writer(&mut out, &record)?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// An [`SpTransport`] that records every packet passing through the
+/// transport it wraps.
+///
+/// Host phase 2 requests are not recorded; they're answered by the
+/// [`SharedSocket`](crate::SharedSocket) and never seen as packets.
+pub struct RecordingTransport<T> {
+    inner: T,
+    // `None` once we've failed to record a packet.
+    recorder: Option<SessionRecorder>,
+}
+
+impl<T: SpTransport> RecordingTransport<T> {
+    pub fn new(inner: T, recorder: SessionRecorder) -> Self {
+        Self { inner, recorder: Some(recorder) }
+    }
+
+    fn record(
+        &mut self,
+        direction: Direction,
+        addr: SocketAddrV6,
+        packet: &[u8],
+    ) {
+        let recorder = match self.recorder.as_mut() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+
+        // Failing to record shouldn't break communication with the SP.
+        if let Err(err) = recorder.record(direction, addr, packet) {
+            warn!(
+                self.inner.log(), "failed to record packet; recording stopped";
+                "direction" => ?direction,
+                "err" => %err,
+            );
+            self.recorder = None;
+        }
+    }
+}
+
+#[async_trait]
+impl<T: SpTransport> SpTransport for RecordingTransport<T> {
+    fn log(&self) -> &Logger {
+        self.inner.log()
+    }
+
+    fn interface(&self) -> &str {
+        self.inner.interface()
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        self.inner.discovery_addr()
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        self.inner.send(data).await?;
+
+        // Our inner transport may only settle on its destination as it sends
+        // (e.g., a `SingleSpHandle` fills in the scope ID of its interface on
+        // first use), so ask where it sent this packet only once it's gone.
+        self.record(Direction::ToSp, self.inner.discovery_addr(), data);
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> SingleSpMessage {
+        let message = self.inner.recv().await;
+        if let Some((addr, packet)) =
+            message.encode(self.inner.discovery_addr())
+        {
+            self.record(Direction::FromSp, addr, &packet);
+        }
+        message
+    }
+
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
+
+// Everything the SP sent after one recorded request: its response (which we
+// identify by `message_id`) and any unsolicited packets (e.g., serial console
+// data) that arrived before our next request.
+#[derive(Debug, Clone)]
+struct RecordedReply {
+    message_id: u32,
+    packets: Vec<(SocketAddrV6, Vec<u8>)>,
+}
+
+// All the replies to one distinct request, in the order they were recorded.
+#[derive(Debug)]
+struct Exchange {
+    request: MgsRequest,
+    replies: VecDeque<RecordedReply>,
+}
+
+/// An [`SpTransport`] that plays the part of an SP by answering requests from
+/// a recorded session.
+///
+/// Each request is matched against requests in the recording by its
+/// [`MgsRequest`] (kind and arguments). Repeated requests are answered with
+/// successive recorded replies; once those run out, the last one is reused.
+/// Requests that never appear in the recording go unanswered, just as if the
+/// SP had stopped responding. Replies are delivered immediately, regardless
+/// of how long the SP originally took.
+pub struct ReplayTransport {
+    log: Logger,
+    discovery_addr: SocketAddrV6,
+    exchanges: Vec<Exchange>,
+    replies_tx: mpsc::UnboundedSender<SingleSpMessage>,
+    replies_rx: mpsc::UnboundedReceiver<SingleSpMessage>,
+}
+
+impl ReplayTransport {
+    /// Build a replay transport from `records` (e.g., as read by
+    /// [`SessionRecord::read_all()`]).
+    pub fn new(records: Vec<SessionRecord>, log: Logger) -> Self {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+        let mut discovery_addr = None;
+
+        // Map of message ID to the (exchange, reply) index of the recorded
+        // request with that ID, so we can attach responses to the right
+        // request even if several were in flight at once.
+        let mut by_message_id: HashMap<u32, (usize, usize)> = HashMap::new();
+        let mut current: Option<(usize, usize)> = None;
+
+        for record in records {
+            let message = match record.message() {
+                Ok((message, _data)) => message,
+                Err(err) => {
+                    warn!(
+                        log, "skipping undecodable record";
+                        "record" => ?record,
+                        "err" => %err,
+                    );
+                    continue;
+                }
+            };
+
+            match (record.direction, message.kind) {
+                (Direction::ToSp, MessageKind::MgsRequest(request)) => {
+                    discovery_addr.get_or_insert(record.addr);
+                    let message_id = message.header.message_id;
+
+                    // Retries of a request reuse its message ID; they're part
+                    // of the same exchange.
+                    if let Some(&(i, _)) = by_message_id.get(&message_id) {
+                        if exchanges[i].request == request {
+                            continue;
+                        }
+                    }
+
+                    let i = match exchanges
+                        .iter()
+                        .position(|e| e.request == request)
+                    {
+                        Some(i) => i,
+                        None => {
+                            exchanges.push(Exchange {
+                                request,
+                                replies: VecDeque::new(),
+                            });
+                            exchanges.len() - 1
+                        }
+                    };
+                    let replies = &mut exchanges[i].replies;
+                    replies.push_back(RecordedReply {
+                        message_id,
+                        packets: Vec::new(),
+                    });
+                    let index = (i, replies.len() - 1);
+                    by_message_id.insert(message_id, index);
+                    current = Some(index);
+                }
+                (Direction::ToSp, kind) => {
+                    debug!(
+                        log, "skipping recorded non-request to SP";
+                        "kind" => ?kind,
+                    );
+                }
+                (Direction::FromSp, kind) => {
+                    let index = match kind {
+                        MessageKind::SpResponse(_) => by_message_id
+                            .get(&message.header.message_id)
+                            .copied(),
+                        _ => current,
+                    };
+                    match index {
+                        Some((i, j)) => exchanges[i].replies[j]
+                            .packets
+                            .push((record.addr, record.packet)),
+                        None => debug!(
+                            log, "skipping recorded packet with no request";
+                            "kind" => ?kind,
+                        ),
+                    }
+                }
+            }
+        }
+
+        let (replies_tx, replies_rx) = mpsc::unbounded_channel();
+        Self {
+            log,
+            discovery_addr: discovery_addr
+                .unwrap_or_else(crate::default_discovery_addr),
+            exchanges,
+            replies_tx,
+            replies_rx,
+        }
+    }
+}
+
+#[async_trait]
+impl SpTransport for ReplayTransport {
+    fn log(&self) -> &Logger {
+        &self.log
+    }
+
+    fn interface(&self) -> &str {
+        "(session replay)"
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        self.discovery_addr
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        let (message, _data) = gateway_messages::deserialize::<Message>(data)
+            .map_err(|err| {
+            CommunicationError::Deserialize { peer: self.discovery_addr, err }
+        })?;
+        let request = match message.kind {
+            MessageKind::MgsRequest(request) => request,
+            _ => {
+                debug!(self.log, "ignoring non-request"; "message" => ?message);
+                return Ok(());
+            }
+        };
+
+        let exchange =
+            match self.exchanges.iter_mut().find(|e| e.request == request) {
+                Some(exchange) => exchange,
+                None => {
+                    warn!(
+                        self.log, "no recorded reply to request";
+                        "request" => ?request,
+                    );
+                    return Ok(());
+                }
+            };
+        let RecordedReply { message_id: recorded_id, packets } =
+            if exchange.replies.len() > 1 {
+                exchange.replies.pop_front().unwrap()
+            } else {
+                exchange.replies[0].clone()
+            };
+
+        for (peer, packet) in packets {
+            let mut reply =
+                match SingleSpMessage::decode(&self.log, peer, &packet) {
+                    Ok(Some(reply)) => reply,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        warn!(
+                            self.log, "skipping undecodable recorded reply";
+                            "err" => %err,
+                        );
+                        continue;
+                    }
+                };
+
+            // Our response should carry the message ID of the request we're
+            // answering, not the one recorded.
+            if let SingleSpMessage::SpResponse { header, .. } = &mut reply {
+                if header.message_id == recorded_id {
+                    header.message_id = message.header.message_id;
+                }
+            }
+
+            // We own `replies_rx`, so this can't fail.
+            _ = self.replies_tx.send(reply);
+        }
+
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> SingleSpMessage {
+        // We hold `replies_tx`, so our channel is never closed.
+        self.replies_rx.recv().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gateway_messages::version;
+    use gateway_messages::Header;
+    use gateway_messages::PowerState;
+    use gateway_messages::SpResponse;
+
+    fn record(
+        direction: Direction,
+        message_id: u32,
+        kind: MessageKind,
+    ) -> SessionRecord {
+        let message = Message {
+            header: Header { version: version::CURRENT, message_id },
+            kind,
+        };
+        let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
+        let n = gateway_messages::serialize(&mut buf, &message).unwrap();
+        SessionRecord {
+            elapsed: Duration::ZERO,
+            direction,
+            addr: "[fe80::1]:11111".parse().unwrap(),
+            packet: buf[..n].to_vec(),
+        }
+    }
+
+    fn request(message_id: u32, request: MgsRequest) -> Vec<u8> {
+        record(Direction::ToSp, message_id, MessageKind::MgsRequest(request))
+            .packet
+    }
+
+    #[tokio::test]
+    async fn replay_answers_from_recording() {
+        let get_power = MessageKind::MgsRequest(MgsRequest::GetPowerState);
+        let power =
+            |state| MessageKind::SpResponse(SpResponse::PowerState(state));
+        let records = vec![
+            record(Direction::ToSp, 1, get_power),
+            // A retry of the same request shouldn't count as a new exchange.
+            record(Direction::ToSp, 1, get_power),
+            record(Direction::FromSp, 1, power(PowerState::A2)),
+            record(Direction::ToSp, 2, get_power),
+            record(Direction::FromSp, 2, power(PowerState::A0)),
+        ];
+
+        // Session files should round trip.
+        let mut file = Vec::new();
+        for record in &records {
+            serde_json::to_writer(&mut file, record).unwrap();
+            file.push(b'\n');
+        }
+        let records = SessionRecord::read_all(file.as_slice()).unwrap();
+
+        let mut transport = ReplayTransport::new(
+            records,
+            Logger::root(slog::Discard, slog::o!()),
+        );
+
+        // Replies should be replayed in order (with our message IDs), and the
+        // last reply should be reused once we've run out.
+        for (message_id, expected) in
+            [(7, PowerState::A2), (8, PowerState::A0), (9, PowerState::A0)]
+        {
+            transport
+                .send(&request(message_id, MgsRequest::GetPowerState))
+                .await
+                .unwrap();
+            match transport.recv().await {
+                SingleSpMessage::SpResponse {
+                    header,
+                    response: SpResponse::PowerState(state),
+                    ..
+                } => {
+                    assert_eq!(header.message_id, message_id);
+                    assert_eq!(state, expected);
+                }
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+
+        // Requests that aren't in the recording go unanswered.
+        transport.send(&request(10, MgsRequest::SpState)).await.unwrap();
+        assert!(transport.replies_rx.try_recv().is_err());
+    }
+
+    // A session file we can read back while its recorder still owns it.
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recorder_writes_records_in_order() {
+        let out = SharedBuf::default();
+        let mut recorder = SessionRecorder::new(out.clone());
+        let addr = "[fe80::1]:11111".parse().unwrap();
+        let packets = [
+            (Direction::ToSp, request(1, MgsRequest::GetPowerState)),
+            (Direction::FromSp, b"response".to_vec()),
+            (Direction::ToSp, request(2, MgsRequest::SpState)),
+        ];
+        for (direction, packet) in &packets {
+            recorder.record(*direction, addr, packet).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let records =
+            SessionRecord::read_all(out.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(records.len(), packets.len());
+        for (record, (direction, packet)) in records.iter().zip(&packets) {
+            assert_eq!(record.direction, *direction);
+            assert_eq!(record.addr, addr);
+            assert_eq!(&record.packet, packet);
+        }
+    }
+
+    #[test]
+    fn recorder_reports_write_failure() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut recorder = SessionRecorder::new(FailingWriter);
+        let addr = "[fe80::1]:11111".parse().unwrap();
+
+        // Our first record is accepted; the writer fails in the background,
+        // and we hear about it on a later record.
+        recorder.record(Direction::ToSp, addr, b"first").unwrap();
+        let err = loop {
+            match recorder.record(Direction::ToSp, addr, b"next") {
+                Ok(()) => std::thread::sleep(Duration::from_millis(1)),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(
+            recorder
+                .record(Direction::ToSp, addr, b"after")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    // A transport that, like a `SingleSpHandle`, only learns the scope ID of
+    // its destination when it first sends.
+    struct ScopedTransport {
+        log: Logger,
+        discovery_addr: SocketAddrV6,
+    }
+
+    #[async_trait]
+    impl SpTransport for ScopedTransport {
+        fn log(&self) -> &Logger {
+            &self.log
+        }
+
+        fn interface(&self) -> &str {
+            "(scoped)"
+        }
+
+        fn discovery_addr(&self) -> SocketAddrV6 {
+            self.discovery_addr
+        }
+
+        async fn send(
+            &mut self,
+            _data: &[u8],
+        ) -> Result<(), CommunicationError> {
+            self.discovery_addr.set_scope_id(7);
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> SingleSpMessage {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn recording_transport_records_actual_destination() {
+        let out = SharedBuf::default();
+        let mut transport = RecordingTransport::new(
+            ScopedTransport {
+                log: Logger::root(slog::Discard, slog::o!()),
+                discovery_addr: "[ff15::1]:11111".parse().unwrap(),
+            },
+            SessionRecorder::new(out.clone()),
+        );
+
+        let packet = request(1, MgsRequest::Discover);
+        transport.send(&packet).await.unwrap();
+        transport.recorder.take().unwrap().finish().unwrap();
+
+        let records =
+            SessionRecord::read_all(out.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].addr.scope_id(), 7);
+        assert_eq!(records[0].packet, packet);
+    }
+}
@@ -8,7 +8,9 @@
 //! it's talking to based on the scope ID of the packet.
 //!
 //! SPs are logically identified by interface names, and scope IDs are mapped to
-//! those interface names.
+//! those interface names. If several SPs are reachable via the same interface
+//! (e.g., on a bench setup where multiple SPs sit behind one switch), they are
+//! further distinguished by their IPv6 address.
 
 use fxhash::FxHashMap;
 use gateway_messages::version;
@@ -16,8 +18,10 @@ use gateway_messages::Header;
 use gateway_messages::Message;
 use gateway_messages::MessageKind;
 use gateway_messages::MgsError;
+use gateway_messages::MgsRequest;
 use gateway_messages::MgsResponse;
 use gateway_messages::SpComponent;
+use gateway_messages::SpPort;
 use gateway_messages::SpRequest;
 use gateway_messages::SpResponse;
 use slog::debug;
@@ -27,6 +31,8 @@ use slog::warn;
 use slog::Logger;
 use std::collections::hash_map;
 use std::io;
+use std::io::Write;
+use std::mem;
 use std::net::Ipv6Addr;
 use std::net::SocketAddr;
 use std::net::SocketAddrV6;
@@ -39,12 +45,19 @@ use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
+use crate::default_discovery_addr;
+use crate::error::CommunicationError;
 use crate::error::HostPhase2Error;
+use crate::pcap::PacketTap;
 use crate::scope_id_cache::InterfaceError;
 use crate::scope_id_cache::Name;
 use crate::scope_id_cache::ScopeIdCache;
+use crate::session::Direction;
 use crate::single_sp::HostPhase2Request;
+use crate::stats::SharedSocketStats;
+use crate::stats::SharedSocketStatsCollector;
 use crate::HostPhase2Provider;
 use crate::SP_TO_MGS_MULTICAST_ADDR;
 
@@ -57,7 +70,8 @@ pub struct BindError {
 
 /// `SharedSocket` wraps a single UDP socket and allows multiple
 /// [`SingleSp`](crate::SingleSp) handles to use it, assuming each is assigned
-/// to a different underlying network interface.
+/// to a different underlying network interface or targets a different SP
+/// address on a shared interface.
 ///
 /// This is designed to match the way management network VLAN interfaces are set
 /// up inside the switch zone: all interfaces are VLANs that sit on top of
@@ -68,20 +82,29 @@ pub struct BindError {
 ///
 /// Instead, MGS can open a single `SharedSocket` and then create a `SingleSp`
 /// handle for each VLAN interface. When creating a `SingleSp` from a
-/// `SharedSocket`, an interface name must be specified, and only one `SingleSp`
-/// handle is allowed per interface. Each `SingleSp` can send data on the shared
-/// socket directly, but receives are handled by `SharedSocket`. When a
+/// `SharedSocket`, an interface name must be specified. Each interface may
+/// have at most one `SingleSp` handle using a multicast discovery address
+/// (which will talk to whichever SP answers on that interface) plus any number
+/// of handles using distinct unicast discovery addresses (each of which only
+/// talks to the SP at that address). Each `SingleSp` can send data on the
+/// shared socket directly, but receives are handled by `SharedSocket`. When a
 /// `SharedSocket` is created, it spawns a background tokio task that receives
 /// messages from SPs and checks the scope ID (i.e., the interface that received
-/// the packet). If it matches an interface that has a `SingleSp` handler, the
-/// message is forwarded to that `SingleSp` instance via a tokio channel;
-/// otherwise, the packet is discarded.
+/// the packet). If it matches an interface that has a `SingleSp` handler for
+/// the address of the sender (or a multicast handler, if no handler targets
+/// that address specifically), the message is forwarded to that `SingleSp`
+/// instance via a tokio channel; otherwise, the packet is discarded.
+///
+/// Dropping a `SharedSocket` aborts its background task immediately; use
+/// [`SharedSocket::shutdown()`] to stop it cleanly.
 #[derive(Debug)]
 pub struct SharedSocket {
     socket: SendOnlyUdpSocket,
     scope_id_cache: Arc<ScopeIdCache>,
-    single_sp_handlers:
-        Arc<Mutex<FxHashMap<Name, mpsc::Sender<SingleSpMessage>>>>,
+    single_sp_handlers: Arc<Mutex<HandlerMap>>,
+    tap: Arc<PacketTap>,
+    stats: Arc<SharedSocketStatsCollector>,
+    shutdown: CancellationToken,
     recv_handler_task: JoinHandle<()>,
     log: Logger,
 }
@@ -128,32 +151,184 @@ impl SharedSocket {
         let scope_id_cache = Arc::default();
         let single_sp_handlers = Arc::default();
 
+        // Captured packets report our local address (if we're bound to a
+        // specific one) and port.
+        let local_addr = match socket.local_addr() {
+            Ok(SocketAddr::V6(addr)) => addr,
+            _ => SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0),
+        };
+        let tap = Arc::new(PacketTap::new(
+            local_addr,
+            Arc::clone(&scope_id_cache),
+            log.clone(),
+        ));
+
+        let stats = Arc::default();
+        let shutdown = CancellationToken::new();
+
         let recv_handler = RecvHandler {
             socket: Arc::clone(&socket),
             scope_id_cache: Arc::clone(&scope_id_cache),
             single_sp_handlers: Arc::clone(&single_sp_handlers),
             host_phase2_provider,
+            host_phase2_tasks: std::sync::Mutex::default(),
+            tap: Arc::clone(&tap),
+            stats: Arc::clone(&stats),
+            shutdown: shutdown.clone(),
             log: log.clone(),
         };
 
         let recv_handler_task = tokio::spawn(recv_handler.run());
 
         Ok(Self {
-            socket: SendOnlyUdpSocket::from(socket),
+            socket: SendOnlyUdpSocket::new(socket, Arc::clone(&tap)),
             scope_id_cache,
             single_sp_handlers,
+            tap,
+            stats,
+            shutdown,
             recv_handler_task,
             log,
         })
     }
 
+    /// Stop receiving packets on this socket.
+    ///
+    /// Waits for any host phase 2 responses we're in the middle of sending to
+    /// finish. [`SingleSp`](crate::SingleSp)s created from this socket stop
+    /// hearing from their SPs, so they should be shut down (see
+    /// [`SingleSp::shutdown()`](crate::SingleSp::shutdown)) first.
+    pub async fn shutdown(mut self) {
+        self.shutdown.cancel();
+        if let Err(err) = (&mut self.recv_handler_task).await {
+            error!(self.log, "shared socket recv task failed"; "err" => %err);
+        }
+    }
+
+    /// Start capturing every UDP datagram sent or received on this socket
+    /// (by any [`SingleSp`](crate::SingleSp) using it) to `out` in pcapng
+    /// format, replacing any capture already in progress.
+    ///
+    /// Each datagram is recorded with the interface it was sent or received
+    /// on, the SP's address, and its direction. Datagrams are written to
+    /// `out` by a separate thread, so a slow `out` doesn't hold up traffic;
+    /// if writing to it fails, the capture stops (and the failure is
+    /// logged).
+    pub fn start_capture<W: Write + Send + 'static>(
+        &self,
+        out: W,
+    ) -> io::Result<()> {
+        self.tap.start(Box::new(out))
+    }
+
+    /// Stop any capture started by [`SharedSocket::start_capture()`].
+    pub fn stop_capture(&self) {
+        self.tap.stop();
+    }
+
+    /// Get counts of the packets we've received (including those we
+    /// discarded, and why) and of the host phase 2 data we've served.
+    pub fn stats(&self) -> SharedSocketStats {
+        self.stats.snapshot()
+    }
+
+    /// Discover all SPs reachable via `interface`.
+    ///
+    /// Sends a single discovery packet to the SP multicast address on
+    /// `interface` and collects every response received within a short
+    /// window. The returned list is unordered and contains each responding SP
+    /// once; an empty list means no SPs responded (which may just mean they
+    /// missed our packet - callers may want to retry).
+    pub async fn discover_all(
+        &self,
+        interface: &str,
+    ) -> Result<Vec<DiscoveredSp>, CommunicationError> {
+        // How long do we wait for SPs to respond to our discovery packet? SPs
+        // respond immediately, so this only needs to cover network latency
+        // and SPs that are busy handling other requests.
+        const DISCOVER_ALL_WINDOW: Duration = Duration::from_secs(1);
+
+        // We expect at most a handful of SPs per interface; this is
+        // comfortably larger than that.
+        const DISCOVER_ALL_CHANNEL_DEPTH: usize = 64;
+
+        let interface = Name::from(interface);
+        let mut addr = default_discovery_addr();
+        addr.set_scope_id(
+            self.scope_id_cache.refresh_by_name(&interface).await?,
+        );
+
+        // Register to receive discovery responses _before_ sending our request
+        // so we can't miss any.
+        let (tx, mut rx) = mpsc::channel(DISCOVER_ALL_CHANNEL_DEPTH);
+        self.single_sp_handlers
+            .lock()
+            .await
+            .entry(interface.clone())
+            .or_default()
+            .discover_all
+            .push(tx);
+
+        // SPs echo back our message ID, but we don't need it to match up
+        // responses: any discovery response on this interface is interesting.
+        let message = Message {
+            header: Header { version: version::CURRENT, message_id: 0 },
+            kind: MessageKind::MgsRequest(MgsRequest::Discover),
+        };
+        let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
+        let n = gateway_messages::serialize(&mut buf, &message).unwrap();
+        let send_result = self.socket.send_to(&buf[..n], addr).await;
+
+        let discovered = if send_result.is_ok() {
+            let deadline = Instant::now() + DISCOVER_ALL_WINDOW;
+            collect_discovered(&mut rx, deadline).await
+        } else {
+            Vec::new()
+        };
+
+        // Unregister ourselves (and remove the interface entirely if nothing
+        // else is using it).
+        drop(rx);
+        {
+            let mut single_sp_handlers = self.single_sp_handlers.lock().await;
+            if let hash_map::Entry::Occupied(mut entry) =
+                single_sp_handlers.entry(interface.clone())
+            {
+                entry.get_mut().discover_all.retain(|tx| !tx.is_closed());
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        }
+
+        send_result.map_err(|err| CommunicationError::UdpSendTo {
+            addr,
+            interface: interface.to_string(),
+            err,
+        })?;
+
+        debug!(
+            self.log, "discovered SPs";
+            "interface" => &*interface,
+            "sps" => ?discovered,
+        );
+
+        Ok(discovered)
+    }
+
     /// Create a handle for communicating with a single SP on the given
     /// interface.
     ///
+    /// If `discovery_addr` is a multicast address, the handle will talk to
+    /// whichever SP responds on `interface`. If it is a unicast address, the
+    /// handle will only receive packets sent from that address, allowing
+    /// multiple handles to share an interface.
+    ///
     /// # Panics
     ///
-    /// Panics if a handle for `interface` has already been created. This
-    /// function should be called once for each interface of interest.
+    /// Panics if a live handle for the same `interface` and (unicast)
+    /// `discovery_addr` has already been created, or if a live handle with a
+    /// multicast `discovery_addr` has already been created for `interface`.
     pub(crate) async fn single_sp_handler(
         &self,
         interface: &str,
@@ -176,17 +351,30 @@ impl SharedSocket {
         let interface = Name::from(interface);
 
         // Insert our handler into our interface -> handler map, or panic if
-        // `interface` is a duplicate.
+        // `(interface, discovery_addr)` is a duplicate. We allow replacing
+        // handlers whose `SingleSp` has been dropped.
         {
             let mut single_sp_handlers = self.single_sp_handlers.lock().await;
+            let handlers =
+                single_sp_handlers.entry(interface.clone()).or_default();
 
-            match single_sp_handlers.entry(interface.clone()) {
-                hash_map::Entry::Occupied(_) => {
-                    panic!("single_sp_handler called with duplicate interface {interface:?}");
-                }
-                hash_map::Entry::Vacant(slot) => {
-                    slot.insert(tx);
-                }
+            let sp_ip = *discovery_addr.ip();
+            let existing = if sp_ip.is_multicast() {
+                handlers.any_sp.as_ref()
+            } else {
+                handlers.by_sp_addr.get(&sp_ip)
+            };
+            if existing.map_or(false, |existing| !existing.is_closed()) {
+                panic!(
+                    "single_sp_handler called with duplicate interface \
+                     {interface:?} and discovery address {discovery_addr}"
+                );
+            }
+
+            if sp_ip.is_multicast() {
+                handlers.any_sp = Some(tx);
+            } else {
+                handlers.by_sp_addr.insert(sp_ip, tx);
             }
         }
 
@@ -195,6 +383,7 @@ impl SharedSocket {
             socket: self.socket.clone(),
             interface,
             scope_id_cache: Arc::clone(&self.scope_id_cache),
+            single_sp_handlers: Arc::clone(&self.single_sp_handlers),
             discovery_addr,
             recv,
             log: self.log.new(o!("interface" => interface_string)),
@@ -202,6 +391,56 @@ impl SharedSocket {
     }
 }
 
+// Collect the SPs sent to `rx` until `deadline`, keeping only the first
+// response from each SP.
+async fn collect_discovered(
+    rx: &mut mpsc::Receiver<DiscoveredSp>,
+    deadline: Instant,
+) -> Vec<DiscoveredSp> {
+    let mut discovered = Vec::new();
+    while let Ok(Some(sp)) = tokio::time::timeout_at(deadline, rx.recv()).await
+    {
+        if !discovered.iter().any(|d: &DiscoveredSp| d.addr == sp.addr) {
+            discovered.push(sp);
+        }
+    }
+    discovered
+}
+
+/// An SP that responded to [`SharedSocket::discover_all()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveredSp {
+    /// Address of the SP; this can be used as the `discovery_addr` of a
+    /// [`SwitchPortConfig`](crate::SwitchPortConfig) to create a
+    /// [`SingleSp`](crate::SingleSp) that only talks to this SP.
+    pub addr: SocketAddrV6,
+    /// The port of the SP on which it received our discovery request.
+    pub sp_port: SpPort,
+}
+
+// All the handlers we know about, keyed by the interface they're listening on.
+// We remove an interface's entry once it has no handlers left.
+type HandlerMap = FxHashMap<Name, InterfaceHandlers>;
+
+#[derive(Debug, Default)]
+struct InterfaceHandlers {
+    // Handler for a `SingleSp` with a multicast discovery address; receives
+    // packets from any SP that doesn't have its own entry in `by_sp_addr`.
+    any_sp: Option<mpsc::Sender<SingleSpMessage>>,
+    // Handlers for `SingleSp`s targeting a specific SP address.
+    by_sp_addr: FxHashMap<Ipv6Addr, mpsc::Sender<SingleSpMessage>>,
+    // In-progress calls to `SharedSocket::discover_all()`.
+    discover_all: Vec<mpsc::Sender<DiscoveredSp>>,
+}
+
+impl InterfaceHandlers {
+    fn is_empty(&self) -> bool {
+        self.any_sp.is_none()
+            && self.by_sp_addr.is_empty()
+            && self.discover_all.is_empty()
+    }
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum SingleSpHandleError {
     #[error("failed to join multicast group {group} on {interface}: {err}")]
@@ -221,6 +460,7 @@ pub(crate) struct SingleSpHandle {
     socket: SendOnlyUdpSocket,
     interface: Name,
     scope_id_cache: Arc<ScopeIdCache>,
+    single_sp_handlers: Arc<Mutex<HandlerMap>>,
     discovery_addr: SocketAddrV6,
     recv: mpsc::Receiver<SingleSpMessage>,
     log: Logger,
@@ -347,13 +587,49 @@ impl SingleSpHandle {
         // panic.
         self.recv.recv().await.expect("recv() task died")
     }
+
+    /// Remove our handler from the `SharedSocket` that created us (and remove
+    /// our interface entirely if nothing else is using it), allowing another
+    /// handle to be created for our interface and discovery address.
+    ///
+    /// We must not `recv()` after calling this method.
+    pub(crate) async fn unregister(&mut self) {
+        // Closing our receiver marks our sender in `single_sp_handlers` as
+        // closed, which is how we find it below.
+        self.recv.close();
+
+        let mut single_sp_handlers = self.single_sp_handlers.lock().await;
+        if let hash_map::Entry::Occupied(mut entry) =
+            single_sp_handlers.entry(self.interface.clone())
+        {
+            let handlers = entry.get_mut();
+            let sp_ip = *self.discovery_addr.ip();
+            if sp_ip.is_multicast() {
+                if handlers.any_sp.as_ref().map_or(false, |tx| tx.is_closed()) {
+                    handlers.any_sp = None;
+                }
+            } else if handlers
+                .by_sp_addr
+                .get(&sp_ip)
+                .map_or(false, |tx| tx.is_closed())
+            {
+                handlers.by_sp_addr.remove(&sp_ip);
+            }
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
 }
 
 // Trivial wrapper around `UdpSocket` that only exposes `send`: in our
 // `SingleSpHandle`, we want to allow direct sends but _not_ recvs, so we
-// use this type to keep ourselves honest.
+// use this type to keep ourselves honest. All sends pass through here, so this
+// is also where we capture outgoing packets.
 use send_only::SendOnlyUdpSocket;
 mod send_only {
+    use crate::pcap::PacketTap;
+    use crate::session::Direction;
     use std::io;
     use std::net::Ipv6Addr;
     use std::net::SocketAddrV6;
@@ -361,21 +637,24 @@ mod send_only {
     use tokio::net::UdpSocket;
 
     #[derive(Debug, Clone)]
-    pub(super) struct SendOnlyUdpSocket(Arc<UdpSocket>);
-
-    impl From<Arc<UdpSocket>> for SendOnlyUdpSocket {
-        fn from(socket: Arc<UdpSocket>) -> Self {
-            Self(socket)
-        }
+    pub(super) struct SendOnlyUdpSocket {
+        socket: Arc<UdpSocket>,
+        tap: Arc<PacketTap>,
     }
 
     impl SendOnlyUdpSocket {
+        pub(super) fn new(socket: Arc<UdpSocket>, tap: Arc<PacketTap>) -> Self {
+            Self { socket, tap }
+        }
+
         pub(super) async fn send_to(
             &self,
             buf: &[u8],
             addr: SocketAddrV6,
         ) -> Result<usize, io::Error> {
-            self.0.send_to(buf, addr).await
+            let n = self.socket.send_to(buf, addr).await?;
+            self.tap.record(Direction::ToSp, addr, &buf[..n]).await;
+            Ok(n)
         }
 
         pub(super) fn join_multicast_v6(
@@ -383,7 +662,7 @@ mod send_only {
             maddr: &Ipv6Addr,
             interface: u32,
         ) -> Result<(), io::Error> {
-            self.0.join_multicast_v6(maddr, interface)
+            self.socket.join_multicast_v6(maddr, interface)
         }
 
         pub(super) fn leave_multicast_v6(
@@ -391,34 +670,106 @@ mod send_only {
             maddr: &Ipv6Addr,
             interface: u32,
         ) -> Result<(), io::Error> {
-            self.0.leave_multicast_v6(maddr, interface)
+            self.socket.leave_multicast_v6(maddr, interface)
         }
     }
 }
 
 #[derive(Debug, Error)]
 enum RecvError {
+    #[error(transparent)]
+    Parse(#[from] ParsePacketError),
+    #[error("invalid message kind ({0})")]
+    InvalidMessageKind(&'static str),
+    #[error("could not find interface from scope ID of {addr}: {err}")]
+    InterfaceForScopeId { addr: SocketAddrV6, err: InterfaceError },
+    #[error("discarding packet from {addr} on {interface:?}: no handler")]
+    NoHandler { interface: String, addr: Ipv6Addr },
+    #[error("discarding message from {addr} on {interface:?}: handler busy")]
+    HandlerBusy { interface: String, addr: Ipv6Addr },
+}
+
+impl RecvError {
+    // Count a packet we discarded because of this error.
+    fn record(&self, stats: &SharedSocketStatsCollector) {
+        let counter = match self {
+            RecvError::Parse(ParsePacketError::VersionMismatch { .. }) => {
+                &stats.version_mismatches
+            }
+            RecvError::Parse(
+                ParsePacketError::DeserializeHeader(_)
+                | ParsePacketError::DeserializeBody(_),
+            ) => &stats.deserialize_errors,
+            RecvError::InvalidMessageKind(_) => &stats.invalid_message_kind,
+            RecvError::InterfaceForScopeId { .. } => {
+                &stats.interface_lookup_failures
+            }
+            RecvError::NoHandler { .. } => &stats.no_handler,
+            RecvError::HandlerBusy { .. } => &stats.handler_busy,
+        };
+        SharedSocketStatsCollector::add(counter, 1);
+    }
+}
+
+/// Parse a packet received from an SP into its header, message, and any
+/// trailing data, checking that the SP is speaking a version of the protocol
+/// we understand.
+pub fn parse_packet(
+    data: &[u8],
+) -> Result<(Header, MessageKind, &[u8]), ParsePacketError> {
+    // Peel off the header first to check the version.
+    let (header, remaining) = gateway_messages::deserialize::<Header>(data)
+        .map_err(ParsePacketError::DeserializeHeader)?;
+    if header.version < version::MIN {
+        return Err(ParsePacketError::VersionMismatch {
+            expected: version::CURRENT,
+            sp: header.version,
+        });
+    }
+
+    // Parse the remainder.
+    let (kind, sp_trailing_data) =
+        match gateway_messages::deserialize::<MessageKind>(remaining) {
+            Ok((kind, sp_trailing_data)) => (kind, sp_trailing_data),
+            // We failed to deserialize, and the message version is higher
+            // than what we know. This almost certainly means they sent a
+            // new message we don't understand; return a version mismatch
+            // error.
+            Err(_) if header.version > version::CURRENT => {
+                return Err(ParsePacketError::VersionMismatch {
+                    expected: version::CURRENT,
+                    sp: header.version,
+                })
+            }
+            // We failed to deserialize but the version is in the range we
+            // should have understood; return a deserialization error.
+            Err(err) => return Err(ParsePacketError::DeserializeBody(err)),
+        };
+
+    Ok((header, kind, sp_trailing_data))
+}
+
+/// Error returned by [`parse_packet()`].
+#[derive(Debug, Error)]
+pub enum ParsePacketError {
     #[error("failed to deserialize message header: {0}")]
     DeserializeHeader(hubpack::Error),
     #[error("failed to deserialize message body: {0}")]
     DeserializeBody(hubpack::Error),
     #[error("version mismatch (expected {expected}, SP sent {sp})")]
     VersionMismatch { expected: u32, sp: u32 },
-    #[error("invalid message kind ({0})")]
-    InvalidMessageKind(&'static str),
-    #[error("could not find interface from scope ID of {addr}: {err}")]
-    InterfaceForScopeId { addr: SocketAddrV6, err: InterfaceError },
-    #[error("discarding packet from interface {interface:?}: no handler")]
-    NoHandler { interface: String },
-    #[error("discarding message from interface {interface:?}: handler busy")]
-    HandlerBusy { interface: String },
 }
 
+/// A message from an SP to be handled by a [`SingleSp`](crate::SingleSp),
+/// as delivered by its [`SpTransport`](crate::SpTransport).
+//
 // When we receive a packet that needs to be handled by a `SingleSp` instance,
-// we look up the `SingleSp` instance by the scope ID of the source of the
-// packet then send it an instance of this enum to handle.
+// we look up the `SingleSp` instance by the scope ID and address of the source
+// of the packet then send it an instance of this enum to handle.
 #[derive(Debug, Clone)]
-pub(crate) enum SingleSpMessage {
+pub enum SingleSpMessage {
+    /// A request for host phase 2 data that the transport has already
+    /// answered; `SingleSp` only records it.
     HostPhase2Request(HostPhase2Request),
     SerialConsole {
         component: SpComponent,
@@ -436,9 +787,13 @@ pub(crate) enum SingleSpMessage {
 struct RecvHandler<T> {
     socket: Arc<UdpSocket>,
     scope_id_cache: Arc<ScopeIdCache>,
-    single_sp_handlers:
-        Arc<Mutex<FxHashMap<Name, mpsc::Sender<SingleSpMessage>>>>,
+    single_sp_handlers: Arc<Mutex<HandlerMap>>,
     host_phase2_provider: Arc<T>,
+    // `SendHostPhase2ResponseTask`s we've spawned that may still be running.
+    host_phase2_tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    tap: Arc<PacketTap>,
+    stats: Arc<SharedSocketStatsCollector>,
+    shutdown: CancellationToken,
     log: Logger,
 }
 
@@ -446,7 +801,11 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
     async fn run(self) {
         let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
         loop {
-            let (n, peer) = match self.socket.recv_from(&mut buf).await {
+            let result = tokio::select! {
+                result = self.socket.recv_from(&mut buf) => result,
+                () = self.shutdown.cancelled() => break,
+            };
+            let (n, peer) = match result {
                 Ok((n, SocketAddr::V6(addr))) => (n, addr),
                 // We only use IPv6; we can't receive from an IPv4 peer.
                 Ok((_, SocketAddr::V4(_))) => unreachable!(),
@@ -465,6 +824,16 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                 }
             };
 
+            SharedSocketStatsCollector::add(&self.stats.packets_received, 1);
+
+            // Capture everything, including packets we're about to discard.
+            crate::probes::recv_packet!(|| (
+                peer.to_string(),
+                buf.as_ptr() as usize as u64,
+                n as u64,
+            ));
+            self.tap.record(Direction::FromSp, peer, &buf[..n]).await;
+
             // Before doing anything else, check our peer's scope ID: If we
             // don't have a `SingleSp` handler for the interface identified by
             // that scope ID, discard this packet.
@@ -475,6 +844,10 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
             {
                 Ok(name) => name,
                 Err(err) => {
+                    SharedSocketStatsCollector::add(
+                        &self.stats.interface_lookup_failures,
+                        1,
+                    );
                     warn!(
                         self.log,
                         "failed to look up interface for peer; discarding packet";
@@ -490,6 +863,7 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                 .await
                 .contains_key(&peer_interface)
             {
+                SharedSocketStatsCollector::add(&self.stats.no_handler, 1);
                 warn!(
                     self.log, "discarding packet from unknown interface";
                     "interface" => peer_interface.to_string(),
@@ -498,9 +872,11 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
             }
 
             let data = &buf[..n];
-            let (header, kind, trailing_data) = match self.parse_message(data) {
+            let (header, kind, trailing_data) = match parse_packet(data) {
                 Ok((header, kind, data)) => (header, kind, data),
                 Err(err) => {
+                    let err = RecvError::from(err);
+                    err.record(&self.stats);
                     warn!(
                         self.log, "failed to parse incoming packet";
                         "data" => ?data,
@@ -515,6 +891,7 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
             if let Err(err) =
                 self.handle_message(&message, trailing_data, peer).await
             {
+                err.record(&self.stats);
                 warn!(
                     self.log, "failed to handle incoming message";
                     "message" => ?Message { header, kind },
@@ -524,42 +901,18 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                 continue;
             }
         }
-    }
 
-    fn parse_message<'a>(
-        &self,
-        data: &'a [u8],
-    ) -> Result<(Header, MessageKind, &'a [u8]), RecvError> {
-        // Peel off the header first to check the version.
-        let (header, remaining) = gateway_messages::deserialize::<Header>(data)
-            .map_err(RecvError::DeserializeHeader)?;
-        if header.version < version::MIN {
-            return Err(RecvError::VersionMismatch {
-                expected: version::CURRENT,
-                sp: header.version,
-            });
+        // We've stopped accepting host phase 2 requests; let any responses
+        // we're already sending finish.
+        let tasks = mem::take(&mut *self.host_phase2_tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(err) = task.await {
+                warn!(
+                    self.log, "host phase 2 response task failed";
+                    "err" => %err,
+                );
+            }
         }
-
-        // Parse the remainder.
-        let (kind, sp_trailing_data) =
-            match gateway_messages::deserialize::<MessageKind>(remaining) {
-                Ok((kind, sp_trailing_data)) => (kind, sp_trailing_data),
-                // We failed to deserialize, and the message version is higher
-                // than what we know. This almost certainly means they sent a
-                // new message we don't understand; return a version mismatch
-                // error.
-                Err(_) if header.version > version::CURRENT => {
-                    return Err(RecvError::VersionMismatch {
-                        expected: version::CURRENT,
-                        sp: header.version,
-                    })
-                }
-                // We failed to deserialize but the version is in the range we
-                // should have understood; return a deserialization error.
-                Err(err) => return Err(RecvError::DeserializeBody(err)),
-            };
-
-        Ok((header, kind, sp_trailing_data))
     }
 
     async fn handle_message(
@@ -596,18 +949,20 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                 // on it. We do not attempt to retry or handle errors in this
                 // task; if something goes wrong, the SP will re-request the
                 // same block of data.
-                tokio::spawn(
+                let task = tokio::spawn(
                     SendHostPhase2ResponseTask {
                         scope_id_cache: Arc::clone(&self.scope_id_cache),
                         single_sp_handlers: Arc::clone(
                             &self.single_sp_handlers,
                         ),
-                        socket: SendOnlyUdpSocket::from(Arc::clone(
-                            &self.socket,
-                        )),
+                        socket: SendOnlyUdpSocket::new(
+                            Arc::clone(&self.socket),
+                            Arc::clone(&self.tap),
+                        ),
                         host_phase2_provider: Arc::clone(
                             &self.host_phase2_provider,
                         ),
+                        stats: Arc::clone(&self.stats),
                         peer,
                         message_id: message.header.message_id,
                         hash,
@@ -616,6 +971,12 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                     }
                     .run(),
                 );
+
+                // Remember the task (forgetting any that have finished) so
+                // `run()` can wait for it when we shut down.
+                let mut tasks = self.host_phase2_tasks.lock().unwrap();
+                tasks.retain(|task| !task.is_finished());
+                tasks.push(task);
                 Ok(())
             }
             &MessageKind::SpRequest(SpRequest::SerialConsole {
@@ -635,7 +996,20 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                 .await
             }
             MessageKind::SpResponse(response) => {
-                forward_to_single_sp(
+                // Discovery responses may also be of interest to in-progress
+                // `discover_all()` calls, in which case it's fine if no
+                // `SingleSp` wants this response.
+                let mut discover_all_listening = false;
+                if let SpResponse::Discover(discovery) = response {
+                    discover_all_listening = notify_discover_all(
+                        &self.scope_id_cache,
+                        &self.single_sp_handlers,
+                        DiscoveredSp { addr: peer, sp_port: discovery.sp_port },
+                    )
+                    .await?;
+                }
+
+                match forward_to_single_sp(
                     &self.scope_id_cache,
                     &self.single_sp_handlers,
                     peer,
@@ -647,6 +1021,14 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
                     },
                 )
                 .await
+                {
+                    Err(RecvError::NoHandler { .. })
+                        if discover_all_listening =>
+                    {
+                        Ok(())
+                    }
+                    result => result,
+                }
             }
         }
     }
@@ -654,7 +1036,7 @@ impl<T: HostPhase2Provider> RecvHandler<T> {
 
 async fn forward_to_single_sp(
     scope_id_cache: &ScopeIdCache,
-    single_sp_handlers: &Mutex<FxHashMap<Name, mpsc::Sender<SingleSpMessage>>>,
+    single_sp_handlers: &Mutex<HandlerMap>,
     peer: SocketAddrV6,
     message: SingleSpMessage,
 ) -> Result<(), RecvError> {
@@ -662,11 +1044,12 @@ async fn forward_to_single_sp(
         .index_to_name(peer.scope_id())
         .await
         .map_err(|err| RecvError::InterfaceForScopeId { addr: peer, err })?;
+    let sp_ip = *peer.ip();
 
     let mut single_sp_handlers = single_sp_handlers.lock().await;
     let slot = single_sp_handlers.entry(interface.clone());
 
-    let entry = match slot {
+    let mut entry = match slot {
         hash_map::Entry::Occupied(entry) => entry,
         hash_map::Entry::Vacant(_) => {
             // This error is _extremely_ unlikely, because we checked
@@ -676,36 +1059,95 @@ async fn forward_to_single_sp(
             // between our check above and our check now.
             return Err(RecvError::NoHandler {
                 interface: interface.to_string(),
+                addr: sp_ip,
             });
         }
     };
+    let handlers = entry.get_mut();
 
     // We are running in the active `recv()` task, and we don't want to
     // allow a sluggish `SingleSp` handler to block us. We use a bounded
     // channel and `try_send`: if there's no room in the channel, we'll log
     // an error and discard the packet.
-    match entry.get().try_send(message) {
-        Ok(()) => Ok(()),
-        Err(TrySendError::Full(_)) => {
-            Err(RecvError::HandlerBusy { interface: interface.to_string() })
+    //
+    // Prefer a handler targeting `peer` specifically, falling back to the
+    // handler for any SP on this interface.
+    let result = if let Some(tx) = handlers.by_sp_addr.get(&sp_ip) {
+        let result = tx.try_send(message);
+        if let Err(TrySendError::Closed(_)) = &result {
+            handlers.by_sp_addr.remove(&sp_ip);
+        }
+        result
+    } else if let Some(tx) = handlers.any_sp.as_ref() {
+        let result = tx.try_send(message);
+        if let Err(TrySendError::Closed(_)) = &result {
+            handlers.any_sp = None;
         }
+        result
+    } else {
+        return Err(RecvError::NoHandler {
+            interface: interface.to_string(),
+            addr: sp_ip,
+        });
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => Err(RecvError::HandlerBusy {
+            interface: interface.to_string(),
+            addr: sp_ip,
+        }),
         Err(TrySendError::Closed(_)) => {
-            // The handler is gone; remove it from our map _and_ fail.
-            entry.remove();
-            Err(RecvError::NoHandler { interface: interface.to_string() })
+            // The handler is gone; we removed it from our map above (and
+            // remove the interface too if that was its last handler), but
+            // still fail.
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            Err(RecvError::NoHandler {
+                interface: interface.to_string(),
+                addr: sp_ip,
+            })
         }
     }
 }
 
+// Forward `sp` to any in-progress `discover_all()` calls on the interface it
+// was received on, returning `true` if there were any.
+async fn notify_discover_all(
+    scope_id_cache: &ScopeIdCache,
+    single_sp_handlers: &Mutex<HandlerMap>,
+    sp: DiscoveredSp,
+) -> Result<bool, RecvError> {
+    let interface = scope_id_cache
+        .index_to_name(sp.addr.scope_id())
+        .await
+        .map_err(|err| RecvError::InterfaceForScopeId { addr: sp.addr, err })?;
+
+    let single_sp_handlers = single_sp_handlers.lock().await;
+    let listeners = match single_sp_handlers.get(&interface) {
+        Some(handlers) => &handlers.discover_all,
+        None => return Ok(false),
+    };
+
+    for tx in listeners {
+        // As in `forward_to_single_sp`, we don't want to block; if a listener
+        // is full or gone, it doesn't need this response anyway.
+        _ = tx.try_send(sp);
+    }
+
+    Ok(!listeners.is_empty())
+}
+
 // Struct holding all the arguments needed to respond to an SP's request for
 // host phase 2 data and report the request/response back to the relevant single
 // SP handler.
 struct SendHostPhase2ResponseTask<T> {
     scope_id_cache: Arc<ScopeIdCache>,
-    single_sp_handlers:
-        Arc<Mutex<FxHashMap<Name, mpsc::Sender<SingleSpMessage>>>>,
+    single_sp_handlers: Arc<Mutex<HandlerMap>>,
     socket: SendOnlyUdpSocket,
     host_phase2_provider: Arc<T>,
+    stats: Arc<SharedSocketStatsCollector>,
     peer: SocketAddrV6,
     message_id: u32,
     hash: [u8; 32],
@@ -775,6 +1217,17 @@ impl<T: HostPhase2Provider> SendHostPhase2ResponseTask<T> {
         match self.socket.send_to(serialized_message, self.peer).await {
             Ok(_) => {
                 if let Some(data_sent) = data_sent {
+                    SharedSocketStatsCollector::add(
+                        &self.stats.host_phase2_bytes_sent,
+                        data_sent,
+                    );
+                    crate::probes::host_phase2_served!(|| (
+                        self.peer.to_string(),
+                        hex::encode(hash),
+                        offset,
+                        data_sent,
+                    ));
+
                     // Notify our handler of this request so it can report
                     // progress to its clients.
                     if let Err(err) = forward_to_single_sp(
@@ -808,3 +1261,323 @@ impl<T: HostPhase2Provider> SendHostPhase2ResponseTask<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryHostPhase2Provider;
+    use gateway_messages::DiscoverResponse;
+
+    // Our fake system knows about two interfaces: "sled0" (scope ID 1) and
+    // "sled1" (scope ID 2).
+    fn fake_scope_id_cache() -> ScopeIdCache {
+        ScopeIdCache::with_lookups(
+            |name| match name {
+                "sled0" => Ok(1),
+                "sled1" => Ok(2),
+                _ => Err(InterfaceError::NoNameFound(0)),
+            },
+            |index| match index {
+                1 => Ok("sled0".to_string()),
+                2 => Ok("sled1".to_string()),
+                _ => Err(InterfaceError::NoNameFound(index)),
+            },
+        )
+    }
+
+    fn sp_addr(n: u16, scope_id: u32) -> SocketAddrV6 {
+        SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, n),
+            crate::SP_PORT,
+            0,
+            scope_id,
+        )
+    }
+
+    fn serial_console(offset: u64) -> SingleSpMessage {
+        SingleSpMessage::SerialConsole {
+            component: SpComponent::SP_ITSELF,
+            offset,
+            data: Vec::new(),
+        }
+    }
+
+    fn received_offset(rx: &mut mpsc::Receiver<SingleSpMessage>) -> u64 {
+        match rx.try_recv() {
+            Ok(SingleSpMessage::SerialConsole { offset, .. }) => offset,
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_prefers_handler_for_sp_addr() {
+        let cache = fake_scope_id_cache();
+        let handlers = Mutex::new(HandlerMap::default());
+        let (sp1_tx, mut sp1_rx) = mpsc::channel(4);
+        let (any_tx, mut any_rx) = mpsc::channel(4);
+        {
+            let mut handlers = handlers.lock().await;
+            let sled0 = handlers.entry(Name::from("sled0")).or_default();
+            sled0.by_sp_addr.insert(*sp_addr(1, 1).ip(), sp1_tx);
+            sled0.any_sp = Some(any_tx);
+        }
+
+        // Packets from SP 1 go to its handler, and packets from any other SP
+        // on the same interface go to the catch-all handler...
+        forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(1),
+        )
+        .await
+        .unwrap();
+        forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(2, 1),
+            serial_console(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(received_offset(&mut sp1_rx), 1);
+        assert_eq!(received_offset(&mut any_rx), 2);
+
+        // ...but packets from the same SP address on an interface we have no
+        // handlers for are discarded.
+        let result = forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 2),
+            serial_console(3),
+        )
+        .await;
+        assert!(matches!(result, Err(RecvError::NoHandler { .. })));
+        assert!(sp1_rx.try_recv().is_err());
+        assert!(any_rx.try_recv().is_err());
+
+        // Once SP 1's handler is gone, we discard the next packet from it (and
+        // forget the handler), then fall back to the catch-all handler.
+        drop(sp1_rx);
+        let result = forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(4),
+        )
+        .await;
+        assert!(matches!(result, Err(RecvError::NoHandler { .. })));
+        assert!(handlers.lock().await[&Name::from("sled0")]
+            .by_sp_addr
+            .is_empty());
+        forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(received_offset(&mut any_rx), 5);
+    }
+
+    #[tokio::test]
+    async fn forward_to_busy_or_closed_handler() {
+        let cache = fake_scope_id_cache();
+        let handlers = Mutex::new(HandlerMap::default());
+        let (tx, rx) = mpsc::channel(1);
+        handlers.lock().await.entry(Name::from("sled0")).or_default().any_sp =
+            Some(tx);
+
+        forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(1),
+        )
+        .await
+        .unwrap();
+        let result = forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(2),
+        )
+        .await;
+        assert!(matches!(result, Err(RecvError::HandlerBusy { .. })));
+
+        // Dropping the last handler on an interface removes the interface.
+        drop(rx);
+        let result = forward_to_single_sp(
+            &cache,
+            &handlers,
+            sp_addr(1, 1),
+            serial_console(3),
+        )
+        .await;
+        assert!(matches!(result, Err(RecvError::NoHandler { .. })));
+        assert!(handlers.lock().await.is_empty());
+    }
+
+    async fn test_recv_handler() -> RecvHandler<InMemoryHostPhase2Provider> {
+        let log = Logger::root(slog::Discard, o!());
+        let socket = Arc::new(UdpSocket::bind("[::1]:0").await.unwrap());
+        let scope_id_cache = Arc::new(fake_scope_id_cache());
+        let tap = Arc::new(PacketTap::new(
+            SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0),
+            Arc::clone(&scope_id_cache),
+            log.clone(),
+        ));
+        RecvHandler {
+            socket,
+            scope_id_cache,
+            single_sp_handlers: Arc::default(),
+            host_phase2_provider: Arc::new(
+                InMemoryHostPhase2Provider::with_capacity(1),
+            ),
+            host_phase2_tasks: std::sync::Mutex::default(),
+            tap,
+            stats: Arc::default(),
+            shutdown: CancellationToken::new(),
+            log,
+        }
+    }
+
+    fn discover_response(sp_port: SpPort) -> Message {
+        Message {
+            header: Header { version: version::CURRENT, message_id: 0 },
+            kind: MessageKind::SpResponse(SpResponse::Discover(
+                DiscoverResponse { sp_port },
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_responses_from_multiple_sps() {
+        let handler = test_recv_handler().await;
+        let (discover_tx1, mut discover_rx1) = mpsc::channel(8);
+        let (discover_tx2, mut discover_rx2) = mpsc::channel(8);
+        let (sp1_tx, mut sp1_rx) = mpsc::channel(8);
+        {
+            let mut handlers = handler.single_sp_handlers.lock().await;
+            let sled0 = handlers.entry(Name::from("sled0")).or_default();
+            sled0.discover_all = vec![discover_tx1, discover_tx2];
+            sled0.by_sp_addr.insert(*sp_addr(1, 1).ip(), sp1_tx);
+        }
+
+        // Three SPs respond on sled0 (one of them twice); only SP 1 has a
+        // `SingleSp` handler, but the others aren't discarded because there
+        // are `discover_all()` calls listening.
+        for (addr, port) in [
+            (sp_addr(1, 1), SpPort::One),
+            (sp_addr(2, 1), SpPort::Two),
+            (sp_addr(3, 1), SpPort::One),
+            (sp_addr(2, 1), SpPort::Two),
+        ] {
+            handler
+                .handle_message(&discover_response(port), &[], addr)
+                .await
+                .unwrap();
+        }
+
+        // Nobody is discovering on sled1.
+        let result = handler
+            .handle_message(&discover_response(SpPort::One), &[], sp_addr(4, 2))
+            .await;
+        assert!(matches!(result, Err(RecvError::NoHandler { .. })));
+
+        // SP 1's handler sees its response...
+        match sp1_rx.try_recv() {
+            Ok(SingleSpMessage::SpResponse {
+                peer,
+                response: SpResponse::Discover(_),
+                ..
+            }) => assert_eq!(peer, sp_addr(1, 1)),
+            other => panic!("unexpected message {other:?}"),
+        }
+        assert!(sp1_rx.try_recv().is_err());
+
+        // ...and every discovery listener sees each SP once.
+        let expected = [
+            DiscoveredSp { addr: sp_addr(1, 1), sp_port: SpPort::One },
+            DiscoveredSp { addr: sp_addr(2, 1), sp_port: SpPort::Two },
+            DiscoveredSp { addr: sp_addr(3, 1), sp_port: SpPort::One },
+        ];
+        for rx in [&mut discover_rx1, &mut discover_rx2] {
+            let deadline = Instant::now() + Duration::from_millis(50);
+            assert_eq!(collect_discovered(rx, deadline).await, expected);
+        }
+    }
+
+    async fn test_shared_socket() -> (SharedSocket, SocketAddr) {
+        let socket = UdpSocket::bind("[::1]:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let shared = SharedSocket::from_socket(
+            socket,
+            Arc::new(InMemoryHostPhase2Provider::with_capacity(1)),
+            Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        (shared, addr)
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_recv_task() {
+        let (shared, addr) = test_shared_socket().await;
+
+        // Make sure our receive task is up and running.
+        let peer = UdpSocket::bind("[::1]:0").await.unwrap();
+        peer.send_to(b"not a message", addr).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while shared.stats().packets_received == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("packet not received");
+
+        let shutdown = shared.shutdown.clone();
+        tokio::time::timeout(Duration::from_secs(2), shared.shutdown())
+            .await
+            .expect("recv task did not stop");
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn unregister_removes_handlers() {
+        let (shared, _addr) = test_shared_socket().await;
+        let sled0 = Name::from("sled0");
+        let sp1_ip = *sp_addr(1, 0).ip();
+
+        let mut any_sp =
+            shared.single_sp_handler("sled0", default_discovery_addr()).await;
+        let mut sp1 = shared.single_sp_handler("sled0", sp_addr(1, 0)).await;
+        {
+            let handlers = shared.single_sp_handlers.lock().await;
+            assert!(handlers[&sled0].any_sp.is_some());
+            assert!(handlers[&sled0].by_sp_addr.contains_key(&sp1_ip));
+        }
+
+        // Unregistering one handle leaves the other in place...
+        sp1.unregister().await;
+        {
+            let handlers = shared.single_sp_handlers.lock().await;
+            assert!(handlers[&sled0].any_sp.is_some());
+            assert!(handlers[&sled0].by_sp_addr.is_empty());
+        }
+
+        // ...and frees its address for a new handle.
+        let mut sp1 = shared.single_sp_handler("sled0", sp_addr(1, 0)).await;
+        assert!(shared.single_sp_handlers.lock().await[&sled0]
+            .by_sp_addr
+            .contains_key(&sp1_ip));
+
+        // Once every handle on an interface is unregistered, the interface
+        // is forgotten.
+        any_sp.unregister().await;
+        sp1.unregister().await;
+        assert!(shared.single_sp_handlers.lock().await.is_empty());
+
+        shared.shutdown().await;
+    }
+}
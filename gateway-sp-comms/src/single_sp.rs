@@ -8,17 +8,31 @@
 
 use crate::error::CommunicationError;
 use crate::error::UpdateError;
-use crate::shared_socket::SingleSpHandle;
-use crate::shared_socket::SingleSpHandleError;
+use crate::rpc_policy::CallOptions;
+use crate::rpc_policy::RequestKind;
+use crate::rpc_policy::RpcPolicies;
+use crate::rpc_policy::RpcPolicy;
+use crate::session::RecordingTransport;
+use crate::session::SessionRecorder;
 use crate::shared_socket::SingleSpMessage;
 use crate::sp_response_ext::SpResponseExt;
+use crate::stats::SingleSpStats;
+use crate::stats::SingleSpStatsCollector;
+use crate::transport::SpTransport;
+use crate::DiscoveryConfig;
 use crate::SharedSocket;
 use crate::SwitchPortConfig;
 use crate::VersionedSpState;
-use async_trait::async_trait;
 use backoff::backoff::Backoff;
 use gateway_messages::ignition::LinkEvents;
 use gateway_messages::ignition::TransceiverSelect;
+use gateway_messages::measurement::ComponentMeasurement;
+use gateway_messages::measurement::Measurement;
+use gateway_messages::measurement::MeasurementV2;
+use gateway_messages::measurement::VersionedMeasurement;
+use gateway_messages::request;
+use gateway_messages::request::Request;
+use gateway_messages::request::TlvResponse;
 use gateway_messages::tlv;
 use gateway_messages::version;
 use gateway_messages::ComponentAction;
@@ -33,15 +47,15 @@ use gateway_messages::Message;
 use gateway_messages::MessageKind;
 use gateway_messages::MgsRequest;
 use gateway_messages::PowerState;
+use gateway_messages::ProtocolCapabilities;
 use gateway_messages::SpComponent;
 use gateway_messages::SpError;
 use gateway_messages::SpPort;
-use gateway_messages::SpRequest;
 use gateway_messages::SpResponse;
 use gateway_messages::StartupOptions;
-use gateway_messages::TlvPage;
 use gateway_messages::UpdateStatus;
 use gateway_messages::MIN_TRAILING_DATA_LEN;
+use lru_cache::LruCache;
 use serde::Serialize;
 use slog::debug;
 use slog::error;
@@ -49,16 +63,16 @@ use slog::info;
 use slog::trace;
 use slog::warn;
 use slog::Logger;
+use std::collections::HashMap;
 use std::io::Cursor;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::net::SocketAddr;
 use std::net::SocketAddrV6;
 use std::str;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::oneshot;
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
@@ -66,18 +80,17 @@ use tokio::time;
 use tokio::time::Instant;
 use uuid::Uuid;
 
+mod health;
 mod update;
 
+pub use self::health::SpHealth;
+
+use self::health::HealthTracker;
 use self::update::start_component_update;
 use self::update::start_rot_update;
 use self::update::start_sp_update;
 use self::update::update_status;
-
-// Once we've discovered an SP, continue to send discovery packets on this
-// interval to detect changes.
-//
-// TODO-correctness/TODO-security What do we do if the SP address changes?
-const DISCOVERY_INTERVAL_IDLE: Duration = Duration::from_secs(60);
+use self::update::UpdateDrivers;
 
 // Minor "malicious / misbehaving SP" denial of service protection: When we ask
 // the SP for its inventory or details of a component, we get back a response
@@ -92,6 +105,16 @@ const DISCOVERY_INTERVAL_IDLE: Duration = Duration::from_secs(60);
 // will require an MGS update.
 const TLV_RPC_TOTAL_ITEMS_DOS_LIMIT: u32 = 1024;
 
+// Maximum number of RPCs we'll have outstanding at once to an SP that supports
+// concurrent RPCs. Further requests wait in our command queue.
+const MAX_CONCURRENT_RPCS: usize = 8;
+
+// Maximum number of impostor SP addresses we remember. Each impostor is
+// identified (costing an RPC) the first time we discover it, so this only
+// needs to cover the handful of other SPs that might answer our discovery
+// packets; if more than this show up, we forget the least recently seen.
+const MAX_IMPOSTOR_ADDRS: usize = 16;
+
 type Result<T, E = CommunicationError> = std::result::Result<T, E>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -121,15 +144,69 @@ pub struct SpComponentDetails {
     pub entries: Vec<ComponentDetails>,
 }
 
+/// Identity of an SP, as reported in its [`VersionedSpState`].
+///
+/// A [`SingleSp`] pins the identity of the SP it first discovers, and from then
+/// on refuses to talk to an SP with a different identity (even if it responds
+/// to discovery on the same interface).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpIdentity {
+    pub serial_number: [u8; 32],
+    pub model: [u8; 32],
+}
+
+impl From<&'_ VersionedSpState> for SpIdentity {
+    fn from(state: &VersionedSpState) -> Self {
+        match state {
+            VersionedSpState::V1(state) => {
+                Self { serial_number: state.serial_number, model: state.model }
+            }
+            VersionedSpState::V2(state) => {
+                Self { serial_number: state.serial_number, model: state.model }
+            }
+        }
+    }
+}
+
+/// Changes to the SP a [`SingleSp`] is communicating with; see
+/// [`SingleSp::identity_events()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpIdentityEvent {
+    /// We discovered our SP for the first time and pinned its identity.
+    Pinned { addr: SocketAddrV6, identity: SpIdentity },
+    /// Our SP is now reachable at a new address, and reported the identity we
+    /// pinned.
+    AddressChanged {
+        old_addr: SocketAddrV6,
+        new_addr: SocketAddrV6,
+        identity: SpIdentity,
+    },
+    /// An SP with an identity other than the one we pinned responded to
+    /// discovery. We will not talk to it.
+    ImpostorDetected {
+        addr: SocketAddrV6,
+        expected: SpIdentity,
+        found: SpIdentity,
+    },
+}
+
 #[derive(Debug)]
 pub struct SingleSp {
     interface: String,
     cmds_tx: mpsc::Sender<InnerCommand>,
     sp_addr_rx: watch::Receiver<Option<(SocketAddrV6, SpPort)>>,
+    identity_rx: watch::Receiver<Option<SpIdentity>>,
+    identity_events_tx: broadcast::Sender<SpIdentityEvent>,
+    health_rx: watch::Receiver<SpHealth>,
+    stats: Arc<SingleSpStatsCollector>,
+    update_drivers: UpdateDrivers,
     inner_task: JoinHandle<()>,
     log: Logger,
 }
 
+// Dropping a `SingleSp` abandons whatever it was doing mid-stream (including
+// any RPC or update chunk in flight); `SingleSp::shutdown()` is the polite
+// alternative.
 impl Drop for SingleSp {
     fn drop(&mut self) {
         self.inner_task.abort();
@@ -154,88 +231,153 @@ impl SingleSp {
     ///    `config.listen_addr` is invalid), the returned `SingleSp` will return
     ///    a "UDP bind failed" error from all methods forever.
     ///
+    /// If `config.discovery_addr` is a unicast address, the returned `SingleSp`
+    /// targets only the SP at that address, and other `SingleSp`s may be
+    /// created for other SPs on the same `config.interface`.
+    ///
     /// Note that `max_attempts_per_rpc` may be overridden for certain kinds of
     /// requests. Today, the only request that overrides this value is resetting
     /// an SP, which (particularly for sidecars) can take much longer than any
     /// other request. `SingleSp` will internally use a higher max attempt count
-    /// for these messages (but will still respect `per_attempt_timeout`).
+    /// for these messages (but will still respect `per_attempt_timeout`); see
+    /// [`RpcPolicies::new()`]. Use [`SingleSp::new_with_rpc_policies()`] for
+    /// finer-grained control.
     pub async fn new(
         shared_socket: &SharedSocket,
         config: SwitchPortConfig,
         max_attempts_per_rpc: usize,
         per_attempt_timeout: Duration,
+    ) -> Self {
+        Self::new_with_rpc_policies(
+            shared_socket,
+            config,
+            RpcPolicies::new(max_attempts_per_rpc, per_attempt_timeout),
+        )
+        .await
+    }
+
+    /// Construct a new `SingleSp` that uses `rpc_policies` to decide how to
+    /// retry and time out each kind of request.
+    ///
+    /// Policies may also be overridden (and deadlines or cancellation tokens
+    /// applied) for individual calls via [`CallOptions::scope()`]. See
+    /// [`SingleSp::new()`] for other details.
+    pub async fn new_with_rpc_policies(
+        shared_socket: &SharedSocket,
+        config: SwitchPortConfig,
+        rpc_policies: RpcPolicies,
     ) -> Self {
         let handle = shared_socket
             .single_sp_handler(&config.interface, config.discovery_addr)
             .await;
 
-        let log = handle.log().clone();
-
-        Self::new_impl(
-            handle,
-            config.interface,
-            max_attempts_per_rpc,
-            per_attempt_timeout,
-            log,
-        )
+        Self::with_transport(handle, config.discovery, rpc_policies)
     }
 
-    /// Create a new `SingleSp` instance specifically for testing (i.e.,
-    /// communicating with a simulated SP).
-    ///
-    /// Unlike [`SingleSp::new()`], this method takes an existing bound
-    /// [`UdpSocket`] and the target address of the SP. This allows multiple
-    /// `SingleSp`s to exist on the same interface (e.g., the loopback
-    /// interface) for testing.
-    pub fn new_direct_socket_for_testing(
-        socket: UdpSocket,
-        discovery_addr: SocketAddrV6,
-        max_attempts_per_rpc: usize,
-        per_attempt_timeout: Duration,
-        log: Logger,
+    /// Like [`SingleSp::new_with_rpc_policies()`], but records every packet
+    /// exchanged with the SP to `recorder`; see [`session`](crate::session).
+    pub async fn new_recording(
+        shared_socket: &SharedSocket,
+        config: SwitchPortConfig,
+        rpc_policies: RpcPolicies,
+        recorder: SessionRecorder,
     ) -> Self {
-        let wrapper =
-            InnerSocketWrapper { socket, discovery_addr, log: log.clone() };
-
-        Self::new_impl(
-            wrapper,
-            "(direct socket handle)".to_string(),
-            max_attempts_per_rpc,
-            per_attempt_timeout,
-            log,
+        let handle = shared_socket
+            .single_sp_handler(&config.interface, config.discovery_addr)
+            .await;
+
+        Self::with_transport(
+            RecordingTransport::new(handle, recorder),
+            config.discovery,
+            rpc_policies,
         )
     }
 
-    // Shared implementation of `new` and `new_direct_socket_for_testing` that
-    // doesn't care whether we're using a `SharedSocket` or a
-    // `InnerSocketWrapper` (the latter for tests).
-    fn new_impl<T: InnerSocket + Send + 'static>(
-        socket: T,
-        interface: String,
-        max_attempts_per_rpc: usize,
-        per_attempt_timeout: Duration,
-        log: Logger,
+    /// Construct a new `SingleSp` that communicates with its SP via
+    /// `transport` (e.g., a [`UdpTransport`](crate::UdpTransport) talking to
+    /// a simulated SP, or an in-process channel).
+    ///
+    /// See [`SingleSp::new_with_rpc_policies()`] for details on
+    /// `rpc_policies`.
+    pub fn with_transport<T: SpTransport>(
+        transport: T,
+        discovery: DiscoveryConfig,
+        rpc_policies: RpcPolicies,
     ) -> Self {
-        // SPs don't support pipelining, so any command we send to
+        let interface = transport.interface().to_string();
+        let log = transport.log().clone();
+
+        // Unless an SP supports concurrent RPCs, any command we send to
         // `Inner` that involves contacting an SP will effectively block
-        // until it completes. We use a more-or-less arbitrary chanel
+        // until it completes (and even if it does, `Inner` limits how many
+        // RPCs it has outstanding). We use a more-or-less arbitrary chanel
         // size of 8 here to allow (a) non-SP commands (e.g., detaching
         // the serial console) and (b) a small number of enqueued SP
         // commands to be submitted without blocking the caller.
         let (cmds_tx, cmds_rx) = mpsc::channel(8);
         let (sp_addr_tx, sp_addr_rx) = watch::channel(None);
+        let (identity_tx, identity_rx) = watch::channel(None);
+        let (health_tx, health_rx) =
+            watch::channel(SpHealth::Unreachable { since: Instant::now() });
+
+        // Identity events are rare (typically one `Pinned` event for the life
+        // of the `SingleSp`); a small buffer is plenty for any subscriber that
+        // isn't completely stalled.
+        let (identity_events_tx, _) = broadcast::channel(16);
+
+        let stats = Arc::default();
 
         let inner = Inner::new(
-            socket,
+            transport,
             sp_addr_tx,
-            max_attempts_per_rpc,
-            per_attempt_timeout,
+            identity_tx,
+            identity_events_tx.clone(),
+            health_tx,
+            Arc::clone(&stats),
+            discovery,
+            rpc_policies,
             cmds_rx,
         );
 
         let inner_task = tokio::spawn(inner.run());
 
-        Self { interface, cmds_tx, sp_addr_rx, inner_task, log }
+        Self {
+            interface,
+            cmds_tx,
+            sp_addr_rx,
+            identity_rx,
+            identity_events_tx,
+            health_rx,
+            stats,
+            update_drivers: UpdateDrivers::default(),
+            inner_task,
+            log,
+        }
+    }
+
+    /// Stop communicating with our SP, cleaning up after ourselves first.
+    ///
+    /// Any update we're delivering in the background is aborted between
+    /// chunks (and the SP asked to abort it too), we finish any requests
+    /// already queued or in flight, and we detach any attached serial
+    /// console. Finally, if we were created from a [`SharedSocket`], we
+    /// unregister from it so another `SingleSp` may be created for the same
+    /// interface and discovery address.
+    ///
+    /// Afterwards, requests made via anything derived from this `SingleSp`
+    /// (e.g., an [`AttachedSerialConsoleSend`]) fail with
+    /// [`CommunicationError::ShutDown`].
+    pub async fn shutdown(mut self) {
+        self.update_drivers.shutdown(&self.log).await;
+
+        let (tx, rx) = oneshot::channel();
+        if self.cmds_tx.send(InnerCommand::Shutdown(tx)).await.is_ok() {
+            _ = rx.await;
+        }
+
+        if let Err(err) = (&mut self.inner_task).await {
+            error!(self.log, "SingleSp task failed"; "err" => %err);
+        }
     }
 
     fn log(&self) -> &Logger {
@@ -254,6 +396,41 @@ impl SingleSp {
         &self.sp_addr_rx
     }
 
+    /// Get the identity of our SP, if we've discovered it.
+    ///
+    /// This identity is pinned the first time we discover an SP; if an SP at a
+    /// different address later responds to discovery, we only switch to it if
+    /// it reports this same identity.
+    pub fn identity(&self) -> Option<SpIdentity> {
+        *self.identity_rx.borrow()
+    }
+
+    /// Retrieve a [`watch::Receiver`] reporting whether our SP is reachable.
+    ///
+    /// This is updated based on the outcome of RPCs (including the periodic
+    /// discovery packets we send while otherwise idle) and any unsolicited
+    /// traffic we receive from the SP; it does not send any packets of its
+    /// own.
+    pub fn health(&self) -> watch::Receiver<SpHealth> {
+        self.health_rx.clone()
+    }
+
+    /// Get counters and round trip times of the RPCs we've made to our SP, and
+    /// counts of other traffic to and from it.
+    ///
+    /// Like [`SingleSp::health()`], this does not send any packets of its
+    /// own.
+    pub fn stats(&self) -> SingleSpStats {
+        self.stats.snapshot()
+    }
+
+    /// Subscribe to changes in the address or identity of our SP.
+    ///
+    /// Only events that occur after this method is called are reported.
+    pub fn identity_events(&self) -> broadcast::Receiver<SpIdentityEvent> {
+        self.identity_events_tx.subscribe()
+    }
+
     /// Get the most recent host phase 2 request we've received from our target
     /// SP.
     ///
@@ -294,11 +471,7 @@ impl SingleSp {
     ///
     /// This will fail if this SP is not connected to an ignition controller.
     pub async fn ignition_state(&self, target: u8) -> Result<IgnitionState> {
-        self.rpc(MgsRequest::IgnitionState { target }).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_ignition_state().map_err(Into::into)
-            },
-        )
+        self.call(request::IgnitionState { target }).await
     }
 
     /// Request the state of all ignition targets.
@@ -317,9 +490,7 @@ impl SingleSp {
     ///
     /// This will fail if this SP is not connected to an ignition controller.
     pub async fn ignition_link_events(&self, target: u8) -> Result<LinkEvents> {
-        self.rpc(MgsRequest::IgnitionLinkEvents { target }).await.and_then(
-            |(_peer, response, _data)| response.expect_ignition_link_events(),
-        )
+        self.call(request::IgnitionLinkEvents { target }).await
     }
 
     /// Request all link events on all ignition targets.
@@ -349,14 +520,11 @@ impl SingleSp {
         target: Option<u8>,
         transceiver_select: Option<TransceiverSelect>,
     ) -> Result<()> {
-        self.rpc(MgsRequest::ClearIgnitionLinkEvents {
+        self.call(request::ClearIgnitionLinkEvents {
             target,
             transceiver_select,
         })
         .await
-        .and_then(|(_peer, response, _data)| {
-            response.expect_clear_ignition_link_events_ack()
-        })
     }
 
     /// Send an ignition command to the given target.
@@ -367,20 +535,12 @@ impl SingleSp {
         target: u8,
         command: IgnitionCommand,
     ) -> Result<()> {
-        self.rpc(MgsRequest::IgnitionCommand { target, command })
-            .await
-            .and_then(|(_peer, response, _data)| {
-                response.expect_ignition_command_ack().map_err(Into::into)
-            })
+        self.call(request::IgnitionCommand { target, command }).await
     }
 
     /// Request the state of the SP.
     pub async fn state(&self) -> Result<VersionedSpState> {
-        self.rpc(MgsRequest::SpState).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_sp_state().map_err(Into::into)
-            },
-        )
+        self.call(request::SpState).await
     }
 
     /// Request the inventory of the SP.
@@ -405,14 +565,24 @@ impl SingleSp {
         Ok(SpComponentDetails { entries })
     }
 
+    /// Request every measurement the SP knows about, across all of its
+    /// components.
+    ///
+    /// This is equivalent to (but requires far fewer round trips than) calling
+    /// [`SingleSp::component_details()`] for every device in the SP's
+    /// inventory with [`DeviceCapabilities::HAS_MEASUREMENT_CHANNELS`] and
+    /// keeping only the measurements.
+    pub async fn all_measurements(&self) -> Result<Vec<ComponentMeasurement>> {
+        self.get_paginated_tlv_data(BulkMeasurementsTlvRpc { log: self.log() })
+            .await
+    }
+
     /// Get the currently-active slot of a particular component.
     pub async fn component_active_slot(
         &self,
         component: SpComponent,
     ) -> Result<u16> {
-        self.rpc(MgsRequest::ComponentGetActiveSlot(component)).await.and_then(
-            |(_peer, response, _data)| response.expect_component_active_slot(),
-        )
+        self.call(request::ComponentGetActiveSlot(component)).await
     }
 
     /// Set the currently-active slot of a particular component.
@@ -422,18 +592,15 @@ impl SingleSp {
         slot: u16,
         persist: bool,
     ) -> Result<()> {
-        let msg = if persist {
-            MgsRequest::ComponentSetAndPersistActiveSlot { component, slot }
+        if persist {
+            self.call(request::ComponentSetAndPersistActiveSlot {
+                component,
+                slot,
+            })
+            .await
         } else {
-            MgsRequest::ComponentSetActiveSlot { component, slot }
-        };
-        self.rpc(msg).await.and_then(|(_peer, response, _data)| {
-            if persist {
-                response.expect_component_set_and_persist_active_slot_ack()
-            } else {
-                response.expect_component_set_active_slot_ack()
-            }
-        })
+            self.call(request::ComponentSetActiveSlot { component, slot }).await
+        }
     }
 
     /// Request that the status of a component be cleared (e.g., resetting
@@ -442,11 +609,7 @@ impl SingleSp {
         &self,
         component: SpComponent,
     ) -> Result<()> {
-        self.rpc(MgsRequest::ComponentClearStatus(component)).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_component_clear_status_ack()
-            },
-        )
+        self.call(request::ComponentClearStatus(component)).await
     }
 
     async fn get_paginated_tlv_data<T: TlvRpc>(
@@ -463,12 +626,8 @@ impl SingleSp {
             // Index of the first entry we want to fetch.
             let offset = entries.len() as u32;
 
-            let (page, data) = self.rpc(rpc.request(offset)).await.and_then(
-                |(_peer, response, data)| {
-                    let page = rpc.parse_response(response)?;
-                    Ok((page, data))
-                },
-            )?;
+            let TlvResponse { page, data } =
+                self.call(rpc.request(offset)).await?;
 
             // Double-check the numbers we got were reasonable: did we get the
             // page we asked for, and is the total correct? If this is the first
@@ -541,11 +700,7 @@ impl SingleSp {
     /// Startup options are only meaningful for sleds and will only take effect
     /// the next time the sled starts up.
     pub async fn get_startup_options(&self) -> Result<StartupOptions> {
-        self.rpc(MgsRequest::GetStartupOptions).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_startup_options().map_err(Into::into)
-            },
-        )
+        self.call(request::GetStartupOptions).await
     }
 
     /// Set startup options on the target SP.
@@ -556,11 +711,7 @@ impl SingleSp {
         &self,
         startup_options: StartupOptions,
     ) -> Result<()> {
-        self.rpc(MgsRequest::SetStartupOptions(startup_options)).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_set_startup_options_ack().map_err(Into::into)
-            },
-        )
+        self.call(request::SetStartupOptions(startup_options)).await
     }
 
     /// Update a component of the SP (or the SP itself!).
@@ -569,6 +720,13 @@ impl SingleSp {
     /// acknowledges that we want to apply an update, we spawn a background task
     /// to stream the update to the SP and then return. Poll the status of the
     /// update via [`Self::update_status()`].
+    ///
+    /// The background task makes its RPCs with the policy and cancellation
+    /// tokens of the [`CallOptions`] in effect when this is called: cancelling
+    /// a token passed to [`CallOptions::with_cancel()`] stops delivery of the
+    /// update. A deadline set via [`CallOptions::with_deadline()`] does not
+    /// bound the update as a whole; instead, each RPC the task makes may take
+    /// as long as remained until that deadline when this was called.
     pub async fn start_update(
         &self,
         component: SpComponent,
@@ -592,13 +750,31 @@ impl SingleSp {
                     ),
                 ));
             }
-            start_sp_update(&self.cmds_tx, update_id, image, self.log()).await
+            start_sp_update(
+                &self.cmds_tx,
+                &self.stats,
+                &self.update_drivers,
+                update_id,
+                image,
+                self.log(),
+            )
+            .await
         } else if component == SpComponent::ROT {
-            start_rot_update(&self.cmds_tx, update_id, slot, image, self.log())
-                .await
+            start_rot_update(
+                &self.cmds_tx,
+                &self.stats,
+                &self.update_drivers,
+                update_id,
+                slot,
+                image,
+                self.log(),
+            )
+            .await
         } else {
             start_component_update(
                 &self.cmds_tx,
+                &self.stats,
+                &self.update_drivers,
                 component,
                 update_id,
                 slot,
@@ -623,29 +799,18 @@ impl SingleSp {
         component: SpComponent,
         update_id: Uuid,
     ) -> Result<()> {
-        self.rpc(MgsRequest::UpdateAbort { component, id: update_id.into() })
+        self.call(request::UpdateAbort { component, id: update_id.into() })
             .await
-            .and_then(|(_peer, response, _data)| {
-                response.expect_update_abort_ack().map_err(Into::into)
-            })
     }
 
     /// Get the current power state.
     pub async fn power_state(&self) -> Result<PowerState> {
-        self.rpc(MgsRequest::GetPowerState).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_power_state().map_err(Into::into)
-            },
-        )
+        self.call(request::GetPowerState).await
     }
 
     /// Set the current power state.
     pub async fn set_power_state(&self, power_state: PowerState) -> Result<()> {
-        self.rpc(MgsRequest::SetPowerState(power_state)).await.and_then(
-            |(_peer, response, _data)| {
-                response.expect_set_power_state_ack().map_err(Into::into)
-            },
-        )
+        self.call(request::SetPowerState(power_state)).await
     }
 
     /// "Attach" to the serial console, setting up a tokio channel for all
@@ -656,8 +821,8 @@ impl SingleSp {
     ) -> Result<AttachedSerialConsole> {
         let (tx, rx) = oneshot::channel();
 
-        // `Inner::run()` doesn't exit until we are dropped, so unwrapping here
-        // only panics if it itself panicked.
+        // `Inner::run()` doesn't exit until we are dropped or shut down, so
+        // unwrapping here only panics if it itself panicked.
         self.cmds_tx
             .send(InnerCommand::SerialConsoleAttach(component, tx))
             .await
@@ -669,6 +834,7 @@ impl SingleSp {
             key: attachment.key,
             rx: attachment.incoming,
             inner_tx: self.cmds_tx.clone(),
+            stats: Arc::clone(&self.stats),
             log: self.log().clone(),
         })
     }
@@ -687,6 +853,18 @@ impl SingleSp {
         rx.await.unwrap()
     }
 
+    /// Send `request` to the SP and wait for its response.
+    ///
+    /// Every request type in [`gateway_messages::request`] knows how to
+    /// recognize its response (including any trailing data the SP sends with
+    /// it), so this is sufficient to make any call that doesn't require us to
+    /// send trailing data of our own. Requests that do (e.g., serial console
+    /// writes or update chunks) should go through the corresponding dedicated
+    /// methods instead.
+    pub async fn call<R: Request>(&self, request: R) -> Result<R::Response> {
+        call(&self.cmds_tx, request).await
+    }
+
     pub(crate) async fn rpc(
         &self,
         kind: MgsRequest,
@@ -695,9 +873,7 @@ impl SingleSp {
     }
 
     pub async fn send_host_nmi(&self) -> Result<()> {
-        self.rpc(MgsRequest::SendHostNmi).await.and_then(
-            |(_peer, response, _data)| response.expect_send_host_nmi_ack(),
-        )
+        self.call(request::SendHostNmi).await
     }
 
     pub async fn set_ipcc_key_lookup_value(
@@ -711,9 +887,9 @@ impl SingleSp {
             return Err(CommunicationError::IpccKeyLookupValueTooLarge);
         }
 
-        let (result, leftover_data) = rpc_with_trailing_data(
+        let (result, leftover_data) = call_with_trailing_data(
             &self.cmds_tx,
-            MgsRequest::SetIpccKeyLookupValue { key },
+            request::SetIpccKeyLookupValue { key },
             Cursor::new(data),
         )
         .await;
@@ -722,9 +898,7 @@ impl SingleSp {
         // never have any leftover data.
         assert!(CursorExt::is_empty(&leftover_data));
 
-        result.and_then(|(_peer, response, _data)| {
-            response.expect_set_ipcc_key_lookup_value_ack()
-        })
+        result
     }
 
     /// Reads a single value from the SP's caboose (in the active slot)
@@ -733,13 +907,7 @@ impl SingleSp {
     /// `read_component_caboose(SpComponent::SP_ITSELF, 0, key)`, once that
     /// message is widely accepted by SPs in the field.
     pub async fn get_caboose_value(&self, key: [u8; 4]) -> Result<Vec<u8>> {
-        let result =
-            rpc(&self.cmds_tx, MgsRequest::ReadCaboose { key }, None).await;
-
-        result.result.map(|(_peer, response, data)| {
-            response.expect_caboose_value().unwrap();
-            data
-        })
+        self.call(request::ReadCaboose { key }).await
     }
 
     /// Instruct the SP that a reset_component_trigger will be coming with a
@@ -757,13 +925,7 @@ impl SingleSp {
         &self,
         component: SpComponent,
     ) -> Result<()> {
-        self.rpc(MgsRequest::ResetComponentPrepare { component })
-            .await
-            .and_then(|(_peer, response, _data)| {
-                response
-                    .expect_sys_reset_component_prepare_ack()
-                    .map_err(Into::into)
-            })
+        self.call(request::ResetComponentPrepare { component }).await
     }
 
     /// Instruct the SP to reset a component.
@@ -781,10 +943,9 @@ impl SingleSp {
         // response because the RoT was reset or because the message got
         // dropped. TODO: have this code and/or SP check a boot nonce or other
         // information to verify that the RoT did reset.
-        let response =
-            self.rpc(MgsRequest::ResetComponentTrigger { component }).await;
-        match response {
-            Ok((_addr, response, _data)) => {
+        let trigger = request::ResetComponentTrigger { component };
+        match self.rpc(trigger.to_mgs_request()).await {
+            Ok((_addr, response, data)) => {
                 if component == SpComponent::SP_ITSELF {
                     // Reset trigger should retry until we get back an error
                     // indicating the SP wasn't expecting a reset trigger
@@ -794,7 +955,7 @@ impl SingleSp {
                         got: response.name(),
                     })
                 } else {
-                    response.expect_sys_reset_component_trigger_ack()
+                    response.expect::<request::ResetComponentTrigger>(&data)
                 }
             }
             Err(CommunicationError::SpError(
@@ -809,11 +970,7 @@ impl SingleSp {
         component: SpComponent,
         action: ComponentAction,
     ) -> Result<()> {
-        self.rpc(MgsRequest::ComponentAction { component, action })
-            .await
-            .and_then(|(_peer, response, _data)| {
-                response.expect_component_action_ack()
-            })
+        self.call(request::ComponentAction { component, action }).await
     }
 
     pub async fn read_component_caboose(
@@ -822,17 +979,7 @@ impl SingleSp {
         slot: u16,
         key: [u8; 4],
     ) -> Result<Vec<u8>> {
-        let result = rpc(
-            &self.cmds_tx,
-            MgsRequest::ReadComponentCaboose { component, slot, key },
-            None,
-        )
-        .await;
-
-        result.result.map(|(_peer, response, data)| {
-            response.expect_caboose_value().unwrap();
-            data
-        })
+        self.call(request::ReadComponentCaboose { component, slot, key }).await
     }
 }
 
@@ -841,14 +988,14 @@ impl SingleSp {
 trait TlvRpc {
     type Item;
 
+    // The request for a single page.
+    type Request: Request<Response = TlvResponse>;
+
     // A description of this message type used in logs.
     const LOG_NAME: &'static str;
 
     // Build the appropriate request for the given offset.
-    fn request(&self, offset: u32) -> MgsRequest;
-
-    // Parse the SP's response into a description of the page contents.
-    fn parse_response(&self, response: SpResponse) -> Result<TlvPage>;
+    fn request(&self, offset: u32) -> Self::Request;
 
     // Parse a single tag/value pair into an `Item`.
     //
@@ -866,15 +1013,12 @@ struct InventoryTlvRpc;
 
 impl TlvRpc for InventoryTlvRpc {
     type Item = SpDevice;
+    type Request = request::Inventory;
 
     const LOG_NAME: &'static str = "inventory";
 
-    fn request(&self, offset: u32) -> MgsRequest {
-        MgsRequest::Inventory { device_index: offset }
-    }
-
-    fn parse_response(&self, response: SpResponse) -> Result<TlvPage> {
-        response.expect_inventory()
+    fn request(&self, offset: u32) -> Self::Request {
+        request::Inventory { device_index: offset }
     }
 
     fn parse_tag_value(
@@ -936,15 +1080,12 @@ struct ComponentDetailsTlvRpc<'a> {
 
 impl TlvRpc for ComponentDetailsTlvRpc<'_> {
     type Item = ComponentDetails;
+    type Request = request::ComponentDetails;
 
     const LOG_NAME: &'static str = "component details";
 
-    fn request(&self, offset: u32) -> MgsRequest {
-        MgsRequest::ComponentDetails { component: self.component, offset }
-    }
-
-    fn parse_response(&self, response: SpResponse) -> Result<TlvPage> {
-        response.expect_component_details()
+    fn request(&self, offset: u32) -> Self::Request {
+        request::ComponentDetails { component: self.component, offset }
     }
 
     fn parse_tag_value(
@@ -952,8 +1093,10 @@ impl TlvRpc for ComponentDetailsTlvRpc<'_> {
         tag: tlv::Tag,
         value: &[u8],
     ) -> Result<Option<Self::Item>> {
-        use gateway_messages::measurement::Measurement;
         use gateway_messages::measurement::MeasurementHeader;
+        use gateway_messages::measurement::MeasurementHeaderV2;
+        use gateway_messages::monorail_port_status::LinkFlapEvent;
+        use gateway_messages::monorail_port_status::MacTableEntry;
         use gateway_messages::monorail_port_status::PortStatus;
         use gateway_messages::monorail_port_status::PortStatusError;
 
@@ -976,31 +1119,49 @@ impl TlvRpc for ComponentDetailsTlvRpc<'_> {
 
                 Ok(Some(ComponentDetails::PortStatus(result)))
             }
-            MeasurementHeader::TAG => {
-                let (header, leftover) =
-                    gateway_messages::deserialize::<MeasurementHeader>(value)
+            MeasurementHeader::TAG | MeasurementHeaderV2::TAG => {
+                Ok(parse_measurement(tag, value)?.map(|m| match m {
+                    VersionedMeasurement::V1(m) => {
+                        ComponentDetails::Measurement(m)
+                    }
+                    VersionedMeasurement::V2(m) => {
+                        ComponentDetails::MeasurementV2(m)
+                    }
+                }))
+            }
+            MacTableEntry::TAG => {
+                let (entry, leftover) =
+                    gateway_messages::deserialize::<MacTableEntry>(value)
                         .map_err(|err| CommunicationError::TlvDeserialize {
-                        tag,
-                        err,
-                    })?;
+                            tag,
+                            err,
+                        })?;
 
-                if leftover.len() != header.name_length as usize {
-                    return Err(CommunicationError::TlvPagination {
-                        reason: "measurement data / header length mismatch",
-                    });
+                if !leftover.is_empty() {
+                    info!(
+                        self.log,
+                        "ignoring unexpected data in MacTableEntry TLV entry"
+                    );
                 }
 
-                let name = str::from_utf8(leftover).map_err(|_| {
-                    CommunicationError::TlvPagination {
-                        reason: "non-UTF8 measurement name",
-                    }
-                })?;
+                Ok(Some(ComponentDetails::MacTableEntry(entry)))
+            }
+            LinkFlapEvent::TAG => {
+                let (event, leftover) =
+                    gateway_messages::deserialize::<LinkFlapEvent>(value)
+                        .map_err(|err| CommunicationError::TlvDeserialize {
+                            tag,
+                            err,
+                        })?;
+
+                if !leftover.is_empty() {
+                    info!(
+                        self.log,
+                        "ignoring unexpected data in LinkFlapEvent TLV entry"
+                    );
+                }
 
-                Ok(Some(ComponentDetails::Measurement(Measurement {
-                    name: name.to_string(),
-                    kind: header.kind,
-                    value: header.value,
-                })))
+                Ok(Some(ComponentDetails::LinkFlapEvent(event)))
             }
             _ => {
                 info!(
@@ -1013,21 +1174,141 @@ impl TlvRpc for ComponentDetailsTlvRpc<'_> {
     }
 }
 
+/// Parse a TLV-encoded measurement (either a [`MeasurementHeader`] or
+/// [`MeasurementHeaderV2`] followed by the measurement name).
+///
+/// Returns `Ok(None)` if `tag` is not a measurement tag.
+///
+/// [`MeasurementHeader`]: gateway_messages::measurement::MeasurementHeader
+/// [`MeasurementHeaderV2`]: gateway_messages::measurement::MeasurementHeaderV2
+fn parse_measurement(
+    tag: tlv::Tag,
+    value: &[u8],
+) -> Result<Option<VersionedMeasurement>> {
+    use gateway_messages::measurement::MeasurementHeader;
+    use gateway_messages::measurement::MeasurementHeaderV2;
+
+    // Both headers are followed by exactly `name_length` bytes of name.
+    fn parse_name(name_length: u32, leftover: &[u8]) -> Result<String> {
+        if leftover.len() != name_length as usize {
+            return Err(CommunicationError::TlvPagination {
+                reason: "measurement data / header length mismatch",
+            });
+        }
+        str::from_utf8(leftover).map(str::to_string).map_err(|_| {
+            CommunicationError::TlvPagination {
+                reason: "non-UTF8 measurement name",
+            }
+        })
+    }
+
+    let measurement = match tag {
+        MeasurementHeader::TAG => {
+            let (header, leftover) = gateway_messages::deserialize::<
+                MeasurementHeader,
+            >(value)
+            .map_err(|err| CommunicationError::TlvDeserialize { tag, err })?;
+            VersionedMeasurement::V1(Measurement {
+                name: parse_name(header.name_length, leftover)?,
+                kind: header.kind,
+                value: header.value,
+            })
+        }
+        MeasurementHeaderV2::TAG => {
+            let (header, leftover) = gateway_messages::deserialize::<
+                MeasurementHeaderV2,
+            >(value)
+            .map_err(|err| CommunicationError::TlvDeserialize { tag, err })?;
+            VersionedMeasurement::V2(MeasurementV2 {
+                name: parse_name(header.name_length, leftover)?,
+                kind: header.kind,
+                value: header.value,
+                metadata: header.metadata,
+            })
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(measurement))
+}
+
+struct BulkMeasurementsTlvRpc<'a> {
+    log: &'a Logger,
+}
+
+impl TlvRpc for BulkMeasurementsTlvRpc<'_> {
+    type Item = ComponentMeasurement;
+    type Request = request::BulkMeasurements;
+
+    const LOG_NAME: &'static str = "bulk measurements";
+
+    fn request(&self, offset: u32) -> Self::Request {
+        request::BulkMeasurements { offset }
+    }
+
+    fn parse_tag_value(
+        &self,
+        tag: tlv::Tag,
+        value: &[u8],
+    ) -> Result<Option<Self::Item>> {
+        match tag {
+            ComponentMeasurement::TAG => {
+                let (component, leftover) =
+                    gateway_messages::deserialize::<SpComponent>(value)
+                        .map_err(|err| CommunicationError::TlvDeserialize {
+                            tag,
+                            err,
+                        })?;
+
+                // The measurement itself is packed in a nested TLV triple.
+                let (inner_tag, inner_value, leftover) = tlv::decode(leftover)
+                    .map_err(CommunicationError::TlvDecode)?;
+
+                if !leftover.is_empty() {
+                    info!(
+                        self.log,
+                        "ignoring unexpected data in ComponentMeasurement TLV \
+                         entry"
+                    );
+                }
+
+                match parse_measurement(inner_tag, inner_value)? {
+                    Some(measurement) => Ok(Some(ComponentMeasurement {
+                        component,
+                        measurement,
+                    })),
+                    None => {
+                        info!(
+                            self.log,
+                            "skipping unknown measurement tag {inner_tag:?}"
+                        );
+                        Ok(None)
+                    }
+                }
+            }
+            _ => {
+                info!(
+                    self.log,
+                    "skipping unknown bulk measurements tag {tag:?}"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
 struct BulkIgnitionStateTlvRpc<'a> {
     log: &'a Logger,
 }
 
 impl TlvRpc for BulkIgnitionStateTlvRpc<'_> {
     type Item = IgnitionState;
+    type Request = request::BulkIgnitionState;
 
     const LOG_NAME: &'static str = "ignition state";
 
-    fn request(&self, offset: u32) -> MgsRequest {
-        MgsRequest::BulkIgnitionState { offset }
-    }
-
-    fn parse_response(&self, response: SpResponse) -> Result<TlvPage> {
-        response.expect_bulk_ignition_state()
+    fn request(&self, offset: u32) -> Self::Request {
+        request::BulkIgnitionState { offset }
     }
 
     fn parse_tag_value(
@@ -1067,15 +1348,12 @@ struct BulkIgnitionLinkEventsTlvRpc<'a> {
 
 impl TlvRpc for BulkIgnitionLinkEventsTlvRpc<'_> {
     type Item = LinkEvents;
+    type Request = request::BulkIgnitionLinkEvents;
 
     const LOG_NAME: &'static str = "ignition link events";
 
-    fn request(&self, offset: u32) -> MgsRequest {
-        MgsRequest::BulkIgnitionLinkEvents { offset }
-    }
-
-    fn parse_response(&self, response: SpResponse) -> Result<TlvPage> {
-        response.expect_bulk_ignition_link_events()
+    fn request(&self, offset: u32) -> Self::Request {
+        request::BulkIgnitionLinkEvents { offset }
     }
 
     fn parse_tag_value(
@@ -1112,6 +1390,79 @@ impl TlvRpc for BulkIgnitionLinkEventsTlvRpc<'_> {
     }
 }
 
+/// Entries decoded from one page of a paginated TLV response by
+/// [`decode_tlv_page()`].
+#[derive(Debug, Clone)]
+pub enum TlvEntries {
+    Inventory(Vec<SpDevice>),
+    ComponentDetails(Vec<ComponentDetails>),
+    BulkMeasurements(Vec<ComponentMeasurement>),
+    BulkIgnitionState(Vec<IgnitionState>),
+    BulkIgnitionLinkEvents(Vec<LinkEvents>),
+}
+
+/// Decode the TLV-encoded trailing `data` that accompanied a single page of a
+/// paginated `response` (e.g., one found in a log or packet capture), the
+/// same way `SingleSp` does when fetching all pages.
+///
+/// Returns `Ok(None)` if `response` is not a paginated TLV response. Entries
+/// with unknown tags are logged to `log` and skipped.
+pub fn decode_tlv_page(
+    response: &SpResponse,
+    data: &[u8],
+    log: &Logger,
+) -> Result<Option<TlvEntries>> {
+    // Collect all the entries in `data` that `rpc` knows how to parse.
+    fn decode_all<T: TlvRpc>(
+        rpc: &T,
+        data: &[u8],
+        log: &Logger,
+    ) -> Result<Vec<T::Item>> {
+        let mut entries = Vec::new();
+        for result in tlv::decode_iter(data) {
+            let (tag, value) = result?;
+            if let Some(entry) = rpc.parse_tag_value(tag, value)? {
+                entries.push(entry);
+            } else {
+                info!(
+                    log,
+                    "skipping unknown tag {tag:?} while parsing {}",
+                    T::LOG_NAME
+                );
+            }
+        }
+        Ok(entries)
+    }
+
+    let entries = match response {
+        SpResponse::Inventory(_) => {
+            TlvEntries::Inventory(decode_all(&InventoryTlvRpc, data, log)?)
+        }
+        SpResponse::ComponentDetails(_) => {
+            // The component is only used to build requests, so any will do.
+            let rpc = ComponentDetailsTlvRpc {
+                component: SpComponent::SP_ITSELF,
+                log,
+            };
+            TlvEntries::ComponentDetails(decode_all(&rpc, data, log)?)
+        }
+        SpResponse::BulkMeasurements(_) => {
+            let rpc = BulkMeasurementsTlvRpc { log };
+            TlvEntries::BulkMeasurements(decode_all(&rpc, data, log)?)
+        }
+        SpResponse::BulkIgnitionState(_) => {
+            let rpc = BulkIgnitionStateTlvRpc { log };
+            TlvEntries::BulkIgnitionState(decode_all(&rpc, data, log)?)
+        }
+        SpResponse::BulkIgnitionLinkEvents(_) => {
+            let rpc = BulkIgnitionLinkEventsTlvRpc { log };
+            TlvEntries::BulkIgnitionLinkEvents(decode_all(&rpc, data, log)?)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(entries))
+}
+
 async fn rpc_with_trailing_data(
     inner_tx: &mpsc::Sender<InnerCommand>,
     kind: MgsRequest,
@@ -1125,24 +1476,61 @@ async fn rpc_with_trailing_data(
     (result, our_trailing_data.unwrap())
 }
 
-async fn rpc(
+async fn call<R: Request>(
     inner_tx: &mpsc::Sender<InnerCommand>,
-    kind: MgsRequest,
-    our_trailing_data: Option<Cursor<Vec<u8>>>,
-) -> RpcResponse {
-    let (resp_tx, resp_rx) = oneshot::channel();
+    request: R,
+) -> Result<R::Response> {
+    let (_peer, response, data) =
+        rpc(inner_tx, request.to_mgs_request(), None).await.result?;
+    response.expect::<R>(&data)
+}
 
-    // `Inner::run()` doesn't exit as long as `inner_tx` exists, so unwrapping
-    // here only panics if it itself panicked.
-    inner_tx
-        .send(InnerCommand::Rpc(RpcRequest {
-            kind,
+async fn call_with_trailing_data<R: Request>(
+    inner_tx: &mpsc::Sender<InnerCommand>,
+    request: R,
+    our_trailing_data: Cursor<Vec<u8>>,
+) -> (Result<R::Response>, Cursor<Vec<u8>>) {
+    let (result, our_trailing_data) = rpc_with_trailing_data(
+        inner_tx,
+        request.to_mgs_request(),
+        our_trailing_data,
+    )
+    .await;
+    let result =
+        result.and_then(|(_peer, response, data)| response.expect::<R>(&data));
+    (result, our_trailing_data)
+}
+
+async fn rpc(
+    inner_tx: &mpsc::Sender<InnerCommand>,
+    kind: MgsRequest,
+    our_trailing_data: Option<Cursor<Vec<u8>>>,
+) -> RpcResponse {
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    let command = InnerCommand::Rpc(RpcRequest {
+        kind,
+        our_trailing_data,
+        options: CallOptions::current(),
+        response_tx: resp_tx,
+    });
+
+    // `Inner::run()` only exits before `inner_tx` is dropped if our `SingleSp`
+    // was shut down (or if it panicked), so `inner_tx` may outlive it (e.g.,
+    // in an `AttachedSerialConsole`).
+    if let Err(mpsc::error::SendError(command)) = inner_tx.send(command).await {
+        let our_trailing_data = match command {
+            InnerCommand::Rpc(rpc) => rpc.our_trailing_data,
+            _ => unreachable!(),
+        };
+        return RpcResponse {
+            result: Err(CommunicationError::ShutDown),
             our_trailing_data,
-            response_tx: resp_tx,
-        }))
-        .await
-        .unwrap();
+        };
+    }
 
+    // `Inner` answers every command it receives, even while shutting down, so
+    // unwrapping here only panics if it panicked.
     resp_rx.await.unwrap()
 }
 
@@ -1151,6 +1539,7 @@ pub struct AttachedSerialConsole {
     key: u64,
     rx: mpsc::Receiver<(u64, Vec<u8>)>,
     inner_tx: mpsc::Sender<InnerCommand>,
+    stats: Arc<SingleSpStatsCollector>,
     log: Logger,
 }
 
@@ -1167,6 +1556,7 @@ impl AttachedSerialConsole {
             AttachedSerialConsoleRecv {
                 rx_offset: 0,
                 rx: self.rx,
+                stats: self.stats,
                 log: self.log,
             },
         )
@@ -1186,9 +1576,9 @@ impl AttachedSerialConsoleSend {
         let mut data = Cursor::new(data);
         let mut remaining_data = CursorExt::remaining_slice(&data).len();
         while remaining_data > 0 {
-            let (result, new_data) = rpc_with_trailing_data(
+            let (result, new_data) = call_with_trailing_data(
                 &self.inner_tx,
-                MgsRequest::SerialConsoleWrite { offset: self.tx_offset },
+                request::SerialConsoleWrite { offset: self.tx_offset },
                 data,
             )
             .await;
@@ -1197,9 +1587,7 @@ impl AttachedSerialConsoleSend {
                 - CursorExt::remaining_slice(&new_data).len())
                 as u64;
 
-            let n = result.and_then(|(_peer, response, _data)| {
-                response.expect_serial_console_write_ack().map_err(Into::into)
-            })?;
+            let n = result?;
 
             // Confirm the ack we got back makes sense; its `n` should be in the
             // range `[self.tx_offset..self.tx_offset + data_sent]`.
@@ -1235,10 +1623,14 @@ impl AttachedSerialConsoleSend {
     pub async fn keepalive(&self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
 
-        self.inner_tx
+        if self
+            .inner_tx
             .send(InnerCommand::SerialConsoleKeepAlive(tx))
             .await
-            .unwrap();
+            .is_err()
+        {
+            return Err(CommunicationError::ShutDown);
+        }
 
         rx.await.unwrap()
     }
@@ -1247,21 +1639,20 @@ impl AttachedSerialConsoleSend {
     pub async fn detach(&self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
 
-        self.inner_tx
+        if self
+            .inner_tx
             .send(InnerCommand::SerialConsoleDetach(Some(self.key), tx))
             .await
-            .unwrap();
+            .is_err()
+        {
+            return Err(CommunicationError::ShutDown);
+        }
 
         rx.await.unwrap()
     }
 
     pub async fn send_break(&self) -> Result<()> {
-        rpc(&self.inner_tx, MgsRequest::SerialConsoleBreak, None)
-            .await
-            .result
-            .and_then(|(_peer, response, _data)| {
-                response.expect_serial_console_break_ack()
-            })
+        call(&self.inner_tx, request::SerialConsoleBreak).await
     }
 }
 
@@ -1269,6 +1660,7 @@ impl AttachedSerialConsoleSend {
 pub struct AttachedSerialConsoleRecv {
     rx_offset: u64,
     rx: mpsc::Receiver<(u64, Vec<u8>)>,
+    stats: Arc<SingleSpStatsCollector>,
     log: Logger,
 }
 
@@ -1277,19 +1669,138 @@ impl AttachedSerialConsoleRecv {
     ///
     /// Returns `None` if the underlying channel has been closed (e.g., if the
     /// serial console has been detached).
+    ///
+    /// Data we've already received (e.g., from a duplicated packet) is
+    /// discarded. An offset of 0 is always accepted, since the SP restarts its
+    /// offsets from 0 if it resets.
     pub async fn recv(&mut self) -> Option<Vec<u8>> {
-        let (offset, data) = self.rx.recv().await?;
-        if offset != self.rx_offset {
-            warn!(
-                self.log,
-                "gap in serial console data (dropped packet or buffer overrun)",
-            );
+        loop {
+            let (offset, mut data) = self.rx.recv().await?;
+            let end = offset + data.len() as u64;
+            if offset < self.rx_offset && offset > 0 {
+                if end <= self.rx_offset {
+                    debug!(
+                        self.log, "discarding duplicate serial console data";
+                        "offset" => offset,
+                        "len" => data.len(),
+                    );
+                    continue;
+                }
+                data.drain(..(self.rx_offset - offset) as usize);
+            } else if offset != self.rx_offset {
+                self.stats.update(|stats| stats.serial_console_gaps += 1);
+                warn!(
+                    self.log,
+                    "gap in serial console data (dropped packet or buffer overrun)",
+                );
+            }
+            self.rx_offset = end;
+            return Some(data);
         }
-        self.rx_offset = offset + data.len() as u64;
-        Some(data)
     }
 }
 
+// Build an `AttachedSerialConsole` that isn't backed by an SP, for
+// `testing::MockSerialConsole`: data sent on the returned sender is delivered
+// to the console's receiving half, and everything its sending half does is
+// reported on the returned receiver.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn mock_serial_console(
+    log: Logger,
+) -> (
+    AttachedSerialConsole,
+    mpsc::Sender<(u64, Vec<u8>)>,
+    mpsc::UnboundedReceiver<crate::testing::SerialConsoleEvent>,
+) {
+    use crate::testing::SerialConsoleEvent;
+    use std::net::Ipv6Addr;
+
+    const CHANNEL_DEPTH: usize = 32;
+
+    let (data_tx, data_rx) = mpsc::channel(CHANNEL_DEPTH);
+    let (inner_tx, mut inner_rx) = mpsc::channel(CHANNEL_DEPTH);
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+
+    // Stand in for `Inner`, handling only the commands an attached serial
+    // console sends. We exit once all senders (held by the console) are gone.
+    tokio::spawn(async move {
+        let peer = SocketAddrV6::new(Ipv6Addr::LOCALHOST, crate::SP_PORT, 0, 0);
+        while let Some(command) = inner_rx.recv().await {
+            match command {
+                InnerCommand::Rpc(mut rpc) => {
+                    let result = match rpc.kind {
+                        MgsRequest::SerialConsoleWrite { offset } => {
+                            // Accept everything we were sent.
+                            let data = match rpc.our_trailing_data.as_mut() {
+                                Some(cursor) => {
+                                    let data =
+                                        CursorExt::remaining_slice(cursor)
+                                            .to_vec();
+                                    cursor.set_position(
+                                        cursor.get_ref().len() as u64
+                                    );
+                                    data
+                                }
+                                None => Vec::new(),
+                            };
+                            let furthest_ingested_offset =
+                                offset + data.len() as u64;
+                            _ = events_tx.send(SerialConsoleEvent::Write(data));
+                            Ok(SpResponse::SerialConsoleWriteAck {
+                                furthest_ingested_offset,
+                            })
+                        }
+                        MgsRequest::SerialConsoleBreak => {
+                            _ = events_tx.send(SerialConsoleEvent::Break);
+                            Ok(SpResponse::SerialConsoleBreakAck)
+                        }
+                        _ => Err(CommunicationError::SpError(
+                            SpError::RequestUnsupportedForSp,
+                        )),
+                    };
+                    _ = rpc.response_tx.send(RpcResponse {
+                        result: result
+                            .map(|response| (peer, response, Vec::new())),
+                        our_trailing_data: rpc.our_trailing_data,
+                    });
+                }
+                InnerCommand::SerialConsoleKeepAlive(response_tx) => {
+                    _ = events_tx.send(SerialConsoleEvent::KeepAlive);
+                    _ = response_tx.send(Ok(()));
+                }
+                InnerCommand::SerialConsoleDetach(_key, response_tx) => {
+                    _ = events_tx.send(SerialConsoleEvent::Detach);
+                    _ = response_tx.send(Ok(()));
+                }
+                InnerCommand::GetMostRecentHostPhase2Request(response_tx) => {
+                    _ = response_tx.send(None);
+                }
+                InnerCommand::ClearMostRecentHostPhase2Request(response_tx) => {
+                    _ = response_tx.send(());
+                }
+                InnerCommand::SerialConsoleAttach(_component, response_tx) => {
+                    _ = response_tx.send(Err(CommunicationError::SpError(
+                        SpError::SerialConsoleAlreadyAttached,
+                    )));
+                }
+                InnerCommand::Shutdown(response_tx) => {
+                    _ = response_tx.send(());
+                    break;
+                }
+            }
+        }
+    });
+
+    let console = AttachedSerialConsole {
+        key: 0,
+        rx: data_rx,
+        inner_tx,
+        stats: Arc::default(),
+        log,
+    };
+    (console, data_tx, events_rx)
+}
+
 // All RPC request/responses are handled by message passing to the `Inner` task
 // below. `our_trailing_data` deserves some extra documentation: Some packet types
 // (e.g., update chunks) want to send potentially-large binary data. We
@@ -1305,7 +1816,51 @@ impl AttachedSerialConsoleRecv {
 struct RpcRequest {
     kind: MgsRequest,
     our_trailing_data: Option<Cursor<Vec<u8>>>,
+    // Set by our caller via `CallOptions::scope()`, if at all.
+    options: CallOptions,
+    response_tx: oneshot::Sender<RpcResponse>,
+}
+
+// When an RPC must complete by, and how long that gave it when it started
+// (reported in `CommunicationError::DeadlineExceeded`).
+#[derive(Debug, Clone, Copy)]
+struct RpcDeadline {
+    at: Instant,
+    budget: Duration,
+}
+
+// An RPC we've sent to an SP that supports concurrent RPCs, waiting in
+// `Inner::in_flight` for a response.
+struct InFlightRpc {
+    kind: MgsRequest,
+    serialized_request: Vec<u8>,
+    our_trailing_data: Option<Cursor<Vec<u8>>>,
+    expected_peer: Option<SocketAddrV6>,
+    policy: RpcPolicy,
+    busy_backoff: backoff::ExponentialBackoff,
+    deadline: Option<RpcDeadline>,
+    options: CallOptions,
     response_tx: oneshot::Sender<RpcResponse>,
+    attempt: usize,
+    first_sent_at: Instant,
+    sent_at: Instant,
+    // When our current attempt times out or, if `busy` is set, when we should
+    // resend the request after the SP told us it was busy.
+    next_event: Instant,
+    busy: bool,
+}
+
+impl InFlightRpc {
+    fn wakeup(&self) -> Instant {
+        match self.deadline {
+            Some(deadline) => deadline.at.min(self.next_event),
+            None => self.next_event,
+        }
+    }
+
+    fn abandoned(&self) -> bool {
+        self.response_tx.is_closed() || self.options.is_cancelled()
+    }
 }
 
 #[derive(Debug)]
@@ -1339,135 +1894,38 @@ enum InnerCommand {
     // automatically when a connection is closed) and "force-detach any session"
     // (performed by a user).
     SerialConsoleDetach(Option<u64>, oneshot::Sender<Result<()>>),
-}
-
-#[async_trait]
-trait InnerSocket {
-    fn log(&self) -> &Logger;
-    fn discovery_addr(&self) -> SocketAddrV6;
-    async fn send(&mut self, data: &[u8]) -> Result<(), SingleSpHandleError>;
-    async fn recv(&mut self) -> SingleSpMessage;
-}
-
-#[async_trait]
-impl InnerSocket for SingleSpHandle {
-    fn log(&self) -> &Logger {
-        SingleSpHandle::log(self)
-    }
-
-    fn discovery_addr(&self) -> SocketAddrV6 {
-        SingleSpHandle::discovery_addr(self)
-    }
-
-    async fn send(&mut self, data: &[u8]) -> Result<(), SingleSpHandleError> {
-        SingleSpHandle::send(self, data).await
-    }
-
-    async fn recv(&mut self) -> SingleSpMessage {
-        SingleSpHandle::recv(self).await
-    }
-}
-
-struct InnerSocketWrapper {
-    socket: UdpSocket,
-    discovery_addr: SocketAddrV6,
-    log: Logger,
-}
-
-#[async_trait]
-impl InnerSocket for InnerSocketWrapper {
-    fn log(&self) -> &Logger {
-        &self.log
-    }
-
-    fn discovery_addr(&self) -> SocketAddrV6 {
-        self.discovery_addr
-    }
-
-    async fn send(&mut self, data: &[u8]) -> Result<(), SingleSpHandleError> {
-        self.socket
-            .send_to(data, self.discovery_addr)
-            .await
-            .map(|n| assert_eq!(n, data.len()))
-            .map_err(|err| SingleSpHandleError::SendTo {
-                addr: self.discovery_addr,
-                interface: "(direct socket handle)".to_string(),
-                err,
-            })
-    }
-
-    // This function is only used if we were created with
-    // `new_direct_socket_for_testing()`, so we're a little lazy with error
-    // handling. The real `SingleSpHandle` handles errors internally, so this
-    // `recv()` is defined as infallible in our trait that wraps both the real
-    // handle and this wrapper-for-tests.
-    async fn recv(&mut self) -> SingleSpMessage {
-        let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
-        loop {
-            let (peer, buf) = match self.socket.recv_from(&mut buf).await {
-                Ok((n, SocketAddr::V6(peer))) => (peer, &buf[..n]),
-                Ok((_, SocketAddr::V4(_))) => unreachable!(),
-                Err(err) => {
-                    error!(self.log, "failed to recv"; "err" => %err);
-                    continue;
-                }
-            };
-
-            let (message, data) =
-                match gateway_messages::deserialize::<Message>(buf) {
-                    Ok((message, data)) => (message, data),
-                    Err(err) => {
-                        error!(
-                            self.log, "failed to deserialize packet";
-                            "err" => %err,
-                        );
-                        continue;
-                    }
-                };
-
-            match &message.kind {
-                // TODO: We could handle `HostPhase2Data` requests with some
-                // work, but currently we have no simulations / tests that need
-                // it, so we omit it for now.
-                MessageKind::MgsRequest(_)
-                | MessageKind::MgsResponse(_)
-                | MessageKind::SpRequest(SpRequest::HostPhase2Data {
-                    ..
-                }) => {
-                    warn!(
-                        self.log, "message kind unsupported by test socket";
-                        "message" => ?message,
-                    );
-                    continue;
-                }
-                &MessageKind::SpRequest(SpRequest::SerialConsole {
-                    component,
-                    offset,
-                }) => {
-                    return SingleSpMessage::SerialConsole {
-                        component,
-                        offset,
-                        data: data.to_owned(),
-                    };
-                }
-                MessageKind::SpResponse(response) => {
-                    return SingleSpMessage::SpResponse {
-                        peer,
-                        header: message.header,
-                        response: *response,
-                        data: data.to_owned(),
-                    };
-                }
-            }
-        }
-    }
+    // Sent by `SingleSp::shutdown()`; we reply once we've cleaned up, and then
+    // exit.
+    Shutdown(oneshot::Sender<()>),
 }
 
 struct Inner<T> {
-    socket_handle: T,
+    transport: T,
     sp_addr_tx: watch::Sender<Option<(SocketAddrV6, SpPort)>>,
-    max_attempts_per_rpc: usize,
-    per_attempt_timeout: Duration,
+    identity_tx: watch::Sender<Option<SpIdentity>>,
+    identity_events_tx: broadcast::Sender<SpIdentityEvent>,
+    health: HealthTracker,
+    stats: Arc<SingleSpStatsCollector>,
+    discovery: DiscoveryConfig,
+    // Address of the SP we've verified has our pinned identity; only
+    // responses from this address are accepted (other than for discovery).
+    sp_addr: Option<SocketAddrV6>,
+    // Addresses of SPs that responded to discovery but reported an identity
+    // other than our pinned identity (at most `MAX_IMPOSTOR_ADDRS` of them).
+    impostor_addrs: LruCache<SocketAddrV6, ()>,
+    // Set if we receive a response from an address we haven't verified; we'll
+    // attempt rediscovery as soon as we finish our current command.
+    rediscover_now: bool,
+    // Protocol features supported by our SP. This is `None` until we've
+    // asked, and again after anything that may have changed the SP's firmware
+    // (a reset or an address change); until we know, we assume it supports
+    // none of them.
+    capabilities: Option<ProtocolCapabilities>,
+    // RPCs sent to an SP that supports concurrent RPCs that are waiting for a
+    // response, keyed by message ID. RPCs to other SPs are handled one at a
+    // time by `rpc_call()` and never appear here.
+    in_flight: HashMap<u32, InFlightRpc>,
+    rpc_policies: RpcPolicies,
     serial_console_tx: Option<mpsc::Sender<(u64, Vec<u8>)>>,
     cmds_rx: mpsc::Receiver<InnerCommand>,
     message_id: u32,
@@ -1475,22 +1933,35 @@ struct Inner<T> {
     most_recent_host_phase2_request: Option<HostPhase2Request>,
 }
 
-impl<T: InnerSocket> Inner<T> {
+impl<T: SpTransport> Inner<T> {
     // This is a private function; squishing the number of arguments down seems
     // like more trouble than it's worth.
     #[allow(clippy::too_many_arguments)]
     fn new(
-        socket_handle: T,
+        transport: T,
         sp_addr_tx: watch::Sender<Option<(SocketAddrV6, SpPort)>>,
-        max_attempts_per_rpc: usize,
-        per_attempt_timeout: Duration,
+        identity_tx: watch::Sender<Option<SpIdentity>>,
+        identity_events_tx: broadcast::Sender<SpIdentityEvent>,
+        health_tx: watch::Sender<SpHealth>,
+        stats: Arc<SingleSpStatsCollector>,
+        discovery: DiscoveryConfig,
+        rpc_policies: RpcPolicies,
         cmds_rx: mpsc::Receiver<InnerCommand>,
     ) -> Self {
         Self {
-            socket_handle,
+            transport,
             sp_addr_tx,
-            max_attempts_per_rpc,
-            per_attempt_timeout,
+            identity_tx,
+            identity_events_tx,
+            health: HealthTracker::new(health_tx),
+            stats,
+            discovery,
+            sp_addr: None,
+            impostor_addrs: LruCache::new(MAX_IMPOSTOR_ADDRS),
+            rediscover_now: false,
+            capabilities: None,
+            in_flight: HashMap::new(),
+            rpc_policies,
             serial_console_tx: None,
             cmds_rx,
             message_id: 0,
@@ -1500,17 +1971,22 @@ impl<T: InnerSocket> Inner<T> {
     }
 
     fn log(&self) -> &Logger {
-        self.socket_handle.log()
+        self.transport.log()
     }
 
     async fn run(mut self) {
+        // If we were given our SP's address, start talking to it immediately;
+        // we'll pin its identity the first time rediscovery succeeds.
         let maybe_known_addr = *self.sp_addr_tx.borrow();
-        let mut sp_addr = match maybe_known_addr {
-            Some((addr, _port)) => addr,
+        let sp_addr = match maybe_known_addr {
+            Some((addr, _port)) => {
+                self.sp_addr = Some(addr);
+                addr
+            }
             None => match self.initial_discovery().await {
                 Some(addr) => addr,
-                // initial_discovery only returns `None` if `cmds_rx` is closed,
-                // which means the `SingleSp` that spawned us is gone.
+                // initial_discovery only returns `None` if `cmds_rx` is
+                // closed, which means the `SingleSp` that spawned us is gone.
                 None => return,
             },
         };
@@ -1520,154 +1996,407 @@ impl<T: InnerSocket> Inner<T> {
             "initial discovery complete";
             "addr" => %sp_addr,
         );
+        self.refresh_capabilities().await;
 
+        // Once we've discovered an SP, continue to send discovery packets to
+        // detect changes. If the SP's address changes, we only follow it if the
+        // SP at the new address reports the same identity we pinned when we
+        // first discovered it; see `rediscover()`.
+        let mut discovery_interval = self.discovery.idle_interval();
         let mut discovery_idle = time::interval_at(
-            Instant::now() + DISCOVERY_INTERVAL_IDLE,
-            DISCOVERY_INTERVAL_IDLE,
+            Instant::now() + discovery_interval,
+            discovery_interval,
         );
 
         loop {
+            let next_in_flight_event = self.next_in_flight_event();
+
             tokio::select! {
-                cmd = self.cmds_rx.recv() => {
+                cmd = self.cmds_rx.recv(), if self.ready_for_command() => {
                     let cmd = match cmd {
                         Some(cmd) => cmd,
                         None => return,
                     };
 
-                    self.handle_command(cmd).await;
-                    discovery_idle.reset();
+                    match cmd {
+                        InnerCommand::Rpc(rpc) if self.concurrent_rpcs() => {
+                            self.start_rpc(rpc).await;
+                        }
+                        InnerCommand::Shutdown(response_tx) => {
+                            self.shutdown().await;
+                            _ = response_tx.send(());
+                            return;
+                        }
+                        cmd => self.handle_command(cmd).await,
+                    }
+
+                    // If we're rediscovering aggressively, our SP isn't
+                    // responding to commands; keep sending discovery packets
+                    // on schedule regardless.
+                    if !self.aggressive_rediscovery() {
+                        discovery_idle.reset();
+                    }
                 }
 
-                message = self.socket_handle.recv() => {
+                message = self.transport.recv() => {
                     self.handle_incoming_message(message).await;
                     discovery_idle.reset();
                 }
 
+                () = time::sleep_until(
+                    next_in_flight_event.unwrap_or_else(Instant::now),
+                ), if next_in_flight_event.is_some() => {
+                    self.handle_in_flight_timers().await;
+                }
+
                 _ = discovery_idle.tick() => {
                     debug!(
                         self.log(), "attempting SP discovery (idle timeout)";
-                        "discovery_addr" => %self.socket_handle.discovery_addr(),
+                        "discovery_addr" => %self.transport.discovery_addr(),
                     );
-                    match self.discover().await {
-                        Ok(addr) => {
-                            if sp_addr == addr {
-                                debug!(
-                                    self.log(), "discovered same SP";
-                                    "addr" => %addr,
-                                );
-                            } else {
-                                warn!(
-                                    self.log(), "discovered new SP";
-                                    "new_addr" => %addr,
-                                    "old_addr" => %sp_addr,
-                                );
-                                sp_addr = addr;
-                            }
-                        }
-                        Err(err) => {
-                            warn!(
-                                self.log(), "idle discovery check failed";
-                                "err" => %err,
-                            );
-                        }
-                    }
+                    self.rediscover().await;
                 }
             }
+
+            if self.rediscover_now {
+                self.rediscover_now = false;
+                debug!(
+                    self.log(),
+                    "attempting SP discovery (response from unknown address)";
+                    "discovery_addr" => %self.transport.discovery_addr(),
+                );
+                self.rediscover().await;
+            }
+
+            // Switch between idle and aggressive rediscovery if our SP has
+            // stopped (or resumed) responding.
+            let wanted_interval = if self.aggressive_rediscovery() {
+                self.discovery.aggressive_interval()
+            } else {
+                self.discovery.idle_interval()
+            };
+            if wanted_interval != discovery_interval {
+                debug!(
+                    self.log(), "changing discovery interval";
+                    "interval" => ?wanted_interval,
+                );
+                discovery_interval = wanted_interval;
+                discovery_idle = time::interval_at(
+                    Instant::now() + discovery_interval,
+                    discovery_interval,
+                );
+            }
+        }
+    }
+
+    // Should we accept another command? We handle commands one at a time,
+    // except that RPCs to an SP that supports concurrent RPCs only occupy us
+    // until they're sent. If our SP stops supporting concurrent RPCs (e.g.,
+    // because it was reset into older firmware), we wait for any RPCs still in
+    // flight before going back to one at a time.
+    fn ready_for_command(&self) -> bool {
+        if self.concurrent_rpcs() {
+            self.in_flight.len() < MAX_CONCURRENT_RPCS
+        } else {
+            self.in_flight.is_empty()
+        }
+    }
+
+    fn concurrent_rpcs(&self) -> bool {
+        self.capabilities.map_or(false, |capabilities| {
+            capabilities.contains(ProtocolCapabilities::CONCURRENT_RPCS)
+        })
+    }
+
+    // Ask our SP which protocol features it supports. SPs that predate
+    // `MgsRequest::ProtocolCapabilities` fail the request, so we treat any
+    // error from the SP as an empty set of capabilities; if we don't hear
+    // back at all, we'll ask again after our next successful discovery.
+    async fn refresh_capabilities(&mut self) {
+        let result = self.call(request::ProtocolCapabilities).await;
+        let capabilities = match result {
+            Ok(capabilities) => capabilities,
+            Err(CommunicationError::SpError(err)) => {
+                debug!(
+                    self.log(), "SP does not report protocol capabilities";
+                    "err" => %err,
+                );
+                ProtocolCapabilities::empty()
+            }
+            Err(err) => {
+                warn!(
+                    self.log(), "failed to get SP protocol capabilities";
+                    "err" => %err,
+                );
+                return;
+            }
+        };
+
+        info!(
+            self.log(), "SP protocol capabilities";
+            "capabilities" => ?capabilities,
+        );
+        self.capabilities = Some(capabilities);
+    }
+
+    fn aggressive_rediscovery(&self) -> bool {
+        match self.discovery.aggressive_after_failures() {
+            Some(n) => self.health.consecutive_failures() >= n,
+            None => false,
         }
     }
 
     // Waits until we've discovered an SP for the first time. Only returns none
-    // if `cmds_rx` is closed, indicating our corresponding `SingleSp` is gone.
+    // if `cmds_rx` is closed, indicating our corresponding `SingleSp` is gone,
+    // or if we've been told to shut down.
     async fn initial_discovery(&mut self) -> Option<SocketAddrV6> {
         // If discovery fails (typically due to timeout, but also possible due
-        // to misconfiguration where we can't send packets at all), how long do
-        // we wait before retrying? If failure is due to misconfiguration, we
-        // will never succeed.
-        const SLEEP_BETWEEN_DISCOVERY_RETRY: Duration = Duration::from_secs(1);
+        // to misconfiguration where we can't send packets at all), we back off
+        // before retrying. If failure is due to misconfiguration, we will never
+        // succeed.
+        let mut retry_backoff = discovery_retry_policy(&self.discovery);
 
         // We can't do anything useful until we find an SP; loop
         // discovery packets first.
         debug!(
             self.log(), "attempting initial SP discovery";
-            "discovery_addr" => %self.socket_handle.discovery_addr(),
+            "discovery_addr" => %self.transport.discovery_addr(),
         );
 
         loop {
-            match self.discover().await {
-                Ok(addr) => return Some(addr),
+            match self.discover_and_identify().await {
+                Ok((addr, sp_port, identity)) => {
+                    self.identity_tx.send_replace(Some(identity));
+                    self.accept_sp_addr(addr, sp_port);
+                    _ = self
+                        .identity_events_tx
+                        .send(SpIdentityEvent::Pinned { addr, identity });
+                    return Some(addr);
+                }
                 Err(err) => {
                     info!(
                         self.log(),
                         "initial discovery failed";
                         "err" => %err,
-                        "addr" => %self.socket_handle.discovery_addr(),
+                        "addr" => %self.transport.discovery_addr(),
                     );
                 }
             }
 
-            // Before re-attempting discovery, peel out any pending commands and
-            // fail them.
+            // Back off before re-attempting discovery, failing any commands
+            // that are pending or arrive in the meantime; if we're asked to
+            // shut down, do so without waiting out the backoff.
+            //
+            // Our retry policy never gives up, so we can unwrap.
+            let retry_at =
+                Instant::now() + retry_backoff.next_backoff().unwrap();
             loop {
-                let response_is_ok = match self.cmds_rx.try_recv() {
-                    Ok(InnerCommand::Rpc(rpc)) => rpc
-                        .response_tx
-                        .send(RpcResponse {
-                            result: Err(CommunicationError::NoSpDiscovered),
-                            our_trailing_data: rpc.our_trailing_data,
-                        })
-                        .is_ok(),
-                    Ok(InnerCommand::GetMostRecentHostPhase2Request(tx)) => {
-                        tx.send(self.most_recent_host_phase2_request).is_ok()
-                    }
-                    Ok(InnerCommand::ClearMostRecentHostPhase2Request(tx)) => {
-                        self.clear_most_recent_host_phase2_request();
-                        tx.send(()).is_ok()
-                    }
-                    Ok(InnerCommand::SerialConsoleAttach(_, tx)) => {
-                        tx.send(Err(CommunicationError::NoSpDiscovered)).is_ok()
-                    }
-                    Ok(
-                        InnerCommand::SerialConsoleKeepAlive(tx)
-                        | InnerCommand::SerialConsoleDetach(_, tx),
-                    ) => {
-                        tx.send(Err(CommunicationError::NoSpDiscovered)).is_ok()
-                    }
-                    Err(TryRecvError::Empty) => break,
-                    Err(TryRecvError::Disconnected) => return None,
-                };
+                tokio::select! {
+                    cmd = self.cmds_rx.recv() => match cmd {
+                        Some(InnerCommand::Shutdown(tx)) => {
+                            self.shutdown().await;
+                            _ = tx.send(());
+                            return None;
+                        }
+                        Some(command) => {
+                            self.fail_command(command, || {
+                                CommunicationError::NoSpDiscovered
+                            });
+                        }
+                        None => return None,
+                    },
 
-                if !response_is_ok {
-                    warn!(
-                        self.log(),
-                        "RPC requester disappeared while waiting for response"
-                    );
+                    () = time::sleep_until(retry_at) => break,
                 }
             }
-
-            tokio::time::sleep(SLEEP_BETWEEN_DISCOVERY_RETRY).await;
         }
     }
 
-    async fn discover(&mut self) -> Result<SocketAddrV6> {
-        let (addr, response, _data) =
-            self.rpc_call(MgsRequest::Discover, None).await?;
+    async fn discover(&mut self) -> Result<(SocketAddrV6, SpPort)> {
+        crate::probes::discovery_attempt!(|| (
+            self.transport.interface(),
+            self.transport.discovery_addr().to_string(),
+        ));
+        let (addr, response, data) =
+            self.rpc_call(request::Discover.to_mgs_request(), None).await?;
+
+        let discovery = response.expect::<request::Discover>(&data)?;
+
+        Ok((addr, discovery.sp_port))
+    }
+
+    // Ask the SP at `addr` for its identity.
+    async fn identify(&mut self, addr: SocketAddrV6) -> Result<SpIdentity> {
+        let (_addr, response, data) = self
+            .rpc_call_expecting_peer(
+                request::SpState.to_mgs_request(),
+                None,
+                Some(addr),
+                self.rpc_policies.get(RequestKind::Other),
+                None,
+            )
+            .await?;
+
+        let state = response.expect::<request::SpState>(&data)?;
+
+        Ok(SpIdentity::from(&state))
+    }
+
+    async fn discover_and_identify(
+        &mut self,
+    ) -> Result<(SocketAddrV6, SpPort, SpIdentity)> {
+        let (addr, sp_port) = self.discover().await?;
+        let identity = self.identify(addr).await?;
+        Ok((addr, sp_port, identity))
+    }
+
+    // Pin the identity of the SP at `addr`, which we were given rather than
+    // discovered (so it hasn't been identified yet).
+    async fn pin_identity(&mut self, addr: SocketAddrV6) {
+        match self.identify(addr).await {
+            Ok(identity) => {
+                self.identity_tx.send_replace(Some(identity));
+                _ = self
+                    .identity_events_tx
+                    .send(SpIdentityEvent::Pinned { addr, identity });
+            }
+            Err(err) => {
+                warn!(
+                    self.log(), "failed to identify SP";
+                    "addr" => %addr,
+                    "err" => %err,
+                );
+            }
+        }
+    }
 
-        let discovery = response.expect_discover()?;
+    fn accept_sp_addr(&mut self, addr: SocketAddrV6, sp_port: SpPort) {
+        self.sp_addr = Some(addr);
 
         // The receiving half of `sp_addr_tx` is held by the `SingleSp` that
         // created us, and it aborts our task when it's dropped. This send
         // therefore can't fail; ignore the returned result.
-        let _ = self.sp_addr_tx.send(Some((addr, discovery.sp_port)));
+        let _ = self.sp_addr_tx.send(Some((addr, sp_port)));
+    }
+
+    // Check that we're still talking to the SP whose identity we pinned during
+    // initial discovery, following it to a new address if necessary.
+    async fn rediscover(&mut self) {
+        let (addr, sp_port) = match self.discover().await {
+            Ok((addr, sp_port)) => (addr, sp_port),
+            Err(err) => {
+                warn!(
+                    self.log(), "idle discovery check failed";
+                    "err" => %err,
+                );
+                return;
+            }
+        };
+
+        let old_addr = match self.sp_addr {
+            Some(old_addr) if old_addr == addr => {
+                debug!(self.log(), "discovered same SP"; "addr" => %addr);
+                self.accept_sp_addr(addr, sp_port);
+                if self.identity_tx.borrow().is_none() {
+                    self.pin_identity(addr).await;
+                }
+                if self.capabilities.is_none() {
+                    self.refresh_capabilities().await;
+                }
+                return;
+            }
+            // We only rediscover after initial discovery (or being given our
+            // SP's address), either of which sets `sp_addr`.
+            Some(old_addr) => old_addr,
+            None => unreachable!("rediscovery before initial discovery"),
+        };
+
+        if self.impostor_addrs.contains_key(&addr) {
+            warn!(
+                self.log(), "discovered impostor SP again; ignoring it";
+                "addr" => %addr,
+            );
+            return;
+        }
+
+        let expected = *self.identity_tx.borrow();
+        let found = match self.identify(addr).await {
+            Ok(identity) => identity,
+            Err(err) => {
+                warn!(
+                    self.log(), "failed to verify identity of discovered SP";
+                    "addr" => %addr,
+                    "err" => %err,
+                );
+                return;
+            }
+        };
+
+        // If we were given our SP's address and haven't been able to pin its
+        // identity yet, we have nothing to check against; follow the SP and
+        // pin whatever it reports.
+        let expected = match expected {
+            Some(expected) => expected,
+            None => {
+                self.identity_tx.send_replace(Some(found));
+                _ = self
+                    .identity_events_tx
+                    .send(SpIdentityEvent::Pinned { addr, identity: found });
+                found
+            }
+        };
 
-        Ok(addr)
+        if found == expected {
+            warn!(
+                self.log(), "SP address changed";
+                "new_addr" => %addr,
+                "old_addr" => %old_addr,
+            );
+            self.accept_sp_addr(addr, sp_port);
+            _ = self.identity_events_tx.send(SpIdentityEvent::AddressChanged {
+                old_addr,
+                new_addr: addr,
+                identity: found,
+            });
+            self.capabilities = None;
+            self.refresh_capabilities().await;
+        } else {
+            error!(
+                self.log(), "discovered SP with unexpected identity";
+                "addr" => %addr,
+                "expected" => ?expected,
+                "found" => ?found,
+            );
+            self.impostor_addrs.insert(addr, ());
+            _ = self.identity_events_tx.send(
+                SpIdentityEvent::ImpostorDetected { addr, expected, found },
+            );
+        }
     }
 
     async fn handle_command(&mut self, command: InnerCommand) {
         match command {
             InnerCommand::Rpc(mut rpc) => {
-                let result = self
-                    .rpc_call(rpc.kind, rpc.our_trailing_data.as_mut())
-                    .await;
+                // If our caller goes away (e.g., because they dropped the
+                // future of the `SingleSp` method that sent us this request),
+                // stop retrying: nobody is waiting for the result, and the
+                // commands queued behind this one are.
+                let result = tokio::select! {
+                    result = self.rpc_call_with_options(
+                        rpc.kind,
+                        rpc.our_trailing_data.as_mut(),
+                        &rpc.options,
+                    ) => result,
+                    () = rpc.response_tx.closed() => {
+                        debug!(
+                            self.log(),
+                            "RPC requester disappeared; abandoning request";
+                            "request" => ?rpc.kind,
+                        );
+                        return;
+                    }
+                };
+                self.note_rpc_complete(&rpc.kind);
                 let response = RpcResponse {
                     result,
                     our_trailing_data: rpc.our_trailing_data,
@@ -1692,12 +2421,7 @@ impl<T: InnerSocket> Inner<T> {
                 _ = response_tx.send(resp);
             }
             InnerCommand::SerialConsoleKeepAlive(response_tx) => {
-                let result = self
-                    .rpc_call(MgsRequest::SerialConsoleKeepAlive, None)
-                    .await
-                    .and_then(|(_peer, response, _trailing_data)| {
-                        response.expect_serial_console_keepalive_ack()
-                    });
+                let result = self.call(request::SerialConsoleKeepAlive).await;
                 _ = response_tx.send(result);
             }
             InnerCommand::SerialConsoleDetach(key, response_tx) => {
@@ -1710,29 +2434,126 @@ impl<T: InnerSocket> Inner<T> {
                 };
                 _ = response_tx.send(resp);
             }
+            InnerCommand::Shutdown(_) => {
+                unreachable!("shutdown is handled by run()")
+            }
         }
     }
 
-    async fn handle_incoming_message(&mut self, message: SingleSpMessage) {
-        match message {
-            SingleSpMessage::HostPhase2Request(request) => {
-                self.set_most_recent_host_phase2_request(request);
+    // Respond to `command` without contacting the SP, failing anything that
+    // would need to with `err()`.
+    fn fail_command(
+        &mut self,
+        command: InnerCommand,
+        err: impl Fn() -> CommunicationError,
+    ) {
+        let response_is_ok = match command {
+            InnerCommand::Rpc(rpc) => rpc
+                .response_tx
+                .send(RpcResponse {
+                    result: Err(err()),
+                    our_trailing_data: rpc.our_trailing_data,
+                })
+                .is_ok(),
+            InnerCommand::GetMostRecentHostPhase2Request(tx) => {
+                tx.send(self.most_recent_host_phase2_request).is_ok()
             }
-            SingleSpMessage::SerialConsole { component, offset, data } => {
-                self.forward_serial_console(component, offset, &data);
+            InnerCommand::ClearMostRecentHostPhase2Request(tx) => {
+                self.clear_most_recent_host_phase2_request();
+                tx.send(()).is_ok()
             }
-            SingleSpMessage::SpResponse { header, response, .. } => {
-                // Reconstruct the message for logging.
-                let message =
-                    Message { header, kind: MessageKind::SpResponse(response) };
-
-                warn!(
-                    self.log(),
-                    "ignoring unexpected RPC response";
-                    "message" => ?message,
-                );
+            InnerCommand::SerialConsoleAttach(_, tx) => {
+                tx.send(Err(err())).is_ok()
             }
-        }
+            InnerCommand::SerialConsoleKeepAlive(tx)
+            | InnerCommand::SerialConsoleDetach(_, tx) => {
+                tx.send(Err(err())).is_ok()
+            }
+            // There's nothing to fail; we're either already shutting down or
+            // will be shortly.
+            InnerCommand::Shutdown(tx) => tx.send(()).is_ok(),
+        };
+
+        if !response_is_ok {
+            warn!(
+                self.log(),
+                "RPC requester disappeared while waiting for response"
+            );
+        }
+    }
+
+    // Clean up before `run()` exits on behalf of `SingleSp::shutdown()`: wait
+    // for any RPCs still in flight, detach our serial console (if one is
+    // attached), fail anything still queued, and release our transport.
+    async fn shutdown(&mut self) {
+        info!(self.log(), "shutting down");
+
+        while let Some(next_event) = self.next_in_flight_event() {
+            tokio::select! {
+                message = self.transport.recv() => {
+                    self.handle_incoming_message(message).await;
+                }
+                () = time::sleep_until(next_event) => {
+                    self.handle_in_flight_timers().await;
+                }
+            }
+        }
+
+        if self.serial_console_tx.is_some() {
+            if let Err(err) = self.detach_serial_console().await {
+                warn!(
+                    self.log(), "failed to detach serial console";
+                    "err" => %err,
+                );
+                self.serial_console_tx = None;
+            }
+        }
+
+        self.cmds_rx.close();
+        while let Ok(command) = self.cmds_rx.try_recv() {
+            self.fail_command(command, || CommunicationError::ShutDown);
+        }
+
+        self.transport.shutdown().await;
+    }
+
+    async fn handle_incoming_message(&mut self, message: SingleSpMessage) {
+        match message {
+            SingleSpMessage::HostPhase2Request(request) => {
+                self.set_most_recent_host_phase2_request(request);
+            }
+            SingleSpMessage::SerialConsole { component, offset, data } => {
+                self.forward_serial_console(component, offset, &data);
+            }
+            SingleSpMessage::SpResponse { peer, header, response, data }
+                if self.in_flight.contains_key(&header.message_id) =>
+            {
+                self.handle_in_flight_response(
+                    peer,
+                    header.message_id,
+                    response,
+                    data,
+                );
+            }
+            SingleSpMessage::SpResponse { header, response, .. } => {
+                // Reconstruct the message for logging.
+                let message =
+                    Message { header, kind: MessageKind::SpResponse(response) };
+
+                self.stats.update(|stats| stats.unexpected_responses += 1);
+                warn!(
+                    self.log(),
+                    "ignoring unexpected RPC response";
+                    "message" => ?message,
+                );
+            }
+        }
+    }
+
+    async fn call<R: Request>(&mut self, request: R) -> Result<R::Response> {
+        let (_peer, response, data) =
+            self.rpc_call(request.to_mgs_request(), None).await?;
+        response.expect::<R>(&data)
     }
 
     async fn rpc_call(
@@ -1740,95 +2561,443 @@ impl<T: InnerSocket> Inner<T> {
         kind: MgsRequest,
         our_trailing_data: Option<&mut Cursor<Vec<u8>>>,
     ) -> Result<(SocketAddrV6, SpResponse, Vec<u8>)> {
-        // We allow our client to specify the max RPC attempts and the
-        // per-attempt timeout; however, it's very easy to set a timeout that is
-        // too low for the "reset the SP" request, especially if the SP being
-        // reset is a sidecar (which means it won't be able to respond until it
-        // brings the management network back online). We will override the max
-        // attempt count for only that message to ensure we give SPs ample time
-        // to reset.
-        const SP_RESET_TIME_ALLOWED: Duration = Duration::from_secs(30);
+        self.rpc_call_with_options(kind, our_trailing_data, &CallOptions::new())
+            .await
+    }
 
-        // Build and serialize our request once.
+    async fn rpc_call_with_options(
+        &mut self,
+        kind: MgsRequest,
+        our_trailing_data: Option<&mut Cursor<Vec<u8>>>,
+        options: &CallOptions,
+    ) -> Result<(SocketAddrV6, SpResponse, Vec<u8>)> {
+        let (policy, deadline) = self.policy_and_deadline(&kind, options);
+        let expected_peer = self.expected_peer(&kind);
+        tokio::select! {
+            result = self.rpc_call_expecting_peer(
+                kind,
+                our_trailing_data,
+                expected_peer,
+                policy,
+                deadline,
+            ) => result,
+            () = options.cancelled() => Err(CommunicationError::Cancelled),
+        }
+    }
+
+    fn policy_and_deadline(
+        &self,
+        kind: &MgsRequest,
+        options: &CallOptions,
+    ) -> (RpcPolicy, Option<RpcDeadline>) {
+        let policy = options
+            .policy()
+            .unwrap_or_else(|| self.rpc_policies.get(RequestKind::of(kind)));
+        // Stop at whichever of our policy's and our caller's deadlines comes
+        // first.
+        let now = Instant::now();
+        let policy_deadline = policy
+            .deadline
+            .map(|budget| RpcDeadline { at: now + budget, budget });
+        let caller_deadline = options.deadline().map(|at| RpcDeadline {
+            at,
+            budget: at.saturating_duration_since(now),
+        });
+        let caller_timeout = options
+            .rpc_timeout()
+            .map(|budget| RpcDeadline { at: now + budget, budget });
+        let deadline = policy_deadline
+            .into_iter()
+            .chain(caller_deadline)
+            .chain(caller_timeout)
+            .min_by_key(|deadline| deadline.at);
+        (policy, deadline)
+    }
+
+    fn expected_peer(&self, kind: &MgsRequest) -> Option<SocketAddrV6> {
+        // Discovery may be answered by any SP (we verify the identity of new
+        // responders separately); all other requests must be answered by the SP
+        // we've verified.
+        match kind {
+            MgsRequest::Discover => None,
+            _ => self.sp_addr,
+        }
+    }
+
+    fn new_request(&mut self, kind: MgsRequest) -> Message {
         self.message_id += 1;
-        let request = Message {
+        Message {
             header: Header {
                 version: version::CURRENT,
                 message_id: self.message_id,
             },
             kind: MessageKind::MgsRequest(kind),
-        };
+        }
+    }
+
+    async fn rpc_call_expecting_peer(
+        &mut self,
+        kind: MgsRequest,
+        our_trailing_data: Option<&mut Cursor<Vec<u8>>>,
+        expected_peer: Option<SocketAddrV6>,
+        policy: RpcPolicy,
+        deadline: Option<RpcDeadline>,
+    ) -> Result<(SocketAddrV6, SpResponse, Vec<u8>)> {
+        self.stats.update_rpc(&kind, |stats| stats.calls += 1);
 
+        // Build and serialize our request once.
+        let request = self.new_request(kind);
         let mut outgoing_buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
-        let n = match our_trailing_data {
-            Some(data) => {
-                let (n, written) =
-                    gateway_messages::serialize_with_trailing_data(
-                        &mut outgoing_buf,
-                        &request,
-                        &[CursorExt::remaining_slice(data)],
-                    );
-                // `data` is an in-memory cursor; seeking can only fail if we
-                // provide a bogus offset, so it's safe to unwrap here.
-                data.seek(SeekFrom::Current(written as i64)).unwrap();
-                n
-            }
-            None => {
-                // We know statically that `outgoing_buf` is large enough to
-                // hold any `Request`, which in practice is the only possible
-                // serialization error. Therefore, we can `.unwrap()`.
-                gateway_messages::serialize(&mut outgoing_buf[..], &request)
-                    .unwrap()
-            }
-        };
+        let n =
+            serialize_request(&mut outgoing_buf, &request, our_trailing_data);
         let outgoing_buf = &outgoing_buf[..n];
 
-        // See comment on `SP_RESET_TIME_ALLOWED` above; bump up the retry count
-        // if we're trying to trigger an SP reset.
-        let calc_reset_attempts = || {
-            let time_desired = SP_RESET_TIME_ALLOWED.as_millis();
-            let per_attempt = self.per_attempt_timeout.as_millis().max(1);
-            ((time_desired + per_attempt - 1) / per_attempt) as usize
-        };
-        let max_attempts = match &request.kind {
-            MessageKind::MgsRequest(MgsRequest::ResetComponentTrigger {
-                component,
-            }) if *component == SpComponent::SP_ITSELF => calc_reset_attempts(),
-            MessageKind::MgsRequest(MgsRequest::ResetTrigger) => {
-                calc_reset_attempts()
-            }
-            _ => self.max_attempts_per_rpc,
-        };
+        // Health reports round trip times from our first send, so that time
+        // spent on retries isn't hidden.
+        let first_sent_at = Instant::now();
 
-        for attempt in 1..=max_attempts {
+        for attempt in 1..=policy.max_attempts {
+            self.stats.update_rpc(&kind, |stats| stats.attempts += 1);
             trace!(
                 self.log(), "sending request to SP";
                 "request" => ?request,
                 "attempt" => attempt,
             );
 
-            match self
-                .rpc_call_one_attempt(request.header.message_id, outgoing_buf)
-                .await?
-            {
+            let one_attempt = self.rpc_call_one_attempt(
+                request.header.message_id,
+                &kind,
+                attempt,
+                outgoing_buf,
+                expected_peer,
+                &policy,
+                first_sent_at,
+            );
+            let result = match deadline {
+                Some(deadline) => {
+                    match time::timeout_at(deadline.at, one_attempt).await {
+                        Ok(result) => result,
+                        Err(_elapsed) => {
+                            crate::probes::rpc_timeout!(|| (
+                                self.transport.interface(),
+                                request.header.message_id,
+                                attempt as u64,
+                                "deadline_exceeded",
+                            ));
+                            self.stats
+                                .update_rpc(&kind, |stats| stats.timeouts += 1);
+                            self.health.record_failure();
+                            return Err(CommunicationError::DeadlineExceeded(
+                                deadline.budget,
+                            ));
+                        }
+                    }
+                }
+                None => one_attempt.await,
+            };
+
+            match result? {
                 Some(result) => return Ok(result),
-                None => continue,
+                None if attempt < policy.max_attempts => {
+                    crate::probes::rpc_retry!(|| (
+                        self.transport.interface(),
+                        request.header.message_id,
+                        attempt as u64,
+                    ));
+                }
+                None => (),
+            }
+        }
+
+        crate::probes::rpc_timeout!(|| (
+            self.transport.interface(),
+            request.header.message_id,
+            policy.max_attempts as u64,
+            "exhausted_attempts",
+        ));
+        self.stats.update_rpc(&kind, |stats| stats.timeouts += 1);
+        self.health.record_failure();
+        Err(CommunicationError::ExhaustedNumAttempts(policy.max_attempts))
+    }
+
+    // Resetting our SP may change its firmware (e.g., to finish an update), so
+    // we need to ask about its capabilities again.
+    fn note_rpc_complete(&mut self, kind: &MgsRequest) {
+        if RequestKind::of(kind) == RequestKind::Reset {
+            self.capabilities = None;
+        }
+    }
+
+    // Send `rpc` to our SP without waiting for a response; the response (or
+    // the lack of one) is handled by `handle_in_flight_response()` and
+    // `handle_in_flight_timers()`. Only used if our SP supports concurrent
+    // RPCs.
+    async fn start_rpc(&mut self, rpc: RpcRequest) {
+        let RpcRequest { kind, mut our_trailing_data, options, response_tx } =
+            rpc;
+        let (policy, deadline) = self.policy_and_deadline(&kind, &options);
+        let expected_peer = self.expected_peer(&kind);
+        self.stats.update_rpc(&kind, |stats| {
+            stats.calls += 1;
+            stats.attempts += 1;
+        });
+
+        let request = self.new_request(kind);
+        let mut outgoing_buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
+        let n = serialize_request(
+            &mut outgoing_buf,
+            &request,
+            our_trailing_data.as_mut(),
+        );
+
+        trace!(
+            self.log(), "sending concurrent request to SP";
+            "request" => ?request,
+        );
+
+        let message_id = request.header.message_id;
+        let now = Instant::now();
+        self.in_flight.insert(
+            message_id,
+            InFlightRpc {
+                kind,
+                serialized_request: outgoing_buf[..n].to_vec(),
+                our_trailing_data,
+                expected_peer,
+                policy,
+                busy_backoff: policy.busy_backoff.policy(),
+                deadline,
+                options,
+                response_tx,
+                attempt: 1,
+                first_sent_at: now,
+                sent_at: now,
+                next_event: now,
+                busy: false,
+            },
+        );
+        self.send_in_flight(message_id).await;
+    }
+
+    // (Re)send the in-flight RPC `message_id`.
+    async fn send_in_flight(&mut self, message_id: u32) {
+        let rpc = &self.in_flight[&message_id];
+        crate::probes::rpc_send!(|| (
+            self.transport.interface(),
+            message_id,
+            crate::request_name(&rpc.kind),
+            rpc.attempt as u64,
+        ));
+        match self.transport.send(&rpc.serialized_request).await {
+            Ok(()) => {
+                // We just looked up this RPC, so we can unwrap.
+                let rpc = self.in_flight.get_mut(&message_id).unwrap();
+                rpc.sent_at = Instant::now();
+                rpc.next_event = rpc.sent_at + rpc.policy.per_attempt_timeout;
+                rpc.busy = false;
+            }
+            Err(err) => self.finish_in_flight(message_id, Err(err)),
+        }
+    }
+
+    fn finish_in_flight(
+        &mut self,
+        message_id: u32,
+        result: Result<(SocketAddrV6, SpResponse, Vec<u8>)>,
+    ) {
+        let rpc = match self.in_flight.remove(&message_id) {
+            Some(rpc) => rpc,
+            None => return,
+        };
+        self.note_rpc_complete(&rpc.kind);
+
+        let response =
+            RpcResponse { result, our_trailing_data: rpc.our_trailing_data };
+        if rpc.response_tx.send(response).is_err() {
+            warn!(
+                self.log(),
+                "RPC requester disappeared while waiting for response"
+            );
+        }
+    }
+
+    // Handle a response to the in-flight RPC `message_id`, applying the same
+    // checks as `rpc_call_one_attempt()`.
+    fn handle_in_flight_response(
+        &mut self,
+        peer: SocketAddrV6,
+        message_id: u32,
+        response: SpResponse,
+        data: Vec<u8>,
+    ) {
+        let rpc = match self.in_flight.get_mut(&message_id) {
+            Some(rpc) => rpc,
+            None => return,
+        };
+
+        if !rpc.expected_peer.map_or(true, |addr| addr == peer) {
+            if self.impostor_addrs.contains_key(&peer) {
+                // An impostor answering with our message ID tells us nothing
+                // about our SP; keep waiting for the real response.
+                warn!(
+                    self.transport.log(),
+                    "ignoring response from impostor SP";
+                    "peer" => %peer,
+                    "message_id" => message_id,
+                    "response" => ?response,
+                );
+            } else {
+                warn!(
+                    self.transport.log(),
+                    "ignoring response from unverified address";
+                    "peer" => %peer,
+                );
+                self.rediscover_now = true;
+            }
+            return;
+        }
+
+        trace!(
+            self.transport.log(), "received response from SP";
+            "message_id" => message_id,
+            "response" => ?response,
+        );
+        let rtt = rpc.sent_at.elapsed();
+        crate::probes::rpc_response!(|| (
+            self.transport.interface(),
+            message_id,
+            response.name(),
+            rtt.as_micros() as u64,
+        ));
+        self.health.record_response(rpc.first_sent_at.elapsed());
+        let is_busy = matches!(response, SpResponse::Error(SpError::Busy));
+        self.stats.update_rpc(&rpc.kind, |stats| {
+            stats.rtt.record(rtt);
+            stats.busy_responses += u64::from(is_busy);
+        });
+
+        match response {
+            SpResponse::Error(SpError::Busy) => {
+                // As in `rpc_call_one_attempt()`, a busy SP doesn't count as a
+                // failed attempt; resend once our backoff elapses. Our SP busy
+                // policy never gives up, so we can unwrap.
+                let backoff_sleep = rpc.busy_backoff.next_backoff().unwrap();
+                crate::probes::rpc_sp_busy!(|| (
+                    self.transport.interface(),
+                    message_id,
+                    backoff_sleep.as_micros() as u64,
+                ));
+                rpc.next_event = Instant::now() + backoff_sleep;
+                rpc.busy = true;
+            }
+            SpResponse::Error(err) => {
+                self.finish_in_flight(message_id, Err(err.into()));
+            }
+            _ => {
+                self.finish_in_flight(message_id, Ok((peer, response, data)));
             }
         }
+    }
+
+    fn next_in_flight_event(&self) -> Option<Instant> {
+        self.in_flight.values().map(InFlightRpc::wakeup).min()
+    }
+
+    // Resend in-flight RPCs whose current attempt has timed out (or whose busy
+    // backoff has elapsed), and fail those that are out of attempts or past
+    // their deadline. RPCs whose callers have gone away are dropped instead.
+    async fn handle_in_flight_timers(&mut self) {
+        let now = Instant::now();
+        let due = self
+            .in_flight
+            .iter()
+            .filter(|(_, rpc)| rpc.wakeup() <= now || rpc.abandoned())
+            .map(|(&message_id, _)| message_id)
+            .collect::<Vec<_>>();
+
+        for message_id in due {
+            let rpc = match self.in_flight.get_mut(&message_id) {
+                Some(rpc) => rpc,
+                None => continue,
+            };
 
-        Err(CommunicationError::ExhaustedNumAttempts(self.max_attempts_per_rpc))
+            if rpc.response_tx.is_closed() {
+                debug!(
+                    self.transport.log(),
+                    "RPC requester disappeared; abandoning request";
+                    "request" => ?rpc.kind,
+                );
+                self.in_flight.remove(&message_id);
+            } else if rpc.options.is_cancelled() {
+                self.finish_in_flight(
+                    message_id,
+                    Err(CommunicationError::Cancelled),
+                );
+            } else if let Some(deadline) =
+                rpc.deadline.filter(|deadline| deadline.at <= now)
+            {
+                crate::probes::rpc_timeout!(|| (
+                    self.transport.interface(),
+                    message_id,
+                    rpc.attempt as u64,
+                    "deadline_exceeded",
+                ));
+                self.stats.update_rpc(&rpc.kind, |stats| stats.timeouts += 1);
+                self.health.record_failure();
+                self.finish_in_flight(
+                    message_id,
+                    Err(CommunicationError::DeadlineExceeded(deadline.budget)),
+                );
+            } else if rpc.busy {
+                self.send_in_flight(message_id).await;
+            } else if rpc.attempt >= rpc.policy.max_attempts {
+                let max_attempts = rpc.policy.max_attempts;
+                crate::probes::rpc_timeout!(|| (
+                    self.transport.interface(),
+                    message_id,
+                    max_attempts as u64,
+                    "exhausted_attempts",
+                ));
+                self.stats.update_rpc(&rpc.kind, |stats| stats.timeouts += 1);
+                self.health.record_failure();
+                self.finish_in_flight(
+                    message_id,
+                    Err(CommunicationError::ExhaustedNumAttempts(max_attempts)),
+                );
+            } else {
+                crate::probes::rpc_retry!(|| (
+                    self.transport.interface(),
+                    message_id,
+                    rpc.attempt as u64,
+                ));
+                rpc.attempt += 1;
+                self.stats.update_rpc(&rpc.kind, |stats| stats.attempts += 1);
+                trace!(
+                    self.transport.log(), "resending request to SP";
+                    "request" => ?rpc.kind,
+                    "attempt" => rpc.attempt,
+                );
+                self.send_in_flight(message_id).await;
+            }
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn rpc_call_one_attempt(
         &mut self,
         message_id: u32,
+        kind: &MgsRequest,
+        attempt: usize,
         serialized_request: &[u8],
+        expected_peer: Option<SocketAddrV6>,
+        policy: &RpcPolicy,
+        first_sent_at: Instant,
     ) -> Result<Option<(SocketAddrV6, SpResponse, Vec<u8>)>> {
         // We consider an RPC attempt to be our attempt to contact the SP. It's
         // possible for the SP to respond and say it's busy; we shouldn't count
         // that as a failed UDP RPC attempt, so we loop within this "one
         // attempt" function to handle busy SP responses.
-        let mut busy_sp_backoff = sp_busy_policy();
+        let mut busy_sp_backoff = policy.busy_backoff.policy();
 
         // We usually resend the request in each iteration of the loop below,
         // but we skip that if we receive an out-of-band packet from the SP
@@ -1843,12 +3012,23 @@ impl<T: InnerSocket> Inner<T> {
         // can loop _without_ resending (and therefore without resetting this
         // interval) - this allows us to still time out even if we're getting a
         // steady stream of out-of-band messages.
-        let mut timeout = tokio::time::interval(self.per_attempt_timeout);
+        let mut timeout = tokio::time::interval(policy.per_attempt_timeout);
+
+        // When did we last send our request? Used to calculate the round trip
+        // time when we get a response.
+        let mut sent_at = Instant::now();
 
         loop {
             if resend_request {
-                self.socket_handle.send(serialized_request).await?;
+                crate::probes::rpc_send!(|| (
+                    self.transport.interface(),
+                    message_id,
+                    crate::request_name(kind),
+                    attempt as u64,
+                ));
+                self.transport.send(serialized_request).await?;
                 timeout.reset();
+                sent_at = Instant::now();
             }
 
             // Reset our default policy of resending requests if we iterate on
@@ -1856,7 +3036,7 @@ impl<T: InnerSocket> Inner<T> {
             resend_request = true;
 
             let message = tokio::select! {
-                result = self.socket_handle.recv() => result,
+                result = self.transport.recv() => result,
                 _ = timeout.tick() => return Ok(None),
             };
 
@@ -1885,15 +3065,58 @@ impl<T: InnerSocket> Inner<T> {
                     response,
                     data,
                 } => {
-                    if message_id == header.message_id {
-                        (peer, header, response, data)
-                    } else {
+                    if self.in_flight.contains_key(&header.message_id) {
+                        // This is a response to one of the RPCs we've sent
+                        // concurrently with this one.
+                        self.handle_in_flight_response(
+                            peer,
+                            header.message_id,
+                            response,
+                            data,
+                        );
+                        resend_request = false;
+                        continue;
+                    } else if message_id != header.message_id {
+                        // Most likely a duplicate or late response to an
+                        // earlier request; it says nothing about whether our
+                        // request made it, so keep waiting without resending.
+                        self.stats
+                            .update(|stats| stats.unexpected_responses += 1);
                         debug!(
                             self.log(), "ignoring unexpected response";
                             "id" => header.message_id,
                             "peer" => %peer,
                         );
-                        return Ok(None);
+                        resend_request = false;
+                        continue;
+                    } else if expected_peer.map_or(true, |addr| addr == peer) {
+                        (peer, header, response, data)
+                    } else if self.impostor_addrs.contains_key(&peer) {
+                        // An impostor answering with our message ID tells us
+                        // nothing about our SP; keep waiting for the real
+                        // response.
+                        warn!(
+                            self.log(),
+                            "ignoring response from impostor SP";
+                            "peer" => %peer,
+                            "header" => ?header,
+                            "response" => ?response,
+                        );
+                        resend_request = false;
+                        continue;
+                    } else {
+                        // Our SP may have changed addresses; we'll keep
+                        // waiting for a response from the address we know,
+                        // but check whether this new address belongs to our
+                        // SP once this RPC is done.
+                        warn!(
+                            self.log(),
+                            "ignoring response from unverified address";
+                            "peer" => %peer,
+                        );
+                        self.rediscover_now = true;
+                        resend_request = false;
+                        continue;
                     }
                 }
             };
@@ -1903,11 +3126,29 @@ impl<T: InnerSocket> Inner<T> {
                 "header" => ?header,
                 "response" => ?response,
             );
+            let rtt = sent_at.elapsed();
+            crate::probes::rpc_response!(|| (
+                self.transport.interface(),
+                message_id,
+                response.name(),
+                rtt.as_micros() as u64,
+            ));
+            self.health.record_response(first_sent_at.elapsed());
+            let is_busy = matches!(response, SpResponse::Error(SpError::Busy));
+            self.stats.update_rpc(kind, |stats| {
+                stats.rtt.record(rtt);
+                stats.busy_responses += u64::from(is_busy);
+            });
 
             match response {
                 SpResponse::Error(SpError::Busy) => {
                     // Our SP busy policy never gives up, so we can unwrap.
                     let backoff_sleep = busy_sp_backoff.next_backoff().unwrap();
+                    crate::probes::rpc_sp_busy!(|| (
+                        self.transport.interface(),
+                        message_id,
+                        backoff_sleep.as_micros() as u64,
+                    ));
                     time::sleep(backoff_sleep).await;
                     continue;
                 }
@@ -1933,6 +3174,10 @@ impl<T: InnerSocket> Inner<T> {
             self.log(), "recording host phase 2 request";
             "request" => ?request,
         );
+        self.health.record_traffic();
+        self.stats.update(|stats| {
+            stats.host_phase2_bytes_sent += request.data_sent;
+        });
         self.most_recent_host_phase2_request = Some(request);
     }
 
@@ -1942,7 +3187,7 @@ impl<T: InnerSocket> Inner<T> {
 
     fn forward_serial_console(
         &mut self,
-        _component: SpComponent,
+        component: SpComponent,
         offset: u64,
         data: &[u8],
     ) {
@@ -1951,13 +3196,33 @@ impl<T: InnerSocket> Inner<T> {
         // the foreseeable future we only support one component, so we skip that
         // for now.
 
+        self.health.record_traffic();
+
         if let Some(tx) = self.serial_console_tx.as_ref() {
             match tx.try_send((offset, data.to_vec())) {
-                Ok(()) => return,
+                Ok(()) => {
+                    crate::probes::serial_console_forwarded!(|| (
+                        self.transport.interface(),
+                        component.to_string(),
+                        offset,
+                        data.len() as u64,
+                    ));
+                    return;
+                }
                 Err(mpsc::error::TrySendError::Closed(_)) => {
                     self.serial_console_tx = None;
                 }
                 Err(mpsc::error::TrySendError::Full(_)) => {
+                    crate::probes::serial_console_dropped!(|| (
+                        self.transport.interface(),
+                        component.to_string(),
+                        offset,
+                        data.len() as u64,
+                        "buffer_full",
+                    ));
+                    self.stats.update(|stats| {
+                        stats.serial_console_bytes_dropped += data.len() as u64;
+                    });
                     error!(
                         self.log(),
                         "discarding SP serial console data (buffer full)"
@@ -1966,6 +3231,16 @@ impl<T: InnerSocket> Inner<T> {
                 }
             }
         }
+        crate::probes::serial_console_dropped!(|| (
+            self.transport.interface(),
+            component.to_string(),
+            offset,
+            data.len() as u64,
+            "no_receiver",
+        ));
+        self.stats.update(|stats| {
+            stats.serial_console_bytes_dropped += data.len() as u64;
+        });
         warn!(self.log(), "discarding SP serial console data (no receiver)");
     }
 
@@ -1993,10 +3268,7 @@ impl<T: InnerSocket> Inner<T> {
             ));
         }
 
-        let (_peer, response, _data) = self
-            .rpc_call(MgsRequest::SerialConsoleAttach(component), None)
-            .await?;
-        response.expect_serial_console_attach_ack()?;
+        self.call(request::SerialConsoleAttach(component)).await?;
 
         let (tx, rx) = mpsc::channel(SERIAL_CONSOLE_CHANNEL_DEPTH);
         self.serial_console_tx = Some(tx);
@@ -2008,23 +3280,48 @@ impl<T: InnerSocket> Inner<T> {
     }
 
     async fn detach_serial_console(&mut self) -> Result<()> {
-        let (_peer, response, _data) =
-            self.rpc_call(MgsRequest::SerialConsoleDetach, None).await?;
-        response.expect_serial_console_detach_ack()?;
+        self.call(request::SerialConsoleDetach).await?;
         self.serial_console_tx = None;
         Ok(())
     }
 }
 
-fn sp_busy_policy() -> backoff::ExponentialBackoff {
-    const INITIAL_INTERVAL: Duration = Duration::from_millis(20);
-    const MAX_INTERVAL: Duration = Duration::from_millis(1_000);
+// Serialize `request` into `out`, followed by as much of `our_trailing_data`
+// (if any) as fits; the cursor is advanced past the data we packed.
+fn serialize_request(
+    out: &mut [u8; gateway_messages::MAX_SERIALIZED_SIZE],
+    request: &Message,
+    our_trailing_data: Option<&mut Cursor<Vec<u8>>>,
+) -> usize {
+    match our_trailing_data {
+        Some(data) => {
+            let (n, written) = gateway_messages::serialize_with_trailing_data(
+                out,
+                request,
+                &[CursorExt::remaining_slice(data)],
+            );
+            // `data` is an in-memory cursor; seeking can only fail if we
+            // provide a bogus offset, so it's safe to unwrap here.
+            data.seek(SeekFrom::Current(written as i64)).unwrap();
+            n
+        }
+        None => {
+            // We know statically that `out` is large enough to hold any
+            // `Request`, which in practice is the only possible serialization
+            // error. Therefore, we can `.unwrap()`.
+            gateway_messages::serialize(&mut out[..], request).unwrap()
+        }
+    }
+}
 
+fn discovery_retry_policy(
+    config: &DiscoveryConfig,
+) -> backoff::ExponentialBackoff {
     backoff::ExponentialBackoff {
-        current_interval: INITIAL_INTERVAL,
-        initial_interval: INITIAL_INTERVAL,
+        current_interval: config.initial_retry_interval(),
+        initial_interval: config.initial_retry_interval(),
         multiplier: 2.0,
-        max_interval: MAX_INTERVAL,
+        max_interval: config.max_retry_interval(),
         max_elapsed_time: None,
         ..Default::default()
     }
@@ -2048,30 +3345,36 @@ impl CursorExt for Cursor<Vec<u8>> {
     }
 }
 
-#[usdt::provider(provider = "gateway_sp_comms")]
-mod probes {
-    fn recv_packet(
-        _source: &SocketAddr,
-        _data: u64, // TODO actually a `*const u8`, but that isn't allowed by usdt
-        _len: u64,
-    ) {
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    // A fake `InnerSocket` whose `recv()` method is connected to a tokio
+    use crate::testing::Fault;
+    use crate::testing::FaultSchedule;
+    use crate::testing::FaultyTransport;
+    use crate::testing::SimulatedSp;
+    use async_trait::async_trait;
+    use gateway_messages::measurement::MeasurementError;
+    use gateway_messages::measurement::MeasurementKind;
+    use gateway_messages::measurement::MeasurementKindV2;
+    use gateway_messages::measurement::MeasurementMetadata;
+    use gateway_messages::measurement::MeasurementUnit;
+    use gateway_messages::monorail_port_status::LinkFlapEvent;
+    use gateway_messages::monorail_port_status::LinkStatus;
+    use gateway_messages::monorail_port_status::MacTableEntry;
+    use gateway_messages::TlvPage;
+    use std::sync::atomic::Ordering;
+    use tokio_util::sync::CancellationToken;
+
+    // A fake `SpTransport` whose `recv()` method is connected to a tokio
     // channel.
     #[derive(Debug)]
-    struct ChannelInnerSocket {
+    struct ChannelTransport {
         log: Logger,
         packets_sent: Vec<Vec<u8>>,
         recv: mpsc::UnboundedReceiver<SingleSpMessage>,
     }
 
-    impl ChannelInnerSocket {
+    impl ChannelTransport {
         fn new(log: Logger) -> (Self, mpsc::UnboundedSender<SingleSpMessage>) {
             let (recv_tx, recv) = mpsc::unbounded_channel();
             (Self { log, packets_sent: Vec::new(), recv }, recv_tx)
@@ -2079,19 +3382,23 @@ mod tests {
     }
 
     #[async_trait]
-    impl InnerSocket for ChannelInnerSocket {
+    impl SpTransport for ChannelTransport {
         fn log(&self) -> &Logger {
             &self.log
         }
 
+        fn interface(&self) -> &str {
+            "(test channel)"
+        }
+
         fn discovery_addr(&self) -> SocketAddrV6 {
-            unimplemented!()
+            "[ff15::1]:11111".parse().unwrap()
         }
 
         async fn send(
             &mut self,
             data: &[u8],
-        ) -> Result<(), SingleSpHandleError> {
+        ) -> Result<(), CommunicationError> {
             self.packets_sent.push(data.into());
             Ok(())
         }
@@ -2101,20 +3408,54 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn rpc_call_one_attempt_times_out_while_receiving_host_request_updates(
-    ) {
-        let (sp_addr_tx, _sp_addr_rx) = watch::channel(None);
-        let (_cmds_tx, cmds_rx) = mpsc::channel(128);
+    fn new_test_inner(
+        rpc_policies: RpcPolicies,
+    ) -> (Inner<ChannelTransport>, mpsc::UnboundedSender<SingleSpMessage>) {
         let (socket, socket_tx) =
-            ChannelInnerSocket::new(Logger::root(slog::Discard, slog::o!()));
-        let mut inner = Inner::new(
-            socket,
+            ChannelTransport::new(Logger::root(slog::Discard, slog::o!()));
+        let (inner, _channels) =
+            new_test_inner_with(socket, None, rpc_policies);
+        (inner, socket_tx)
+    }
+
+    // Our ends of the channels connected to an `Inner` built by
+    // `new_test_inner_with()`. `Inner::run()` returns once `cmds_tx` is
+    // dropped, so tests that run it must hold on to this.
+    struct TestInnerChannels {
+        health_rx: watch::Receiver<SpHealth>,
+        _cmds_tx: mpsc::Sender<InnerCommand>,
+    }
+
+    fn new_test_inner_with<T: SpTransport>(
+        transport: T,
+        sp_addr: Option<(SocketAddrV6, SpPort)>,
+        rpc_policies: RpcPolicies,
+    ) -> (Inner<T>, TestInnerChannels) {
+        let (sp_addr_tx, _sp_addr_rx) = watch::channel(sp_addr);
+        let (identity_tx, _identity_rx) = watch::channel(None);
+        let (identity_events_tx, _) = broadcast::channel(16);
+        let (health_tx, health_rx) =
+            watch::channel(SpHealth::Unreachable { since: Instant::now() });
+        let (cmds_tx, cmds_rx) = mpsc::channel(128);
+        let inner = Inner::new(
+            transport,
             sp_addr_tx,
-            1,
-            Duration::from_millis(200),
+            identity_tx,
+            identity_events_tx,
+            health_tx,
+            Arc::default(),
+            DiscoveryConfig::default(),
+            rpc_policies,
             cmds_rx,
         );
+        (inner, TestInnerChannels { health_rx, _cmds_tx: cmds_tx })
+    }
+
+    #[tokio::test]
+    async fn rpc_call_one_attempt_times_out_while_receiving_host_request_updates(
+    ) {
+        let (mut inner, socket_tx) =
+            new_test_inner(RpcPolicies::new(1, Duration::from_millis(200)));
 
         // Spawn a task that emulates the SP sending host phase 2 requests on a
         // frequency that's higher than our timeout (we'll do 20ms, so 10x
@@ -2140,7 +3481,15 @@ mod tests {
         let start = Instant::now();
         match tokio::time::timeout(
             Duration::from_secs(2),
-            inner.rpc_call_one_attempt(0, b"dummy"),
+            inner.rpc_call_one_attempt(
+                0,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                None,
+                &RpcPolicy::new(1, Duration::from_millis(200)),
+                Instant::now(),
+            ),
         )
         .await
         {
@@ -2163,4 +3512,1000 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn rpc_call_one_attempt_only_accepts_responses_from_verified_sp() {
+        let (mut inner, socket_tx) =
+            new_test_inner(RpcPolicies::new(1, Duration::from_secs(2)));
+
+        let sp_addr = "[fe80::1]:11111".parse().unwrap();
+        let unverified_addr = "[fe80::2]:11111".parse().unwrap();
+        let impostor_addr = "[fe80::3]:11111".parse().unwrap();
+        inner.impostor_addrs.insert(impostor_addr, ());
+
+        let response_from = |peer| SingleSpMessage::SpResponse {
+            peer,
+            header: Header { version: version::CURRENT, message_id: 1 },
+            response: SpResponse::SerialConsoleKeepAliveAck,
+            data: Vec::new(),
+        };
+
+        // A response from an address we haven't verified should be skipped
+        // (and trigger rediscovery), and we should keep waiting for the
+        // response from our SP.
+        let policy = RpcPolicy::new(1, Duration::from_secs(2));
+        socket_tx.send(response_from(unverified_addr)).unwrap();
+        socket_tx.send(response_from(sp_addr)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                1,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &policy,
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(Some((peer, _response, _data))) => assert_eq!(peer, sp_addr),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(inner.rediscover_now);
+
+        inner.rediscover_now = false;
+
+        // A response from a known impostor should be logged and skipped
+        // without triggering rediscovery, and we should keep waiting for the
+        // response from our SP.
+        socket_tx.send(response_from(impostor_addr)).unwrap();
+        socket_tx.send(response_from(sp_addr)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                1,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &policy,
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(Some((peer, _response, _data))) => assert_eq!(peer, sp_addr),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(!inner.rediscover_now);
+    }
+
+    // Policy for the tests below: without cancellation, RPCs retry for ~10
+    // seconds.
+    fn slow_rpc_policies() -> RpcPolicies {
+        RpcPolicies::uniform(RpcPolicy::new(100, Duration::from_millis(100)))
+    }
+
+    #[tokio::test]
+    async fn rpc_stops_retrying_when_requester_disappears() {
+        let (mut inner, _socket_tx) = new_test_inner(slow_rpc_policies());
+
+        // Emulate a caller that drops its `SingleSp` future after 200ms.
+        let (response_tx, response_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(response_rx);
+        });
+
+        let rpc = RpcRequest {
+            kind: MgsRequest::SpState,
+            our_trailing_data: None,
+            options: CallOptions::new(),
+            response_tx,
+        };
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            inner.handle_command(InnerCommand::Rpc(rpc)),
+        )
+        .await
+        .expect("abandoned RPC was not cancelled");
+
+        let attempts = inner.transport.packets_sent.len();
+        assert!(attempts < 100, "abandoned RPC made {attempts} attempts");
+    }
+
+    #[tokio::test]
+    async fn rpc_call_with_options_stops_when_cancelled() {
+        let (mut inner, _socket_tx) = new_test_inner(slow_rpc_policies());
+
+        let cancel = CancellationToken::new();
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                cancel.cancel();
+            }
+        });
+
+        let options = CallOptions::new().with_cancel(cancel);
+        match tokio::time::timeout(
+            Duration::from_secs(2),
+            inner.rpc_call_with_options(MgsRequest::SpState, None, &options),
+        )
+        .await
+        {
+            Ok(Err(CommunicationError::Cancelled)) => (),
+            Ok(other) => panic!("unexpected result {other:?}"),
+            Err(_elapsed) => panic!("cancelled RPC kept retrying"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rpc_call_with_options_stops_at_deadline() {
+        let (mut inner, _socket_tx) = new_test_inner(slow_rpc_policies());
+
+        let start = Instant::now();
+        let options =
+            CallOptions::new().with_timeout(Duration::from_millis(200));
+        match tokio::time::timeout(
+            Duration::from_secs(2),
+            inner.rpc_call_with_options(MgsRequest::SpState, None, &options),
+        )
+        .await
+        {
+            Ok(Err(CommunicationError::DeadlineExceeded(budget))) => {
+                assert!(start.elapsed() >= Duration::from_millis(200));
+                assert!(budget <= Duration::from_millis(200));
+                assert!(budget >= Duration::from_millis(150));
+            }
+            Ok(other) => panic!("unexpected result {other:?}"),
+            Err(_elapsed) => panic!("RPC kept retrying past its deadline"),
+        }
+    }
+
+    fn test_rpc(
+        kind: MgsRequest,
+    ) -> (RpcRequest, oneshot::Receiver<RpcResponse>) {
+        let (response_tx, response_rx) = oneshot::channel();
+        let rpc = RpcRequest {
+            kind,
+            our_trailing_data: None,
+            options: CallOptions::new(),
+            response_tx,
+        };
+        (rpc, response_rx)
+    }
+
+    fn sent_message_id(packet: &[u8]) -> u32 {
+        gateway_messages::deserialize::<Message>(packet)
+            .unwrap()
+            .0
+            .header
+            .message_id
+    }
+
+    #[tokio::test]
+    async fn concurrent_rpcs_complete_out_of_order() {
+        let (mut inner, _socket_tx) =
+            new_test_inner(RpcPolicies::new(3, Duration::from_secs(2)));
+        let sp_addr = "[fe80::1]:11111".parse().unwrap();
+        inner.sp_addr = Some(sp_addr);
+        inner.capabilities = Some(ProtocolCapabilities::CONCURRENT_RPCS);
+
+        // Both requests should be sent without waiting for a response.
+        let (rpc1, mut rx1) = test_rpc(MgsRequest::SerialConsoleKeepAlive);
+        let (rpc2, mut rx2) = test_rpc(MgsRequest::GetPowerState);
+        inner.start_rpc(rpc1).await;
+        inner.start_rpc(rpc2).await;
+        assert_eq!(inner.transport.packets_sent.len(), 2);
+        assert_eq!(inner.in_flight.len(), 2);
+        let id1 = sent_message_id(&inner.transport.packets_sent[0]);
+        let id2 = sent_message_id(&inner.transport.packets_sent[1]);
+
+        // If our SP stops supporting concurrent RPCs, we shouldn't accept any
+        // more commands until both of these are done.
+        inner.capabilities = Some(ProtocolCapabilities::empty());
+        assert!(!inner.ready_for_command());
+
+        let response_to = |message_id, response| SingleSpMessage::SpResponse {
+            peer: sp_addr,
+            header: Header { version: version::CURRENT, message_id },
+            response,
+            data: Vec::new(),
+        };
+
+        // Respond to the second request first.
+        inner
+            .handle_incoming_message(response_to(
+                id2,
+                SpResponse::PowerState(PowerState::A2),
+            ))
+            .await;
+        match rx2.try_recv().unwrap().result {
+            Ok((_, SpResponse::PowerState(PowerState::A2), _)) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(rx1.try_recv().is_err());
+
+        inner
+            .handle_incoming_message(response_to(
+                id1,
+                SpResponse::SerialConsoleKeepAliveAck,
+            ))
+            .await;
+        match rx1.try_recv().unwrap().result {
+            Ok((_, SpResponse::SerialConsoleKeepAliveAck, _)) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(inner.in_flight.is_empty());
+        assert!(inner.ready_for_command());
+    }
+
+    #[tokio::test]
+    async fn concurrent_rpc_retries_until_out_of_attempts() {
+        let (mut inner, _socket_tx) = new_test_inner(RpcPolicies::uniform(
+            RpcPolicy::new(2, Duration::from_millis(50)),
+        ));
+        inner.capabilities = Some(ProtocolCapabilities::CONCURRENT_RPCS);
+
+        let (rpc, mut rx) = test_rpc(MgsRequest::GetPowerState);
+        inner.start_rpc(rpc).await;
+
+        // Drive our timers until we give up; bound our iterations in case we
+        // never do.
+        for _ in 0..10 {
+            match inner.next_in_flight_event() {
+                Some(when) => {
+                    time::sleep_until(when).await;
+                    inner.handle_in_flight_timers().await;
+                }
+                None => break,
+            }
+        }
+
+        assert_eq!(inner.transport.packets_sent.len(), 2);
+        match rx.try_recv().unwrap().result {
+            Err(CommunicationError::ExhaustedNumAttempts(2)) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    fn new_faulty_inner(
+        from_sp: FaultSchedule,
+    ) -> (
+        Inner<FaultyTransport<ChannelTransport>>,
+        mpsc::UnboundedSender<SingleSpMessage>,
+    ) {
+        let (socket, socket_tx) =
+            ChannelTransport::new(Logger::root(slog::Discard, slog::o!()));
+        let socket =
+            FaultyTransport::new(socket, FaultSchedule::none(), from_sp);
+        let (inner, _channels) = new_test_inner_with(
+            socket,
+            None,
+            RpcPolicies::new(1, Duration::from_secs(2)),
+        );
+        (inner, socket_tx)
+    }
+
+    fn keep_alive_ack(peer: SocketAddrV6, message_id: u32) -> SingleSpMessage {
+        SingleSpMessage::SpResponse {
+            peer,
+            header: Header { version: version::CURRENT, message_id },
+            response: SpResponse::SerialConsoleKeepAliveAck,
+            data: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn health_rtt_is_measured_from_first_send() {
+        let (socket, socket_tx) =
+            ChannelTransport::new(Logger::root(slog::Discard, slog::o!()));
+        let (mut inner, TestInnerChannels { health_rx, .. }) =
+            new_test_inner_with(
+                socket,
+                None,
+                RpcPolicies::new(1, Duration::from_secs(2)),
+            );
+
+        // Pretend our first attempt at this RPC went out a while ago; the
+        // response to this attempt arrives immediately.
+        let sp_addr = "[fe80::1]:11111".parse().unwrap();
+        let retry_delay = Duration::from_millis(500);
+        socket_tx.send(keep_alive_ack(sp_addr, 1)).unwrap();
+        inner
+            .rpc_call_one_attempt(
+                1,
+                &MgsRequest::SerialConsoleKeepAlive,
+                2,
+                b"dummy",
+                Some(sp_addr),
+                &RpcPolicy::new(2, Duration::from_secs(2)),
+                Instant::now() - retry_delay,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        match *health_rx.borrow() {
+            SpHealth::Reachable { rtt: Some(rtt), .. } => {
+                assert!(rtt >= retry_delay, "rtt {rtt:?} excludes retries");
+            }
+            health => panic!("unexpected health {health:?}"),
+        }
+
+        // Per-request stats still report the round trip of each attempt.
+        let stats = inner.stats.snapshot();
+        let rtt = &stats.rpcs["SerialConsoleKeepAlive"].rtt;
+        assert_eq!(rtt.count(), 1);
+        assert!(rtt.total < retry_delay);
+    }
+
+    #[tokio::test]
+    async fn duplicate_responses_are_ignored() {
+        let (mut inner, socket_tx) =
+            new_faulty_inner(FaultSchedule::none().then(Fault::Duplicate));
+        let sp_addr = "[fe80::1]:11111".parse().unwrap();
+        let policy = RpcPolicy::new(1, Duration::from_secs(2));
+
+        socket_tx.send(keep_alive_ack(sp_addr, 1)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                1,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &policy,
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(Some((peer, _response, _data))) => assert_eq!(peer, sp_addr),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // The duplicate of the first response is still waiting for us; it
+        // should be skipped in favor of the response to our second request.
+        socket_tx.send(keep_alive_ack(sp_addr, 2)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                2,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &policy,
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(Some((peer, _response, _data))) => assert_eq!(peer, sp_addr),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert_eq!(inner.transport.inner().packets_sent.len(), 2);
+
+        let stats = inner.stats.snapshot();
+        assert_eq!(stats.unexpected_responses, 1);
+        assert_eq!(stats.rpcs["SerialConsoleKeepAlive"].rtt.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn stale_response_does_not_cost_an_attempt() {
+        // Delay the response to our first request past its timeout, and the
+        // response to our second request until after that stale response
+        // arrives.
+        let (mut inner, socket_tx) = new_faulty_inner(
+            FaultSchedule::none()
+                .then(Fault::Delay(Duration::from_millis(150)))
+                .then(Fault::Delay(Duration::from_millis(300))),
+        );
+        let sp_addr = "[fe80::1]:11111".parse().unwrap();
+
+        socket_tx.send(keep_alive_ack(sp_addr, 1)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                1,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &RpcPolicy::new(1, Duration::from_millis(100)),
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(None) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        socket_tx.send(keep_alive_ack(sp_addr, 2)).unwrap();
+        match inner
+            .rpc_call_one_attempt(
+                2,
+                &MgsRequest::SerialConsoleKeepAlive,
+                1,
+                b"dummy",
+                Some(sp_addr),
+                &RpcPolicy::new(1, Duration::from_secs(2)),
+                Instant::now(),
+            )
+            .await
+        {
+            Ok(Some((peer, _response, _data))) => assert_eq!(peer, sp_addr),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // Receiving the stale response should not have prompted a resend.
+        assert_eq!(inner.transport.inner().packets_sent.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn serial_console_skips_duplicates_and_tolerates_gaps() {
+        let (mut inner, socket_tx) = new_faulty_inner(
+            FaultSchedule::none()
+                .then(Fault::None)
+                .then(Fault::Duplicate)
+                .then(Fault::Drop)
+                .then(Fault::None),
+        );
+        let (tx, rx) = mpsc::channel(16);
+        inner.serial_console_tx = Some(tx);
+        let mut console = AttachedSerialConsoleRecv {
+            rx_offset: 0,
+            rx,
+            stats: Arc::default(),
+            log: Logger::root(slog::Discard, slog::o!()),
+        };
+
+        for (offset, data) in [(0, b"ab"), (2, b"cd"), (4, b"ef"), (6, b"gh")] {
+            socket_tx
+                .send(SingleSpMessage::SerialConsole {
+                    component: SpComponent::SP3_HOST_CPU,
+                    offset,
+                    data: data.to_vec(),
+                })
+                .unwrap();
+        }
+
+        // "cd" is delivered twice and "ef" is dropped.
+        for _ in 0..4 {
+            let message = inner.transport.recv().await;
+            inner.handle_incoming_message(message).await;
+        }
+        inner.serial_console_tx = None;
+
+        let mut received = Vec::new();
+        while let Some(data) = console.recv().await {
+            received.push(data);
+        }
+        assert_eq!(received, [b"ab".to_vec(), b"cd".to_vec(), b"gh".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_detaches_console_and_fails_queued_commands() {
+        let (mut inner, _socket_tx) = new_test_inner(RpcPolicies::uniform(
+            RpcPolicy::new(1, Duration::from_millis(50)),
+        ));
+        let (cmds_tx, cmds_rx) = mpsc::channel(8);
+        inner.cmds_rx = cmds_rx;
+        let (console_tx, mut console_rx) = mpsc::channel(1);
+        inner.serial_console_tx = Some(console_tx);
+
+        // Queue a command before shutting down...
+        let (response_tx, response_rx) = oneshot::channel();
+        cmds_tx
+            .send(InnerCommand::Rpc(RpcRequest {
+                kind: MgsRequest::SerialConsoleWrite { offset: 0 },
+                our_trailing_data: Some(Cursor::new(b"queued".to_vec())),
+                options: CallOptions::new(),
+                response_tx,
+            }))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), inner.shutdown())
+            .await
+            .expect("shutdown did not complete");
+
+        // ...which fails (but gives back our data), as does anything sent
+        // afterwards.
+        let response = response_rx.await.unwrap();
+        assert!(matches!(response.result, Err(CommunicationError::ShutDown)));
+        assert_eq!(response.our_trailing_data.unwrap().get_ref(), b"queued");
+
+        let (result, data) = rpc_with_trailing_data(
+            &cmds_tx,
+            MgsRequest::SerialConsoleWrite { offset: 0 },
+            Cursor::new(b"late".to_vec()),
+        )
+        .await;
+        assert!(matches!(result, Err(CommunicationError::ShutDown)));
+        assert_eq!(data.get_ref(), b"late");
+
+        // Our SP never answers, so detaching fails, but we still let go of
+        // the console.
+        assert!(console_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn parse_measurement_mea0() {
+        let measurement = Measurement {
+            name: "fan0".to_string(),
+            kind: MeasurementKind::Speed,
+            value: Err(MeasurementError::NoReading),
+        };
+        let mut buf = [0; 64];
+        let n = measurement.serialize(&mut buf).unwrap();
+
+        let parsed =
+            parse_measurement(measurement.tag(), &buf[..n]).unwrap().unwrap();
+        let parsed = match parsed {
+            VersionedMeasurement::V1(m) => m,
+            other => panic!("unexpected measurement {other:?}"),
+        };
+        assert_eq!(parsed.name, "fan0");
+        assert_eq!(parsed.kind, MeasurementKind::Speed);
+        assert_eq!(parsed.value, Err(MeasurementError::NoReading));
+
+        // A header whose name is cut short is rejected rather than
+        // misparsed.
+        assert!(parse_measurement(measurement.tag(), &buf[..n - 1]).is_err());
+    }
+
+    #[test]
+    fn parse_measurement_mea1() {
+        let metadata = MeasurementMetadata {
+            sensor_id: 3,
+            unit: MeasurementUnit::RelativeHumidity,
+            warning_threshold: Some(80.0),
+            critical_threshold: Some(95.0),
+            min_since_boot: None,
+            max_since_boot: Some(42.5),
+            timestamp_ms: 1234,
+        };
+        let measurement = MeasurementV2 {
+            name: "rh0".to_string(),
+            kind: MeasurementKindV2::Humidity,
+            value: Ok(41.0),
+            metadata,
+        };
+        let mut buf = [0; 128];
+        let n = measurement.serialize(&mut buf).unwrap();
+
+        let parsed =
+            parse_measurement(measurement.tag(), &buf[..n]).unwrap().unwrap();
+        assert_eq!(parsed.name(), "rh0");
+        assert_eq!(parsed.kind(), MeasurementKindV2::Humidity);
+        assert_eq!(parsed.value(), Ok(41.0));
+        assert_eq!(parsed.metadata(), Some(metadata));
+        assert!(matches!(parsed, VersionedMeasurement::V2(_)));
+
+        // Unknown tags are skipped.
+        assert!(parse_measurement(tlv::Tag(*b"MEA9"), &buf[..n])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn component_details_parse_both_measurement_versions() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let rpc = ComponentDetailsTlvRpc {
+            component: SpComponent::SP_ITSELF,
+            log: &log,
+        };
+
+        let v1 = Measurement {
+            name: "t0".to_string(),
+            kind: MeasurementKind::Temperature,
+            value: Ok(30.0),
+        };
+        let mut buf = [0; 64];
+        let n = v1.serialize(&mut buf).unwrap();
+        match rpc.parse_tag_value(v1.tag(), &buf[..n]).unwrap() {
+            Some(ComponentDetails::Measurement(m)) => {
+                assert_eq!(m.name, "t0");
+                assert_eq!(m.kind, MeasurementKind::Temperature);
+            }
+            other => panic!("unexpected details {other:?}"),
+        }
+
+        let v2 = MeasurementV2 {
+            name: "e0".to_string(),
+            kind: MeasurementKindV2::Energy,
+            value: Ok(7.0),
+            metadata: MeasurementMetadata {
+                sensor_id: 0,
+                unit: MeasurementUnit::Joules,
+                warning_threshold: None,
+                critical_threshold: None,
+                min_since_boot: None,
+                max_since_boot: None,
+                timestamp_ms: 0,
+            },
+        };
+        let mut buf = [0; 128];
+        let n = v2.serialize(&mut buf).unwrap();
+        match rpc.parse_tag_value(v2.tag(), &buf[..n]).unwrap() {
+            Some(ComponentDetails::MeasurementV2(m)) => {
+                assert_eq!(m.name, "e0");
+                assert_eq!(m.kind, MeasurementKindV2::Energy);
+                assert_eq!(m.metadata.unit, MeasurementUnit::Joules);
+            }
+            other => panic!("unexpected details {other:?}"),
+        }
+    }
+
+    fn component_details_round_trip(
+        details: ComponentDetails,
+    ) -> Result<Option<ComponentDetails>> {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let rpc = ComponentDetailsTlvRpc {
+            component: SpComponent::SP_ITSELF,
+            log: &log,
+        };
+        let mut buf = [0; 64];
+        let n = details.serialize(&mut buf).unwrap();
+        rpc.parse_tag_value(details.tag(), &buf[..n])
+    }
+
+    #[test]
+    fn component_details_mac_table_entry_round_trip() {
+        let entry = MacTableEntry {
+            mac: [0xa8, 0x40, 0x25, 0x01, 0x02, 0x03],
+            vlan: 0x301,
+            port: 7,
+            age_secs: 300,
+        };
+        match component_details_round_trip(ComponentDetails::MacTableEntry(
+            entry,
+        )) {
+            Ok(Some(ComponentDetails::MacTableEntry(parsed))) => {
+                assert_eq!(parsed, entry);
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // A truncated entry is a deserialization error.
+        let log = Logger::root(slog::Discard, slog::o!());
+        let rpc = ComponentDetailsTlvRpc {
+            component: SpComponent::SP_ITSELF,
+            log: &log,
+        };
+        let mut buf = [0; 64];
+        let n =
+            ComponentDetails::MacTableEntry(entry).serialize(&mut buf).unwrap();
+        assert!(matches!(
+            rpc.parse_tag_value(MacTableEntry::TAG, &buf[..n - 1]),
+            Err(CommunicationError::TlvDeserialize { .. })
+        ));
+    }
+
+    #[test]
+    fn component_details_link_flap_event_round_trip() {
+        for status in [LinkStatus::Error, LinkStatus::Down, LinkStatus::Up] {
+            let event = LinkFlapEvent {
+                port: 12,
+                timestamp_ms: 0x0102_0304_0506,
+                status,
+            };
+            match component_details_round_trip(ComponentDetails::LinkFlapEvent(
+                event,
+            )) {
+                Ok(Some(ComponentDetails::LinkFlapEvent(parsed))) => {
+                    assert_eq!(parsed, event);
+                }
+                other => panic!("unexpected result {other:?}"),
+            }
+        }
+    }
+
+    // Append `value` to `page` as a TLV triple tagged `tag`.
+    fn push_tlv(
+        page: &mut Vec<u8>,
+        tag: tlv::Tag,
+        value: impl FnOnce(&mut [u8]) -> hubpack::error::Result<usize>,
+    ) {
+        let mut buf = [0; 256];
+        let n = tlv::encode(&mut buf, tag, value).unwrap();
+        page.extend_from_slice(&buf[..n]);
+    }
+
+    #[test]
+    fn bulk_measurements_page_with_multiple_components() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let metadata = MeasurementMetadata {
+            sensor_id: 9,
+            unit: MeasurementUnit::Watts,
+            warning_threshold: None,
+            critical_threshold: Some(500.0),
+            min_since_boot: Some(1.0),
+            max_since_boot: Some(450.0),
+            timestamp_ms: 5000,
+        };
+        let measurements = [
+            ComponentMeasurement {
+                component: SpComponent::SP_ITSELF,
+                measurement: Measurement {
+                    name: "sp-temp".to_string(),
+                    kind: MeasurementKind::Temperature,
+                    value: Ok(40.0),
+                }
+                .into(),
+            },
+            ComponentMeasurement {
+                component: SpComponent::ROT,
+                measurement: Measurement {
+                    name: "rot-temp".to_string(),
+                    kind: MeasurementKind::Temperature,
+                    value: Err(MeasurementError::DeviceOff),
+                }
+                .into(),
+            },
+            ComponentMeasurement {
+                component: SpComponent::MONORAIL,
+                measurement: MeasurementV2 {
+                    name: "psu-out".to_string(),
+                    kind: MeasurementKindV2::OutputPower,
+                    value: Ok(320.5),
+                    metadata,
+                }
+                .into(),
+            },
+        ];
+
+        let mut page = Vec::new();
+        for m in &measurements {
+            push_tlv(&mut page, ComponentMeasurement::TAG, |buf| {
+                m.serialize(buf)
+            });
+        }
+        // Entries with an unknown outer tag, or whose nested measurement has
+        // an unknown tag, are skipped.
+        push_tlv(&mut page, tlv::Tag(*b"XXX0"), |_| Ok(0));
+        push_tlv(&mut page, ComponentMeasurement::TAG, |buf| {
+            let n = hubpack::serialize(buf, &SpComponent::ROT)?;
+            let m = tlv::encode(&mut buf[n..], tlv::Tag(*b"MEA9"), |_| {
+                Ok::<_, hubpack::error::Error>(0)
+            })
+            .map_err(|_| hubpack::error::Error::Overrun)?;
+            Ok(n + m)
+        });
+
+        let response = SpResponse::BulkMeasurements(TlvPage {
+            offset: 0,
+            total: measurements.len() as u32 + 2,
+        });
+        let parsed = match decode_tlv_page(&response, &page, &log).unwrap() {
+            Some(TlvEntries::BulkMeasurements(parsed)) => parsed,
+            other => panic!("unexpected entries {other:?}"),
+        };
+
+        assert_eq!(parsed.len(), measurements.len());
+        for (parsed, expected) in parsed.iter().zip(&measurements) {
+            assert_eq!(parsed.component, expected.component);
+            assert_eq!(parsed.measurement.name(), expected.measurement.name());
+            assert_eq!(parsed.measurement.kind(), expected.measurement.kind());
+            assert_eq!(
+                parsed.measurement.value(),
+                expected.measurement.value()
+            );
+            assert_eq!(
+                parsed.measurement.metadata(),
+                expected.measurement.metadata()
+            );
+        }
+        assert!(matches!(parsed[0].measurement, VersionedMeasurement::V1(_)));
+        assert!(matches!(parsed[2].measurement, VersionedMeasurement::V2(_)));
+
+        // A nested triple that runs past the end of its entry is an error.
+        let mut page = Vec::new();
+        push_tlv(&mut page, ComponentMeasurement::TAG, |buf| {
+            let n = measurements[0].serialize(buf)?;
+            Ok(n - 1)
+        });
+        assert!(decode_tlv_page(&response, &page, &log).is_err());
+    }
+
+    // A `SpTransport` that forwards every packet it sends to a channel.
+    struct SendTapTransport {
+        log: Logger,
+        sent_tx: mpsc::UnboundedSender<Vec<u8>>,
+        recv: mpsc::UnboundedReceiver<SingleSpMessage>,
+    }
+
+    #[async_trait]
+    impl SpTransport for SendTapTransport {
+        fn log(&self) -> &Logger {
+            &self.log
+        }
+
+        fn interface(&self) -> &str {
+            "(test send tap)"
+        }
+
+        fn discovery_addr(&self) -> SocketAddrV6 {
+            "[ff15::1]:11111".parse().unwrap()
+        }
+
+        async fn send(
+            &mut self,
+            data: &[u8],
+        ) -> Result<(), CommunicationError> {
+            _ = self.sent_tx.send(data.to_vec());
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> SingleSpMessage {
+            self.recv.recv().await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn run_skips_initial_discovery_with_known_sp_addr() {
+        let sp_addr: SocketAddrV6 = "[fe80::1]:11111".parse().unwrap();
+        let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+        let (_recv_tx, recv) = mpsc::unbounded_channel();
+        let transport = SendTapTransport {
+            log: Logger::root(slog::Discard, slog::o!()),
+            sent_tx,
+            recv,
+        };
+        let (inner, _channels) = new_test_inner_with(
+            transport,
+            Some((sp_addr, SpPort::One)),
+            RpcPolicies::new(1, Duration::from_secs(5)),
+        );
+        let task = tokio::spawn(inner.run());
+
+        // The first thing we send should be addressed to our SP directly
+        // rather than a discovery packet.
+        let packet =
+            tokio::time::timeout(Duration::from_secs(2), sent_rx.recv())
+                .await
+                .expect("nothing sent")
+                .unwrap();
+        let (message, _) =
+            gateway_messages::deserialize::<Message>(&packet).unwrap();
+        assert_eq!(
+            message.kind,
+            MessageKind::MgsRequest(MgsRequest::ProtocolCapabilities)
+        );
+
+        task.abort();
+    }
+
+    fn discovery_requests_since(
+        requests: &std::sync::Mutex<Vec<(Instant, MgsRequest)>>,
+        since: Instant,
+    ) -> usize {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(sent, request)| {
+                *sent >= since && *request == MgsRequest::Discover
+            })
+            .count()
+    }
+
+    async fn wait_for_identity(sp: &SingleSp) {
+        while sp.identity().is_none() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_rediscovery_follows_idle_interval() {
+        let transport = SimulatedSp::new("sidecar0", SpPort::One, 1);
+        let requests = transport.requests();
+        let defaults = DiscoveryConfig::default();
+        let discovery = DiscoveryConfig::new(
+            defaults.initial_retry_interval(),
+            defaults.max_retry_interval(),
+            Duration::from_millis(100),
+            None,
+            defaults.aggressive_interval(),
+        )
+        .unwrap();
+        let sp = SingleSp::with_transport(
+            transport,
+            discovery,
+            RpcPolicies::new(1, Duration::from_millis(50)),
+        );
+        wait_for_identity(&sp).await;
+
+        // With no other traffic, we should send a discovery packet roughly
+        // every 100ms: 5 in 550ms, with some slack for a slow test host.
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(550)).await;
+        let n = discovery_requests_since(&requests, start);
+        assert!((3..=6).contains(&n), "sent {n} discovery requests");
+
+        // Traffic to the SP pushes back idle discovery.
+        let start = Instant::now();
+        for _ in 0..6 {
+            sp.state().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(discovery_requests_since(&requests, start), 0);
+    }
+
+    #[tokio::test]
+    async fn aggressive_rediscovery_while_sp_is_unresponsive() {
+        let transport = SimulatedSp::new("sidecar0", SpPort::One, 1);
+        let requests = transport.requests();
+        let reachable = transport.reachable();
+        let defaults = DiscoveryConfig::default();
+        let discovery = DiscoveryConfig::new(
+            defaults.initial_retry_interval(),
+            defaults.max_retry_interval(),
+            Duration::from_secs(60),
+            Some(1),
+            Duration::from_millis(50),
+        )
+        .unwrap();
+        let sp = SingleSp::with_transport(
+            transport,
+            discovery,
+            RpcPolicies::new(1, Duration::from_millis(20)),
+        );
+        wait_for_identity(&sp).await;
+
+        // Once an RPC fails, we should start sending discovery packets every
+        // 50ms (each of which also fails while the SP is unreachable).
+        reachable.store(false, Ordering::SeqCst);
+        sp.state().await.unwrap_err();
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let n = discovery_requests_since(&requests, start);
+        assert!(n >= 3, "sent {n} discovery requests");
+
+        // Once the SP answers discovery again, we go back to idle discovery,
+        // which won't fire again during this test.
+        reachable.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(discovery_requests_since(&requests, start), 0);
+    }
+
+    #[tokio::test]
+    async fn shutdown_interrupts_initial_discovery_backoff() {
+        let transport = SimulatedSp::new("sidecar0", SpPort::One, 1);
+        let requests = transport.requests();
+        transport.reachable().store(false, Ordering::SeqCst);
+        let defaults = DiscoveryConfig::default();
+        let discovery = DiscoveryConfig::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+            defaults.idle_interval(),
+            defaults.aggressive_after_failures(),
+            defaults.aggressive_interval(),
+        )
+        .unwrap();
+        let sp = SingleSp::with_transport(
+            transport,
+            discovery,
+            RpcPolicies::new(1, Duration::from_millis(20)),
+        );
+
+        // Wait for our first discovery attempt to fail, after which we back
+        // off for a minute.
+        let start = Instant::now();
+        while discovery_requests_since(&requests, start) == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Requests made while we're backing off fail right away...
+        match tokio::time::timeout(Duration::from_secs(2), sp.state()).await {
+            Ok(Err(CommunicationError::NoSpDiscovered)) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // ... and we shut down without waiting out the backoff.
+        tokio::time::timeout(Duration::from_secs(2), sp.shutdown())
+            .await
+            .expect("shutdown waited for discovery backoff");
+        assert_eq!(discovery_requests_since(&requests, start), 1);
+    }
 }
@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+// How many consecutive failed RPCs (including discovery attempts) do we
+// tolerate before declaring an SP unreachable?
+const FAILURES_BEFORE_UNREACHABLE: usize = 3;
+
+/// Reachability of an SP, as observed by a [`SingleSp`](crate::SingleSp); see
+/// [`SingleSp::health()`](crate::SingleSp::health).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpHealth {
+    /// We've recently heard from the SP.
+    Reachable {
+        /// When we last received any packet from the SP.
+        last_seen: Instant,
+        /// Round trip time of the most recent RPC to the SP, if we've
+        /// completed one. This is measured from when we first sent the
+        /// request, so it includes any time spent on retries.
+        rtt: Option<Duration>,
+    },
+    /// Our most recent RPCs to the SP have failed, but not (yet) enough of
+    /// them for us to consider it unreachable.
+    Degraded { consecutive_failures: usize },
+    /// We have not heard from the SP since `since`, despite trying. This is
+    /// also the initial state of a `SingleSp`, with `since` set to the time it
+    /// was created.
+    Unreachable { since: Instant },
+}
+
+// Tracks the health of an SP and publishes changes to it.
+pub(super) struct HealthTracker {
+    health_tx: watch::Sender<SpHealth>,
+    consecutive_failures: usize,
+    first_failure: Option<Instant>,
+    last_rtt: Option<Duration>,
+}
+
+impl HealthTracker {
+    pub(super) fn new(health_tx: watch::Sender<SpHealth>) -> Self {
+        Self {
+            health_tx,
+            consecutive_failures: 0,
+            first_failure: None,
+            last_rtt: None,
+        }
+    }
+
+    pub(super) fn consecutive_failures(&self) -> usize {
+        self.consecutive_failures
+    }
+
+    /// Record a response to an RPC (including error responses: the SP is
+    /// alive, even if it didn't like our request) that took `rtt` since we
+    /// first sent it.
+    pub(super) fn record_response(&mut self, rtt: Duration) {
+        self.last_rtt = Some(rtt);
+        self.record_traffic();
+    }
+
+    /// Record an unsolicited packet from the SP (e.g., serial console data).
+    pub(super) fn record_traffic(&mut self) {
+        self.consecutive_failures = 0;
+        self.first_failure = None;
+        self.publish(SpHealth::Reachable {
+            last_seen: Instant::now(),
+            rtt: self.last_rtt,
+        });
+    }
+
+    /// Record an RPC that got no response from the SP.
+    pub(super) fn record_failure(&mut self) {
+        let now = Instant::now();
+        self.consecutive_failures += 1;
+        let first_failure = *self.first_failure.get_or_insert(now);
+
+        // If we're already unreachable (e.g., because we've never heard from
+        // this SP), stay that way and keep reporting the original time.
+        let health = match *self.health_tx.borrow() {
+            SpHealth::Unreachable { since } => SpHealth::Unreachable { since },
+            SpHealth::Reachable { .. } | SpHealth::Degraded { .. }
+                if self.consecutive_failures < FAILURES_BEFORE_UNREACHABLE =>
+            {
+                SpHealth::Degraded {
+                    consecutive_failures: self.consecutive_failures,
+                }
+            }
+            SpHealth::Reachable { .. } | SpHealth::Degraded { .. } => {
+                SpHealth::Unreachable { since: first_failure }
+            }
+        };
+        self.publish(health);
+    }
+
+    fn publish(&self, health: SpHealth) {
+        // The receiving half of `health_tx` is held by our `SingleSp`, but we
+        // don't need anyone to be listening; use `send_replace` so our state
+        // is updated regardless.
+        self.health_tx.send_replace(health);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> (HealthTracker, watch::Receiver<SpHealth>, Instant) {
+        let created = Instant::now();
+        let (health_tx, health_rx) =
+            watch::channel(SpHealth::Unreachable { since: created });
+        (HealthTracker::new(health_tx), health_rx, created)
+    }
+
+    #[test]
+    fn failures_before_first_contact_stay_unreachable() {
+        let (mut tracker, health_rx, created) = tracker();
+
+        for _ in 0..FAILURES_BEFORE_UNREACHABLE + 1 {
+            tracker.record_failure();
+            assert_eq!(
+                *health_rx.borrow(),
+                SpHealth::Unreachable { since: created }
+            );
+        }
+        assert_eq!(
+            tracker.consecutive_failures(),
+            FAILURES_BEFORE_UNREACHABLE + 1
+        );
+    }
+
+    #[test]
+    fn response_makes_sp_reachable() {
+        let (mut tracker, health_rx, _) = tracker();
+
+        tracker.record_response(Duration::from_millis(3));
+        match *health_rx.borrow() {
+            SpHealth::Reachable { rtt, .. } => {
+                assert_eq!(rtt, Some(Duration::from_millis(3)));
+            }
+            health => panic!("unexpected health {health:?}"),
+        }
+    }
+
+    #[test]
+    fn traffic_without_response_has_no_rtt() {
+        let (mut tracker, health_rx, _) = tracker();
+
+        tracker.record_traffic();
+        assert!(matches!(
+            *health_rx.borrow(),
+            SpHealth::Reachable { rtt: None, .. }
+        ));
+    }
+
+    #[test]
+    fn failures_degrade_then_make_sp_unreachable() {
+        let (mut tracker, health_rx, _) = tracker();
+        tracker.record_response(Duration::from_millis(3));
+
+        for n in 1..FAILURES_BEFORE_UNREACHABLE {
+            tracker.record_failure();
+            assert_eq!(
+                *health_rx.borrow(),
+                SpHealth::Degraded { consecutive_failures: n }
+            );
+        }
+
+        // Once we cross the threshold, we report the time of the first
+        // failure in this run, and keep reporting it on further failures.
+        tracker.record_failure();
+        let since = match *health_rx.borrow() {
+            SpHealth::Unreachable { since } => since,
+            health => panic!("unexpected health {health:?}"),
+        };
+        assert_eq!(Some(since), tracker.first_failure);
+        tracker.record_failure();
+        assert_eq!(*health_rx.borrow(), SpHealth::Unreachable { since });
+    }
+
+    #[test]
+    fn traffic_resets_failures_and_keeps_last_rtt() {
+        let (mut tracker, health_rx, _) = tracker();
+        tracker.record_response(Duration::from_millis(3));
+        tracker.record_failure();
+        tracker.record_failure();
+
+        tracker.record_traffic();
+        assert_eq!(tracker.consecutive_failures(), 0);
+        assert!(matches!(
+            *health_rx.borrow(),
+            SpHealth::Reachable { rtt: Some(rtt), .. }
+                if rtt == Duration::from_millis(3)
+        ));
+
+        // The failure count starts over rather than going straight back to
+        // unreachable.
+        tracker.record_failure();
+        assert_eq!(
+            *health_rx.borrow(),
+            SpHealth::Degraded { consecutive_failures: 1 }
+        );
+    }
+}
@@ -7,10 +7,12 @@
 use super::CursorExt;
 use super::InnerCommand;
 use super::Result;
+use crate::error::CommunicationError;
 use crate::error::UpdateError;
-use crate::sp_response_ext::SpResponseExt;
+use crate::rpc_policy::CallOptions;
+use crate::stats::SingleSpStatsCollector;
+use gateway_messages::request;
 use gateway_messages::ComponentUpdatePrepare;
-use gateway_messages::MgsRequest;
 use gateway_messages::SpComponent;
 use gateway_messages::SpUpdatePrepare;
 use gateway_messages::UpdateChunk;
@@ -24,18 +26,67 @@ use slog::info;
 use slog::warn;
 use slog::Logger;
 use std::convert::TryInto;
+use std::future::Future;
 use std::io::Cursor;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use tlvc::TlvcReader;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// The tasks a `SingleSp` has spawned to deliver updates to its SP.
+#[derive(Debug, Default)]
+pub(super) struct UpdateDrivers {
+    // Cancelled when our `SingleSp` is shutting down; drivers stop sending
+    // chunks and abort their update.
+    shutdown: CancellationToken,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl UpdateDrivers {
+    // Drivers make their RPCs with the `CallOptions` in effect when they're
+    // spawned (i.e., those of the caller starting the update); task-local
+    // options aren't inherited by spawned tasks on their own. The caller's
+    // deadline becomes a per-RPC timeout (see `CallOptions::detach()`), since
+    // the update as a whole takes far longer than starting it.
+    fn spawn<F, Fut>(&self, driver: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let options = CallOptions::current().detach();
+        let task = tokio::spawn(options.scope(driver(self.shutdown.clone())));
+
+        // Forget any drivers that have already finished.
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Tell all our drivers to stop, and wait for them to do so.
+    pub(super) async fn shutdown(&self, log: &Logger) {
+        self.shutdown.cancel();
+        let tasks = mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(err) = task.await {
+                error!(log, "update driver task failed"; "err" => %err);
+            }
+        }
+    }
+}
+
 /// Start an update to the SP itself.
 ///
 /// If the SP acks that the update can begin, spawns a task to deliver the
 /// update.
 pub(super) async fn start_sp_update(
     cmds_tx: &mpsc::Sender<InnerCommand>,
+    stats: &Arc<SingleSpStatsCollector>,
+    drivers: &UpdateDrivers,
     update_id: Uuid,
     image: Vec<u8>,
     log: &Logger,
@@ -72,13 +123,7 @@ pub(super) async fn start_sp_update(
     // In the future, we could use `ReadComponentCaboose` here instead, but
     // `ReadCaboose` is older (and thus more widely compatible with SP images).
     let sp_board =
-        super::rpc(cmds_tx, MgsRequest::ReadCaboose { key: *b"BORD" }, None)
-            .await
-            .result
-            .and_then(|(_peer, response, data)| {
-                response.expect_caboose_value()?;
-                Ok(data)
-            })?;
+        super::call(cmds_tx, request::ReadCaboose { key: *b"BORD" }).await?;
     if archive_board != sp_board {
         return Err(UpdateError::BoardMismatch {
             sp: String::from_utf8_lossy(&sp_board).to_string(),
@@ -111,29 +156,25 @@ pub(super) async fn start_sp_update(
         "aux_flash_size" => aux_flash_size,
         "sp_image_size" => sp_image_size,
     );
-    super::rpc(
+    super::call(
         cmds_tx,
-        MgsRequest::SpUpdatePrepare(SpUpdatePrepare {
+        request::SpUpdatePrepare(SpUpdatePrepare {
             id: update_id.into(),
             aux_flash_size,
             aux_flash_chck,
             sp_image_size,
         }),
-        None,
     )
-    .await
-    .result
-    .and_then(|(_peer, response, _data)| {
-        response.expect_sp_update_prepare_ack().map_err(Into::into)
-    })?;
-
-    tokio::spawn(drive_sp_update(
-        cmds_tx.clone(),
-        update_id,
-        aux_image,
-        sp_image,
-        log.clone(),
-    ));
+    .await?;
+
+    let cmds_tx = cmds_tx.clone();
+    let stats = Arc::clone(stats);
+    let log = log.clone();
+    drivers.spawn(move |shutdown| {
+        drive_sp_update(
+            cmds_tx, stats, shutdown, update_id, aux_image, sp_image, log,
+        )
+    });
 
     Ok(())
 }
@@ -142,6 +183,8 @@ pub(super) async fn start_sp_update(
 /// completion.
 async fn drive_sp_update(
     cmds_tx: mpsc::Sender<InnerCommand>,
+    stats: Arc<SingleSpStatsCollector>,
+    shutdown: CancellationToken,
     update_id: Uuid,
     aux_image: Option<Vec<u8>>,
     sp_image: Vec<u8>,
@@ -152,6 +195,7 @@ async fn drive_sp_update(
     // Wait until the SP has finished preparing for this update.
     let sp_matched_chck = match poll_until_update_prep_complete(
         &cmds_tx,
+        &shutdown,
         SpComponent::SP_ITSELF,
         id,
         aux_image.is_some(),
@@ -172,6 +216,14 @@ async fn drive_sp_update(
                 "err" => message,
                 "update_id" => %update_id,
             );
+            abort_if_shutting_down(
+                &cmds_tx,
+                &shutdown,
+                SpComponent::SP_ITSELF,
+                update_id,
+                &log,
+            )
+            .await;
             return;
         }
     };
@@ -184,6 +236,8 @@ async fn drive_sp_update(
         let data = aux_image.unwrap();
         match send_update_in_chunks(
             &cmds_tx,
+            &stats,
+            &shutdown,
             SpComponent::SP_AUX_FLASH,
             update_id,
             data,
@@ -200,6 +254,14 @@ async fn drive_sp_update(
                     "id" => %update_id,
                     "err" => %err,
                 );
+                abort_if_shutting_down(
+                    &cmds_tx,
+                    &shutdown,
+                    SpComponent::SP_ITSELF,
+                    update_id,
+                    &log,
+                )
+                .await;
                 return;
             }
         }
@@ -208,6 +270,8 @@ async fn drive_sp_update(
     // Deliver the SP image.
     match send_update_in_chunks(
         &cmds_tx,
+        &stats,
+        &shutdown,
         SpComponent::SP_ITSELF,
         update_id,
         sp_image,
@@ -224,6 +288,14 @@ async fn drive_sp_update(
                 "id" => %update_id,
                 "err" => %err,
             );
+            abort_if_shutting_down(
+                &cmds_tx,
+                &shutdown,
+                SpComponent::SP_ITSELF,
+                update_id,
+                &log,
+            )
+            .await;
         }
     }
 }
@@ -265,6 +337,8 @@ fn read_auxi_check_from_tlvc(data: &[u8]) -> Result<[u8; 32], UpdateError> {
 /// Start an update to the RoT.
 pub(super) async fn start_rot_update(
     cmds_tx: &mpsc::Sender<InnerCommand>,
+    stats: &Arc<SingleSpStatsCollector>,
+    drivers: &UpdateDrivers,
     update_id: Uuid,
     slot: u16,
     image: Vec<u8>,
@@ -306,6 +380,8 @@ pub(super) async fn start_rot_update(
 
     start_component_update(
         cmds_tx,
+        stats,
+        drivers,
         SpComponent::ROT,
         update_id,
         slot,
@@ -321,6 +397,8 @@ pub(super) async fn start_rot_update(
 /// update.
 pub(super) async fn start_component_update(
     cmds_tx: &mpsc::Sender<InnerCommand>,
+    stats: &Arc<SingleSpStatsCollector>,
+    drivers: &UpdateDrivers,
     component: SpComponent,
     update_id: Uuid,
     slot: u16,
@@ -336,29 +414,25 @@ pub(super) async fn start_component_update(
         "id" => %update_id,
         "total_size" => total_size,
     );
-    super::rpc(
+    super::call(
         cmds_tx,
-        MgsRequest::ComponentUpdatePrepare(ComponentUpdatePrepare {
+        request::ComponentUpdatePrepare(ComponentUpdatePrepare {
             component,
             id: update_id.into(),
             slot,
             total_size,
         }),
-        None,
     )
-    .await
-    .result
-    .and_then(|(_peer, response, _data)| {
-        response.expect_component_update_prepare_ack().map_err(Into::into)
-    })?;
-
-    tokio::spawn(drive_component_update(
-        cmds_tx.clone(),
-        component,
-        update_id,
-        image,
-        log.clone(),
-    ));
+    .await?;
+
+    let cmds_tx = cmds_tx.clone();
+    let stats = Arc::clone(stats);
+    let log = log.clone();
+    drivers.spawn(move |shutdown| {
+        drive_component_update(
+            cmds_tx, stats, shutdown, component, update_id, image, log,
+        )
+    });
 
     Ok(())
 }
@@ -367,6 +441,8 @@ pub(super) async fn start_component_update(
 /// completion.
 async fn drive_component_update(
     cmds_tx: mpsc::Sender<InnerCommand>,
+    stats: Arc<SingleSpStatsCollector>,
+    shutdown: CancellationToken,
     component: SpComponent,
     update_id: Uuid,
     image: Vec<u8>,
@@ -375,8 +451,10 @@ async fn drive_component_update(
     let id = update_id.into();
 
     // Wait until the SP has finished preparing for this update.
-    match poll_until_update_prep_complete(&cmds_tx, component, id, false, &log)
-        .await
+    match poll_until_update_prep_complete(
+        &cmds_tx, &shutdown, component, id, false, &log,
+    )
+    .await
     {
         Ok(_) => {
             info!(
@@ -390,13 +468,19 @@ async fn drive_component_update(
                 "err" => message,
                 "update_id" => %update_id,
             );
+            abort_if_shutting_down(
+                &cmds_tx, &shutdown, component, update_id, &log,
+            )
+            .await;
             return;
         }
     }
 
     // Deliver the update in chunks.
-    match send_update_in_chunks(&cmds_tx, component, update_id, image, &log)
-        .await
+    match send_update_in_chunks(
+        &cmds_tx, &stats, &shutdown, component, update_id, image, &log,
+    )
+    .await
     {
         Ok(()) => {
             info!(log, "update complete"; "id" => %update_id);
@@ -407,6 +491,39 @@ async fn drive_component_update(
                 "id" => %update_id,
                 "err" => %err,
             );
+            abort_if_shutting_down(
+                &cmds_tx, &shutdown, component, update_id, &log,
+            )
+            .await;
+        }
+    }
+}
+
+/// If our driver gave up on an update because our `SingleSp` is shutting
+/// down, ask the SP to abort it rather than leaving it waiting for chunks that
+/// will never arrive.
+async fn abort_if_shutting_down(
+    cmds_tx: &mpsc::Sender<InnerCommand>,
+    shutdown: &CancellationToken,
+    component: SpComponent,
+    update_id: Uuid,
+    log: &Logger,
+) {
+    if !shutdown.is_cancelled() {
+        return;
+    }
+
+    let request = request::UpdateAbort { component, id: update_id.into() };
+    match super::call(cmds_tx, request).await {
+        Ok(()) => {
+            info!(log, "aborted update (shutting down)"; "id" => %update_id);
+        }
+        Err(err) => {
+            warn!(
+                log, "failed to abort update";
+                "id" => %update_id,
+                "err" => %err,
+            );
         }
     }
 }
@@ -428,8 +545,11 @@ async fn drive_component_update(
 /// result in an error being returned. We always return `Ok(true)` upon seeing
 /// `InProgress` (i.e., if `update_has_aux_image` is `false`, we will either
 /// return `Ok(true)` or an error, never `Ok(false)`).
+///
+/// We also give up (returning an error) if `shutdown` is cancelled.
 async fn poll_until_update_prep_complete(
     cmds_tx: &mpsc::Sender<InnerCommand>,
+    shutdown: &CancellationToken,
     component: SpComponent,
     id: UpdateId,
     update_has_aux_image: bool,
@@ -466,8 +586,13 @@ async fn poll_until_update_prep_complete(
                         "SP still preparing; sleeping for {:?}",
                         POLL_UPDATE_STATUS_INTERVAL
                     );
-                    tokio::time::sleep(POLL_UPDATE_STATUS_INTERVAL).await;
-                    continue;
+                    let sleep = tokio::time::sleep(POLL_UPDATE_STATUS_INTERVAL);
+                    tokio::select! {
+                        () = sleep => continue,
+                        () = shutdown.cancelled() => {
+                            return Err("shutting down".to_string());
+                        }
+                    }
                 }
                 // Else: fall through to returning an error.
             }
@@ -505,17 +630,14 @@ pub(super) async fn update_status(
     cmds_tx: &mpsc::Sender<InnerCommand>,
     component: SpComponent,
 ) -> Result<UpdateStatus> {
-    super::rpc(cmds_tx, MgsRequest::UpdateStatus(component), None)
-        .await
-        .result
-        .and_then(|(_peer, response, _data)| {
-            response.expect_update_status().map_err(Into::into)
-        })
+    super::call(cmds_tx, request::UpdateStatus(component)).await
 }
 
 /// Send an update image to the SP in chunks.
 async fn send_update_in_chunks(
     cmds_tx: &mpsc::Sender<InnerCommand>,
+    stats: &SingleSpStatsCollector,
+    shutdown: &CancellationToken,
     component: SpComponent,
     update_id: Uuid,
     data: Vec<u8>,
@@ -525,6 +647,11 @@ async fn send_update_in_chunks(
     let mut offset = 0;
     let id = update_id.into();
     while !CursorExt::is_empty(&image) {
+        // Stop between chunks (never mid-chunk) if we're shutting down.
+        if shutdown.is_cancelled() {
+            return Err(CommunicationError::ShutDown);
+        }
+
         let prior_pos = image.position();
         debug!(
             log, "sending update chunk";
@@ -534,9 +661,17 @@ async fn send_update_in_chunks(
 
         image = send_single_update_chunk(cmds_tx, component, id, offset, image)
             .await?;
+        let chunk_len = image.position() - prior_pos;
+        crate::probes::update_chunk_sent!(|| (
+            update_id.to_string(),
+            component.to_string(),
+            u64::from(offset),
+            chunk_len,
+        ));
+        stats.update(|stats| stats.update_bytes_sent += chunk_len);
 
         // Update our offset according to how far our cursor advanced.
-        offset += (image.position() - prior_pos) as u32;
+        offset += chunk_len as u32;
     }
     Ok(())
 }
@@ -553,16 +688,173 @@ async fn send_single_update_chunk(
     data: Cursor<Vec<u8>>,
 ) -> Result<Cursor<Vec<u8>>> {
     let update_chunk = UpdateChunk { component, id, offset };
-    let (result, data) = super::rpc_with_trailing_data(
+    let (result, data) = super::call_with_trailing_data(
         cmds_tx,
-        MgsRequest::UpdateChunk(update_chunk),
+        request::UpdateChunk(update_chunk),
         data,
     )
     .await;
-
-    result.and_then(|(_peer, response, _data)| {
-        response.expect_update_chunk_ack().map_err(Into::into)
-    })?;
+    result?;
 
     Ok(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc_policy::RpcPolicy;
+    use crate::single_sp::RpcResponse;
+    use gateway_messages::MgsRequest;
+    use gateway_messages::SpResponse;
+    use gateway_messages::UpdateInProgressStatus;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn drivers_inherit_call_options() {
+        let drivers = UpdateDrivers::default();
+        let cancel = CancellationToken::new();
+        let policy = RpcPolicy::new(3, Duration::from_secs(1));
+        let (options_tx, options_rx) = oneshot::channel();
+
+        CallOptions::new()
+            .with_policy(policy)
+            .with_timeout(Duration::from_secs(60))
+            .with_cancel(cancel.clone())
+            .scope(async {
+                drivers.spawn(move |_shutdown| async move {
+                    _ = options_tx.send(CallOptions::current());
+                });
+            })
+            .await;
+
+        let options = options_rx.await.unwrap();
+        assert_eq!(options.policy(), Some(policy));
+        // The caller's deadline doesn't bound the update as a whole; each RPC
+        // instead gets however long the caller had left.
+        assert_eq!(options.deadline(), None);
+        let rpc_timeout = options.rpc_timeout().unwrap();
+        assert!(
+            rpc_timeout > Duration::from_secs(50)
+                && rpc_timeout <= Duration::from_secs(60),
+            "unexpected per-RPC timeout {rpc_timeout:?}"
+        );
+        assert!(!options.is_cancelled());
+        cancel.cancel();
+        assert!(options.is_cancelled());
+    }
+
+    // Answer the RPCs made by an update driver the way an SP accepting an
+    // update would, recording each request. Each chunk consumes 4 bytes of
+    // the image; we tell `first_chunk_tx` when the first chunk arrives and
+    // wait for `resume_rx` before acknowledging it.
+    async fn fake_sp(
+        mut cmds_rx: mpsc::Receiver<InnerCommand>,
+        requests: Arc<Mutex<Vec<MgsRequest>>>,
+        first_chunk_tx: oneshot::Sender<()>,
+        resume_rx: oneshot::Receiver<()>,
+    ) {
+        let mut first_chunk = Some((first_chunk_tx, resume_rx));
+        while let Some(command) = cmds_rx.recv().await {
+            let rpc = match command {
+                InnerCommand::Rpc(rpc) => rpc,
+                other => panic!("unexpected command {other:?}"),
+            };
+            requests.lock().unwrap().push(rpc.kind);
+
+            let mut our_trailing_data = rpc.our_trailing_data;
+            let response = match rpc.kind {
+                MgsRequest::UpdateStatus(_) => SpResponse::UpdateStatus(
+                    UpdateStatus::InProgress(UpdateInProgressStatus {
+                        id: Uuid::from_u128(1).into(),
+                        bytes_received: 0,
+                        total_size: 16,
+                    }),
+                ),
+                MgsRequest::UpdateChunk(_) => {
+                    if let Some((first_chunk_tx, resume_rx)) =
+                        first_chunk.take()
+                    {
+                        first_chunk_tx.send(()).unwrap();
+                        resume_rx.await.unwrap();
+                    }
+                    let data = our_trailing_data.as_mut().unwrap();
+                    data.set_position(data.position() + 4);
+                    SpResponse::UpdateChunkAck
+                }
+                MgsRequest::UpdateAbort { .. } => SpResponse::UpdateAbortAck,
+                other => panic!("unexpected request {other:?}"),
+            };
+
+            _ = rpc.response_tx.send(RpcResponse {
+                result: Ok((
+                    "[fe80::1]:11111".parse().unwrap(),
+                    response,
+                    Vec::new(),
+                )),
+                our_trailing_data,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_drivers_between_chunks_and_aborts_update() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let drivers = UpdateDrivers::default();
+        let stats = Arc::new(SingleSpStatsCollector::default());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (cmds_tx, cmds_rx) = mpsc::channel(8);
+        let (first_chunk_tx, first_chunk_rx) = oneshot::channel();
+        let (resume_tx, resume_rx) = oneshot::channel();
+        tokio::spawn(fake_sp(
+            cmds_rx,
+            Arc::clone(&requests),
+            first_chunk_tx,
+            resume_rx,
+        ));
+
+        let component = SpComponent::ROT;
+        let update_id = Uuid::from_u128(1);
+        {
+            let stats = Arc::clone(&stats);
+            let log = log.clone();
+            drivers.spawn(move |shutdown| {
+                drive_component_update(
+                    cmds_tx,
+                    stats,
+                    shutdown,
+                    component,
+                    update_id,
+                    vec![0; 16],
+                    log,
+                )
+            });
+        }
+
+        // Start shutting down while the SP is holding on to the first chunk;
+        // `join!` polls `shutdown()` (which cancels our drivers) before
+        // letting the SP acknowledge the chunk.
+        first_chunk_rx.await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(drivers.shutdown(&log), async {
+                resume_tx.send(()).unwrap();
+            })
+        })
+        .await
+        .expect("shutdown did not wait for the driver to finish");
+
+        // Our driver should have stopped after the first chunk and asked the
+        // SP to abort the update.
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 3, "{requests:?}");
+        assert_eq!(requests[0], MgsRequest::UpdateStatus(component));
+        match requests[1] {
+            MgsRequest::UpdateChunk(chunk) => assert_eq!(chunk.offset, 0),
+            other => panic!("unexpected request {other:?}"),
+        }
+        assert_eq!(
+            requests[2],
+            MgsRequest::UpdateAbort { component, id: update_id.into() }
+        );
+        assert_eq!(stats.snapshot().update_bytes_sent, 4);
+    }
+}
@@ -0,0 +1,246 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Counters describing the traffic handled by a [`SingleSp`](crate::SingleSp)
+//! or [`SharedSocket`](crate::SharedSocket).
+
+use gateway_messages::MgsRequest;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const NUM_LATENCY_BUCKETS: usize = 12;
+
+/// A histogram of latencies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// `counts[i]` is the number of samples no longer than
+    /// [`LatencyHistogram::BUCKETS`]`[i]` (and longer than the previous
+    /// bucket).
+    pub counts: [u64; NUM_LATENCY_BUCKETS],
+    /// Number of samples longer than the last bucket.
+    pub overflow: u64,
+    /// Sum of all samples.
+    pub total: Duration,
+}
+
+impl LatencyHistogram {
+    /// Upper bounds of each bucket.
+    pub const BUCKETS: [Duration; NUM_LATENCY_BUCKETS] = [
+        Duration::from_micros(100),
+        Duration::from_micros(250),
+        Duration::from_micros(500),
+        Duration::from_millis(1),
+        Duration::from_micros(2_500),
+        Duration::from_millis(5),
+        Duration::from_millis(10),
+        Duration::from_millis(25),
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+        Duration::from_millis(250),
+        Duration::from_millis(500),
+    ];
+
+    /// Total number of samples.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum::<u64>() + self.overflow
+    }
+
+    /// Mean of all samples, if there are any.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            n => Some(Duration::from_nanos(
+                (self.total.as_nanos() / u128::from(n)) as u64,
+            )),
+        }
+    }
+
+    pub(crate) fn record(&mut self, sample: Duration) {
+        match Self::BUCKETS.iter().position(|&bound| sample <= bound) {
+            Some(i) => self.counts[i] += 1,
+            None => self.overflow += 1,
+        }
+        self.total += sample;
+    }
+}
+
+/// Statistics for one kind of RPC.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RpcStats {
+    /// Number of RPCs we started.
+    pub calls: u64,
+    /// Number of attempts across all RPCs; each attempt sends the request
+    /// once (plus any resends after the SP reports it's busy).
+    pub attempts: u64,
+    /// Number of RPCs that failed because they ran out of attempts or passed
+    /// their deadline without a response.
+    pub timeouts: u64,
+    /// Number of [`SpError::Busy`](gateway_messages::SpError::Busy) responses.
+    pub busy_responses: u64,
+    /// Round trip time of every response (including busy and error
+    /// responses), measured from the most recent send of its request.
+    pub rtt: LatencyHistogram,
+}
+
+/// Statistics for a [`SingleSp`](crate::SingleSp); see
+/// [`SingleSp::stats()`](crate::SingleSp::stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SingleSpStats {
+    /// Statistics for each kind of RPC we've made, keyed by the name of its
+    /// [`MgsRequest`] variant (e.g., `"SpState"`).
+    pub rpcs: BTreeMap<String, RpcStats>,
+    /// Number of responses that did not match any outstanding request (e.g.,
+    /// duplicates, or late responses to requests we've given up on).
+    pub unexpected_responses: u64,
+    /// Number of gaps in serial console data received by attached clients.
+    pub serial_console_gaps: u64,
+    /// Bytes of serial console data discarded because no client was attached
+    /// or the attached client wasn't keeping up.
+    pub serial_console_bytes_dropped: u64,
+    /// Bytes of update images acknowledged by the SP.
+    pub update_bytes_sent: u64,
+    /// Bytes of host phase 2 data sent to the SP.
+    pub host_phase2_bytes_sent: u64,
+}
+
+// Shared between a `SingleSp`, its `Inner` task, and anything else (update
+// tasks, serial console clients) that has something to count.
+#[derive(Debug, Default)]
+pub(crate) struct SingleSpStatsCollector {
+    stats: Mutex<SingleSpStats>,
+}
+
+impl SingleSpStatsCollector {
+    pub(crate) fn snapshot(&self) -> SingleSpStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub(crate) fn update<F: FnOnce(&mut SingleSpStats)>(&self, f: F) {
+        f(&mut self.stats.lock().unwrap());
+    }
+
+    pub(crate) fn update_rpc<F: FnOnce(&mut RpcStats)>(
+        &self,
+        request: &MgsRequest,
+        f: F,
+    ) {
+        let name = crate::request_name(request);
+        f(self.stats.lock().unwrap().rpcs.entry(name).or_default());
+    }
+}
+
+/// Statistics for a [`SharedSocket`](crate::SharedSocket); see
+/// [`SharedSocket::stats()`](crate::SharedSocket::stats).
+///
+/// Every packet we receive is counted in `packets_received`; packets we
+/// discard are also counted in exactly one of the other fields (other than
+/// `host_phase2_bytes_sent`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SharedSocketStats {
+    pub packets_received: u64,
+    /// Packets we couldn't map to an interface via their scope ID.
+    pub interface_lookup_failures: u64,
+    /// Packets for which there was no `SingleSp` handler.
+    pub no_handler: u64,
+    /// Packets dropped because the `SingleSp` they were destined for wasn't
+    /// keeping up.
+    pub handler_busy: u64,
+    /// Packets we could not deserialize.
+    pub deserialize_errors: u64,
+    /// Packets from an SP speaking an incompatible protocol version.
+    pub version_mismatches: u64,
+    /// Packets containing a message SPs shouldn't send (e.g., an MGS request).
+    pub invalid_message_kind: u64,
+    /// Bytes of host phase 2 data sent to all SPs.
+    pub host_phase2_bytes_sent: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SharedSocketStatsCollector {
+    pub(crate) packets_received: AtomicU64,
+    pub(crate) interface_lookup_failures: AtomicU64,
+    pub(crate) no_handler: AtomicU64,
+    pub(crate) handler_busy: AtomicU64,
+    pub(crate) deserialize_errors: AtomicU64,
+    pub(crate) version_mismatches: AtomicU64,
+    pub(crate) invalid_message_kind: AtomicU64,
+    pub(crate) host_phase2_bytes_sent: AtomicU64,
+}
+
+impl SharedSocketStatsCollector {
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> SharedSocketStats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        SharedSocketStats {
+            packets_received: get(&self.packets_received),
+            interface_lookup_failures: get(&self.interface_lookup_failures),
+            no_handler: get(&self.no_handler),
+            handler_busy: get(&self.handler_busy),
+            deserialize_errors: get(&self.deserialize_errors),
+            version_mismatches: get(&self.version_mismatches),
+            invalid_message_kind: get(&self.invalid_message_kind),
+            host_phase2_bytes_sent: get(&self.host_phase2_bytes_sent),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_histogram_buckets() {
+        let mut hist = LatencyHistogram::default();
+        assert_eq!(hist.mean(), None);
+
+        hist.record(Duration::from_micros(100));
+        hist.record(Duration::from_micros(101));
+        hist.record(Duration::from_millis(3));
+        hist.record(Duration::from_secs(1));
+
+        assert_eq!(hist.counts[0], 1);
+        assert_eq!(hist.counts[1], 1);
+        assert_eq!(hist.counts[5], 1);
+        assert_eq!(hist.overflow, 1);
+        assert_eq!(hist.count(), 4);
+        assert_eq!(
+            hist.mean(),
+            Some(Duration::from_micros(100 + 101 + 3_000 + 1_000_000) / 4)
+        );
+    }
+
+    #[test]
+    fn rpc_stats_are_keyed_by_request_variant() {
+        use gateway_messages::PowerState;
+
+        let collector = SingleSpStatsCollector::default();
+        collector.update_rpc(&MgsRequest::SpState, |stats| stats.calls += 1);
+        collector
+            .update_rpc(&MgsRequest::GetPowerState, |stats| stats.calls += 1);
+        for state in [PowerState::A0, PowerState::A2] {
+            collector.update_rpc(&MgsRequest::SetPowerState(state), |stats| {
+                stats.calls += 1
+            });
+        }
+
+        let stats = collector.snapshot();
+        let calls = stats
+            .rpcs
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats.calls))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [("GetPowerState", 1), ("SetPowerState", 2), ("SpState", 1)]
+        );
+    }
+}
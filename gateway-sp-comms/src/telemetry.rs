@@ -0,0 +1,751 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Periodic collection of telemetry (measurements, power state, ignition
+//! state, and monorail port counters) from a single SP.
+
+use crate::error::CommunicationError;
+use crate::SingleSp;
+use crate::SpClient;
+use backoff::backoff::Backoff;
+use gateway_messages::measurement::ComponentMeasurement;
+use gateway_messages::measurement::MeasurementError;
+use gateway_messages::measurement::MeasurementKindV2;
+use gateway_messages::measurement::MeasurementMetadata;
+use gateway_messages::monorail_port_status::PortStatus;
+use gateway_messages::BadRequestReason;
+use gateway_messages::ComponentDetails;
+use gateway_messages::DeviceCapabilities;
+use gateway_messages::IgnitionState;
+use gateway_messages::PowerState;
+use gateway_messages::SpComponent;
+use gateway_messages::SpError;
+use slog::debug;
+use slog::warn;
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio::time::Instant;
+use tokio::time::Interval;
+use tokio::time::MissedTickBehavior;
+
+/// How often a [`TelemetrySampler`] collects each group of metrics.
+///
+/// Setting any interval to `None` disables collection of that group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetrySchedule {
+    /// Interval for collecting all measurements (via
+    /// [`SingleSp::all_measurements()`], or [`SingleSp::component_details()`]
+    /// for each device with measurement channels if the SP predates bulk
+    /// measurements).
+    pub measurements: Option<Duration>,
+    /// Interval for collecting the SP's power state.
+    pub power_state: Option<Duration>,
+    /// Interval for collecting ignition state of all targets; only meaningful
+    /// for SPs that are ignition controllers.
+    pub ignition: Option<Duration>,
+    /// Interval for collecting monorail port status and counters; only
+    /// meaningful for sidecar SPs.
+    pub monorail: Option<Duration>,
+    /// Maximum number of samples retained per metric; older samples are
+    /// discarded first.
+    pub history_len: usize,
+    /// Upper bound on the delay between collection attempts while the SP is
+    /// unreachable.
+    pub max_backoff: Duration,
+}
+
+impl Default for TelemetrySchedule {
+    fn default() -> Self {
+        Self {
+            measurements: Some(Duration::from_secs(10)),
+            power_state: Some(Duration::from_secs(10)),
+            ignition: None,
+            monorail: None,
+            history_len: 360,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Identifier of a single time series collected by a [`TelemetrySampler`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MetricKey {
+    Measurement { component: SpComponent, name: String },
+    PowerState,
+    Ignition { target: u8 },
+    MonorailPort { port: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub enum MetricValue {
+    Measurement {
+        kind: MeasurementKindV2,
+        value: Result<f32, MeasurementError>,
+        /// Units, thresholds, etc., if the SP reported them.
+        metadata: Option<MeasurementMetadata>,
+    },
+    PowerState(PowerState),
+    Ignition(IgnitionState),
+    MonorailPort(PortStatus),
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetrySample {
+    pub key: MetricKey,
+    /// Time at which MGS received the sample from the SP.
+    pub time: Instant,
+    pub value: MetricValue,
+}
+
+/// Most recent sample of every metric collected so far.
+pub type TelemetrySnapshot = BTreeMap<MetricKey, TelemetrySample>;
+
+/// Periodically collects telemetry from a [`SingleSp`] on a
+/// [`TelemetrySchedule`].
+///
+/// Samples are retained in a bounded per-metric history and are also
+/// published on a broadcast channel as they're collected. If the SP becomes
+/// unreachable, collection backs off exponentially (up to
+/// [`TelemetrySchedule::max_backoff`]) until it responds again.
+///
+/// Dropping the sampler stops collection.
+#[derive(Debug)]
+pub struct TelemetrySampler {
+    sp: Arc<SingleSp>,
+    history: Arc<Mutex<TelemetryHistory>>,
+    samples_tx: broadcast::Sender<TelemetrySample>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for TelemetrySampler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl TelemetrySampler {
+    /// Depth of the broadcast channel returned by [`Self::subscribe()`];
+    /// subscribers that fall further behind than this will miss samples.
+    const BROADCAST_DEPTH: usize = 256;
+
+    pub fn new(sp: SingleSp, schedule: TelemetrySchedule, log: Logger) -> Self {
+        let sp = Arc::new(sp);
+        let history =
+            Arc::new(Mutex::new(TelemetryHistory::new(schedule.history_len)));
+        let (samples_tx, _) = broadcast::channel(Self::BROADCAST_DEPTH);
+
+        let collector = Collector {
+            sp: Arc::clone(&sp),
+            schedule,
+            history: Arc::clone(&history),
+            samples_tx: samples_tx.clone(),
+            bulk_measurements_unsupported: AtomicBool::new(false),
+            log,
+        };
+        let task = tokio::spawn(collector.run());
+
+        Self { sp, history, samples_tx, task }
+    }
+
+    /// The SP this sampler is collecting from.
+    pub fn sp(&self) -> &SingleSp {
+        &self.sp
+    }
+
+    /// Get the most recent sample of every metric collected so far.
+    pub fn latest(&self) -> TelemetrySnapshot {
+        self.history.lock().unwrap().latest()
+    }
+
+    /// Get all retained samples for `key`, oldest first.
+    pub fn history(&self, key: &MetricKey) -> Vec<TelemetrySample> {
+        self.history.lock().unwrap().samples(key)
+    }
+
+    /// Subscribe to all samples collected from this point forward.
+    pub fn subscribe(&self) -> broadcast::Receiver<TelemetrySample> {
+        self.samples_tx.subscribe()
+    }
+}
+
+#[derive(Debug)]
+struct TelemetryHistory {
+    max_len: usize,
+    series: BTreeMap<MetricKey, VecDeque<TelemetrySample>>,
+}
+
+impl TelemetryHistory {
+    fn new(max_len: usize) -> Self {
+        // Always keep at least one sample so `latest()` is meaningful.
+        Self { max_len: usize::max(max_len, 1), series: BTreeMap::new() }
+    }
+
+    fn record(&mut self, sample: TelemetrySample) {
+        let series = self.series.entry(sample.key.clone()).or_default();
+        while series.len() >= self.max_len {
+            series.pop_front();
+        }
+        series.push_back(sample);
+    }
+
+    fn latest(&self) -> TelemetrySnapshot {
+        self.series
+            .iter()
+            .filter_map(|(key, series)| {
+                series.back().map(|sample| (key.clone(), sample.clone()))
+            })
+            .collect()
+    }
+
+    fn samples(&self, key: &MetricKey) -> Vec<TelemetrySample> {
+        self.series
+            .get(key)
+            .map(|series| series.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MetricGroup {
+    Measurements,
+    PowerState,
+    Ignition,
+    Monorail,
+}
+
+struct Collector<C> {
+    sp: Arc<C>,
+    schedule: TelemetrySchedule,
+    history: Arc<Mutex<TelemetryHistory>>,
+    samples_tx: broadcast::Sender<TelemetrySample>,
+    // Set once the SP has rejected a bulk measurements request, after which we
+    // only collect measurements via component details.
+    bulk_measurements_unsupported: AtomicBool,
+    log: Logger,
+}
+
+impl<C: SpClient> Collector<C> {
+    async fn run(self) {
+        let mut measurements = make_interval(self.schedule.measurements);
+        let mut power_state = make_interval(self.schedule.power_state);
+        let mut ignition = make_interval(self.schedule.ignition);
+        let mut monorail = make_interval(self.schedule.monorail);
+        let mut backoff = unreachable_policy(self.schedule.max_backoff);
+
+        loop {
+            let group = tokio::select! {
+                _ = tick(&mut measurements) => MetricGroup::Measurements,
+                _ = tick(&mut power_state) => MetricGroup::PowerState,
+                _ = tick(&mut ignition) => MetricGroup::Ignition,
+                _ = tick(&mut monorail) => MetricGroup::Monorail,
+            };
+
+            if let Some(delay) =
+                self.collect_and_record(group, &mut backoff).await
+            {
+                time::sleep(delay).await;
+            }
+        }
+    }
+
+    /// Collect `group` and record the resulting samples.
+    ///
+    /// Returns how long to wait before collecting anything else if the SP
+    /// appears to be unreachable.
+    async fn collect_and_record(
+        &self,
+        group: MetricGroup,
+        backoff: &mut backoff::ExponentialBackoff,
+    ) -> Option<Duration> {
+        match self.collect(group).await {
+            Ok(samples) => {
+                backoff.reset();
+                let mut history = self.history.lock().unwrap();
+                for sample in samples {
+                    history.record(sample.clone());
+                    // We don't care if there are no subscribers.
+                    let _ = self.samples_tx.send(sample);
+                }
+                None
+            }
+            // The SP is reachable but refused this request (e.g., asking a
+            // gimlet for ignition state); that isn't a reason to back off.
+            Err(CommunicationError::SpError(err)) => {
+                debug!(
+                    self.log, "SP returned error collecting telemetry";
+                    "group" => ?group,
+                    "err" => %err,
+                );
+                None
+            }
+            Err(err) => {
+                let delay =
+                    backoff.next_backoff().unwrap_or(self.schedule.max_backoff);
+                warn!(
+                    self.log, "failed to collect telemetry; backing off";
+                    "group" => ?group,
+                    "err" => %err,
+                    "delay" => ?delay,
+                );
+                Some(delay)
+            }
+        }
+    }
+
+    async fn measurements(
+        &self,
+    ) -> Result<Vec<ComponentMeasurement>, CommunicationError> {
+        if !self.bulk_measurements_unsupported.load(Ordering::Relaxed) {
+            match self.sp.all_measurements().await {
+                // SPs that predate bulk measurements don't recognize the
+                // request. Since we send a newer protocol version than theirs,
+                // they report a version mismatch; an SP that does claim our
+                // version would report a deserialization failure instead.
+                Err(CommunicationError::SpError(SpError::BadRequest(
+                    BadRequestReason::WrongVersion { .. }
+                    | BadRequestReason::DeserializationError,
+                ))) => {
+                    debug!(
+                        self.log,
+                        "SP does not support bulk measurements; \
+                         falling back to component details"
+                    );
+                    self.bulk_measurements_unsupported
+                        .store(true, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+
+        let inventory = self.sp.inventory().await?;
+        let mut measurements = Vec::new();
+        for device in inventory.devices {
+            if !device
+                .capabilities
+                .contains(DeviceCapabilities::HAS_MEASUREMENT_CHANNELS)
+            {
+                continue;
+            }
+            let component = device.component;
+            let details = self.sp.component_details(component).await?;
+            for entry in details.entries {
+                let measurement = match entry {
+                    ComponentDetails::Measurement(m) => m.into(),
+                    ComponentDetails::MeasurementV2(m) => m.into(),
+                    _ => continue,
+                };
+                measurements
+                    .push(ComponentMeasurement { component, measurement });
+            }
+        }
+        Ok(measurements)
+    }
+
+    async fn collect(
+        &self,
+        group: MetricGroup,
+    ) -> Result<Vec<TelemetrySample>, CommunicationError> {
+        let samples = match group {
+            MetricGroup::Measurements => {
+                let measurements = self.measurements().await?;
+                let time = Instant::now();
+                measurements
+                    .into_iter()
+                    .map(|m| TelemetrySample {
+                        key: MetricKey::Measurement {
+                            component: m.component,
+                            name: m.measurement.name().to_string(),
+                        },
+                        time,
+                        value: MetricValue::Measurement {
+                            kind: m.measurement.kind(),
+                            value: m.measurement.value(),
+                            metadata: m.measurement.metadata(),
+                        },
+                    })
+                    .collect()
+            }
+            MetricGroup::PowerState => {
+                let state = self.sp.power_state().await?;
+                vec![TelemetrySample {
+                    key: MetricKey::PowerState,
+                    time: Instant::now(),
+                    value: MetricValue::PowerState(state),
+                }]
+            }
+            MetricGroup::Ignition => {
+                let states = self.sp.bulk_ignition_state().await?;
+                let time = Instant::now();
+                states
+                    .into_iter()
+                    .enumerate()
+                    .map(|(target, state)| TelemetrySample {
+                        // Ignition targets are addressed by `u8`; the SP never
+                        // reports more than 256 of them.
+                        key: MetricKey::Ignition { target: target as u8 },
+                        time,
+                        value: MetricValue::Ignition(state),
+                    })
+                    .collect()
+            }
+            MetricGroup::Monorail => {
+                let details =
+                    self.sp.component_details(SpComponent::MONORAIL).await?;
+                let time = Instant::now();
+                details
+                    .entries
+                    .into_iter()
+                    .filter_map(|entry| match entry {
+                        ComponentDetails::PortStatus(Ok(status)) => {
+                            Some(TelemetrySample {
+                                key: MetricKey::MonorailPort {
+                                    port: status.port,
+                                },
+                                time,
+                                value: MetricValue::MonorailPort(status),
+                            })
+                        }
+                        ComponentDetails::PortStatus(Err(err)) => {
+                            debug!(
+                                self.log, "skipping monorail port";
+                                "port" => err.port,
+                                "err" => ?err.code,
+                            );
+                            None
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            }
+        };
+        Ok(samples)
+    }
+}
+
+fn make_interval(period: Option<Duration>) -> Option<Interval> {
+    period.map(|period| {
+        let mut interval = time::interval(period);
+        // If we spend a while backing off, don't try to catch up on all the
+        // ticks we missed once the SP returns.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    })
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+fn unreachable_policy(max_interval: Duration) -> backoff::ExponentialBackoff {
+    const INITIAL_INTERVAL: Duration = Duration::from_secs(1);
+
+    backoff::ExponentialBackoff {
+        current_interval: INITIAL_INTERVAL,
+        initial_interval: INITIAL_INTERVAL,
+        multiplier: 2.0,
+        max_interval,
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockCall;
+    use crate::testing::MockSpClient;
+    use crate::SpComponentDetails;
+    use crate::SpDevice;
+    use crate::SpInventory;
+    use gateway_messages::measurement::ComponentMeasurement;
+    use gateway_messages::measurement::Measurement;
+    use gateway_messages::measurement::MeasurementKind;
+    use gateway_messages::measurement::MeasurementUnit;
+    use gateway_messages::measurement::MeasurementV2;
+    use gateway_messages::version;
+    use gateway_messages::DevicePresence;
+
+    fn power_state_sample(state: PowerState) -> TelemetrySample {
+        TelemetrySample {
+            key: MetricKey::PowerState,
+            time: Instant::now(),
+            value: MetricValue::PowerState(state),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded_per_metric() {
+        let mut history = TelemetryHistory::new(2);
+
+        for state in [PowerState::A2, PowerState::A1, PowerState::A0] {
+            history.record(power_state_sample(state));
+        }
+
+        let samples = history.samples(&MetricKey::PowerState);
+        let states = samples
+            .iter()
+            .map(|s| match s.value {
+                MetricValue::PowerState(state) => state,
+                _ => panic!("unexpected value {:?}", s.value),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(states, [PowerState::A1, PowerState::A0]);
+
+        let latest = history.latest();
+        assert_eq!(latest.len(), 1);
+        assert!(matches!(
+            latest[&MetricKey::PowerState].value,
+            MetricValue::PowerState(PowerState::A0)
+        ));
+    }
+
+    fn mock_collector(
+        max_backoff: Duration,
+    ) -> (Collector<MockSpClient>, Arc<MockSpClient>) {
+        let sp = Arc::new(MockSpClient::new());
+        let schedule = TelemetrySchedule { max_backoff, ..Default::default() };
+        let (samples_tx, _) = broadcast::channel(16);
+        let collector = Collector {
+            sp: Arc::clone(&sp),
+            schedule,
+            history: Arc::new(Mutex::new(TelemetryHistory::new(
+                schedule.history_len,
+            ))),
+            samples_tx,
+            log: Logger::root(slog::Discard, slog::o!()),
+        };
+        (collector, sp)
+    }
+
+    #[tokio::test]
+    async fn measurements_are_collected_with_metadata() {
+        let (collector, sp) = mock_collector(Duration::from_secs(60));
+        let mut samples_rx = collector.samples_tx.subscribe();
+        let metadata = MeasurementMetadata {
+            sensor_id: 4,
+            unit: MeasurementUnit::Celsius,
+            warning_threshold: Some(70.0),
+            critical_threshold: Some(85.0),
+            min_since_boot: Some(20.0),
+            max_since_boot: Some(61.5),
+            timestamp_ms: 1000,
+        };
+        sp.expect_ok(
+            MockCall::AllMeasurements,
+            vec![
+                ComponentMeasurement {
+                    component: SpComponent::SP_ITSELF,
+                    measurement: Measurement {
+                        name: "fan0".to_string(),
+                        kind: MeasurementKind::Speed,
+                        value: Ok(2400.0),
+                    }
+                    .into(),
+                },
+                ComponentMeasurement {
+                    component: SpComponent::ROT,
+                    measurement: MeasurementV2 {
+                        name: "rot-temp".to_string(),
+                        kind: MeasurementKindV2::Temperature,
+                        value: Ok(45.0),
+                        metadata,
+                    }
+                    .into(),
+                },
+            ],
+        );
+
+        let mut backoff = unreachable_policy(Duration::from_secs(60));
+        let delay = collector
+            .collect_and_record(MetricGroup::Measurements, &mut backoff)
+            .await;
+        assert_eq!(delay, None);
+        sp.checkpoint();
+
+        let latest = collector.history.lock().unwrap().latest();
+        assert_eq!(latest.len(), 2);
+        let fan = &latest[&MetricKey::Measurement {
+            component: SpComponent::SP_ITSELF,
+            name: "fan0".to_string(),
+        }];
+        match &fan.value {
+            MetricValue::Measurement { kind, value, metadata } => {
+                assert_eq!(*kind, MeasurementKindV2::Speed);
+                assert_eq!(*value, Ok(2400.0));
+                assert_eq!(*metadata, None);
+            }
+            other => panic!("unexpected value {other:?}"),
+        }
+        let rot = &latest[&MetricKey::Measurement {
+            component: SpComponent::ROT,
+            name: "rot-temp".to_string(),
+        }];
+        match &rot.value {
+            MetricValue::Measurement { kind, value, metadata: Some(md) } => {
+                assert_eq!(*kind, MeasurementKindV2::Temperature);
+                assert_eq!(*value, Ok(45.0));
+                assert_eq!(*md, metadata);
+            }
+            other => panic!("unexpected value {other:?}"),
+        }
+
+        // Both samples were also published to subscribers.
+        for _ in 0..2 {
+            let sample = samples_rx.try_recv().unwrap();
+            assert!(matches!(sample.key, MetricKey::Measurement { .. }));
+        }
+        assert!(samples_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_sp_backs_off_until_it_responds() {
+        let max_backoff = Duration::from_secs(4);
+        let (collector, sp) = mock_collector(max_backoff);
+        let mut backoff = unreachable_policy(max_backoff);
+        // Remove the jitter so the delays are predictable.
+        backoff.randomization_factor = 0.0;
+
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            sp.expect_err(
+                MockCall::PowerState,
+                CommunicationError::ExhaustedNumAttempts(3),
+            );
+            delays.push(
+                collector
+                    .collect_and_record(MetricGroup::PowerState, &mut backoff)
+                    .await,
+            );
+        }
+        let secs = |n| Some(Duration::from_secs(n));
+        assert_eq!(delays, [secs(1), secs(2), secs(4), secs(4)]);
+
+        // An error from a reachable SP neither delays us nor resets the
+        // backoff...
+        sp.expect_err(
+            MockCall::PowerState,
+            CommunicationError::SpError(SpError::RequestUnsupportedForSp),
+        );
+        let delay = collector
+            .collect_and_record(MetricGroup::PowerState, &mut backoff)
+            .await;
+        assert_eq!(delay, None);
+        assert!(collector.history.lock().unwrap().latest().is_empty());
+
+        // ...but a successful collection does.
+        sp.expect_ok(MockCall::PowerState, PowerState::A0);
+        let delay = collector
+            .collect_and_record(MetricGroup::PowerState, &mut backoff)
+            .await;
+        assert_eq!(delay, None);
+        assert!(matches!(
+            collector.history.lock().unwrap().latest()[&MetricKey::PowerState]
+                .value,
+            MetricValue::PowerState(PowerState::A0)
+        ));
+
+        sp.expect_err(
+            MockCall::PowerState,
+            CommunicationError::ExhaustedNumAttempts(3),
+        );
+        let delay = collector
+            .collect_and_record(MetricGroup::PowerState, &mut backoff)
+            .await;
+        assert_eq!(delay, secs(1));
+        sp.checkpoint();
+    }
+
+    #[tokio::test]
+    async fn measurements_fall_back_to_component_details() {
+        let (collector, sp) = mock_collector(Duration::from_secs(60));
+        let device = |component, capabilities| SpDevice {
+            component,
+            device: "dev".to_string(),
+            description: "test device".to_string(),
+            capabilities,
+            presence: DevicePresence::Present,
+        };
+        let inventory = SpInventory {
+            devices: vec![
+                device(
+                    SpComponent::SP_ITSELF,
+                    DeviceCapabilities::HAS_MEASUREMENT_CHANNELS,
+                ),
+                device(SpComponent::SYSTEM_LED, DeviceCapabilities::IS_LED),
+            ],
+        };
+        let details = || SpComponentDetails {
+            entries: vec![ComponentDetails::Measurement(Measurement {
+                name: "temp".to_string(),
+                kind: MeasurementKind::Temperature,
+                value: Ok(30.0),
+            })],
+        };
+
+        // A pre-v9 SP doesn't know our version, so it rejects a bulk
+        // measurements request...
+        sp.expect_err(
+            MockCall::AllMeasurements,
+            CommunicationError::SpError(SpError::BadRequest(
+                BadRequestReason::WrongVersion {
+                    sp: 8,
+                    request: version::CURRENT,
+                },
+            )),
+        );
+        sp.expect_ok(MockCall::Inventory, inventory.clone());
+        sp.expect_ok(
+            MockCall::ComponentDetails(SpComponent::SP_ITSELF),
+            details(),
+        );
+        let mut backoff = unreachable_policy(Duration::from_secs(60));
+        let delay = collector
+            .collect_and_record(MetricGroup::Measurements, &mut backoff)
+            .await;
+        assert_eq!(delay, None);
+        sp.checkpoint();
+
+        let key = MetricKey::Measurement {
+            component: SpComponent::SP_ITSELF,
+            name: "temp".to_string(),
+        };
+        let latest = collector.history.lock().unwrap().latest();
+        assert_eq!(latest.len(), 1);
+        assert!(matches!(
+            latest[&key].value,
+            MetricValue::Measurement { value: Ok(v), metadata: None, .. }
+                if v == 30.0
+        ));
+
+        // ...and we don't bother asking again.
+        sp.expect_ok(MockCall::Inventory, inventory);
+        sp.expect_ok(
+            MockCall::ComponentDetails(SpComponent::SP_ITSELF),
+            details(),
+        );
+        let delay = collector
+            .collect_and_record(MetricGroup::Measurements, &mut backoff)
+            .await;
+        assert_eq!(delay, None);
+        sp.checkpoint();
+        assert_eq!(collector.history.lock().unwrap().samples(&key).len(), 2);
+    }
+}
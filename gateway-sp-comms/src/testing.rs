@@ -0,0 +1,388 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Tools for testing [`SingleSp`](crate::SingleSp) (and code built on it)
+//! under adverse network conditions, or without an SP at all (see
+//! [`MockSpClient`]). Only available with the `testing` feature.
+
+use crate::error::CommunicationError;
+use crate::shared_socket::SingleSpMessage;
+use crate::transport::SpTransport;
+use async_trait::async_trait;
+use slog::debug;
+use slog::warn;
+use slog::Logger;
+use std::collections::VecDeque;
+use std::net::SocketAddrV6;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub use crate::session::Direction;
+pub use mock::MockCall;
+pub use mock::MockSerialConsole;
+pub use mock::MockSpClient;
+pub use mock::SerialConsoleEvent;
+#[cfg(test)]
+pub(crate) use simulated::SimulatedSp;
+
+mod mock;
+#[cfg(test)]
+mod simulated;
+
+/// A fault applied to a single packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Deliver the packet unmodified.
+    None,
+    Drop,
+    /// Deliver the packet twice.
+    Duplicate,
+    /// Deliver the packet after the given delay, without holding up the
+    /// packets behind it.
+    Delay(Duration),
+    /// Hold the packet until the next packet in the same direction has been
+    /// delivered.
+    Reorder,
+    /// Deliver only the first `n` bytes of the packet.
+    Truncate(usize),
+    /// Flip one bit of the packet (the given bit index modulo the length of
+    /// the packet in bits).
+    Corrupt(usize),
+}
+
+/// Probabilities of each kind of fault being applied to a packet; any packet
+/// that doesn't draw a fault is delivered unmodified.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FaultRates {
+    pub drop: f64,
+    pub duplicate: f64,
+    pub delay: f64,
+    /// Delayed packets are delayed by a random duration up to this long.
+    pub max_delay: Duration,
+    pub reorder: f64,
+    pub truncate: f64,
+    pub corrupt: f64,
+}
+
+/// Decides which [`Fault`] to apply to each packet in one direction.
+///
+/// Scripted faults (see [`FaultSchedule::then()`]) are applied to packets in
+/// order; once the script is exhausted, faults are drawn at random according
+/// to our [`FaultRates`] from a generator seeded by `seed`, so any failure can
+/// be reproduced by reusing its seed.
+#[derive(Debug, Clone)]
+pub struct FaultSchedule {
+    rng: SplitMix64,
+    rates: FaultRates,
+    script: VecDeque<Fault>,
+}
+
+impl FaultSchedule {
+    pub fn new(seed: u64, rates: FaultRates) -> Self {
+        Self { rng: SplitMix64(seed), rates, script: VecDeque::new() }
+    }
+
+    /// A schedule that injects no faults (other than any scripted faults
+    /// added via [`FaultSchedule::then()`]).
+    pub fn none() -> Self {
+        Self::new(0, FaultRates::default())
+    }
+
+    /// Apply `fault` to the next packet not already covered by our script.
+    pub fn then(mut self, fault: Fault) -> Self {
+        self.script.push_back(fault);
+        self
+    }
+
+    fn next_fault(&mut self, packet_len: usize) -> Fault {
+        if let Some(fault) = self.script.pop_front() {
+            return fault;
+        }
+
+        let rates = self.rates;
+        let mut x = self.rng.next_f64();
+        for (rate, fault) in [
+            (rates.drop, FaultKind::Drop),
+            (rates.duplicate, FaultKind::Duplicate),
+            (rates.delay, FaultKind::Delay),
+            (rates.reorder, FaultKind::Reorder),
+            (rates.truncate, FaultKind::Truncate),
+            (rates.corrupt, FaultKind::Corrupt),
+        ] {
+            if x < rate {
+                return match fault {
+                    FaultKind::Drop => Fault::Drop,
+                    FaultKind::Duplicate => Fault::Duplicate,
+                    FaultKind::Delay => Fault::Delay(
+                        rates.max_delay.mul_f64(self.rng.next_f64()),
+                    ),
+                    FaultKind::Reorder => Fault::Reorder,
+                    FaultKind::Truncate => Fault::Truncate(
+                        self.rng.next_u64() as usize % packet_len.max(1),
+                    ),
+                    FaultKind::Corrupt => {
+                        Fault::Corrupt(self.rng.next_u64() as usize)
+                    }
+                };
+            }
+            x -= rate;
+        }
+        Fault::None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FaultKind {
+    Drop,
+    Duplicate,
+    Delay,
+    Reorder,
+    Truncate,
+    Corrupt,
+}
+
+// A small, fast, seedable PRNG; we don't need anything fancier to pick
+// faults.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// The bytes of a packet in flight in either direction.
+trait PacketBytes: Clone {
+    fn bytes_mut(&mut self) -> &mut Vec<u8>;
+}
+
+impl PacketBytes for Vec<u8> {
+    fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        self
+    }
+}
+
+// Packets from the SP also carry their source address.
+impl PacketBytes for (SocketAddrV6, Vec<u8>) {
+    fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.1
+    }
+}
+
+// Packets traveling in one direction, after faults have been applied.
+struct Lane<P> {
+    direction: Direction,
+    schedule: FaultSchedule,
+    ready: VecDeque<P>,
+    delayed: Vec<(Instant, P)>,
+    held: Option<P>,
+}
+
+impl<P: PacketBytes> Lane<P> {
+    fn new(direction: Direction, schedule: FaultSchedule) -> Self {
+        Self {
+            direction,
+            schedule,
+            ready: VecDeque::new(),
+            delayed: Vec::new(),
+            held: None,
+        }
+    }
+
+    fn push(&mut self, mut packet: P, log: &Logger) {
+        let fault = self.schedule.next_fault(packet.bytes_mut().len());
+        if fault != Fault::None {
+            debug!(
+                log, "injecting fault";
+                "direction" => ?self.direction,
+                "fault" => ?fault,
+            );
+        }
+
+        match fault {
+            Fault::None => self.deliver(packet),
+            Fault::Drop => (),
+            Fault::Duplicate => {
+                self.deliver(packet.clone());
+                self.deliver(packet);
+            }
+            Fault::Delay(delay) => {
+                self.delayed.push((Instant::now() + delay, packet));
+            }
+            Fault::Reorder => {
+                if let Some(prev) = self.held.replace(packet) {
+                    self.ready.push_back(prev);
+                }
+            }
+            Fault::Truncate(n) => {
+                packet.bytes_mut().truncate(n);
+                self.deliver(packet);
+            }
+            Fault::Corrupt(bit) => {
+                let bytes = packet.bytes_mut();
+                if !bytes.is_empty() {
+                    let bit = bit % (bytes.len() * 8);
+                    bytes[bit / 8] ^= 1 << (bit % 8);
+                }
+                self.deliver(packet);
+            }
+        }
+    }
+
+    fn deliver(&mut self, packet: P) {
+        self.ready.push_back(packet);
+        if let Some(held) = self.held.take() {
+            self.ready.push_back(held);
+        }
+    }
+
+    // Move any delayed packets whose time has come into `ready`.
+    fn promote_due(&mut self) {
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.delayed.len() {
+            if self.delayed[i].0 <= now {
+                let (_, packet) = self.delayed.remove(i);
+                self.deliver(packet);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.delayed.iter().map(|(when, _)| *when).min()
+    }
+}
+
+/// An [`SpTransport`] that wraps another transport and drops, duplicates,
+/// delays, reorders, truncates and corrupts packets in either direction
+/// according to a pair of [`FaultSchedule`]s.
+///
+/// Packets from the SP are re-serialized before faults are applied and
+/// decoded again afterwards, so (like the real network) corrupted packets may
+/// be discarded or may be delivered as a different, valid message. Host phase
+/// 2 requests are already handled by the wrapped transport and are passed
+/// through unmodified.
+pub struct FaultyTransport<T> {
+    inner: T,
+    to_sp: Lane<Vec<u8>>,
+    from_sp: Lane<(SocketAddrV6, Vec<u8>)>,
+    passthrough: VecDeque<SingleSpMessage>,
+}
+
+impl<T: SpTransport> FaultyTransport<T> {
+    pub fn new(inner: T, to_sp: FaultSchedule, from_sp: FaultSchedule) -> Self {
+        Self {
+            inner,
+            to_sp: Lane::new(Direction::ToSp, to_sp),
+            from_sp: Lane::new(Direction::FromSp, from_sp),
+            passthrough: VecDeque::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    // Send any packets to the SP that are ready to go.
+    async fn flush_to_sp(&mut self) -> Result<(), CommunicationError> {
+        self.to_sp.promote_due();
+        while let Some(packet) = self.to_sp.ready.front() {
+            self.inner.send(packet).await?;
+            self.to_sp.ready.pop_front();
+        }
+        Ok(())
+    }
+
+    fn push_from_sp(&mut self, message: SingleSpMessage) {
+        match message.encode(self.inner.discovery_addr()) {
+            Some(packet) => self.from_sp.push(packet, self.inner.log()),
+            None => self.passthrough.push_back(message),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: SpTransport> SpTransport for FaultyTransport<T> {
+    fn log(&self) -> &Logger {
+        self.inner.log()
+    }
+
+    fn interface(&self) -> &str {
+        self.inner.interface()
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        self.inner.discovery_addr()
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        self.to_sp.push(data.to_vec(), self.inner.log());
+        self.flush_to_sp().await
+    }
+
+    async fn recv(&mut self) -> SingleSpMessage {
+        loop {
+            // Packets to the SP that we've delayed are sent from here, since
+            // our `SingleSp` spends its idle time waiting on us.
+            if let Err(err) = self.flush_to_sp().await {
+                warn!(
+                    self.inner.log(), "failed to send delayed packet";
+                    "err" => %err,
+                );
+                self.to_sp.ready.pop_front();
+            }
+
+            if let Some(message) = self.passthrough.pop_front() {
+                return message;
+            }
+
+            self.from_sp.promote_due();
+            while let Some((peer, packet)) = self.from_sp.ready.pop_front() {
+                match SingleSpMessage::decode(self.inner.log(), peer, &packet) {
+                    Ok(Some(message)) => return message,
+                    Ok(None) => (),
+                    Err(err) => {
+                        debug!(
+                            self.inner.log(), "discarding damaged packet";
+                            "err" => %err,
+                        );
+                    }
+                }
+            }
+
+            let next_due = [self.to_sp.next_due(), self.from_sp.next_due()]
+                .into_iter()
+                .flatten()
+                .min();
+            tokio::select! {
+                message = self.inner.recv() => self.push_from_sp(message),
+                () = tokio::time::sleep_until(
+                    next_due.unwrap_or_else(Instant::now),
+                ), if next_due.is_some() => (),
+            }
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
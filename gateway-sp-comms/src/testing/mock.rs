@@ -0,0 +1,531 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! A scriptable [`SpClient`] for testing code built on it without an SP.
+
+use crate::error::CommunicationError;
+use crate::error::UpdateError;
+use crate::AttachedSerialConsole;
+use crate::SpClient;
+use crate::SpComponentDetails;
+use crate::SpInventory;
+use crate::VersionedSpState;
+use async_trait::async_trait;
+use gateway_messages::ignition::LinkEvents;
+use gateway_messages::ignition::TransceiverSelect;
+use gateway_messages::measurement::ComponentMeasurement;
+use gateway_messages::ComponentAction;
+use gateway_messages::IgnitionCommand;
+use gateway_messages::IgnitionState;
+use gateway_messages::PowerState;
+use gateway_messages::SpComponent;
+use gateway_messages::StartupOptions;
+use gateway_messages::UpdateStatus;
+use slog::Logger;
+use std::any::type_name;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+type Result<T, E = CommunicationError> = std::result::Result<T, E>;
+
+/// A call made to a [`MockSpClient`]: the [`SpClient`] method and its
+/// arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    State,
+    Inventory,
+    ComponentDetails(SpComponent),
+    AllMeasurements,
+    ComponentActiveSlot(SpComponent),
+    SetComponentActiveSlot {
+        component: SpComponent,
+        slot: u16,
+        persist: bool,
+    },
+    ComponentClearStatus(SpComponent),
+    ComponentAction {
+        component: SpComponent,
+        action: ComponentAction,
+    },
+    GetStartupOptions,
+    SetStartupOptions(StartupOptions),
+    PowerState,
+    SetPowerState(PowerState),
+    IgnitionState(u8),
+    BulkIgnitionState,
+    IgnitionLinkEvents(u8),
+    BulkIgnitionLinkEvents,
+    ClearIgnitionLinkEvents {
+        target: Option<u8>,
+        transceiver_select: Option<TransceiverSelect>,
+    },
+    IgnitionCommand {
+        target: u8,
+        command: IgnitionCommand,
+    },
+    StartUpdate {
+        component: SpComponent,
+        update_id: Uuid,
+        slot: u16,
+        image: Vec<u8>,
+    },
+    UpdateStatus(SpComponent),
+    UpdateAbort {
+        component: SpComponent,
+        update_id: Uuid,
+    },
+    SerialConsoleAttach(SpComponent),
+    SerialConsoleDetach,
+    GetCabooseValue([u8; 4]),
+    ReadComponentCaboose {
+        component: SpComponent,
+        slot: u16,
+        key: [u8; 4],
+    },
+    ResetComponentPrepare(SpComponent),
+    ResetComponentTrigger(SpComponent),
+    SendHostNmi,
+    SetIpccKeyLookupValue {
+        key: u8,
+        value: Vec<u8>,
+    },
+}
+
+/// An [`SpClient`] that answers each call from a script of expected calls.
+///
+/// Expectations are consumed in the order they're added via
+/// [`MockSpClient::expect_ok()`] or [`MockSpClient::expect_err()`]. A call
+/// that doesn't match the next expectation (or that arrives when there are no
+/// expectations left) panics, as does dropping a `MockSpClient` with
+/// unsatisfied expectations (unless we're already panicking).
+///
+/// ```ignore
+/// let sp = MockSpClient::new();
+/// sp.expect_ok(MockCall::PowerState, PowerState::A2);
+/// sp.expect_ok(MockCall::SetPowerState(PowerState::A0), ());
+///
+/// power_on(&sp).await.unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct MockSpClient {
+    state: Mutex<MockState>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    expectations: VecDeque<Expectation>,
+    calls: Vec<MockCall>,
+}
+
+#[derive(Debug)]
+struct Expectation {
+    call: MockCall,
+    response: Box<dyn Any + Send>,
+}
+
+impl MockSpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect `call` next, and answer it successfully with `value`.
+    ///
+    /// `value` must have the type the corresponding [`SpClient`] method
+    /// returns on success (e.g., `()` for [`MockCall::SetPowerState`]).
+    pub fn expect_ok<T: Send + 'static>(&self, call: MockCall, value: T) {
+        self.push(call, Ok::<T, ()>(value));
+    }
+
+    /// Expect `call` next, and fail it with `err`.
+    ///
+    /// `err` must have the type the corresponding [`SpClient`] method returns
+    /// on failure: [`UpdateError`] for [`MockCall::StartUpdate`], and
+    /// [`CommunicationError`] for everything else.
+    pub fn expect_err<E: Send + 'static>(&self, call: MockCall, err: E) {
+        self.push(call, Err::<(), E>(err));
+    }
+
+    /// Every call made so far, in order (including any that panicked because
+    /// they were unexpected).
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Panic if any expectations have not yet been satisfied.
+    pub fn checkpoint(&self) {
+        let state = self.state.lock().unwrap();
+        if !state.expectations.is_empty() {
+            let remaining = state
+                .expectations
+                .iter()
+                .map(|expectation| &expectation.call)
+                .collect::<Vec<_>>();
+            panic!("unsatisfied expectations: {remaining:?}");
+        }
+    }
+
+    fn push<T: Send + 'static, E: Send + 'static>(
+        &self,
+        call: MockCall,
+        response: Result<T, E>,
+    ) {
+        let response = Box::new(response);
+        self.state
+            .lock()
+            .unwrap()
+            .expectations
+            .push_back(Expectation { call, response });
+    }
+
+    fn respond<T: 'static, E: 'static>(&self, call: MockCall) -> Result<T, E> {
+        let expectation = {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(call.clone());
+            state.expectations.pop_front()
+        };
+        let expectation = match expectation {
+            Some(expectation) => expectation,
+            None => panic!("unexpected call (no expectations left): {call:?}"),
+        };
+        if expectation.call != call {
+            panic!(
+                "unexpected call: expected {:?}, got {call:?}",
+                expectation.call
+            );
+        }
+
+        // `expect_ok()` and `expect_err()` each only know one of the two
+        // types, so accept either kind of response as long as the type we do
+        // know matches.
+        let response = match expectation.response.downcast::<Result<T, ()>>() {
+            Ok(response) => match *response {
+                Ok(value) => return Ok(value),
+                Err(()) => unreachable!("`expect_ok()` only stores `Ok(_)`"),
+            },
+            Err(response) => response,
+        };
+        match response.downcast::<Result<(), E>>() {
+            Ok(response) => match *response {
+                Err(err) => Err(err),
+                Ok(()) => unreachable!("`expect_err()` only stores `Err(_)`"),
+            },
+            Err(_) => panic!(
+                "response to {call:?} has the wrong type: expected Result<{}, \
+                 {}>",
+                type_name::<T>(),
+                type_name::<E>(),
+            ),
+        }
+    }
+}
+
+impl Drop for MockSpClient {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.checkpoint();
+        }
+    }
+}
+
+#[async_trait]
+impl SpClient for MockSpClient {
+    async fn state(&self) -> Result<VersionedSpState> {
+        self.respond(MockCall::State)
+    }
+
+    async fn inventory(&self) -> Result<SpInventory> {
+        self.respond(MockCall::Inventory)
+    }
+
+    async fn component_details(
+        &self,
+        component: SpComponent,
+    ) -> Result<SpComponentDetails> {
+        self.respond(MockCall::ComponentDetails(component))
+    }
+
+    async fn all_measurements(&self) -> Result<Vec<ComponentMeasurement>> {
+        self.respond(MockCall::AllMeasurements)
+    }
+
+    async fn component_active_slot(
+        &self,
+        component: SpComponent,
+    ) -> Result<u16> {
+        self.respond(MockCall::ComponentActiveSlot(component))
+    }
+
+    async fn set_component_active_slot(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        persist: bool,
+    ) -> Result<()> {
+        self.respond(MockCall::SetComponentActiveSlot {
+            component,
+            slot,
+            persist,
+        })
+    }
+
+    async fn component_clear_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        self.respond(MockCall::ComponentClearStatus(component))
+    }
+
+    async fn component_action(
+        &self,
+        component: SpComponent,
+        action: ComponentAction,
+    ) -> Result<()> {
+        self.respond(MockCall::ComponentAction { component, action })
+    }
+
+    async fn get_startup_options(&self) -> Result<StartupOptions> {
+        self.respond(MockCall::GetStartupOptions)
+    }
+
+    async fn set_startup_options(
+        &self,
+        startup_options: StartupOptions,
+    ) -> Result<()> {
+        self.respond(MockCall::SetStartupOptions(startup_options))
+    }
+
+    async fn power_state(&self) -> Result<PowerState> {
+        self.respond(MockCall::PowerState)
+    }
+
+    async fn set_power_state(&self, power_state: PowerState) -> Result<()> {
+        self.respond(MockCall::SetPowerState(power_state))
+    }
+
+    async fn ignition_state(&self, target: u8) -> Result<IgnitionState> {
+        self.respond(MockCall::IgnitionState(target))
+    }
+
+    async fn bulk_ignition_state(&self) -> Result<Vec<IgnitionState>> {
+        self.respond(MockCall::BulkIgnitionState)
+    }
+
+    async fn ignition_link_events(&self, target: u8) -> Result<LinkEvents> {
+        self.respond(MockCall::IgnitionLinkEvents(target))
+    }
+
+    async fn bulk_ignition_link_events(&self) -> Result<Vec<LinkEvents>> {
+        self.respond(MockCall::BulkIgnitionLinkEvents)
+    }
+
+    async fn clear_ignition_link_events(
+        &self,
+        target: Option<u8>,
+        transceiver_select: Option<TransceiverSelect>,
+    ) -> Result<()> {
+        self.respond(MockCall::ClearIgnitionLinkEvents {
+            target,
+            transceiver_select,
+        })
+    }
+
+    async fn ignition_command(
+        &self,
+        target: u8,
+        command: IgnitionCommand,
+    ) -> Result<()> {
+        self.respond(MockCall::IgnitionCommand { target, command })
+    }
+
+    async fn start_update(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+        slot: u16,
+        image: Vec<u8>,
+    ) -> Result<(), UpdateError> {
+        self.respond(MockCall::StartUpdate {
+            component,
+            update_id,
+            slot,
+            image,
+        })
+    }
+
+    async fn update_status(
+        &self,
+        component: SpComponent,
+    ) -> Result<UpdateStatus> {
+        self.respond(MockCall::UpdateStatus(component))
+    }
+
+    async fn update_abort(
+        &self,
+        component: SpComponent,
+        update_id: Uuid,
+    ) -> Result<()> {
+        self.respond(MockCall::UpdateAbort { component, update_id })
+    }
+
+    async fn serial_console_attach(
+        &self,
+        component: SpComponent,
+    ) -> Result<AttachedSerialConsole> {
+        self.respond(MockCall::SerialConsoleAttach(component))
+    }
+
+    async fn serial_console_detach(&self) -> Result<()> {
+        self.respond(MockCall::SerialConsoleDetach)
+    }
+
+    async fn get_caboose_value(&self, key: [u8; 4]) -> Result<Vec<u8>> {
+        self.respond(MockCall::GetCabooseValue(key))
+    }
+
+    async fn read_component_caboose(
+        &self,
+        component: SpComponent,
+        slot: u16,
+        key: [u8; 4],
+    ) -> Result<Vec<u8>> {
+        self.respond(MockCall::ReadComponentCaboose { component, slot, key })
+    }
+
+    async fn reset_component_prepare(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        self.respond(MockCall::ResetComponentPrepare(component))
+    }
+
+    async fn reset_component_trigger(
+        &self,
+        component: SpComponent,
+    ) -> Result<()> {
+        self.respond(MockCall::ResetComponentTrigger(component))
+    }
+
+    async fn send_host_nmi(&self) -> Result<()> {
+        self.respond(MockCall::SendHostNmi)
+    }
+
+    async fn set_ipcc_key_lookup_value(
+        &self,
+        key: u8,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.respond(MockCall::SetIpccKeyLookupValue { key, value })
+    }
+}
+
+/// Something the client of a [`MockSerialConsole`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialConsoleEvent {
+    /// The client wrote this data (all of which we accepted).
+    Write(Vec<u8>),
+    Break,
+    KeepAlive,
+    Detach,
+}
+
+/// The SP's side of an [`AttachedSerialConsole`] not backed by an SP, for
+/// answering [`MockCall::SerialConsoleAttach`].
+#[derive(Debug)]
+pub struct MockSerialConsole {
+    data_tx: mpsc::Sender<(u64, Vec<u8>)>,
+    offset: u64,
+    events_rx: mpsc::UnboundedReceiver<SerialConsoleEvent>,
+}
+
+impl MockSerialConsole {
+    /// Create a mock console and the client's side of it.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(log: &Logger) -> (Self, AttachedSerialConsole) {
+        let (console, data_tx, events_rx) =
+            crate::single_sp::mock_serial_console(log.clone());
+        (Self { data_tx, offset: 0, events_rx }, console)
+    }
+
+    /// Send `data` to the client as though the SP had written it to its
+    /// serial console.
+    ///
+    /// Returns `false` if the client is gone.
+    pub async fn send(&mut self, data: &[u8]) -> bool {
+        let offset = self.offset;
+        self.offset += data.len() as u64;
+        self.data_tx.send((offset, data.to_vec())).await.is_ok()
+    }
+
+    /// Wait for the client's next action; returns `None` once the client is
+    /// gone.
+    pub async fn next_event(&mut self) -> Option<SerialConsoleEvent> {
+        self.events_rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gateway_messages::SpError;
+
+    #[tokio::test]
+    async fn mock_sp_client_follows_script() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let sp = MockSpClient::new();
+        let (mut mock_console, console) = MockSerialConsole::new(&log);
+
+        sp.expect_ok(MockCall::PowerState, PowerState::A2);
+        sp.expect_err(
+            MockCall::SetPowerState(PowerState::A0),
+            CommunicationError::SpError(SpError::Busy),
+        );
+        sp.expect_ok(
+            MockCall::SerialConsoleAttach(SpComponent::SP3_HOST_CPU),
+            console,
+        );
+
+        // Exercise the mock through the trait, as code under test would.
+        let client: &dyn SpClient = &sp;
+        assert_eq!(client.power_state().await.unwrap(), PowerState::A2);
+        match client.set_power_state(PowerState::A0).await {
+            Err(CommunicationError::SpError(SpError::Busy)) => (),
+            other => panic!("unexpected result {other:?}"),
+        }
+        let (mut tx, mut rx) = client
+            .serial_console_attach(SpComponent::SP3_HOST_CPU)
+            .await
+            .unwrap()
+            .split();
+        sp.checkpoint();
+
+        assert!(mock_console.send(b"hello").await);
+        assert_eq!(rx.recv().await.unwrap(), b"hello");
+
+        tx.write(b"world".to_vec()).await.unwrap();
+        tx.detach().await.unwrap();
+        assert_eq!(
+            mock_console.next_event().await,
+            Some(SerialConsoleEvent::Write(b"world".to_vec()))
+        );
+        assert_eq!(
+            mock_console.next_event().await,
+            Some(SerialConsoleEvent::Detach)
+        );
+
+        assert_eq!(
+            sp.calls(),
+            [
+                MockCall::PowerState,
+                MockCall::SetPowerState(PowerState::A0),
+                MockCall::SerialConsoleAttach(SpComponent::SP3_HOST_CPU),
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! An in-process SP for this crate's own tests.
+
+use crate::error::CommunicationError;
+use crate::shared_socket::SingleSpMessage;
+use crate::transport::SpTransport;
+use async_trait::async_trait;
+use gateway_messages::version;
+use gateway_messages::DiscoverResponse;
+use gateway_messages::Header;
+use gateway_messages::Message;
+use gateway_messages::MessageKind;
+use gateway_messages::MgsRequest;
+use gateway_messages::PowerState;
+use gateway_messages::ProtocolCapabilities;
+use gateway_messages::RotError;
+use gateway_messages::SpPort;
+use gateway_messages::SpResponse;
+use gateway_messages::SpStateV2;
+use slog::Logger;
+use std::net::SocketAddrV6;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// A transport to an in-process SP that answers the handful of requests
+/// `SingleSp` needs to discover it and fetch its state, and drops every packet
+/// while it's unreachable.
+pub(crate) struct SimulatedSp {
+    log: Logger,
+    interface: &'static str,
+    sp_port: SpPort,
+    serial_number: u8,
+    reachable: Arc<AtomicBool>,
+    requests: Arc<Mutex<Vec<(Instant, MgsRequest)>>>,
+    responses_tx: mpsc::UnboundedSender<SingleSpMessage>,
+    responses_rx: mpsc::UnboundedReceiver<SingleSpMessage>,
+}
+
+impl SimulatedSp {
+    /// Create a reachable SP on `sp_port` whose serial number is
+    /// `serial_number` repeated.
+    pub(crate) fn new(
+        interface: &'static str,
+        sp_port: SpPort,
+        serial_number: u8,
+    ) -> Self {
+        let (responses_tx, responses_rx) = mpsc::unbounded_channel();
+        Self {
+            log: Logger::root(slog::Discard, slog::o!()),
+            interface,
+            sp_port,
+            serial_number,
+            reachable: Arc::new(AtomicBool::new(true)),
+            requests: Arc::default(),
+            responses_tx,
+            responses_rx,
+        }
+    }
+
+    /// Flag controlling whether this SP receives (and answers) our packets.
+    pub(crate) fn reachable(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.reachable)
+    }
+
+    /// Every request sent to this SP (whether or not it was reachable), and
+    /// when it was sent.
+    pub(crate) fn requests(&self) -> Arc<Mutex<Vec<(Instant, MgsRequest)>>> {
+        Arc::clone(&self.requests)
+    }
+}
+
+#[async_trait]
+impl SpTransport for SimulatedSp {
+    fn log(&self) -> &Logger {
+        &self.log
+    }
+
+    fn interface(&self) -> &str {
+        self.interface
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        "[ff15::1]:11111".parse().unwrap()
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        let (message, _) =
+            gateway_messages::deserialize::<Message>(data).unwrap();
+        let request = match message.kind {
+            MessageKind::MgsRequest(request) => request,
+            kind => panic!("unexpected message {kind:?}"),
+        };
+        self.requests.lock().unwrap().push((Instant::now(), request));
+
+        if !self.reachable.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let response = match request {
+            MgsRequest::Discover => {
+                SpResponse::Discover(DiscoverResponse { sp_port: self.sp_port })
+            }
+            MgsRequest::SpState => SpResponse::SpStateV2(SpStateV2 {
+                hubris_archive_id: [0; 8],
+                serial_number: [self.serial_number; 32],
+                model: [0; 32],
+                revision: 0,
+                base_mac_address: [0; 6],
+                power_state: PowerState::A2,
+                rot: Err(RotError::MessageError { code: 0 }),
+            }),
+            MgsRequest::ProtocolCapabilities => {
+                SpResponse::ProtocolCapabilities(ProtocolCapabilities::empty())
+            }
+            request => panic!("unexpected request {request:?}"),
+        };
+        _ = self.responses_tx.send(SingleSpMessage::SpResponse {
+            peer: "[fe80::1]:11111".parse().unwrap(),
+            header: Header {
+                version: version::CURRENT,
+                message_id: message.header.message_id,
+            },
+            response,
+            data: Vec::new(),
+        });
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> SingleSpMessage {
+        // We hold `responses_tx` ourselves, so this never returns `None`.
+        self.responses_rx.recv().await.unwrap()
+    }
+}
@@ -0,0 +1,284 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2023 Oxide Computer Company
+
+//! Transports over which a [`SingleSp`](crate::SingleSp) exchanges packets
+//! with its SP.
+
+use crate::error::CommunicationError;
+use crate::shared_socket::SingleSpHandle;
+use crate::shared_socket::SingleSpMessage;
+use async_trait::async_trait;
+use gateway_messages::version;
+use gateway_messages::Header;
+use gateway_messages::Message;
+use gateway_messages::MessageKind;
+use gateway_messages::SpRequest;
+use slog::error;
+use slog::warn;
+use slog::Logger;
+use std::net::SocketAddr;
+use std::net::SocketAddrV6;
+use tokio::net::UdpSocket;
+
+/// A bidirectional packet transport between a [`SingleSp`](crate::SingleSp)
+/// and one SP.
+///
+/// `SingleSp` sends serialized [`Message`]s via [`SpTransport::send()`]
+/// (retrying as its [`RpcPolicy`](crate::RpcPolicy) dictates) and expects
+/// everything the SP sends back to arrive via [`SpTransport::recv()`].
+/// Transports are not expected to be reliable: lost packets are handled the
+/// same way as they are on the management network.
+#[async_trait]
+pub trait SpTransport: Send + 'static {
+    /// Logger used by the `SingleSp` driving this transport.
+    fn log(&self) -> &Logger;
+
+    /// Name of the interface (or other channel) this transport uses, as
+    /// reported by [`SingleSp::interface()`](crate::SingleSp::interface).
+    fn interface(&self) -> &str;
+
+    /// Address to which we send requests. For transports that aren't IPv6
+    /// based, this can be any address; it's only used for logging.
+    fn discovery_addr(&self) -> SocketAddrV6;
+
+    /// Send one serialized packet to the SP.
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError>;
+
+    /// Wait for the next message from the SP.
+    ///
+    /// Transports should log and skip packets that are not valid messages
+    /// rather than returning them; see [`SingleSpMessage::decode()`].
+    async fn recv(&mut self) -> SingleSpMessage;
+
+    /// Release anything this transport holds on behalf of its `SingleSp`.
+    ///
+    /// Called once by [`SingleSp::shutdown()`](crate::SingleSp::shutdown)
+    /// after its last exchange with the SP; the transport is not used again
+    /// afterwards. The default implementation does nothing.
+    async fn shutdown(&mut self) {}
+}
+
+#[async_trait]
+impl SpTransport for SingleSpHandle {
+    fn log(&self) -> &Logger {
+        SingleSpHandle::log(self)
+    }
+
+    fn interface(&self) -> &str {
+        SingleSpHandle::interface(self)
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        SingleSpHandle::discovery_addr(self)
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        SingleSpHandle::send(self, data).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> SingleSpMessage {
+        SingleSpHandle::recv(self).await
+    }
+
+    async fn shutdown(&mut self) {
+        SingleSpHandle::unregister(self).await
+    }
+}
+
+/// A transport that sends all requests to a fixed address via its own UDP
+/// socket.
+///
+/// Unlike a [`SharedSocket`](crate::SharedSocket), this allows multiple
+/// `SingleSp`s to exist on the same interface (e.g., the loopback interface),
+/// which is useful when communicating with simulated SPs.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    discovery_addr: SocketAddrV6,
+    log: Logger,
+}
+
+impl UdpTransport {
+    pub fn new(
+        socket: UdpSocket,
+        discovery_addr: SocketAddrV6,
+        log: Logger,
+    ) -> Self {
+        Self { socket, discovery_addr, log }
+    }
+}
+
+#[async_trait]
+impl SpTransport for UdpTransport {
+    fn log(&self) -> &Logger {
+        &self.log
+    }
+
+    fn interface(&self) -> &str {
+        "(direct socket handle)"
+    }
+
+    fn discovery_addr(&self) -> SocketAddrV6 {
+        self.discovery_addr
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        self.socket
+            .send_to(data, self.discovery_addr)
+            .await
+            .map(|n| assert_eq!(n, data.len()))
+            .map_err(|err| CommunicationError::UdpSendTo {
+                addr: self.discovery_addr,
+                interface: self.interface().to_string(),
+                err,
+            })
+    }
+
+    // The real `SingleSpHandle` handles errors internally, so `recv()` is
+    // defined as infallible; we're a little lazy here and just log and skip
+    // anything we don't like.
+    async fn recv(&mut self) -> SingleSpMessage {
+        let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
+        loop {
+            let (peer, buf) = match self.socket.recv_from(&mut buf).await {
+                Ok((n, SocketAddr::V6(peer))) => (peer, &buf[..n]),
+                // SPs only speak IPv6, but nothing stops our caller from
+                // handing us an IPv4 socket.
+                Ok((_, SocketAddr::V4(peer))) => {
+                    warn!(
+                        self.log, "ignoring packet from IPv4 peer";
+                        "peer" => %peer,
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    error!(self.log, "failed to recv"; "err" => %err);
+                    continue;
+                }
+            };
+
+            match SingleSpMessage::decode(&self.log, peer, buf) {
+                Ok(Some(message)) => return message,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!(
+                        self.log, "failed to deserialize packet";
+                        "err" => %err,
+                    );
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+impl SingleSpMessage {
+    /// Decode a packet received from the SP at `peer`.
+    ///
+    /// Returns `Ok(None)` (after logging to `log`) for valid messages that a
+    /// `SingleSp` doesn't handle. We don't currently handle `HostPhase2Data`
+    /// requests outside of a [`SharedSocket`](crate::SharedSocket); we could
+    /// with some work, but we have no simulations / tests that need it.
+    pub fn decode(
+        log: &Logger,
+        peer: SocketAddrV6,
+        packet: &[u8],
+    ) -> Result<Option<Self>, CommunicationError> {
+        let (message, data) = gateway_messages::deserialize::<Message>(packet)
+            .map_err(|err| CommunicationError::Deserialize { peer, err })?;
+
+        match &message.kind {
+            MessageKind::MgsRequest(_)
+            | MessageKind::MgsResponse(_)
+            | MessageKind::SpRequest(SpRequest::HostPhase2Data { .. }) => {
+                warn!(
+                    log, "message kind unsupported by transport";
+                    "message" => ?message,
+                );
+                Ok(None)
+            }
+            &MessageKind::SpRequest(SpRequest::SerialConsole {
+                component,
+                offset,
+            }) => Ok(Some(Self::SerialConsole {
+                component,
+                offset,
+                data: data.to_owned(),
+            })),
+            MessageKind::SpResponse(response) => Ok(Some(Self::SpResponse {
+                peer,
+                header: message.header,
+                response: *response,
+                data: data.to_owned(),
+            })),
+        }
+    }
+
+    /// Serialize this message back into the packet the SP sent, returning the
+    /// packet and the address it came from (or `discovery_addr` for serial
+    /// console packets, whose source we don't track).
+    ///
+    /// Returns `None` for host phase 2 requests, which are answered by the
+    /// [`SharedSocket`](crate::SharedSocket) and never arrive as packets.
+    pub fn encode(
+        &self,
+        discovery_addr: SocketAddrV6,
+    ) -> Option<(SocketAddrV6, Vec<u8>)> {
+        let (peer, message, data) = match self {
+            Self::HostPhase2Request(_) => return None,
+            &Self::SerialConsole { component, offset, ref data } => {
+                let message = Message {
+                    header: Header { version: version::CURRENT, message_id: 0 },
+                    kind: MessageKind::SpRequest(SpRequest::SerialConsole {
+                        component,
+                        offset,
+                    }),
+                };
+                (discovery_addr, message, data)
+            }
+            &Self::SpResponse { peer, header, response, ref data } => {
+                let message =
+                    Message { header, kind: MessageKind::SpResponse(response) };
+                (peer, message, data)
+            }
+        };
+
+        // `data` arrived in a single packet along with `message`, so it all
+        // fits.
+        let mut buf = [0; gateway_messages::MAX_SERIALIZED_SIZE];
+        let (n, _) = gateway_messages::serialize_with_trailing_data(
+            &mut buf,
+            &message,
+            &[data],
+        );
+        Some((peer, buf[..n].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn udp_transport_skips_ipv4_packets() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut transport = UdpTransport::new(
+            socket,
+            "[::1]:11111".parse().unwrap(),
+            Logger::root(slog::Discard, slog::o!()),
+        );
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.send_to(b"not from an SP", addr).await.unwrap();
+
+        // We should drop the packet and keep waiting rather than panic.
+        tokio::time::timeout(Duration::from_millis(100), transport.recv())
+            .await
+            .unwrap_err();
+    }
+}